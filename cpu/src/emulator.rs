@@ -0,0 +1,213 @@
+use core::fmt::{self, Display, Formatter};
+use crate::instruction::{ArithmeticOperation, BranchType, CarryOperation, Condition, Instruction, Register};
+use crate::stream::InstructionStream;
+
+/// Flags, as bits of `rflags`. Bit `n` is the flag tested by the condition
+/// encoded as `n + 1`.
+pub mod flags {
+    pub const GREATER: u8 = 1 << 0;
+    pub const LESS: u8 = 1 << 1;
+    pub const EQUAL: u8 = 1 << 2;
+    pub const NOT_EQUAL: u8 = 1 << 3;
+    pub const OVERFLOW: u8 = 1 << 4;
+    pub const EVEN: u8 = 1 << 5;
+    pub const CARRY: u8 = 1 << 6;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    /// The word at `address` isn't a valid instruction.
+    InvalidInstruction {
+        address: u8,
+        word: u16,
+    },
+    /// The program counter went past the end of the rom.
+    OutOfRom(u8),
+    /// The program counter points at the immediate of an instruction.
+    Misaligned(u8),
+}
+
+impl Display for EmulatorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::InvalidInstruction { address, word } => {
+                write!(f, "invalid instruction {word:04x} at address {address}")
+            }
+            EmulatorError::OutOfRom(address) => write!(f, "address {address} is past the end of the rom"),
+            EmulatorError::Misaligned(address) => write!(f, "address {address} is in the middle of an instruction"),
+        }
+    }
+}
+
+impl core::error::Error for EmulatorError {}
+
+/// The state of the computer, with 8 bit registers. `rnull` always reads 0,
+/// `rone` always 1, `rin` reads [`Cpu::input`], writes to `rout` end up in
+/// [`Cpu::output`], and writing `rpc` jumps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cpu {
+    pub registers: [u8; 8],
+    pub pc: u8,
+    pub flags: u8,
+    pub input: u8,
+    pub output: u8,
+    /// Instructions executed so far.
+    pub cycles: u64,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self, register: Register) -> u8 {
+        match register {
+            Register::Rnull | Register::Rreserved1 | Register::Rreserved2 => 0,
+            Register::Rone => 1,
+            Register::Rout => self.output,
+            Register::Rin => self.input,
+            Register::Rflags => self.flags,
+            Register::Rpc => self.pc,
+            general => self.registers[general.encode() as usize],
+        }
+    }
+
+    /// Write a register. Returns whether the write was a jump.
+    fn write(&mut self, register: Register, value: u8) -> bool {
+        match register {
+            Register::Rnull | Register::Rone | Register::Rin
+            | Register::Rreserved1 | Register::Rreserved2 => {}
+            Register::Rout => self.output = value,
+            Register::Rflags => self.flags = value,
+            Register::Rpc => {
+                self.pc = value;
+                return true;
+            }
+            general => self.registers[general.encode() as usize] = value,
+        }
+
+        false
+    }
+
+    pub fn holds(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Unconditional => true,
+            other => (self.flags >> (other.encode() - 1)) & 1 == 1,
+        }
+    }
+
+    fn set_flags(&mut self, a: u8, b: u8, result: u8, carry: bool, overflow: bool) {
+        let mut res = 0;
+        if a > b { res |= flags::GREATER; }
+        if a < b { res |= flags::LESS; }
+        if result == 0 { res |= flags::EQUAL; } else { res |= flags::NOT_EQUAL; }
+        if overflow { res |= flags::OVERFLOW; }
+        if result.is_multiple_of(2) { res |= flags::EVEN; }
+        if carry { res |= flags::CARRY; }
+
+        self.flags = res;
+    }
+
+    /// Execute one instruction.
+    pub fn execute(&mut self, instruction: Instruction) {
+        let jumped = match instruction {
+            Instruction::Arithmetic { op, carry, src1, src2, dst } => {
+                let a = self.read(src1.into());
+                let b = self.read(src2);
+                let carry_in = carry == CarryOperation::WithCarry && self.flags & flags::CARRY != 0;
+
+                // subtraction is addition of the complement, with the carry
+                // meaning "no borrow"
+                let (operand, carry_in) = match op {
+                    ArithmeticOperation::Add => (b, carry_in as u16),
+                    ArithmeticOperation::Sub => (!b, if carry == CarryOperation::WithCarry { carry_in as u16 } else { 1 }),
+                };
+
+                let wide = a as u16 + operand as u16 + carry_in;
+                let result = wide as u8;
+                let overflow = (a ^ result) & (operand ^ result) & 0x80 != 0;
+
+                self.set_flags(a, b, result, wide > 0xff, overflow);
+                self.write(dst, result)
+            }
+            Instruction::Move { condition, set_flags, src, dst } => {
+                if self.holds(condition) {
+                    let value = self.read(src);
+                    if set_flags {
+                        self.set_flags(value, 0, value, false, false);
+                    }
+                    self.write(dst, value)
+                } else {
+                    false
+                }
+            }
+            Instruction::Branch { address, branch_type, condition } => {
+                if self.holds(condition) {
+                    self.pc = match branch_type {
+                        BranchType::Absolute => address,
+                        BranchType::Relative => self.pc.wrapping_add(address),
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+            // registers are 8 bits wide, the high byte is for wider ones
+            Instruction::Immediate { value, dst } => self.write(dst, value as u8),
+        };
+
+        if !jumped {
+            self.pc = self.pc.wrapping_add(instruction.len() as u8);
+        }
+        self.cycles += 1;
+    }
+
+    /// Fetch, decode and execute the instruction at the program counter,
+    /// along with its immediate if it has one.
+    pub fn step(&mut self, rom: &[u16]) -> Result<(), EmulatorError> {
+        let address = self.pc;
+        let word = *rom.get(address as usize).ok_or(EmulatorError::OutOfRom(address))?;
+        let end = address as usize + Instruction::len_of(word);
+        if end > rom.len() {
+            // the immediate is the only word that can be missing
+            return Err(EmulatorError::OutOfRom(address.wrapping_add(1)));
+        }
+        let instruction = Instruction::decode_words(&rom[address as usize..end])
+            .ok_or(EmulatorError::InvalidInstruction { address, word })?;
+
+        self.execute(instruction);
+        Ok(())
+    }
+
+    /// Run at most `max_steps` instructions.
+    pub fn run(&mut self, rom: &[u16], max_steps: u64) -> Result<(), EmulatorError> {
+        for _ in 0..max_steps {
+            self.step(rom)?;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`step`](Self::step), but takes the instruction from a resolved
+    /// program instead of decoding it, so errors can be traced back to the
+    /// source through [`InstructionStream::at`].
+    pub fn step_stream(&mut self, program: &InstructionStream) -> Result<(), EmulatorError> {
+        let address = self.pc;
+        let Some(located) = program.at(address as usize) else {
+            let inside = (program.base()..program.end()).contains(&(address as usize));
+            return Err(if inside { EmulatorError::Misaligned(address) } else { EmulatorError::OutOfRom(address) });
+        };
+
+        self.execute(located.instruction);
+        Ok(())
+    }
+
+    /// Like [`run`](Self::run), for a resolved program.
+    pub fn run_stream(&mut self, program: &InstructionStream, max_steps: u64) -> Result<(), EmulatorError> {
+        for _ in 0..max_steps {
+            self.step_stream(program)?;
+        }
+
+        Ok(())
+    }
+}
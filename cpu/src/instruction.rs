@@ -0,0 +1,613 @@
+use core::fmt::{self, Display, Formatter, Write};
+
+/// Writes everything in lowercase, to show enum variants the way they're
+/// written in assembly.
+struct Lowercase<'a, 'b>(&'a mut Formatter<'b>);
+
+impl Write for Lowercase<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0.write_char(c.to_ascii_lowercase())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReducedRegister {
+    Rra = 0,
+    Rrb = 1,
+    Rrc = 2,
+    Rrd = 3,
+    Rre = 4,
+    Rrf = 5,
+    Rrg = 6,
+    Rrh = 7,
+}
+
+impl ReducedRegister {
+    pub fn encode(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_num(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Self::Rra),
+            1 => Some(Self::Rrb),
+            2 => Some(Self::Rrc),
+            3 => Some(Self::Rrd),
+            4 => Some(Self::Rre),
+            5 => Some(Self::Rrf),
+            6 => Some(Self::Rrg),
+            7 => Some(Self::Rrh),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ReducedRegister {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(Lowercase(f), "{self:?}")
+    }
+}
+
+impl From<ReducedRegister> for Register {
+    fn from(value: ReducedRegister) -> Self {
+        Register::from_num(value.encode()).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Register {
+    Ra = 0,
+    Rb = 1,
+    Rc = 2,
+    Rd = 3,
+    Re = 4,
+    Rf = 5,
+    Rg = 6,
+    Rh = 7,
+    Rnull = 8,
+    Rone = 9,
+    Rout = 10,
+    Rin = 11,
+    Rreserved1 = 12,
+    Rreserved2 = 13,
+    Rflags = 14,
+    Rpc = 15,
+}
+
+impl Register {
+    pub fn encode(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn reduce(&self) -> Option<ReducedRegister> {
+        ReducedRegister::from_num(self.encode())
+    }
+
+    pub fn from_num(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Self::Ra),
+            1 => Some(Self::Rb),
+            2 => Some(Self::Rc),
+            3 => Some(Self::Rd),
+            4 => Some(Self::Re),
+            5 => Some(Self::Rf),
+            6 => Some(Self::Rg),
+            7 => Some(Self::Rh),
+            8 => Some(Self::Rnull),
+            9 => Some(Self::Rone),
+            10 => Some(Self::Rout),
+            11 => Some(Self::Rin),
+            12 => Some(Self::Rreserved1),
+            13 => Some(Self::Rreserved2),
+            14 => Some(Self::Rflags),
+            15 => Some(Self::Rpc),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(Lowercase(f), "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Condition {
+    Unconditional = 0,
+    Greater = 1,
+    Less = 2,
+    Equal = 3,
+    NotEqual = 4,
+    Overflow = 5,
+    Even = 6,
+    Carry = 7,
+}
+
+impl Condition {
+    pub fn encode(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_num(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Self::Unconditional),
+            1 => Some(Self::Greater),
+            2 => Some(Self::Less),
+            3 => Some(Self::Equal),
+            4 => Some(Self::NotEqual),
+            5 => Some(Self::Overflow),
+            6 => Some(Self::Even),
+            7 => Some(Self::Carry),
+            _ => None,
+        }
+    }
+
+    /// The suffix used for this condition in mnemonics, like `eq` in `jeq`.
+    pub(crate) fn suffix(&self) -> &'static str {
+        match self {
+            Self::Unconditional => "",
+            Self::Greater => "gt",
+            Self::Less => "lt",
+            Self::Equal => "eq",
+            Self::NotEqual => "neq",
+            Self::Overflow => "ov",
+            Self::Even => "even",
+            Self::Carry => "c",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum MemoryOperation {
+    Load,
+    Store,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CarryOperation {
+    WithCarry,
+    WithoutCarry,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ArithmeticOperation {
+    Add,
+    Sub,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum BranchType {
+    Relative,
+    Absolute,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Instruction {
+    Arithmetic {
+        op: ArithmeticOperation,
+        carry: CarryOperation,
+        src1: ReducedRegister,
+        src2: Register,
+        dst: Register,
+    },
+    Move {
+        condition: Condition,
+        set_flags: bool,
+        src: Register,
+        dst: Register,
+    },
+    Branch {
+        address: u8,
+        branch_type: BranchType,
+        condition: Condition,
+    },
+    /// Load `value` into `dst`. The value doesn't fit in the instruction
+    /// word, so it's in the word after it.
+    Immediate {
+        value: u16,
+        dst: Register,
+    },
+}
+
+/// Instructions are shown with the same names as the [`shorthands`] used to
+/// write them, e.g. `add rra, rc, rc` or `jeq_rel -3`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Arithmetic { op, carry, src1, src2, dst } => {
+                let name = match op {
+                    ArithmeticOperation::Add => "add",
+                    ArithmeticOperation::Sub => "sub",
+                };
+                let carry = if carry == CarryOperation::WithCarry { "_carry" } else { "" };
+
+                write!(f, "{name}{carry} {src1}, {src2}, {dst}")
+            }
+            Instruction::Move { condition: Condition::Unconditional, set_flags: false, src: Register::Rnull, dst: Register::Rnull } => {
+                write!(f, "nop")
+            }
+            Instruction::Move { condition, set_flags, src, dst } => {
+                let name = if condition == Condition::Unconditional { "mov" } else { "cmov" };
+                let flags = if set_flags { "_flags" } else { "" };
+
+                write!(f, "{name}{}{flags} {src}, {dst}", condition.suffix())
+            }
+            Instruction::Branch { address, branch_type, condition } => {
+                let name = if condition == Condition::Unconditional { "jmp" } else { "j" };
+
+                match branch_type {
+                    BranchType::Absolute => write!(f, "{name}{} {address}", condition.suffix()),
+                    BranchType::Relative => write!(f, "{name}{}_rel {}", condition.suffix(), address as i8),
+                }
+            }
+            Instruction::Immediate { value, dst } => write!(f, "ldi {value}, {dst}"),
+        }
+    }
+}
+
+macro_rules! shorthand {
+    ($name: ident ($($param: ident: $ty: ty),*) -> $variant:ident { $($fieldname: ident: $value: expr),* }) => {
+        pub const fn $name($($param: $ty),*) -> Instruction {
+            Instruction::$variant {
+                $(
+                    $fieldname: $value,
+                )*
+                $(
+                    $param
+                ),*
+            }
+        }
+    };
+    ($name: ident ($($param: ident: $ty: ty),*) no default -> $variant:ident { $($fieldname: ident: $value: expr),* }) => {
+        pub const fn $name($($param: $ty),*) -> Instruction {
+            Instruction::$variant {
+                $(
+                    $fieldname: $value,
+                )*
+            }
+        }
+    };
+}
+
+pub mod shorthands {
+    use super::*;
+
+    shorthand!(nop() -> Move {set_flags: false, condition: Condition::Unconditional, src: Register::Rnull, dst: Register::Rnull});
+
+    shorthand!(add(src1: ReducedRegister, src2: Register, dst: Register) -> Arithmetic {op: ArithmeticOperation::Add, carry: CarryOperation::WithoutCarry});
+    shorthand!(add_carry(src1: ReducedRegister, src2: Register, dst: Register) -> Arithmetic {op: ArithmeticOperation::Add, carry: CarryOperation::WithCarry});
+    shorthand!(inc(src1: ReducedRegister, dst: Register) -> Arithmetic {op: ArithmeticOperation::Add, carry: CarryOperation::WithoutCarry, src2: Register::Rone});
+
+    shorthand!(sub(src1: ReducedRegister, src2: Register, dst: Register) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithoutCarry});
+    shorthand!(sub_carry(src1: ReducedRegister, src2: Register, dst: Register) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithCarry});
+    shorthand!(dec(src1: ReducedRegister, dst: Register) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithoutCarry, src2: Register::Rone});
+
+    shorthand!(cmp(src1: ReducedRegister, src2: Register) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithoutCarry, dst: Register::Rnull});
+    shorthand!(cmp_carry(src1: ReducedRegister, src2: Register) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithCarry, dst: Register::Rnull});
+    shorthand!(cmp_0(src1: ReducedRegister) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithoutCarry, src2: Register::Rnull, dst: Register::Rnull});
+    shorthand!(cmp_1(src1: ReducedRegister) -> Arithmetic {op: ArithmeticOperation::Sub, carry: CarryOperation::WithoutCarry, src2: Register::Rone, dst: Register::Rnull});
+
+    shorthand!(mov(src: Register, dst: Register) -> Move {set_flags: false, condition: Condition::Unconditional});
+    shorthand!(cmoveq(src: Register, dst: Register) -> Move {set_flags: false, condition: Condition::Equal});
+    shorthand!(cmovneq(src: Register, dst: Register) -> Move {set_flags: false, condition: Condition::NotEqual});
+
+    shorthand!(jmp(address: u8) -> Branch {branch_type: BranchType::Absolute, condition: Condition::Unconditional});
+    shorthand!(jmp_rel(address: i8) no default -> Branch {branch_type: BranchType::Relative, address: address as u8, condition: Condition::Unconditional});
+
+    shorthand!(jeq(address: u8) -> Branch {branch_type: BranchType::Absolute, condition: Condition::Equal});
+    shorthand!(jeq_rel(address: i8) no default -> Branch {branch_type: BranchType::Relative, address: address as u8, condition: Condition::Equal});
+
+    shorthand!(ldi(value: u16, dst: Register) -> Immediate {});
+}
+
+/// Why an instruction can't be encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    Reserved(Instruction, Register),
+    ReadOnly(Instruction, Register),
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Reserved(instruction, register) => write!(f, "{instruction}: {register} is reserved"),
+            EncodeError::ReadOnly(instruction, register) => write!(f, "{instruction}: {register} can't be written to"),
+        }
+    }
+}
+
+impl core::error::Error for EncodeError {}
+
+impl Register {
+    fn is_reserved(&self) -> bool {
+        matches!(self, Self::Rreserved1 | Self::Rreserved2)
+    }
+
+    /// Registers that can't be written to: writes to them are lost.
+    fn is_read_only(&self) -> bool {
+        matches!(self, Self::Rone | Self::Rin)
+    }
+}
+
+impl Instruction {
+    fn check_src(&self, src: Register) -> Result<(), EncodeError> {
+        if src.is_reserved() {
+            return Err(EncodeError::Reserved(*self, src));
+        }
+
+        Ok(())
+    }
+
+    fn check_dst(&self, dst: Register) -> Result<(), EncodeError> {
+        if dst.is_reserved() {
+            return Err(EncodeError::Reserved(*self, dst));
+        }
+        if dst.is_read_only() {
+            return Err(EncodeError::ReadOnly(*self, dst));
+        }
+
+        Ok(())
+    }
+
+    /// Check that this instruction does what it looks like it does.
+    /// [`encode`](Self::encode) will happily encode instructions that use
+    /// reserved registers, or write to a register that can't be written to.
+    pub fn validate(&self) -> Result<(), EncodeError> {
+        match *self {
+            Instruction::Arithmetic { src1, src2, dst, .. } => {
+                self.check_src(src1.into())?;
+                self.check_src(src2)?;
+                self.check_dst(dst)?;
+            }
+            Instruction::Move { src, dst, .. } => {
+                self.check_src(src)?;
+                // nop moves rnull to itself, and rnull may be written to
+                self.check_dst(dst)?;
+            }
+            Instruction::Branch { .. } => {}
+            Instruction::Immediate { dst, .. } => self.check_dst(dst)?,
+        }
+
+        Ok(())
+    }
+
+    /// Like [`encode_words`](Self::encode_words), but fails on invalid
+    /// instructions.
+    pub fn try_encode(&self) -> Result<impl Iterator<Item=u16>, EncodeError> {
+        self.validate()?;
+        Ok(self.encode_words())
+    }
+
+    /// The number of words this instruction takes up: 2 for instructions
+    /// with an immediate in the next word, 1 for all others.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        1 + self.immediate().is_some() as usize
+    }
+
+    /// The number of words of the instruction that starts with `word`, which
+    /// the cpu knows after fetching only that word.
+    pub fn len_of(word: u16) -> usize {
+        match word >> 13 {
+            0b110 => 2,
+            _ => 1,
+        }
+    }
+
+    /// The word after the instruction word, for instructions that have one.
+    pub fn immediate(&self) -> Option<u16> {
+        match *self {
+            Instruction::Immediate { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The instruction `word` encodes, if it's a valid encoding of an
+    /// instruction of a single word. See [`decode_words`](Self::decode_words)
+    /// for the others.
+    pub fn decode(word: u16) -> Option<Instruction> {
+        Self::decode_words(&[word])
+    }
+
+    /// The instruction at the start of `words`, if they start with a valid
+    /// encoding. Only the first [`len`](Self::len) words are used.
+    pub fn decode_words(words: &[u16]) -> Option<Instruction> {
+        let word = *words.first()?;
+        let register = |shift: u16| Register::from_num((word >> shift & 0b1111) as u8);
+        let condition = || Condition::from_num((word >> 9 & 0b1111) as u8);
+
+        match word >> 13 {
+            0b001 => Some(Instruction::Arithmetic {
+                op: if word >> 12 & 1 == 1 { ArithmeticOperation::Add } else { ArithmeticOperation::Sub },
+                carry: if word >> 11 & 1 == 1 { CarryOperation::WithCarry } else { CarryOperation::WithoutCarry },
+                src1: ReducedRegister::from_num((word >> 8 & 0b111) as u8)?,
+                src2: register(4)?,
+                dst: register(0)?,
+            }),
+            0b100 => Some(Instruction::Move {
+                condition: condition()?,
+                set_flags: word >> 8 & 1 == 1,
+                src: register(4)?,
+                dst: register(0)?,
+            }),
+            0b101 => Some(Instruction::Branch {
+                address: word as u8,
+                branch_type: if word >> 8 & 1 == 1 { BranchType::Relative } else { BranchType::Absolute },
+                condition: condition()?,
+            }),
+            // the bits between the opcode and the register are unused
+            0b110 if word >> 4 & 0b1_1111_1111 == 0 => Some(Instruction::Immediate {
+                value: *words.get(1)?,
+                dst: register(0)?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Every word of the encoding: [`encode`](Self::encode), followed by the
+    /// [`immediate`](Self::immediate) if there is one.
+    pub fn encode_words(&self) -> impl Iterator<Item=u16> {
+        core::iter::once(self.encode()).chain(self.immediate())
+    }
+
+    /// The first word of the encoding, which is all of it for instructions of
+    /// a single word.
+    // the digits are grouped by the fields of the instruction
+    #[allow(clippy::unusual_byte_groupings)]
+    pub fn encode(&self) -> u16 {
+        match *self {
+            Instruction::Arithmetic { op, carry, src1, src2, dst } => {
+                let opcode: u16 = 0b001_0_0_000_0000_0000;
+                let op: u16 = if op == ArithmeticOperation::Add {0b000_1_0_000_0000_0000} else {0b000_0_0_000_0000_0000};
+                let carry: u16 = if carry == CarryOperation::WithCarry {0b000_0_1_000_0000_0000} else {0b000_0_0_000_0000_0000};
+
+                let src1 = (src1.encode() as u16) << 8;
+                let src2 = (src2.encode() as u16) << 4;
+                let dst = dst.encode() as u16;
+
+                opcode | op | carry | src1 | src2 | dst
+            }
+            Instruction::Move { condition, set_flags, src, dst } => {
+                let opcode: u16 = 0b100_0000_0_0000_0000;
+                let set_flags: u16 = if set_flags {0b000_0000_1_0000_0000} else {0b000_0000_0_0000_0000};
+
+                let condition = (condition.encode() as u16) << 9;
+                let src = (src.encode() as u16) << 4;
+                let dst = dst.encode() as u16;
+
+                opcode | condition | set_flags | src | dst
+            },
+            Instruction::Branch { address, branch_type, condition } => {
+                let opcode: u16 = 0b101_0000_0_0000_0000;
+                let branch_type: u16 = if branch_type == BranchType::Relative {0b000_0000_1_0000_0000} else {0b000_0000_0_0000_0000};
+
+                let condition = (condition.encode() as u16) << 9;
+
+                opcode | condition | branch_type | address as u16
+            },
+            Instruction::Immediate { dst, .. } => {
+                let opcode: u16 = 0b1100_0000_0000_0000;
+
+                opcode | dst.encode() as u16
+            }
+        }
+    }
+}
+
+/// Everything needed to build instructions by hand: the shorthands and the
+/// register, condition and branch type variants. [`program!`] doesn't need
+/// this, it resolves its operands itself.
+#[allow(unused_imports)]
+pub mod prelude {
+    pub use super::shorthands::*;
+    pub use super::{Instruction, Register, ReducedRegister, Condition, BranchType};
+    pub use super::BranchType::*;
+    pub use super::Condition::*;
+    pub use super::Register::*;
+    pub use super::ReducedRegister::*;
+}
+
+/// Resolve a register, condition or branch type name used in [`program!`] to
+/// its full path, so nothing has to be imported into the caller's scope.
+/// Anything else, like a constant or an expression, is passed through unchanged.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! operand {
+    (Ra) => { $crate::instruction::Register::Ra };
+    (Rb) => { $crate::instruction::Register::Rb };
+    (Rc) => { $crate::instruction::Register::Rc };
+    (Rd) => { $crate::instruction::Register::Rd };
+    (Re) => { $crate::instruction::Register::Re };
+    (Rf) => { $crate::instruction::Register::Rf };
+    (Rg) => { $crate::instruction::Register::Rg };
+    (Rh) => { $crate::instruction::Register::Rh };
+    (Rnull) => { $crate::instruction::Register::Rnull };
+    (Rone) => { $crate::instruction::Register::Rone };
+    (Rout) => { $crate::instruction::Register::Rout };
+    (Rin) => { $crate::instruction::Register::Rin };
+    (Rreserved1) => { $crate::instruction::Register::Rreserved1 };
+    (Rreserved2) => { $crate::instruction::Register::Rreserved2 };
+    (Rflags) => { $crate::instruction::Register::Rflags };
+    (Rpc) => { $crate::instruction::Register::Rpc };
+    (Rra) => { $crate::instruction::ReducedRegister::Rra };
+    (Rrb) => { $crate::instruction::ReducedRegister::Rrb };
+    (Rrc) => { $crate::instruction::ReducedRegister::Rrc };
+    (Rrd) => { $crate::instruction::ReducedRegister::Rrd };
+    (Rre) => { $crate::instruction::ReducedRegister::Rre };
+    (Rrf) => { $crate::instruction::ReducedRegister::Rrf };
+    (Rrg) => { $crate::instruction::ReducedRegister::Rrg };
+    (Rrh) => { $crate::instruction::ReducedRegister::Rrh };
+    (Unconditional) => { $crate::instruction::Condition::Unconditional };
+    (Greater) => { $crate::instruction::Condition::Greater };
+    (Less) => { $crate::instruction::Condition::Less };
+    (Equal) => { $crate::instruction::Condition::Equal };
+    (NotEqual) => { $crate::instruction::Condition::NotEqual };
+    (Overflow) => { $crate::instruction::Condition::Overflow };
+    (Even) => { $crate::instruction::Condition::Even };
+    (Carry) => { $crate::instruction::Condition::Carry };
+    (Relative) => { $crate::instruction::BranchType::Relative };
+    (Absolute) => { $crate::instruction::BranchType::Absolute };
+    ($($other: tt)*) => { $($other)* };
+}
+
+/// Build a [`Program`](crate::program::Program) from a list of instructions,
+/// separated by `;`. Operands are separated by `,` and can be any expression,
+/// e.g. `jmp START + 2` or `add Rra, my_register, Rc`. `name:` defines a label
+/// and `jmp @name` branches to it.
+#[macro_export]
+macro_rules! program {
+    (@munch $program: ident) => {};
+    (@munch $program: ident $label: ident : $($rest: tt)*) => {
+        $program.label(stringify!($label));
+        $crate::program!(@munch $program $($rest)*)
+    };
+    (@munch $program: ident $instruction: ident @ $label: ident $(; $($rest: tt)*)?) => {
+        $program.branch_to($crate::instruction::shorthands::$instruction(0), stringify!($label));
+        $crate::program!(@munch $program $($($rest)*)?)
+    };
+    (@munch $program: ident $instruction: ident $($rest: tt)*) => {
+        $crate::program!(@operands $program $instruction [] [] $($rest)*)
+    };
+
+    // end of an instruction
+    (@operands $program: ident $instruction: ident [$($done: tt)*] [] ; $($rest: tt)*) => {
+        $program.push($crate::program!(@emit $instruction [$($done)*]));
+        $crate::program!(@munch $program $($rest)*)
+    };
+    (@operands $program: ident $instruction: ident [$($done: tt)*] [$($current: tt)+] ; $($rest: tt)*) => {
+        $program.push($crate::program!(@emit $instruction [$($done)* ($($current)+)]));
+        $crate::program!(@munch $program $($rest)*)
+    };
+    // end of an operand
+    (@operands $program: ident $instruction: ident [$($done: tt)*] [$($current: tt)+] , $($rest: tt)*) => {
+        $crate::program!(@operands $program $instruction [$($done)* ($($current)+)] [] $($rest)*)
+    };
+    // end of the program, without a trailing `;`
+    (@operands $program: ident $instruction: ident [$($done: tt)*] []) => {
+        $program.push($crate::program!(@emit $instruction [$($done)*]));
+    };
+    (@operands $program: ident $instruction: ident [$($done: tt)*] [$($current: tt)+]) => {
+        $program.push($crate::program!(@emit $instruction [$($done)* ($($current)+)]));
+    };
+    // part of an operand
+    (@operands $program: ident $instruction: ident [$($done: tt)*] [$($current: tt)*] $next: tt $($rest: tt)*) => {
+        $crate::program!(@operands $program $instruction [$($done)*] [$($current)* $next] $($rest)*)
+    };
+
+    (@emit $instruction: ident [$($operand: tt)*]) => {
+        $crate::instruction::shorthands::$instruction($($crate::operand! $operand),*)
+    };
+
+    ($($tokens: tt)*) => {
+        {
+            let mut program = $crate::program::Program::new();
+            $crate::program!(@munch program $($tokens)*);
+            program
+        }
+    };
+}
@@ -0,0 +1,13 @@
+//! The instruction set of the redstone computer, its assembler and an emulator.
+//! This only needs `alloc`, so it also runs on the microcontroller in the
+//! physical control panel.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod instruction;
+pub mod program;
+pub mod stream;
+pub mod emulator;
+pub mod parse;
@@ -0,0 +1,232 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+use crate::instruction::{
+    ArithmeticOperation, BranchType, CarryOperation, Condition, Instruction, ReducedRegister, Register,
+};
+use crate::program::Program;
+use crate::stream::SourceLocation;
+
+/// Why a line of assembly couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+const CONDITIONS: [Condition; 8] = [
+    Condition::Unconditional,
+    Condition::Greater,
+    Condition::Less,
+    Condition::Equal,
+    Condition::NotEqual,
+    Condition::Overflow,
+    Condition::Even,
+    Condition::Carry,
+];
+
+fn register(name: &str) -> Result<Register, String> {
+    (0..16)
+        .filter_map(Register::from_num)
+        .find(|i| i.to_string() == name)
+        .ok_or_else(|| format!("unknown register {name}"))
+}
+
+fn reduced_register(name: &str) -> Result<ReducedRegister, String> {
+    (0..8)
+        .filter_map(ReducedRegister::from_num)
+        .find(|i| i.to_string() == name)
+        .ok_or_else(|| format!("{name} isn't one of rra to rrh"))
+}
+
+fn number(s: &str) -> Result<i64, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }.map_err(|_| format!("invalid number {s}"))?;
+
+    Ok(if negative { -value } else { value })
+}
+
+/// Split a condition suffix, like `eq`, off the start of `s`.
+fn condition(s: &str) -> Option<(Condition, &str)> {
+    CONDITIONS.iter()
+        .filter(|i| **i != Condition::Unconditional)
+        .find_map(|i| Some((*i, s.strip_prefix(i.suffix())?)))
+}
+
+fn expect_operands(mnemonic: &str, operands: &[&str], n: usize) -> Result<(), String> {
+    if operands.len() != n {
+        return Err(format!("{mnemonic} takes {n} operands, not {}", operands.len()));
+    }
+
+    Ok(())
+}
+
+/// Parse one instruction, and the label it branches to if it's written as
+/// `@label`.
+fn instruction(statement: &str) -> Result<(Instruction, Option<String>), String> {
+    let (mnemonic, rest) = statement.split_once(char::is_whitespace).unwrap_or((statement, ""));
+    let operands: Vec<_> = if rest.trim().is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    let ops = &operands[..];
+
+    let arithmetic = |op, carry, src1: &str, src2: &str, dst: &str| -> Result<_, String> {
+        Ok((Instruction::Arithmetic {
+            op,
+            carry,
+            src1: reduced_register(src1)?,
+            src2: register(src2)?,
+            dst: register(dst)?,
+        }, None))
+    };
+    let carry = |with| if with { CarryOperation::WithCarry } else { CarryOperation::WithoutCarry };
+
+    match mnemonic {
+        "nop" => {
+            expect_operands(mnemonic, ops, 0)?;
+            return Ok((crate::instruction::shorthands::nop(), None));
+        }
+        "add" | "add_carry" | "sub" | "sub_carry" => {
+            expect_operands(mnemonic, ops, 3)?;
+            let op = if mnemonic.starts_with("add") { ArithmeticOperation::Add } else { ArithmeticOperation::Sub };
+            return arithmetic(op, carry(mnemonic.ends_with("_carry")), ops[0], ops[1], ops[2]);
+        }
+        "inc" | "dec" => {
+            expect_operands(mnemonic, ops, 2)?;
+            let op = if mnemonic == "inc" { ArithmeticOperation::Add } else { ArithmeticOperation::Sub };
+            return arithmetic(op, carry(false), ops[0], "rone", ops[1]);
+        }
+        "cmp" | "cmp_carry" => {
+            expect_operands(mnemonic, ops, 2)?;
+            return arithmetic(ArithmeticOperation::Sub, carry(mnemonic == "cmp_carry"), ops[0], ops[1], "rnull");
+        }
+        "cmp_0" | "cmp_1" => {
+            expect_operands(mnemonic, ops, 1)?;
+            let src2 = if mnemonic == "cmp_0" { "rnull" } else { "rone" };
+            return arithmetic(ArithmeticOperation::Sub, carry(false), ops[0], src2, "rnull");
+        }
+        "ldi" => {
+            expect_operands(mnemonic, ops, 2)?;
+            let value = number(ops[0])?;
+            if !(0..=u16::MAX as i64).contains(&value) {
+                return Err(format!("immediate {value} doesn't fit in a word"));
+            }
+
+            return Ok((Instruction::Immediate { value: value as u16, dst: register(ops[1])? }, None));
+        }
+        _ => {}
+    }
+
+    let unknown = || format!("unknown instruction {mnemonic}");
+
+    let moved = mnemonic.strip_prefix("mov").map(|rest| (Condition::Unconditional, rest))
+        .or_else(|| condition(mnemonic.strip_prefix("cmov")?));
+    if let Some((condition, rest)) = moved {
+        let set_flags = match rest {
+            "" => false,
+            "_flags" => true,
+            _ => return Err(unknown()),
+        };
+        expect_operands(mnemonic, ops, 2)?;
+
+        return Ok((Instruction::Move {
+            condition,
+            set_flags,
+            src: register(ops[0])?,
+            dst: register(ops[1])?,
+        }, None));
+    }
+
+    let branch = mnemonic.strip_prefix("jmp").map(|rest| (Condition::Unconditional, rest))
+        .or_else(|| condition(mnemonic.strip_prefix('j')?));
+    if let Some((condition, rest)) = branch {
+        let branch_type = match rest {
+            "" => BranchType::Absolute,
+            "_rel" => BranchType::Relative,
+            _ => return Err(unknown()),
+        };
+        expect_operands(mnemonic, ops, 1)?;
+
+        if let Some(label) = ops[0].strip_prefix('@') {
+            return Ok((Instruction::Branch { address: 0, branch_type, condition }, Some(label.to_string())));
+        }
+
+        let address = number(ops[0])?;
+        let range = match branch_type {
+            BranchType::Absolute => 0..=u8::MAX as i64,
+            BranchType::Relative => i8::MIN as i64..=i8::MAX as i64,
+        };
+        if !range.contains(&address) {
+            return Err(format!("branch target {address} is out of range"));
+        }
+
+        return Ok((Instruction::Branch { address: address as u8, branch_type, condition }, None));
+    }
+
+    Err(unknown())
+}
+
+impl Program {
+    /// Parse a program written as text, in the same syntax as [`program!`]:
+    /// instructions separated by newlines or `;`, `label:` to define a label
+    /// and `@label` to branch to one. Everything after `#` or `//` on a line
+    /// is a comment. Instructions are named like in a
+    /// [`listing`](Program::listing), so listings can be parsed too, once
+    /// the addresses and encodings in front are left out.
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut res = Program::new();
+
+        for (idx, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let line = line.split("//").next().unwrap_or("");
+            let error = |message| ParseError { line: idx + 1, message };
+
+            for statement in line.split(';').map(str::trim).filter(|i| !i.is_empty()) {
+                if let Some(label) = statement.strip_suffix(':') {
+                    let label = label.trim();
+                    if label.is_empty() || !label.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        return Err(error(format!("invalid label {label:?}")));
+                    }
+
+                    res.label(label);
+                    continue;
+                }
+
+                let source = SourceLocation { line: idx + 1 };
+                match instruction(statement).map_err(error)? {
+                    (instruction, Some(label)) => res.branch_to_from(instruction, label, source),
+                    (instruction, None) => res.push_from(instruction, source),
+                }
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
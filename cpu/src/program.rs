@@ -0,0 +1,277 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter, Write};
+use crate::instruction::{BranchType, EncodeError, Instruction};
+use crate::stream::{InstructionStream, Located, SourceLocation};
+
+/// Why a program can't be assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleError {
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    LabelOutOfRange {
+        label: String,
+        address: usize,
+    },
+    BranchTooFar {
+        label: String,
+        offset: i64,
+    },
+    NotABranch(Instruction),
+    TooLong {
+        words: usize,
+        base: u8,
+    },
+    Invalid {
+        address: usize,
+        error: EncodeError,
+    },
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleError::DuplicateLabel(label) => write!(f, "label {label} is defined more than once"),
+            AssembleError::UndefinedLabel(label) => write!(f, "undefined label {label}"),
+            AssembleError::LabelOutOfRange { label, address } => {
+                write!(f, "label {label} is at address {address}, past the end of memory")
+            }
+            AssembleError::BranchTooFar { label, offset } => {
+                write!(f, "label {label} is too far away for a relative branch ({offset})")
+            }
+            AssembleError::NotABranch(instruction) => {
+                write!(f, "only branches can refer to a label, not {instruction:?}")
+            }
+            AssembleError::TooLong { words, base } => {
+                write!(f, "program of {words} words doesn't fit at address {base}")
+            }
+            AssembleError::Invalid { address, error } => {
+                write!(f, "invalid instruction at address {address}: {error}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for AssembleError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Instruction {
+        instruction: Instruction,
+        source: Option<SourceLocation>,
+    },
+    Label(String),
+    /// A branch whose address is filled in once the label's address is known.
+    Branch {
+        instruction: Instruction,
+        label: String,
+        source: Option<SourceLocation>,
+    },
+}
+
+impl Item {
+    /// The number of words the item takes up in the assembled program.
+    fn len(&self) -> usize {
+        match self {
+            Item::Instruction { instruction, .. } | Item::Branch { instruction, .. } => instruction.len(),
+            Item::Label(_) => 0,
+        }
+    }
+}
+
+/// A list of instructions and labels, built with [`program!`]. Branches can
+/// refer to labels instead of addresses, which are only resolved when the
+/// program is assembled. Programs can be appended to each other before that,
+/// so routines written separately can refer to each other's labels.
+///
+/// Programs [parsed](Program::parse) from text remember the line every
+/// instruction is on, which ends up in the [`InstructionStream`] and listing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    items: Vec<Item>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, instruction: Instruction) {
+        self.items.push(Item::Instruction { instruction, source: None });
+    }
+
+    /// Like [`push`](Self::push), for an instruction written at `source`.
+    pub fn push_from(&mut self, instruction: Instruction, source: SourceLocation) {
+        self.items.push(Item::Instruction { instruction, source: Some(source) });
+    }
+
+    /// Mark the position of the next instruction with a label.
+    pub fn label(&mut self, name: impl Into<String>) {
+        self.items.push(Item::Label(name.into()));
+    }
+
+    /// Add a branch to `label`. The address in `instruction` is ignored.
+    pub fn branch_to(&mut self, instruction: Instruction, label: impl Into<String>) {
+        self.items.push(Item::Branch {
+            instruction,
+            label: label.into(),
+            source: None,
+        });
+    }
+
+    /// Like [`branch_to`](Self::branch_to), for a branch written at `source`.
+    pub fn branch_to_from(&mut self, instruction: Instruction, label: impl Into<String>, source: SourceLocation) {
+        self.items.push(Item::Branch {
+            instruction,
+            label: label.into(),
+            source: Some(source),
+        });
+    }
+
+    /// Add all of `other` to the end of this program.
+    pub fn append(&mut self, mut other: Program) {
+        self.items.append(&mut other.items);
+    }
+
+    pub fn then(mut self, other: Program) -> Self {
+        self.append(other);
+        self
+    }
+
+    /// The number of instructions in this program.
+    pub fn len(&self) -> usize {
+        self.items.iter()
+            .filter(|i| !matches!(i, Item::Label(_)))
+            .count()
+    }
+
+    /// The number of words this program assembles to, which is more than
+    /// [`len`](Self::len) if some instructions have an immediate.
+    pub fn words(&self) -> usize {
+        self.items.iter().map(Item::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The address of every label, when the program is placed at `base`.
+    /// Addresses count words, not instructions.
+    pub fn labels(&self, base: u8) -> Result<BTreeMap<String, u8>, AssembleError> {
+        let mut res = BTreeMap::new();
+        let mut address = base as usize;
+
+        for i in &self.items {
+            match i {
+                Item::Label(name) => {
+                    if address > u8::MAX as usize {
+                        return Err(AssembleError::LabelOutOfRange { label: name.clone(), address });
+                    }
+                    if res.insert(name.clone(), address as u8).is_some() {
+                        return Err(AssembleError::DuplicateLabel(name.clone()));
+                    }
+                }
+                _ => address += i.len(),
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Resolve all labels, placing the program at `base`. Relative branches
+    /// are relative to the address of the branch itself.
+    pub fn resolve(&self, base: u8) -> Result<InstructionStream, AssembleError> {
+        let labels = self.labels(base)?;
+        let mut res = InstructionStream::new(base);
+
+        for i in &self.items {
+            match i {
+                Item::Label(_) => {}
+                Item::Instruction { instruction, source } => res.push(*instruction, *source),
+                Item::Branch { instruction, label, source } => {
+                    let target = *labels.get(label)
+                        .ok_or_else(|| AssembleError::UndefinedLabel(label.clone()))?;
+                    let Instruction::Branch { branch_type, condition, .. } = *instruction else {
+                        return Err(AssembleError::NotABranch(*instruction));
+                    };
+
+                    let address = match branch_type {
+                        BranchType::Absolute => target,
+                        BranchType::Relative => {
+                            let offset = target as i64 - res.end() as i64;
+                            if offset < i8::MIN as i64 || offset > i8::MAX as i64 {
+                                return Err(AssembleError::BranchTooFar { label: label.clone(), offset });
+                            }
+                            offset as i8 as u8
+                        }
+                    };
+
+                    res.push(Instruction::Branch { address, branch_type, condition }, *source);
+                }
+            }
+        }
+
+        if res.end() > u8::MAX as usize + 1 {
+            return Err(AssembleError::TooLong { words: res.words(), base });
+        }
+
+        Ok(res)
+    }
+
+    /// Resolve all labels, placing the program at `base`, and encode it.
+    pub fn assemble(&self, base: u8) -> Result<Vec<u16>, AssembleError> {
+        self.resolve(base)?.encode()
+    }
+}
+
+impl Program {
+    /// Turn encoded words back into a program, without labels. Returns `None`
+    /// if any of the words isn't a valid encoding, or the last instruction is
+    /// missing its immediate.
+    pub fn disassemble(words: &[u16]) -> Option<Self> {
+        let mut res = Self::new();
+        res.extend(InstructionStream::decode(words, 0)?.iter().map(|i| i.instruction));
+
+        Some(res)
+    }
+
+    /// A human readable listing of the assembled program: every instruction
+    /// with its address, encoding and mnemonic, and the labels in between.
+    /// Immediates are shown after the instruction word, and the line an
+    /// instruction was parsed from in a comment after it.
+    pub fn listing(&self, base: u8) -> Result<String, AssembleError> {
+        let resolved = self.resolve(base)?;
+        let mut resolved = resolved.iter();
+        let mut res = String::new();
+
+        for i in &self.items {
+            if let Item::Label(name) = i {
+                // writing to a string can't fail
+                let _ = writeln!(res, "{name}:");
+                continue;
+            }
+
+            let Located { address, instruction, source } = *resolved.next().expect("one instruction per item");
+            let _ = write!(res, "    {address:3}: {:04x}", instruction.encode());
+            if let Some(immediate) = instruction.immediate() {
+                let _ = write!(res, " {immediate:04x}");
+            }
+            let _ = write!(res, "  {instruction}");
+            if let Some(source) = source {
+                let _ = write!(res, "  # {source}");
+            }
+            res.push('\n');
+        }
+
+        Ok(res)
+    }
+}
+
+impl Extend<Instruction> for Program {
+    fn extend<T: IntoIterator<Item=Instruction>>(&mut self, iter: T) {
+        for i in iter {
+            self.push(i);
+        }
+    }
+}
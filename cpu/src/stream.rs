@@ -0,0 +1,128 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use crate::instruction::Instruction;
+use crate::program::AssembleError;
+
+/// Where an instruction was written, for programs parsed from text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// Counting from 1.
+    pub line: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}", self.line)
+    }
+}
+
+/// An instruction at its place in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Located {
+    pub address: usize,
+    pub instruction: Instruction,
+    /// `None` for instructions that weren't parsed from text.
+    pub source: Option<SourceLocation>,
+}
+
+impl Located {
+    /// The address right after this instruction and its immediate.
+    pub fn end(&self) -> usize {
+        self.address + self.instruction.len()
+    }
+}
+
+/// A program with all labels resolved: every instruction with the address it
+/// ends up at. This is what [`Program::resolve`](crate::program::Program::resolve)
+/// produces, and what everything after the assembler works with, so none of
+/// them have to work out addresses themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstructionStream {
+    base: usize,
+    instructions: Vec<Located>,
+}
+
+impl InstructionStream {
+    /// An empty stream, starting at address `base`.
+    pub fn new(base: u8) -> Self {
+        Self { base: base as usize, instructions: Vec::new() }
+    }
+
+    /// Add `instruction` right after the last one.
+    pub fn push(&mut self, instruction: Instruction, source: Option<SourceLocation>) {
+        let address = self.end();
+        self.instructions.push(Located { address, instruction, source });
+    }
+
+    /// The address of the first instruction.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// The address right after the last instruction. Can be one past the end
+    /// of memory, for a program that fills it completely.
+    pub fn end(&self) -> usize {
+        self.instructions.last().map_or(self.base, Located::end)
+    }
+
+    /// The number of words the instructions take up.
+    pub fn words(&self) -> usize {
+        self.end() - self.base
+    }
+
+    /// The number of instructions.
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Located> {
+        self.instructions.iter()
+    }
+
+    /// The instruction starting at `address`, if there is one. Addresses
+    /// of immediates don't have an instruction.
+    pub fn at(&self, address: usize) -> Option<&Located> {
+        let idx = self.instructions.binary_search_by_key(&address, |i| i.address).ok()?;
+        Some(&self.instructions[idx])
+    }
+
+    /// Encode every instruction, failing on the first invalid one. See
+    /// [`Instruction::validate`].
+    pub fn encode(&self) -> Result<Vec<u16>, AssembleError> {
+        let mut res = Vec::with_capacity(self.words());
+        for i in &self.instructions {
+            let words = i.instruction.try_encode()
+                .map_err(|error| AssembleError::Invalid { address: i.address, error })?;
+            res.extend(words);
+        }
+
+        Ok(res)
+    }
+
+    /// Decode `words`, placed at `base`. Returns `None` if any of the words
+    /// isn't a valid encoding, or the last instruction is missing its
+    /// immediate.
+    pub fn decode(mut words: &[u16], base: u8) -> Option<Self> {
+        let mut res = Self::new(base);
+        while !words.is_empty() {
+            let instruction = Instruction::decode_words(words)?;
+            words = &words[instruction.len()..];
+            res.push(instruction, None);
+        }
+
+        Some(res)
+    }
+}
+
+impl<'a> IntoIterator for &'a InstructionStream {
+    type Item = &'a Located;
+    type IntoIter = core::slice::Iter<'a, Located>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.instructions.iter()
+    }
+}
@@ -0,0 +1,219 @@
+use proptest::prelude::*;
+use proptest::sample::select;
+use schematics_cpu::emulator::{Cpu, EmulatorError};
+use schematics_cpu::instruction::{
+    ArithmeticOperation, BranchType, CarryOperation, Condition, Instruction, ReducedRegister, Register,
+};
+use schematics_cpu::program::Program;
+
+const SOURCES: [Register; 14] = [
+    Register::Ra, Register::Rb, Register::Rc, Register::Rd,
+    Register::Re, Register::Rf, Register::Rg, Register::Rh,
+    Register::Rnull, Register::Rone, Register::Rout, Register::Rin,
+    Register::Rflags, Register::Rpc,
+];
+
+const DESTINATIONS: [Register; 12] = [
+    Register::Ra, Register::Rb, Register::Rc, Register::Rd,
+    Register::Re, Register::Rf, Register::Rg, Register::Rh,
+    Register::Rnull, Register::Rout, Register::Rflags, Register::Rpc,
+];
+
+/// Everything in a generated program. Labels are numbered, and every label is
+/// placed exactly once.
+#[derive(Debug, Clone)]
+enum Op {
+    Instruction(Instruction),
+    Branch {
+        branch_type: BranchType,
+        condition: Condition,
+        label: usize,
+    },
+}
+
+fn condition() -> impl Strategy<Value=Condition> {
+    (0u8..8).prop_map(|i| Condition::from_num(i).unwrap())
+}
+
+fn branch_type() -> impl Strategy<Value=BranchType> {
+    prop_oneof![Just(BranchType::Absolute), Just(BranchType::Relative)]
+}
+
+fn instruction() -> impl Strategy<Value=Instruction> {
+    let arithmetic = (
+        prop_oneof![Just(ArithmeticOperation::Add), Just(ArithmeticOperation::Sub)],
+        prop_oneof![Just(CarryOperation::WithCarry), Just(CarryOperation::WithoutCarry)],
+        (0u8..8).prop_map(|i| ReducedRegister::from_num(i).unwrap()),
+        select(&SOURCES[..]),
+        select(&DESTINATIONS[..]),
+    ).prop_map(|(op, carry, src1, src2, dst)| Instruction::Arithmetic { op, carry, src1, src2, dst });
+
+    let mov = (condition(), any::<bool>(), select(&SOURCES[..]), select(&DESTINATIONS[..]))
+        .prop_map(|(condition, set_flags, src, dst)| Instruction::Move { condition, set_flags, src, dst });
+
+    let branch = (any::<u8>(), branch_type(), condition())
+        .prop_map(|(address, branch_type, condition)| Instruction::Branch { address, branch_type, condition });
+
+    let immediate = (any::<u16>(), select(&DESTINATIONS[..]))
+        .prop_map(|(value, dst)| Instruction::Immediate { value, dst });
+
+    prop_oneof![arithmetic, mov, branch, immediate]
+}
+
+impl Op {
+    fn len(&self) -> usize {
+        match self {
+            Op::Instruction(i) => i.len(),
+            Op::Branch { .. } => 1,
+        }
+    }
+}
+
+/// The address of every op relative to the start of the program, and of the
+/// end of the program.
+fn addresses(ops: &[Op]) -> Vec<usize> {
+    let mut res = vec![0];
+    for op in ops {
+        res.push(res.last().unwrap() + op.len());
+    }
+
+    res
+}
+
+/// A program of at most 100 instructions, some of which branch to one of
+/// `labels` labels, the positions of those labels, and an address to put the
+/// program at where it fits, even if every instruction takes two words.
+fn program() -> impl Strategy<Value=(Vec<Op>, Vec<usize>, u8)> {
+    (1usize..100, 1usize..8).prop_flat_map(|(len, labels)| {
+        let op = prop_oneof![
+            instruction().prop_map(Op::Instruction),
+            (branch_type(), condition(), 0..labels)
+                .prop_map(|(branch_type, condition, label)| Op::Branch { branch_type, condition, label }),
+        ];
+
+        (
+            prop::collection::vec(op, len),
+            prop::collection::vec(0..=len, labels),
+            0..=(255 - 2 * len) as u8,
+        )
+    })
+}
+
+fn build(ops: &[Op], labels: &[usize]) -> Program {
+    let mut res = Program::new();
+
+    for idx in 0..=ops.len() {
+        for (label, _) in labels.iter().enumerate().filter(|(_, pos)| **pos == idx) {
+            res.label(format!("l{label}"));
+        }
+
+        match ops.get(idx) {
+            Some(Op::Instruction(i)) => res.push(*i),
+            Some(Op::Branch { branch_type, condition, label }) => res.branch_to(
+                Instruction::Branch { address: 0, branch_type: *branch_type, condition: *condition },
+                format!("l{label}"),
+            ),
+            None => {}
+        }
+    }
+
+    res
+}
+
+proptest! {
+    #[test]
+    fn assemble_disassemble_fixpoint((ops, labels, base) in program()) {
+        let words = build(&ops, &labels).assemble(base).unwrap();
+        prop_assert_eq!(words.len(), *addresses(&ops).last().unwrap());
+
+        let disassembled = Program::disassemble(&words).unwrap();
+        prop_assert_eq!(disassembled.assemble(base).unwrap(), words.clone());
+        // without labels, where the program is placed doesn't matter
+        prop_assert_eq!(disassembled.assemble(0).unwrap(), words);
+    }
+
+    #[test]
+    fn branches_land_on_their_label((ops, labels, base) in program()) {
+        let words = build(&ops, &labels).assemble(base).unwrap();
+        let addresses = addresses(&ops);
+
+        for (idx, op) in ops.iter().enumerate() {
+            let Op::Branch { label, .. } = op else {
+                continue;
+            };
+
+            let mut cpu = Cpu::new();
+            cpu.pc = base + addresses[idx] as u8;
+            // every condition holds
+            cpu.flags = 0x7f;
+            cpu.execute(Instruction::decode(words[addresses[idx]]).unwrap());
+
+            prop_assert_eq!(
+                cpu.pc as usize,
+                base as usize + addresses[labels[*label]],
+                "branch at {} to l{}", idx, label,
+            );
+        }
+    }
+
+    #[test]
+    fn listing_has_every_label((ops, labels, base) in program()) {
+        let listing = build(&ops, &labels).listing(base).unwrap();
+
+        for label in 0..labels.len() {
+            let line = format!("l{label}:");
+            prop_assert!(listing.lines().any(|i| i == line));
+        }
+        prop_assert_eq!(listing.lines().count(), ops.len() + labels.len());
+    }
+}
+
+#[test]
+fn immediates_take_a_word() {
+    let program = schematics_cpu::program! {
+        ldi 0x1234, Ra;
+        after:
+        mov Ra, Rout;
+        jmp @after;
+    };
+
+    assert_eq!(program.len(), 3);
+    assert_eq!(program.words(), 4);
+    assert_eq!(program.labels(10).unwrap()["after"], 12);
+
+    let words = program.assemble(0).unwrap();
+    assert_eq!(words[1], 0x1234);
+    assert_eq!(words[3], Instruction::Branch { address: 2, branch_type: BranchType::Absolute, condition: Condition::Unconditional }.encode());
+    assert_eq!(Program::disassemble(&words).unwrap().assemble(0).unwrap(), words);
+    assert_eq!(Program::disassemble(&words[..1]), None);
+
+    let listing = program.listing(0).unwrap();
+    assert!(listing.lines().any(|i| i.ends_with(&format!("{:04x} 1234  ldi 4660, ra", words[0]))));
+    let reparsed = Program::parse("ldi 4660, ra\nafter:\nmov ra, rout\njmp @after").unwrap();
+    assert_eq!(reparsed.assemble(0).unwrap(), words);
+
+    let mut cpu = Cpu::new();
+    cpu.run(&words, 3).unwrap();
+    assert_eq!(cpu.output, 0x34);
+    assert_eq!(cpu.pc, 2);
+}
+
+#[test]
+fn resolved_programs_know_where_instructions_are() {
+    let program = Program::parse("start:\nldi 300, ra\n\nmov ra, rout; jmp @start").unwrap();
+    let stream = program.resolve(4).unwrap();
+
+    let located: Vec<_> = stream.iter().map(|i| (i.address, i.source.map(|i| i.line))).collect();
+    assert_eq!(located, [(4, Some(2)), (6, Some(4)), (7, Some(4))]);
+    assert_eq!(stream.words(), 4);
+    assert!(stream.at(5).is_none());
+    assert!(program.listing(4).unwrap().contains("mov ra, rout  # line 4"));
+
+    let mut cpu = Cpu::new();
+    cpu.pc = 4;
+    cpu.run_stream(&stream, 3).unwrap();
+    assert_eq!((cpu.pc, cpu.output), (4, 44));
+
+    cpu.pc = 5;
+    assert_eq!(cpu.step_stream(&stream), Err(EmulatorError::Misaligned(5)));
+}
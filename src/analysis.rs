@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter, Write};
+use std::rc::Rc;
+use perpendicular::Vector3;
+use rand::Rng;
+use rand::seq::IteratorRandom;
+use crate::logic::facing_offset;
+use crate::schematic::{BlockState, Schematic, FACE_NEIGHBOURS};
+
+const REDSTONE_COMPONENTS: &[&str] = &[
+    "redstone_wire",
+    "redstone_torch",
+    "redstone_wall_torch",
+    "repeater",
+    "comparator",
+    "redstone_block",
+    "redstone_lamp",
+    "lever",
+    "observer",
+    "target",
+];
+
+/// Face neighbours, plus the diagonal up/down steps redstone wire can climb.
+pub const REDSTONE_NEIGHBOURS: [[i64; 3]; 14] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+    [1, 1, 0],
+    [-1, 1, 0],
+    [0, 1, 1],
+    [0, 1, -1],
+    [1, -1, 0],
+    [-1, -1, 0],
+    [0, -1, 1],
+    [0, -1, -1],
+];
+
+/// Neighbours that redstone wire passes its signal on to.
+const WIRE_NEIGHBOURS: [[i64; 3]; 12] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+    [1, 1, 0],
+    [-1, 1, 0],
+    [0, 1, 1],
+    [0, 1, -1],
+    [1, -1, 0],
+    [-1, -1, 0],
+    [0, -1, 1],
+    [0, -1, -1],
+];
+
+/// Redstone wire loses one power level per block, starting at this level.
+pub const MAX_POWER: u8 = 15;
+
+pub fn is_wire(state: &BlockState) -> bool {
+    state.id() == "minecraft:redstone_wire"
+}
+
+pub fn is_redstone_component(state: &BlockState) -> bool {
+    let Some(name) = state.id().strip_prefix("minecraft:") else {
+        return false;
+    };
+
+    REDSTONE_COMPONENTS.contains(&name)
+        || name.ends_with("_button")
+        || name.ends_with("_pressure_plate")
+}
+
+pub struct Component {
+    pub positions: HashSet<Vector3<i64>>,
+    pub min: Vector3<i64>,
+    pub max: Vector3<i64>,
+}
+
+impl Component {
+    fn new(positions: HashSet<Vector3<i64>>) -> Self {
+        let min = |f: fn(&Vector3<i64>) -> i64| positions.iter().map(f).min().unwrap_or(0);
+        let max = |f: fn(&Vector3<i64>) -> i64| positions.iter().map(f).max().unwrap_or(0);
+
+        Self {
+            min: Vector3::new3(min(|i| *i.x()), min(|i| *i.y()), min(|i| *i.z())),
+            max: Vector3::new3(max(|i| *i.x()), max(|i| *i.y()), max(|i| *i.z())),
+            positions,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+impl Display for Component {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} blocks from ({}, {}, {}) to ({}, {}, {})",
+            self.size(),
+            self.min.x(), self.min.y(), self.min.z(),
+            self.max.x(), self.max.y(), self.max.z(),
+        )
+    }
+}
+
+/// Label every connected group of redstone components in the schematic.
+/// Components are ordered from largest to smallest.
+pub fn label_components(schematic: &Schematic) -> Vec<Component> {
+    let mut seen = HashSet::new();
+    let mut components = Vec::new();
+
+    for (pos, blk) in schematic.blocks() {
+        if seen.contains(&pos) || !is_redstone_component(blk) {
+            continue;
+        }
+
+        let positions = schematic.flood_select_with(
+            pos.clone(),
+            &REDSTONE_NEIGHBOURS,
+            |_, state| is_redstone_component(state),
+            usize::MAX,
+        );
+
+        seen.extend(positions.iter().cloned());
+        components.push(Component::new(positions));
+    }
+
+    components.sort_by_key(|i| std::cmp::Reverse(i.size()));
+    components
+}
+
+pub struct Node {
+    pub pos: Vector3<i64>,
+    pub id: String,
+}
+
+/// A graph of the redstone components (torches, repeaters, ...) in a schematic.
+/// Two components are connected when they touch, or when they both touch the
+/// same connected stretch of redstone wire.
+pub struct Netlist {
+    pub nodes: Vec<Node>,
+    pub edges: BTreeSet<(usize, usize)>,
+}
+
+impl Netlist {
+    pub fn extract(schematic: &Schematic) -> Self {
+        let mut nodes: Vec<Node> = schematic.blocks()
+            .filter(|(_, blk)| is_redstone_component(blk) && !is_wire(blk))
+            .map(|(pos, blk)| Node { pos: pos.clone(), id: blk.id().to_string() })
+            .collect();
+        nodes.sort_by_key(|i| (*i.pos.x(), *i.pos.y(), *i.pos.z()));
+
+        let index: HashMap<_, _> = nodes.iter()
+            .enumerate()
+            .map(|(idx, node)| (node.pos.clone(), idx))
+            .collect();
+
+        let neighbours_of = |pos: &Vector3<i64>| {
+            REDSTONE_NEIGHBOURS.iter()
+                .map(|[dx, dy, dz]| Vector3::new3(pos.x() + dx, pos.y() + dy, pos.z() + dz))
+                .filter_map(|i| index.get(&i).copied())
+                .collect::<Vec<_>>()
+        };
+
+        let mut edges = BTreeSet::new();
+        let mut connect = |a: usize, b: usize| {
+            if a != b {
+                edges.insert((a.min(b), a.max(b)));
+            }
+        };
+
+        for (idx, node) in nodes.iter().enumerate() {
+            for other in neighbours_of(&node.pos) {
+                connect(idx, other);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for (pos, blk) in schematic.blocks() {
+            if seen.contains(&pos) || !is_wire(blk) {
+                continue;
+            }
+
+            let net = schematic.flood_select_with(
+                pos.clone(),
+                &REDSTONE_NEIGHBOURS,
+                |_, state| is_wire(state),
+                usize::MAX,
+            );
+
+            let touching: BTreeSet<usize> = net.iter()
+                .flat_map(|i| neighbours_of(i))
+                .collect();
+            for a in &touching {
+                for b in &touching {
+                    connect(*a, *b);
+                }
+            }
+
+            seen.extend(net);
+        }
+
+        Self { nodes, edges }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut res = String::from("graph netlist {\n");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                res,
+                "    n{idx} [label=\"{} ({}, {}, {})\"];",
+                node.id, node.pos.x(), node.pos.y(), node.pos.z(),
+            ).unwrap();
+        }
+        for (a, b) in &self.edges {
+            writeln!(res, "    n{a} -- n{b};").unwrap();
+        }
+        res.push_str("}\n");
+
+        res
+    }
+
+    pub fn to_graphml(&self) -> String {
+        let mut res = String::new();
+        res.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        res.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        res.push_str("  <key id=\"block\" for=\"node\" attr.name=\"block\" attr.type=\"string\"/>\n");
+        res.push_str("  <key id=\"x\" for=\"node\" attr.name=\"x\" attr.type=\"long\"/>\n");
+        res.push_str("  <key id=\"y\" for=\"node\" attr.name=\"y\" attr.type=\"long\"/>\n");
+        res.push_str("  <key id=\"z\" for=\"node\" attr.name=\"z\" attr.type=\"long\"/>\n");
+        res.push_str("  <graph id=\"netlist\" edgedefault=\"undirected\">\n");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            writeln!(res, "    <node id=\"n{idx}\">").unwrap();
+            writeln!(res, "      <data key=\"block\">{}</data>", node.id).unwrap();
+            writeln!(res, "      <data key=\"x\">{}</data>", node.pos.x()).unwrap();
+            writeln!(res, "      <data key=\"y\">{}</data>", node.pos.y()).unwrap();
+            writeln!(res, "      <data key=\"z\">{}</data>", node.pos.z()).unwrap();
+            res.push_str("    </node>\n");
+        }
+        for (a, b) in &self.edges {
+            writeln!(res, "    <edge source=\"n{a}\" target=\"n{b}\"/>").unwrap();
+        }
+        res.push_str("  </graph>\n");
+        res.push_str("</graphml>\n");
+
+        res
+    }
+}
+
+/// Pick `n` random blocks (or all of them, if there are fewer) from the schematic.
+pub fn sample_blocks(schematic: &Schematic, n: usize, rng: &mut impl Rng) -> Vec<(Vector3<i64>, Rc<BlockState>)> {
+    // in a fixed order, so the same seed picks the same blocks
+    let mut blocks: Vec<_> = schematic.blocks().collect();
+    blocks.sort_by_key(|(pos, _)| (*pos.y(), *pos.z(), *pos.x()));
+
+    let mut res: Vec<_> = blocks.into_iter()
+        .choose_multiple(rng, n)
+        .into_iter()
+        .map(|(pos, blk)| (pos.clone(), blk.clone()))
+        .collect();
+
+    res.sort_by_key(|(pos, _)| (*pos.y(), *pos.z(), *pos.x()));
+    res
+}
+
+/// Count the blocks of every id on each y layer of the schematic.
+pub fn layer_histograms(schematic: &Schematic) -> BTreeMap<i64, BTreeMap<String, usize>> {
+    let mut res = BTreeMap::new();
+
+    for (pos, blk) in schematic.blocks() {
+        *res.entry(*pos.y())
+            .or_insert_with(BTreeMap::new)
+            .entry(blk.id().to_string())
+            .or_insert(0) += 1;
+    }
+
+    res
+}
+
+/// Whether the block at `pos` feeds a full strength signal into wire at `wire`.
+fn drives_wire(pos: &Vector3<i64>, state: &BlockState, wire: &Vector3<i64>) -> bool {
+    let Some(name) = state.id().strip_prefix("minecraft:") else {
+        return false;
+    };
+
+    match name {
+        "repeater" | "comparator" => facing_offset(state)
+            .map(|[dx, dy, dz]| Vector3::new3(pos.x() - dx, pos.y() - dy, pos.z() - dz) == *wire)
+            .unwrap_or(false),
+        "redstone_torch" | "redstone_wall_torch" | "redstone_block" | "lever" | "observer" => true,
+        _ => name.ends_with("_button") || name.ends_with("_pressure_plate"),
+    }
+}
+
+/// The power level every wire would have if all of its drivers were on. Wire
+/// that isn't connected to any driver is left out.
+pub fn wire_power_levels(schematic: &Schematic) -> HashMap<Vector3<i64>, u8> {
+    let blocks: HashMap<_, _> = schematic.blocks().collect();
+    let offset = |pos: &Vector3<i64>, [dx, dy, dz]: &[i64; 3]| Vector3::new3(pos.x() + dx, pos.y() + dy, pos.z() + dz);
+
+    let mut distances = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    for (pos, blk) in &blocks {
+        if !is_wire(blk) {
+            continue;
+        }
+
+        let driven = FACE_NEIGHBOURS.iter().any(|i| {
+            let neighbour = offset(pos, i);
+            blocks.get(&neighbour)
+                .map(|state| drives_wire(&neighbour, state, pos))
+                .unwrap_or(false)
+        });
+
+        if driven {
+            distances.insert((*pos).clone(), 0u32);
+            queue.push_back((*pos).clone());
+        }
+    }
+
+    while let Some(pos) = queue.pop_front() {
+        let distance = distances[&pos];
+        for i in &WIRE_NEIGHBOURS {
+            let neighbour = offset(&pos, i);
+            let wire = blocks.get(&neighbour).map(|i| is_wire(i)).unwrap_or(false);
+            if wire && !distances.contains_key(&neighbour) {
+                distances.insert(neighbour.clone(), distance + 1);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    distances.into_iter()
+        .map(|(pos, distance)| (pos, (MAX_POWER as u32).saturating_sub(distance) as u8))
+        .collect()
+}
+
+/// Positions where a driven signal has faded out completely: wire at power 0
+/// right after wire at power 1, i.e. where a repeater is missing.
+pub fn signal_losses(schematic: &Schematic) -> Vec<Vector3<i64>> {
+    let levels = wire_power_levels(schematic);
+    let mut res: Vec<_> = levels.iter()
+        .filter(|(pos, power)| {
+            **power == 0 && WIRE_NEIGHBOURS.iter().any(|[dx, dy, dz]| {
+                levels.get(&Vector3::new3(pos.x() + dx, pos.y() + dy, pos.z() + dz)) == Some(&1)
+            })
+        })
+        .map(|(pos, _)| pos.clone())
+        .collect();
+
+    res.sort_by_key(|i| (*i.x(), *i.y(), *i.z()));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use perpendicular::Vector3;
+    use crate::schematic::{BlockState, Schematic};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use super::{label_components, layer_histograms, sample_blocks, signal_losses, wire_power_levels, Netlist};
+
+    #[test]
+    fn labels_connected_components() {
+        let wire = BlockState::new("minecraft:redstone_wire");
+        let schematic = Schematic::from_blocks([
+            ([0, 0, 0], wire.clone()),
+            ([1, 0, 0], wire.clone()),
+            // wire climbs a step
+            ([2, 1, 0], wire.clone()),
+            ([3, 1, 0], BlockState::new("minecraft:repeater")),
+            ([3, 0, 0], BlockState::new("minecraft:stone")),
+            ([10, 0, 0], BlockState::new("minecraft:lever")),
+        ]);
+
+        let components = label_components(&schematic);
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].size(), 4);
+        assert_eq!(components[0].min, Vector3::new3(0, 0, 0));
+        assert_eq!(components[0].max, Vector3::new3(3, 1, 0));
+        assert_eq!(components[1].size(), 1);
+        assert_eq!(components[1].to_string(), "1 blocks from (10, 0, 0) to (10, 0, 0)");
+    }
+
+    #[test]
+    fn connects_components_through_wire() {
+        let wire = BlockState::new("minecraft:redstone_wire");
+        let schematic = Schematic::from_blocks([
+            ([0, 0, 0], BlockState::new("minecraft:redstone_torch")),
+            ([1, 0, 0], wire.clone()),
+            ([2, 0, 0], wire.clone()),
+            ([3, 0, 0], wire.clone()),
+            ([4, 0, 0], BlockState::new("minecraft:repeater")),
+            ([5, 0, 0], BlockState::new("minecraft:redstone_lamp")),
+            ([20, 0, 0], BlockState::new("minecraft:lever")),
+        ]);
+
+        let netlist = Netlist::extract(&schematic);
+        let ids: Vec<_> = netlist.nodes.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["minecraft:redstone_torch", "minecraft:repeater", "minecraft:redstone_lamp", "minecraft:lever"]);
+        assert_eq!(netlist.edges.iter().copied().collect::<Vec<_>>(), [(0, 1), (1, 2)]);
+
+        let dot = netlist.to_dot();
+        assert!(dot.contains("    n0 [label=\"minecraft:redstone_torch (0, 0, 0)\"];\n"));
+        assert!(dot.contains("    n0 -- n1;\n    n1 -- n2;\n}\n"));
+        assert!(!dot.contains("n3 --"));
+
+        let graphml = netlist.to_graphml();
+        assert!(graphml.contains("<edge source=\"n0\" target=\"n1\"/>"));
+        assert!(graphml.contains("<edge source=\"n1\" target=\"n2\"/>"));
+        assert_eq!(graphml.matches("<edge ").count(), 2);
+        assert_eq!(graphml.matches("<node ").count(), 4);
+    }
+
+    #[test]
+    fn samples_the_same_blocks_with_the_same_seed() {
+        let schematic = Schematic::from_blocks((0..100).map(|i| ([i % 10, i / 10, 0], BlockState::new("minecraft:stone"))));
+
+        let sample = |seed| sample_blocks(&schematic, 5, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(sample(1).len(), 5);
+        let positions = |seed| sample(seed).into_iter().map(|(pos, _)| pos).collect::<Vec<_>>();
+        assert_eq!(positions(1), positions(1));
+
+        assert_eq!(sample_blocks(&schematic, 1000, &mut StdRng::seed_from_u64(1)).len(), 100);
+    }
+
+    #[test]
+    fn counts_blocks_per_layer() {
+        let schematic = Schematic::from_blocks([
+            ([0, 0, 0], BlockState::new("minecraft:stone")),
+            ([1, 0, 0], BlockState::new("minecraft:stone")),
+            ([0, 0, 1], BlockState::new("minecraft:redstone_wire")),
+            ([0, 3, 0], BlockState::new("minecraft:stone")),
+        ]);
+
+        let histograms = layer_histograms(&schematic);
+        assert_eq!(histograms.keys().copied().collect::<Vec<_>>(), [0, 3]);
+        assert_eq!(histograms[&0]["minecraft:stone"], 2);
+        assert_eq!(histograms[&0]["minecraft:redstone_wire"], 1);
+        assert_eq!(histograms[&3].len(), 1);
+    }
+
+    #[test]
+    fn fades_wire_out_after_fifteen_blocks() {
+        let wire = BlockState::new("minecraft:redstone_wire");
+        let mut blocks: Vec<_> = (1..=17).map(|x| ([x, 0, 0], wire.clone())).collect();
+        blocks.push(([0, 0, 0], BlockState::new("minecraft:redstone_torch")));
+        // not connected to anything that drives it
+        blocks.push(([0, 0, 5], wire.clone()));
+        let schematic = Schematic::from_blocks(blocks);
+
+        let levels = wire_power_levels(&schematic);
+        let at = |x| levels[&Vector3::new3(x, 0, 0)];
+        assert_eq!(at(1), 15);
+        assert_eq!(at(15), 1);
+        assert_eq!(at(16), 0);
+        assert_eq!(at(17), 0);
+        assert!(!levels.contains_key(&Vector3::new3(0, 0, 5)));
+
+        assert_eq!(signal_losses(&schematic), [Vector3::new3(16, 0, 0)]);
+    }
+}
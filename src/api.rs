@@ -0,0 +1,194 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use schematics_cpu::program::Program;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::cli::load;
+use crate::rom;
+use crate::store::Store;
+use crate::workspace::{Workspace, PROGRAMS};
+
+/// An error, sent to the client as plain text.
+struct ApiError {
+    status: StatusCode,
+    error: color_eyre::Report,
+}
+
+impl ApiError {
+    fn bad_request(error: impl Into<color_eyre::Report>) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, error: error.into() }
+    }
+}
+
+impl<E: Into<color_eyre::Report>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        Self { status: StatusCode::INTERNAL_SERVER_ERROR, error: error.into() }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        warn!("request failed: {:?}", self.error);
+        (self.status, format!("{:#}", self.error)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Clone)]
+struct ApiState {
+    /// Where uploaded programs are kept.
+    programs: PathBuf,
+    /// The template programs are written into when the request doesn't name one.
+    template: String,
+}
+
+impl ApiState {
+    fn program_path(&self, name: &str) -> ApiResult<PathBuf> {
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err(ApiError::bad_request(eyre!("invalid program name {name:?}")));
+        }
+
+        Ok(self.programs.join(format!("{name}.asm")))
+    }
+
+    fn program(&self, name: &str) -> ApiResult<Program> {
+        let path = self.program_path(name)?;
+        let source = fs::read_to_string(&path).map_err(|_| ApiError {
+            status: StatusCode::NOT_FOUND,
+            error: eyre!("no program named {name}"),
+        })?;
+
+        Program::parse(&source).map_err(ApiError::bad_request)
+    }
+}
+
+/// Run `f` on the blocking thread pool: schematics aren't `Send`, and loading
+/// and programming them takes a while.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> ApiResult<T> + Send + 'static) -> ApiResult<T> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// Store a program, after checking that it assembles. Returns its listing.
+async fn put_program(State(state): State<ApiState>, Path(name): Path<String>, source: String) -> ApiResult<String> {
+    let path = state.program_path(&name)?;
+    let program = Program::parse(&source).map_err(ApiError::bad_request)?;
+    let listing = program.listing(0).map_err(ApiError::bad_request)?;
+
+    fs::write(path, source).wrap_err("write program")?;
+    info!("stored program {name}");
+
+    Ok(listing)
+}
+
+async fn get_program(State(state): State<ApiState>, Path(name): Path<String>) -> ApiResult<String> {
+    let path = state.program_path(&name)?;
+    fs::read_to_string(path).map_err(|_| ApiError {
+        status: StatusCode::NOT_FOUND,
+        error: eyre!("no program named {name}"),
+    })
+}
+
+/// The assembled program, one word in hex per line.
+async fn get_hex(State(state): State<ApiState>, Path(name): Path<String>) -> ApiResult<String> {
+    let words = state.program(&name)?.assemble(0).map_err(ApiError::bad_request)?;
+    Ok(words.iter().map(|i| format!("{i:04x}\n")).collect())
+}
+
+#[derive(Deserialize)]
+struct BuildQuery {
+    template: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Built {
+    /// The ref the result is stored under, the name of the program.
+    name: String,
+    hash: String,
+}
+
+/// Write a program into a template and store the result under the program's
+/// name.
+async fn build(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    Query(query): Query<BuildQuery>,
+) -> ApiResult<Json<Built>> {
+    let program = state.program(&name)?;
+    let template = query.template.unwrap_or_else(|| state.template.clone());
+
+    let built = blocking(move || {
+        let rom = load(&template).map_err(ApiError::bad_request)?;
+        let programmed = rom::program_rom(rom, &program).map_err(ApiError::bad_request)?.into_schematic();
+
+        let store = Store::open_default()?;
+        let hash = store.add(&programmed, &Default::default())?;
+        store.set_ref(&name, &hash)?;
+        info!("built {name} into {template} as {hash}");
+
+        Ok(Built { name, hash })
+    }).await?;
+
+    Ok(Json(built))
+}
+
+/// A schematic from the store, by ref or hash.
+async fn download(Path(name): Path<String>) -> ApiResult<Response> {
+    let data = blocking(move || {
+        let store = Store::open_default()?;
+        let path = store.path(&name).map_err(|e| ApiError { status: StatusCode::NOT_FOUND, error: e })?;
+        Ok(fs::read(path)?)
+    }).await?;
+
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], data).into_response())
+}
+
+/// Serve the API on `addr`, until the process is stopped:
+///
+/// - `PUT /programs/{name}` stores a program, written as text (see
+///   [`Program::parse`]), and returns its listing
+/// - `GET /programs/{name}` returns the program
+/// - `GET /programs/{name}/hex` returns the assembled program in hex
+/// - `POST /programs/{name}/build?template={template}` writes the program into
+///   a template and stores the result under the name of the program
+/// - `GET /schematics/{name}` downloads a schematic from the store
+///
+/// Programs are kept in the `programs` directory of the workspace, or of the
+/// current directory outside of one.
+pub fn serve(addr: SocketAddr) -> color_eyre::Result<()> {
+    let workspace = Workspace::find_current()?;
+    let state = ApiState {
+        programs: match &workspace {
+            Some(workspace) => workspace.dir(PROGRAMS),
+            None => PROGRAMS.into(),
+        },
+        template: workspace.as_ref()
+            .and_then(|i| i.config.template.clone())
+            .unwrap_or_else(|| "rom".to_string()),
+    };
+    fs::create_dir_all(&state.programs).wrap_err("create programs directory")?;
+
+    let app = Router::new()
+        .route("/programs/:name", get(get_program).put(put_program))
+        .route("/programs/:name/hex", get(get_hex))
+        .route("/programs/:name/build", post(build))
+        .route("/schematics/:name", get(download))
+        .with_state(state);
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        info!("listening on {addr}");
+        if let Err(e) = axum::Server::bind(&addr).serve(app.into_make_service()).await {
+            bail!("server stopped: {e}");
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,126 @@
+use perpendicular::Vector3;
+use crate::pos::BlockPos;
+
+/// A cuboid of blocks between `min` and `max`, both inclusive, like the
+/// regions given to [`Schematic::crop`](crate::schematic::Schematic::crop).
+/// A box with `max` below `min` along any axis holds no blocks at all, see
+/// [`BoundingBox::empty`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub min: Vector3<i64>,
+    pub max: Vector3<i64>,
+}
+
+impl BoundingBox {
+    pub fn new(min: impl Into<BlockPos>, max: impl Into<BlockPos>) -> Self {
+        Self { min: min.into().vector(), max: max.into().vector() }
+    }
+
+    /// A box without any blocks. It's what the bounds of an empty schematic
+    /// are, and it leaves boxes unchanged in a [`union`](Self::union).
+    pub fn empty() -> Self {
+        Self::new([0, 0, 0], [-1, -1, -1])
+    }
+
+    /// The smallest box holding all of `positions`, or an empty one if there
+    /// aren't any.
+    pub fn around<P: Into<BlockPos>>(positions: impl IntoIterator<Item=P>) -> Self {
+        let mut positions = positions.into_iter().map(|i| <[i64; 3]>::from(i.into()));
+        let Some(first) = positions.next() else {
+            return Self::empty();
+        };
+
+        let (mut min, mut max) = (first, first);
+        for pos in positions {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(pos[axis]);
+                max[axis] = max[axis].max(pos[axis]);
+            }
+        }
+
+        Self::new(min, max)
+    }
+
+    pub fn min(&self) -> [i64; 3] {
+        BlockPos::from(&self.min).into()
+    }
+
+    pub fn max(&self) -> [i64; 3] {
+        BlockPos::from(&self.max).into()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let (min, max) = (self.min(), self.max());
+        (0..3).any(|axis| max[axis] < min[axis])
+    }
+
+    /// The number of blocks along each axis.
+    pub fn size(&self) -> [i64; 3] {
+        if self.is_empty() {
+            return [0, 0, 0];
+        }
+
+        let (min, max) = (self.min(), self.max());
+        [0, 1, 2].map(|axis| max[axis] - min[axis] + 1)
+    }
+
+    /// The number of blocks in the box.
+    pub fn volume(&self) -> i64 {
+        self.size().iter().product()
+    }
+
+    pub fn contains(&self, pos: impl Into<BlockPos>) -> bool {
+        let pos: [i64; 3] = pos.into().into();
+        let (min, max) = (self.min(), self.max());
+        (0..3).all(|axis| (min[axis]..=max[axis]).contains(&pos[axis]))
+    }
+
+    /// The blocks that are in both boxes, which is empty if they don't
+    /// overlap.
+    pub fn intersect(&self, other: &BoundingBox) -> BoundingBox {
+        let (a, b) = ((self.min(), self.max()), (other.min(), other.max()));
+        let res = Self::new(
+            [0, 1, 2].map(|axis| a.0[axis].max(b.0[axis])),
+            [0, 1, 2].map(|axis| a.1[axis].min(b.1[axis])),
+        );
+
+        if res.is_empty() { Self::empty() } else { res }
+    }
+
+    /// The smallest box holding both boxes. Empty boxes are ignored, rather
+    /// than stretching the result to wherever their corners happen to be.
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        if self.is_empty() {
+            return other.clone();
+        }
+        if other.is_empty() {
+            return self.clone();
+        }
+
+        let (a, b) = ((self.min(), self.max()), (other.min(), other.max()));
+        Self::new(
+            [0, 1, 2].map(|axis| a.0[axis].min(b.0[axis])),
+            [0, 1, 2].map(|axis| a.1[axis].max(b.1[axis])),
+        )
+    }
+
+    /// The box grown by `by` blocks on every side, or shrunk for negative
+    /// `by`. An empty box stays empty.
+    pub fn expand(&self, by: i64) -> BoundingBox {
+        if self.is_empty() {
+            return Self::empty();
+        }
+
+        let res = Self::new(self.min().map(|i| i - by), self.max().map(|i| i + by));
+        if res.is_empty() { Self::empty() } else { res }
+    }
+
+    /// The box moved by `offset`.
+    pub fn translate(&self, offset: [i64; 3]) -> BoundingBox {
+        let (min, max) = (self.min(), self.max());
+        Self::new(
+            [0, 1, 2].map(|axis| min[axis] + offset[axis]),
+            [0, 1, 2].map(|axis| max[axis] + offset[axis]),
+        )
+    }
+}
@@ -0,0 +1,184 @@
+use std::rc::Rc;
+use perpendicular::Vector3;
+use crate::props::Facing;
+use crate::schematic::{BlockState, Schematic};
+
+/// Builds a schematic from scratch, block by block or from smaller schematics.
+/// Positions are absolute, later blocks replace earlier ones.
+#[derive(Clone)]
+pub struct SchematicBuilder {
+    schematic: Schematic,
+}
+
+impl Default for SchematicBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchematicBuilder {
+    pub fn new() -> Self {
+        Self { schematic: Schematic::new() }
+    }
+
+    pub fn block(mut self, pos: [i64; 3], state: Rc<BlockState>) -> Self {
+        self.schematic.set_block(Vector3::new3(pos[0], pos[1], pos[2]), state);
+        self
+    }
+
+    /// Fill the box between `from` and `to`, both inclusive.
+    pub fn fill(mut self, from: [i64; 3], to: [i64; 3], state: Rc<BlockState>) -> Self {
+        let [min, max] = [i64::min, i64::max].map(|f| Vector3::new3(
+            f(from[0], to[0]),
+            f(from[1], to[1]),
+            f(from[2], to[2]),
+        ));
+        self.schematic.fill(min, max, state);
+        self
+    }
+
+    /// Place `part` with its minimum corner at `at`.
+    pub fn place(mut self, part: &Schematic, at: [i64; 3]) -> Self {
+        let min = part.bounds().min();
+        let offset = [0, 1, 2].map(|axis| at[axis] - min[axis]);
+        self.schematic.insert_translated(part, offset);
+        self
+    }
+
+    /// Continue building relative to a cursor, starting at `at`.
+    pub fn cursor(self, at: [i64; 3]) -> Cursor {
+        Cursor { builder: self, pos: at, saved: Vec::new() }
+    }
+
+    pub fn build(self) -> Schematic {
+        self.schematic
+    }
+}
+
+/// Builds a circuit relative to a position that moves along as blocks are
+/// placed, so small circuits can be written down the way they're built in
+/// game:
+///
+/// ```ignore
+/// SchematicBuilder::new().cursor([0, 0, 0])
+///     .block(BlockState::stone())
+///     .up(1)
+///     .wall_torch(Facing::North)
+///     .step(Facing::East, 1)
+///     .wire()
+///     .build()
+/// ```
+///
+/// Placing doesn't move the cursor; only [`step`](Self::step),
+/// [`up`](Self::up), [`down`](Self::down), [`shift`](Self::shift) and the
+/// `line` methods do.
+#[derive(Clone)]
+pub struct Cursor {
+    builder: SchematicBuilder,
+    pos: [i64; 3],
+    /// Positions to go back to with [`Cursor::restore`].
+    saved: Vec<[i64; 3]>,
+}
+
+impl Cursor {
+    pub fn pos(&self) -> [i64; 3] {
+        self.pos
+    }
+
+    /// Move to `pos`.
+    pub fn at(mut self, pos: [i64; 3]) -> Self {
+        self.pos = pos;
+        self
+    }
+
+    /// Move by `offset`.
+    pub fn shift(mut self, offset: [i64; 3]) -> Self {
+        for axis in 0..3 {
+            self.pos[axis] += offset[axis];
+        }
+        self
+    }
+
+    /// Move `n` blocks towards `direction`.
+    pub fn step(self, direction: Facing, n: i64) -> Self {
+        let offset = direction.offset().map(|i| i * n);
+        self.shift(offset)
+    }
+
+    pub fn up(self, n: i64) -> Self {
+        self.step(Facing::Up, n)
+    }
+
+    pub fn down(self, n: i64) -> Self {
+        self.step(Facing::Down, n)
+    }
+
+    /// Remember the current position, to come back to with
+    /// [`Cursor::restore`], e.g. to build a branch of a circuit.
+    pub fn save(mut self) -> Self {
+        self.saved.push(self.pos);
+        self
+    }
+
+    /// Go back to the position last remembered with [`Cursor::save`]. Does
+    /// nothing if there is none.
+    pub fn restore(mut self) -> Self {
+        if let Some(pos) = self.saved.pop() {
+            self.pos = pos;
+        }
+        self
+    }
+
+    /// Place `state` at the cursor.
+    pub fn block(mut self, state: Rc<BlockState>) -> Self {
+        self.builder = self.builder.block(self.pos, state);
+        self
+    }
+
+    /// Place `state` `n` times in a row towards `direction`, starting at the
+    /// cursor. The cursor ends on the last block.
+    pub fn line(mut self, direction: Facing, n: i64, state: Rc<BlockState>) -> Self {
+        for i in 0..n {
+            if i > 0 {
+                self = self.step(direction, 1);
+            }
+            self = self.block(state.clone());
+        }
+        self
+    }
+
+    /// Redstone wire. Its shape is left for the game to work out.
+    pub fn wire(self) -> Self {
+        self.block(BlockState::new("minecraft:redstone_wire"))
+    }
+
+    /// A redstone torch standing on the block below.
+    pub fn torch(self) -> Self {
+        self.block(BlockState::new("minecraft:redstone_torch"))
+    }
+
+    /// A redstone torch on the side of a block, pointing `facing`: away from
+    /// the block it's attached to.
+    pub fn wall_torch(self, facing: Facing) -> Self {
+        self.block(BlockState::builder("minecraft:redstone_wall_torch").prop("facing", facing).build())
+    }
+
+    /// A repeater passing a signal on towards `output`, with a delay of
+    /// `delay` redstone ticks.
+    pub fn repeater(self, output: Facing, delay: u8) -> Self {
+        // the facing of a repeater is the side its input is on
+        self.block(BlockState::builder("minecraft:repeater")
+            .prop("facing", output.opposite())
+            .prop("delay", delay)
+            .build())
+    }
+
+    /// Stop using the cursor, to continue with absolute positions.
+    pub fn done(self) -> SchematicBuilder {
+        self.builder
+    }
+
+    pub fn build(self) -> Schematic {
+        self.builder.build()
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::{Component, Path};
+use color_eyre::eyre::{bail, ContextCompat, WrapErr};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+use crate::schematic::{Schematic, WriteOptions};
+
+const EXTENSION: &str = ".schem";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn of(path: &Path) -> color_eyre::Result<Self> {
+        let name = path.to_string_lossy();
+        if name.ends_with(".zip") {
+            Ok(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(Self::Tar)
+        } else {
+            bail!("unknown archive type for {name} (expected .zip, .tar, .tar.gz or .tgz)")
+        }
+    }
+}
+
+/// A set of named schematics shipped together as one archive, e.g. a cpu with
+/// its rom banks and display. Names are the paths of the schematics inside the
+/// archive, without the `.schem` extension.
+#[derive(Default, Clone)]
+pub struct Bundle {
+    schematics: BTreeMap<String, Schematic>,
+}
+
+impl Bundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Schematic> {
+        self.schematics.get(name)
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, schematic: Schematic) {
+        self.schematics.insert(name.into(), schematic);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.schematics.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &Schematic)> {
+        self.schematics.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Add the schematic in the archive entry at `path`. Names end up as paths
+    /// when the bundle is extracted, so entries that would land outside of the
+    /// directory they're extracted to are refused.
+    fn insert_entry(&mut self, path: &Path, reader: impl Read) -> color_eyre::Result<()> {
+        if !path.components().all(|i| matches!(i, Component::Normal(_) | Component::CurDir)) {
+            bail!("archive entry {} points outside of the archive", path.display());
+        }
+
+        let path = path.to_string_lossy();
+        let Some(name) = path.strip_suffix(EXTENSION) else {
+            return Ok(());
+        };
+
+        let schematic = Schematic::from_reader(reader)
+            .wrap_err_with(|| format!("read {name} from archive"))?;
+        self.insert(name, schematic);
+
+        Ok(())
+    }
+
+    fn read_tar(&mut self, reader: impl Read) -> color_eyre::Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            self.insert_entry(&path, entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_zip(&mut self, reader: impl Read + Seek) -> color_eyre::Result<()> {
+        let mut archive = ZipArchive::new(reader)?;
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let path = entry.enclosed_name()
+                .wrap_err_with(|| format!("archive entry {} points outside of the archive", entry.name()))?
+                .to_path_buf();
+            self.insert_entry(&path, entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_tar<W: Write>(&self, writer: W, options: &WriteOptions) -> color_eyre::Result<W> {
+        let mut builder = tar::Builder::new(writer);
+        for (name, schematic) in &self.schematics {
+            let data = schematic.to_bytes_with(options)?;
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("{name}{EXTENSION}"), data.as_slice())?;
+        }
+
+        Ok(builder.into_inner()?)
+    }
+
+    pub fn from_archive(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).wrap_err("open archive")?;
+        let mut res = Self::new();
+
+        match ArchiveKind::of(path)? {
+            ArchiveKind::Zip => res.read_zip(file)?,
+            ArchiveKind::Tar => res.read_tar(file)?,
+            ArchiveKind::TarGz => res.read_tar(GzDecoder::new(file))?,
+        }
+
+        Ok(res)
+    }
+
+    pub fn to_archive(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        self.to_archive_with(path, &WriteOptions::default())
+    }
+
+    pub fn to_archive_with(&self, path: impl AsRef<Path>, options: &WriteOptions) -> color_eyre::Result<()> {
+        let path = path.as_ref();
+        let kind = ArchiveKind::of(path)?;
+        let file = File::create(path).wrap_err("create archive")?;
+
+        match kind {
+            ArchiveKind::Zip => {
+                let mut file_options = FileOptions::default();
+                if options.deterministic {
+                    file_options = file_options.last_modified_time(zip::DateTime::default());
+                }
+
+                let mut archive = ZipWriter::new(file);
+                for (name, schematic) in &self.schematics {
+                    archive.start_file(format!("{name}{EXTENSION}"), file_options)?;
+                    archive.write_all(&schematic.to_bytes_with(options)?)?;
+                }
+                archive.finish()?;
+            }
+            ArchiveKind::Tar => {
+                self.write_tar(file, options)?;
+            }
+            ArchiveKind::TarGz => {
+                self.write_tar(GzEncoder::new(file, Compression::default()), options)?.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Schematic {
+    /// Load a single schematic out of a bundle archive.
+    pub fn from_archive(path: impl AsRef<Path>, name: &str) -> color_eyre::Result<Self> {
+        let mut bundle = Bundle::from_archive(path)?;
+        bundle.schematics.remove(name)
+            .wrap_err_with(|| format!("no schematic named {name} in archive"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+    use crate::schematic::{BlockState, Schematic};
+    use super::Bundle;
+
+    fn schematic() -> Vec<u8> {
+        Schematic::from_blocks([([0, 0, 0], BlockState::new("minecraft:stone"))]).to_bytes().unwrap()
+    }
+
+    fn zip_with(name: &str) -> Cursor<Vec<u8>> {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        archive.start_file(name, FileOptions::default()).unwrap();
+        archive.write_all(&schematic()).unwrap();
+        let mut res = archive.finish().unwrap();
+        res.set_position(0);
+        res
+    }
+
+    #[test]
+    fn reads_nested_entries() {
+        let mut bundle = Bundle::new();
+        bundle.read_zip(zip_with("roms/bank0.schem")).unwrap();
+        assert_eq!(bundle.names().collect::<Vec<_>>(), ["roms/bank0"]);
+    }
+
+    #[test]
+    fn refuses_entries_outside_of_the_archive() {
+        for name in ["../evil.schem", "roms/../../evil.schem", "/etc/evil.schem"] {
+            assert!(Bundle::new().read_zip(zip_with(name)).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn refuses_tar_entries_outside_of_the_archive() {
+        let data = schematic();
+        // the tar builder refuses `..` itself, so write the name into the header directly
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..13].copy_from_slice(b"../evil.schem");
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, data.as_slice()).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        assert!(Bundle::new().read_tar(archive.as_slice()).is_err());
+    }
+}
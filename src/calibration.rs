@@ -0,0 +1,90 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use color_eyre::eyre::{bail, WrapErr};
+use serde::{Deserialize, Serialize};
+use crate::bounds::BoundingBox;
+use crate::history::hash_bytes;
+use crate::pos::BlockPos;
+use crate::rom::{find_program_lines, RomLayout};
+use crate::schematic::Schematic;
+use crate::workspace::{Workspace, LAYOUTS};
+
+pub const EXTENSION: &str = "romlayout";
+
+/// What a rom template looked like when it was calibrated, kept in a
+/// `.romlayout` file (see [`RomCalibration::path_for`]): its layout, and a
+/// fingerprint of the blocks that hold the torches up. Someone editing the
+/// build in-game changes the fingerprint, and words would end up in the
+/// wrong torches, so programming checks the template against it first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomCalibration {
+    #[serde(flatten)]
+    pub layout: RomLayout,
+    pub fingerprint: String,
+}
+
+impl RomCalibration {
+    pub fn calibrate(template: &Schematic) -> color_eyre::Result<Self> {
+        Ok(Self {
+            layout: RomLayout::detect(&find_program_lines(template))?,
+            fingerprint: fingerprint(template),
+        })
+    }
+
+    /// Where the calibration of the template called `name` is kept: in the
+    /// layouts directory of the workspace, or the current directory outside
+    /// of one.
+    pub fn path_for(workspace: Option<&Workspace>, name: &str) -> PathBuf {
+        let file = format!("{name}.{EXTENSION}");
+        match workspace {
+            Some(workspace) => workspace.dir(LAYOUTS).join(file),
+            None => file.into(),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let data = fs::read_to_string(path).wrap_err("read rom layout")?;
+        toml::from_str(&data).wrap_err("parse rom layout")
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        fs::write(path, toml::to_string(self)?).wrap_err("write rom layout")
+    }
+
+    /// Fail if `template` isn't shaped like it was when it was calibrated.
+    pub fn verify(&self, template: &Schematic) -> color_eyre::Result<()> {
+        let current = Self::calibrate(template)?;
+        if current.layout != self.layout {
+            bail!(
+                "rom template holds {} words of {} bits, but was calibrated with {} words of {} bits; recalibrate with `calibrate-rom`",
+                current.layout.words(), current.layout.bits, self.layout.words(), self.layout.bits,
+            );
+        }
+        if current.fingerprint != self.fingerprint {
+            bail!(
+                "rom template changed shape since it was calibrated (fingerprint {}, expected {}); recalibrate with `calibrate-rom`",
+                current.fingerprint, self.fingerprint,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// A hash of every block that isn't air or a torch, by its position from the
+/// lowest corner of those blocks, so moving the template doesn't change it.
+pub fn fingerprint(schematic: &Schematic) -> String {
+    let supports: Vec<_> = schematic.blocks_sorted()
+        .into_iter()
+        .filter(|(_, blk)| !blk.is_air() && !blk.path().ends_with("torch"))
+        .collect();
+    let min = BoundingBox::around(supports.iter().map(|(pos, _)| pos)).min();
+
+    let mut data = String::new();
+    for (pos, blk) in supports {
+        let pos: [i64; 3] = BlockPos::from(pos).into();
+        data += &format!("{} {} {} {blk}\n", pos[0] - min[0], pos[1] - min[1], pos[2] - min[2]);
+    }
+
+    hash_bytes(data.as_bytes())
+}
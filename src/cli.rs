@@ -0,0 +1,1079 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use dialoguer::FuzzySelect;
+use dialoguer::theme::ColorfulTheme;
+use perpendicular::Vector3;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use schematics_cpu::program::Program;
+use tracing::info;
+use crate::api;
+use crate::analysis::{label_components, layer_histograms, sample_blocks, signal_losses, Netlist, MAX_POWER};
+use crate::bundle::Bundle;
+use crate::datapage::DataMedium;
+use crate::diff::{diff, DiffOptions, SchematicDiff};
+use crate::deploy::{deploy, setblock_commands, write_functions, DeployConfig};
+use crate::hooks::{Event, Hooks};
+use crate::logic::LogicSpec;
+use crate::mask::Mask;
+use crate::calibration::{self, RomCalibration};
+use crate::notify::{notify, Deployment};
+use crate::palette::{palette_diff, Remapping};
+use crate::pattern::Pattern;
+use crate::plugin::WasmPlugin;
+use crate::prefab::Prefab;
+use crate::queue::{Queue, Schedule};
+use crate::rcon::RconClient;
+use crate::reader::SchematicReader;
+use crate::rotation::RotationRules;
+use crate::rom::{self, RomLayout};
+use crate::schematic::{Axis, NbtCompression, PasteMode, Schematic, WriteOptions};
+use crate::search::BlockQuery;
+use crate::secrets;
+use crate::selftest;
+use crate::signing;
+use crate::server::{ServerConfig, DEFAULT_UPLOAD_CHUNK};
+use crate::storage::Storage;
+use crate::store::Store;
+use crate::tags::TagRegistry;
+use crate::transform::Transform;
+use crate::web::export_web;
+use crate::workspace::Workspace;
+
+#[derive(Parser)]
+#[command(name = "schematics")]
+pub struct Args {
+    /// Only program the rom torches matching this mask when programming the
+    /// rom, e.g. one bank of a build holding several
+    #[arg(long)]
+    pub region: Option<Mask>,
+    /// Produce byte-identical output for identical inputs
+    #[arg(long, global = true)]
+    pub deterministic: bool,
+    /// Don't write any block entities, like the contents of chests and signs
+    #[arg(long, global = true)]
+    pub no_block_entities: bool,
+    /// Don't write block entities with this id, like `minecraft:sign`
+    #[arg(long, global = true, value_name = "ID")]
+    pub skip_block_entity: Vec<String>,
+    /// Don't write any entities
+    #[arg(long, global = true)]
+    pub no_entities: bool,
+    /// Don't write entities with this id, like `minecraft:armor_stand`
+    #[arg(long, global = true, value_name = "ID")]
+    pub skip_entity: Vec<String>,
+    /// Write positions without a block as structure void instead of air,
+    /// so pasting leaves what's already there
+    #[arg(long, global = true)]
+    pub structure_void: bool,
+    /// Server profile from pipeline.toml to talk to
+    #[arg(long, global = true)]
+    pub server: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Compare the palettes of two versions of a build and write a remapping file
+    PaletteDiff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Where to write the suggested remapping
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List the positions where two versions of a build differ
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Don't report blocks matching this mask, like `#schematics:cosmetic`
+        #[arg(long)]
+        ignore: Option<Mask>,
+        /// Only report changes to redstone and other functional blocks
+        #[arg(long)]
+        functional: bool,
+        /// Extra tag definitions, added to the builtin ones
+        #[arg(long)]
+        tags: Option<PathBuf>,
+        /// Report every change, including air and block entities, ignoring
+        /// `--ignore` and `--functional`
+        #[arg(long, conflicts_with_all = ["ignore", "functional"])]
+        exact: bool,
+        /// Also write the changes to a patch file, for `apply-patch`
+        #[arg(long, requires = "exact")]
+        patch: Option<PathBuf>,
+    },
+    /// Apply a patch written by `diff --exact --patch` to the schematic it was made from
+    ApplyPatch {
+        input: PathBuf,
+        patch: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Replace block states in a schematic according to a remapping file
+    Remap {
+        input: PathBuf,
+        mapping: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// List the connected redstone components in a schematic
+    Components {
+        input: PathBuf,
+    },
+    /// Export the graph of redstone components in a schematic
+    Netlist {
+        input: PathBuf,
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// Where to write the graph, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Simulate a combinational region and compare it against a truth table
+    TruthTable {
+        input: PathBuf,
+        /// TOML file describing the inputs, outputs and expected table
+        spec: PathBuf,
+    },
+    /// Print per-layer block counts and a few randomly sampled blocks
+    Stats {
+        input: PathBuf,
+        /// Print how often every block state occurs instead
+        #[arg(long)]
+        palette: bool,
+        /// How many random blocks to show
+        #[arg(short, long, default_value_t = 10)]
+        sample: usize,
+        /// Seed for picking the random blocks, to show the same ones again
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// List the items needed to build a schematic, in shulker boxes and stacks
+    Materials {
+        input: PathBuf,
+        /// Count blocks by id instead of items
+        #[arg(long)]
+        blocks: bool,
+        /// Also write the list as CSV
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Write a page to look at a schematic in 3D in the browser, for sharing
+    ExportWeb {
+        out_dir: PathBuf,
+        #[arg(short, long, default_value = "generated.schem")]
+        input: PathBuf,
+    },
+    /// List where a block is in a schematic file, reading it block by block
+    /// instead of loading all of it
+    Scan {
+        input: PathBuf,
+        /// Block id, `minecraft:` if it has no namespace
+        id: String,
+        /// Only blocks with this property, like `facing=north`
+        #[arg(long = "prop")]
+        props: Vec<String>,
+        /// Match the id in any namespace
+        #[arg(long)]
+        any_namespace: bool,
+    },
+    /// Report where signals on redstone wire die out for lack of repeaters
+    WirePower {
+        input: PathBuf,
+    },
+    /// Place several schematics side by side in one output schematic
+    Concat {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Axis::X)]
+        axis: Axis,
+        /// Blocks of air between two inputs
+        #[arg(long, default_value_t = 0)]
+        gap: i64,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Create a project workspace in a directory
+    Init {
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// List the positions of blocks matching a mask like `!air` or `#minecraft:wool`
+    Find {
+        input: PathBuf,
+        mask: Mask,
+        /// Extra tag definitions, added to the builtin ones
+        #[arg(long)]
+        tags: Option<PathBuf>,
+    },
+    /// Replace blocks matching a mask with a pattern like `50%stone,50%cobblestone`
+    Replace {
+        input: PathBuf,
+        #[arg(long)]
+        from: Mask,
+        #[arg(long)]
+        to: String,
+        /// Extra tag definitions, added to the builtin ones
+        #[arg(long)]
+        tags: Option<PathBuf>,
+        /// Seed for picking blocks from random patterns
+        #[arg(long)]
+        seed: Option<u64>,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Turn and mirror a schematic, keeping blocks attached the right way
+    Transform {
+        input: PathBuf,
+        /// Quarter turns clockwise, seen from above
+        #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+        rotate: i64,
+        /// Mirror along this axis, after turning
+        #[arg(long, value_enum)]
+        mirror: Option<Axis>,
+        /// Extra rotation rules for modded blocks, added to the builtin ones
+        #[arg(long)]
+        rules: Option<PathBuf>,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Turn or mirror many schematics at once, writing them to a directory
+    TransformAll {
+        inputs: Vec<PathBuf>,
+        /// Quarter turns clockwise, seen from above
+        #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+        rotate: i64,
+        /// Mirror along this axis, after turning
+        #[arg(long, value_enum)]
+        mirror: Option<Axis>,
+        /// Extra rotation rules for modded blocks, added to the builtin ones
+        #[arg(long)]
+        rules: Option<PathBuf>,
+        /// Directory to write the results to, under their original names
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Run a WebAssembly plugin on a schematic
+    Plugin {
+        plugin: PathBuf,
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Generate a small component from the prefab library
+    Prefab {
+        #[arg(value_enum)]
+        kind: Prefab,
+        /// Length or height of the component
+        #[arg(long, default_value_t = 8)]
+        size: usize,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Add words to a rom template by repeating its last group of lines
+    ExtendRom {
+        input: PathBuf,
+        /// How many words to add, a multiple of the lines in a group
+        #[arg(long)]
+        words: usize,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Record the layout and shape of a rom template, which programming checks
+    /// it against from then on
+    CalibrateRom {
+        input: PathBuf,
+        /// Defaults to the input with a .romlayout extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show which words of a rom programming it with a program would change
+    PreviewRom {
+        input: PathBuf,
+        /// Assembly source of the program
+        program: PathBuf,
+    },
+    /// Cut out part of a schematic, or all of it without the surrounding air
+    Crop {
+        input: PathBuf,
+        /// Lowest corner of the part to keep, trims air if not given
+        #[arg(long, num_args = 3, allow_negative_numbers = true, requires = "max")]
+        min: Option<Vec<i64>>,
+        /// Highest corner of the part to keep, inclusive
+        #[arg(long, num_args = 3, allow_negative_numbers = true, requires = "min")]
+        max: Option<Vec<i64>>,
+        /// Keep the smallest box around the blocks matching this mask instead
+        #[arg(long, conflicts_with = "min")]
+        mask: Option<Mask>,
+        /// Extra tag definitions, added to the builtin ones
+        #[arg(long)]
+        tags: Option<PathBuf>,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Move a schematic, changing where it's pasted
+    Translate {
+        input: PathBuf,
+        #[arg(long, num_args = 3, allow_negative_numbers = true, required = true)]
+        by: Vec<i64>,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Paste one schematic into another
+    Paste {
+        input: PathBuf,
+        /// The schematic to paste into the input
+        other: PathBuf,
+        /// Where the lowest corner of the pasted schematic ends up
+        #[arg(long, num_args = 3, allow_negative_numbers = true, required = true)]
+        at: Vec<i64>,
+        #[arg(long, value_enum, default_value_t = PasteMode::Replace)]
+        mode: PasteMode,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Write a program into a lectern or container, for an in-game bootloader to read
+    ProgramData {
+        input: PathBuf,
+        /// Assembly source of the program
+        program: PathBuf,
+        #[arg(long, value_enum, default_value_t = DataMedium::Lectern)]
+        medium: DataMedium,
+        /// Where the lectern or container is
+        #[arg(long, num_args = 3, allow_negative_numbers = true, required = true)]
+        at: Vec<i64>,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Convert a schematic file to another format, version or compression
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Data version to write, by default the one of the input
+        #[arg(long)]
+        data_version: Option<i32>,
+        /// Sponge format version to write, 2 or 3; by default 3 only for
+        /// schematics too large for 2
+        #[arg(long)]
+        format_version: Option<i32>,
+        #[arg(long, value_enum, default_value_t = NbtCompression::Gzip)]
+        compression: NbtCompression,
+        /// Read the output back and check it holds the same as the input
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Upload a schematic to the server in chunks, without writing the file to
+    /// disk first. The schematic itself is still loaded and encoded in memory
+    Upload {
+        input: PathBuf,
+        /// Name of the schematic on the server, defaults to the file name
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Size of the chunks to send, in MiB
+        #[arg(long, default_value_t = DEFAULT_UPLOAD_CHUNK >> 20)]
+        chunk_mib: usize,
+    },
+    /// Download a schematic from the server
+    Download {
+        /// Name of the schematic on the server
+        name: Option<String>,
+        /// Pick the schematic from a list, with fuzzy search
+        #[arg(short, long)]
+        interactive: bool,
+        /// Where to save it, defaults to the name of the schematic
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Serve an HTTP API to store, assemble and build programs
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:3000")]
+        addr: SocketAddr,
+    },
+    /// Check that programming and serialization work, using a built-in rom
+    SelfTest,
+    /// Show how a schematic was produced
+    History {
+        input: PathBuf,
+    },
+    /// Place a schematic in the world with setblock commands over rcon
+    Deploy {
+        input: PathBuf,
+        /// Where the lowest corner of the schematic ends up
+        #[arg(long, num_args = 3, allow_negative_numbers = true, required = true)]
+        origin: Vec<i64>,
+        /// Maximum number of commands sent per second
+        #[arg(long, default_value_t = 200.0)]
+        rate: f64,
+        /// Number of commands sent before pausing for a second
+        #[arg(long, default_value_t = 1000)]
+        chunk: usize,
+        /// Write the commands as .mcfunction files to this directory instead
+        #[arg(long)]
+        functions: Option<PathBuf>,
+    },
+    /// Manage credentials stored in the system keyring
+    #[command(subcommand)]
+    Secret(SecretCommand),
+    /// Work with archives containing several schematics
+    #[command(subcommand)]
+    Bundle(BundleCommand),
+    /// Sign schematics and check their signatures
+    #[command(subcommand)]
+    Sign(SignCommand),
+    /// Keep generated schematics in a local store, by hash
+    #[command(subcommand)]
+    Store(StoreCommand),
+    /// Upload schematics that were queued because of the `[schedule]`
+    #[command(subcommand)]
+    Queue(QueueCommand),
+}
+
+#[derive(Subcommand)]
+pub enum SignCommand {
+    /// Generate a signing key, kept in the secret store, and write its public key
+    Keygen {
+        name: String,
+        #[arg(short, long)]
+        public: PathBuf,
+    },
+    /// Write a detached signature next to a file
+    File {
+        input: PathBuf,
+        /// Name of the signing key
+        #[arg(short, long)]
+        key: String,
+    },
+    /// Check the detached signature of a file
+    Verify {
+        input: PathBuf,
+        /// File with the public key
+        #[arg(short, long)]
+        public: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StoreCommand {
+    /// Add a schematic to the store, optionally under a name
+    Add {
+        input: PathBuf,
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// List the named schematics in the store
+    List,
+    /// Copy a schematic out of the store, by name or hash
+    Get {
+        name: String,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Remove schematics that have no name
+    Gc {
+        /// How many of the most recent unnamed schematics to keep
+        #[arg(long, default_value_t = 0)]
+        keep: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum QueueCommand {
+    /// List the schematics waiting to be uploaded
+    List,
+    /// Upload the queued schematics, if the schedule allows it
+    Run {
+        /// Keep checking every minute until everything is uploaded
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Remove everything from the queue without uploading it
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum SecretCommand {
+    /// Store a secret, read from stdin
+    Set {
+        name: String,
+    },
+    /// Remove a stored secret
+    Delete {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BundleCommand {
+    /// Pack schematics into a .zip, .tar or .tar.gz archive
+    Create {
+        output: PathBuf,
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// List the schematics in an archive
+    List {
+        archive: PathBuf,
+    },
+    /// Unpack all schematics in an archive into a directory
+    Extract {
+        archive: PathBuf,
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Graphml,
+}
+
+/// Load a schematic from a file, from an archive with `archive.zip#name`, or
+/// from the store with `store:name`. Inside a workspace, templates and bundles
+/// can also be referred to by name.
+pub(crate) fn load(path: impl AsRef<Path>) -> color_eyre::Result<Schematic> {
+    let path = path.as_ref().to_string_lossy();
+    if let Some(name) = path.strip_prefix("store:") {
+        return Store::open_default()?.get(name);
+    }
+
+    let workspace = Workspace::find_current()?;
+
+    match path.split_once('#') {
+        Some((archive, name)) => match workspace {
+            Some(workspace) => Schematic::from_archive(workspace.resolve_bundle(archive), name),
+            None => Schematic::from_archive(archive, name),
+        },
+        None => match workspace {
+            Some(workspace) => Schematic::from_file(workspace.resolve_schematic(&path)),
+            None => Schematic::from_file(&*path),
+        },
+    }
+}
+
+fn load_tags(extra: Option<PathBuf>) -> color_eyre::Result<TagRegistry> {
+    let mut tags = TagRegistry::builtin();
+    if let Some(extra) = extra {
+        tags.extend(TagRegistry::from_file(extra)?);
+    }
+
+    Ok(tags)
+}
+
+/// The transform for `rotate` quarter turns and an optional mirror, with the
+/// builtin rotation rules and those in `rules`.
+fn transform_with_rules(rotate: i64, mirror: Option<Axis>, rules: Option<PathBuf>) -> color_eyre::Result<(Transform, RotationRules)> {
+    let mut transform = Transform::rotate_y(rotate);
+    if let Some(axis) = mirror {
+        transform = transform.then(&Transform::mirror(axis));
+    }
+
+    let mut all_rules = RotationRules::builtin().clone();
+    if let Some(rules) = rules {
+        all_rules.extend(RotationRules::from_file(rules)?);
+    }
+
+    Ok((transform, all_rules))
+}
+
+/// Check that schematics in `path` can be read and written, going by its
+/// extension. Only sponge schematics are supported for now.
+fn check_format(path: &Path) -> color_eyre::Result<()> {
+    match path.extension().and_then(|i| i.to_str()) {
+        Some("schem") => Ok(()),
+        Some(extension @ ("litematic" | "schematic" | "nbt")) => {
+            bail!("{}: .{extension} files aren't supported yet, only sponge schematics (.schem)", path.display())
+        }
+        _ => bail!("{}: unknown schematic format, expected a .schem file", path.display()),
+    }
+}
+
+/// Let the user pick one of the schematics on `server`, starting the search
+/// with `query` if there is one.
+fn pick_schematic(server: &ServerConfig, query: Option<&str>) -> color_eyre::Result<String> {
+    let mut names = server.list_schematics()?;
+    if names.is_empty() {
+        bail!("there are no schematics in {} on {}", server.schematic_dir(), server.host);
+    }
+
+    let choice = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("schematic")
+        .items(&names)
+        .with_initial_text(query.unwrap_or_default())
+        .default(0)
+        .interact_opt()?;
+
+    match choice {
+        Some(idx) => Ok(names.swap_remove(idx)),
+        None => bail!("no schematic selected"),
+    }
+}
+
+pub fn run(command: Command, options: &WriteOptions, server: Option<&str>) -> color_eyre::Result<()> {
+    match command {
+        Command::PaletteDiff { old, new, output } => {
+            let old = load(old)?;
+            let new = load(new)?;
+
+            let diff = palette_diff(&old, &new);
+            print!("{diff}");
+
+            if let Some(output) = output {
+                diff.remapping().to_file(output)?;
+            }
+        }
+        Command::Diff { old, new, exact: true, patch, .. } => {
+            let diff = load(old)?.diff(&load(new)?);
+            for change in diff.changes() {
+                println!("{change}");
+            }
+            for change in &diff.block_entities {
+                println!("{change}");
+            }
+            info!("{diff}");
+
+            if let Some(patch) = patch {
+                diff.to_file(patch)?;
+            }
+        }
+        Command::ApplyPatch { input, patch, output } => {
+            let mut schematic = load(input)?;
+            let changed = SchematicDiff::from_file(patch)?.apply(&mut schematic)?;
+            info!("patched {changed} blocks");
+
+            schematic.to_file_with(output, options)?;
+        }
+        Command::Diff { old, new, ignore, functional, tags, .. } => {
+            let options = DiffOptions { ignore, functional_only: functional };
+            let changes = diff(&load(old)?, &load(new)?, &options, &load_tags(tags)?);
+            for change in &changes {
+                println!("{change}");
+            }
+            info!("{} positions differ", changes.len());
+        }
+        Command::Remap { input, mapping, output } => {
+            let mut schematic = load(input)?;
+            let changed = Remapping::from_file(mapping)?.apply(&mut schematic)?;
+            info!("remapped {changed} blocks");
+
+            schematic.to_file_with(output, options)?;
+        }
+        Command::Components { input } => {
+            let schematic = load(input)?;
+            for (idx, component) in label_components(&schematic).iter().enumerate() {
+                println!("{idx}: {component}");
+            }
+        }
+        Command::Netlist { input, format, output } => {
+            let netlist = Netlist::extract(&load(input)?);
+            let graph = match format {
+                GraphFormat::Dot => netlist.to_dot(),
+                GraphFormat::Graphml => netlist.to_graphml(),
+            };
+
+            match output {
+                Some(output) => fs::write(output, graph)?,
+                None => print!("{graph}"),
+            }
+        }
+        Command::TruthTable { input, spec } => {
+            let schematic = load(input)?;
+            let spec = LogicSpec::from_file(spec)?;
+
+            let table = spec.truth_table(&schematic)?;
+            print!("{table}");
+
+            let mismatches = table.mismatches(&spec.table);
+            for (inputs, expected, actual) in &mismatches {
+                println!("mismatch for {inputs}: expected {expected}, got {actual}");
+            }
+            if !mismatches.is_empty() {
+                bail!("{} rows differ from the expected truth table", mismatches.len());
+            }
+        }
+        Command::Stats { input, palette, sample, seed } => {
+            let schematic = load(input)?;
+            if palette {
+                println!("{} block states:", schematic.palette_size());
+                for (state, count) in schematic.palette() {
+                    println!("    {count:>6} {state}");
+                }
+                return Ok(());
+            }
+
+            for (y, histogram) in layer_histograms(&schematic) {
+                println!("layer {y}:");
+                for (id, count) in histogram {
+                    println!("    {count:>6} {id}");
+                }
+            }
+
+            println!("sample:");
+            let mut rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None if options.deterministic => StdRng::seed_from_u64(0),
+                None => StdRng::from_entropy(),
+            };
+            for (pos, blk) in sample_blocks(&schematic, sample, &mut rng) {
+                println!("    ({}, {}, {}) {blk}", pos.x(), pos.y(), pos.z());
+            }
+        }
+        Command::Materials { input, blocks, csv } => {
+            let schematic = load(input)?;
+            if blocks {
+                for (id, count) in schematic.material_list() {
+                    println!("{count:>8} {id}");
+                }
+                return Ok(());
+            }
+
+            let materials = schematic.bill_of_materials();
+            print!("{materials}");
+
+            if let Some(csv) = csv {
+                materials.to_csv_file(csv)?;
+            }
+        }
+        Command::Scan { input, id, props, any_namespace } => {
+            let mut query = BlockQuery::id(id);
+            if any_namespace {
+                query = query.any_namespace();
+            }
+            for prop in props {
+                let Some((key, value)) = prop.split_once('=') else {
+                    bail!("expected a property like `key=value`, not {prop:?}");
+                };
+                query = query.prop(key, value);
+            }
+
+            for block in SchematicReader::open(input)? {
+                let (pos, blk) = block?;
+                if query.matches(&blk) {
+                    println!("{} {} {} {blk}", pos.x(), pos.y(), pos.z());
+                }
+            }
+        }
+        Command::ExportWeb { out_dir, input } => {
+            let title = input.file_stem()
+                .map(|i| i.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "schematic".to_string());
+            export_web(&load(&input)?, &title, &out_dir)?;
+            println!("open {} in a browser", out_dir.join("index.html").display());
+        }
+        Command::WirePower { input } => {
+            let schematic = load(input)?;
+            for pos in signal_losses(&schematic) {
+                println!("signal dies after {MAX_POWER} blocks at ({}, {}, {})", pos.x(), pos.y(), pos.z());
+            }
+        }
+        Command::Concat { inputs, axis, gap, output } => {
+            let parts = inputs.into_iter()
+                .map(load)
+                .collect::<color_eyre::Result<Vec<_>>>()?;
+
+            Schematic::concat(&parts, axis, gap)?.to_file_with(output, options)?;
+        }
+        Command::Init { dir } => {
+            Workspace::init(dir)?;
+        }
+        Command::Find { input, mask, tags } => {
+            let schematic = load(input)?;
+            for pos in schematic.find_matching(&mask, &load_tags(tags)?) {
+                println!("{} {} {}", pos.x(), pos.y(), pos.z());
+            }
+        }
+        Command::Replace { input, from, to, tags, seed, output } => {
+            let mut schematic = load(input)?;
+            let pattern: Pattern = to.parse()?;
+            let mut rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None if options.deterministic => StdRng::seed_from_u64(0),
+                None => StdRng::from_entropy(),
+            };
+
+            let replaced = schematic.replace_matching_pattern(&from, &load_tags(tags)?, &pattern, &mut rng);
+            info!("replaced {replaced} blocks");
+
+            schematic.to_file_with(output, options)?;
+        }
+        Command::Transform { input, rotate, mirror, rules, output } => {
+            let (transform, rules) = transform_with_rules(rotate, mirror, rules)?;
+            load(input)?
+                .transformed_with(&transform, &rules)
+                .to_file_with(output, options)?;
+        }
+        Command::TransformAll { inputs, rotate, mirror, rules, output } => {
+            let (transform, rules) = transform_with_rules(rotate, mirror, rules)?;
+            fs::create_dir_all(&output)?;
+
+            for input in &inputs {
+                let Some(name) = input.file_name() else {
+                    bail!("{} isn't a file", input.display());
+                };
+
+                let mut schematic = Schematic::from_file(input)
+                    .wrap_err_with(|| format!("load {}", input.display()))?;
+                // with dense storage every distinct block state is turned once,
+                // instead of once for every block holding it
+                schematic.set_storage(Storage::Dense);
+                schematic
+                    .transformed_with(&transform, &rules)
+                    .to_file_with(output.join(name), options)?;
+            }
+            info!("transformed {} schematics", inputs.len());
+        }
+        Command::Plugin { plugin, input, output } => {
+            WasmPlugin::load(plugin)?
+                .transform(&load(input)?)?
+                .to_file_with(output, options)?;
+        }
+        Command::Prefab { kind, size, output } => {
+            kind.generate(size).to_file_with(output, options)?;
+        }
+        Command::CalibrateRom { input, output } => {
+            let calibrated = RomCalibration::calibrate(&load(&input)?)?;
+            let output = output.unwrap_or_else(|| input.with_extension(calibration::EXTENSION));
+            if let Some(dir) = output.parent().filter(|i| !i.as_os_str().is_empty()) {
+                fs::create_dir_all(dir).wrap_err("create layouts directory")?;
+            }
+            calibrated.to_file(&output)?;
+            info!("rom holds {} words, fingerprint {}", calibrated.layout.words(), calibrated.fingerprint);
+        }
+        Command::ExtendRom { input, words, output } => {
+            let schematic = load(input)?;
+            let layout = RomLayout::detect(&rom::find_program_lines(&schematic))?;
+            let (extended, layout) = rom::extend(schematic, &layout, words)?;
+            info!("rom now holds {} words", layout.words());
+
+            extended.to_file_with(output, options)?;
+        }
+        Command::PreviewRom { input, program } => {
+            let source = fs::read_to_string(&program).wrap_err("read program")?;
+            let words = Program::parse(&source)?.assemble(0)?;
+
+            print!("{}", rom::preview_program(&load(input)?, &words)?);
+        }
+        Command::Crop { input, min, max, mask, tags, output } => {
+            let schematic = load(input)?;
+            let cropped = match (min.zip(max), mask) {
+                (Some((min, max)), _) => schematic.crop(
+                    Vector3::new3(min[0], min[1], min[2]),
+                    Vector3::new3(max[0], max[1], max[2]),
+                ),
+                (None, Some(mask)) => schematic.crop_matching(&mask, &load_tags(tags)?),
+                (None, None) => schematic.trim(),
+            };
+            info!("cropped to {}x{}x{}", cropped.width(), cropped.height(), cropped.length());
+
+            cropped.to_file_with(output, options)?;
+        }
+        Command::Translate { input, by, output } => {
+            let mut schematic = load(input)?;
+            schematic.translate(Vector3::new3(by[0], by[1], by[2]));
+            schematic.to_file_with(output, options)?;
+        }
+        Command::Paste { input, other, at, mode, output } => {
+            let mut schematic = load(input)?;
+            let placed = schematic.paste(&load(other)?, Vector3::new3(at[0], at[1], at[2]), mode);
+            info!("pasted {placed} blocks");
+
+            schematic.to_file_with(output, options)?;
+        }
+        Command::ProgramData { input, program, medium, at, output } => {
+            let source = fs::read_to_string(&program).wrap_err("read program")?;
+            let words = Program::parse(&source)?.assemble(0)?;
+
+            let mut schematic = load(input)?;
+            medium.write(&mut schematic, Vector3::new3(at[0], at[1], at[2]), &words)?;
+            info!("wrote {} words", words.len());
+
+            schematic.to_file_with(output, options)?;
+        }
+        Command::Convert { input, output, data_version, format_version, compression, verify } => {
+            check_format(&input)?;
+            check_format(&output)?;
+
+            let options = WriteOptions { compression, data_version, version: format_version, ..options.clone() };
+            let schematic = load(input)?;
+            schematic.to_file_with(&output, &options)?;
+
+            if verify {
+                let written = load(&output)?;
+                if written != schematic {
+                    bail!("{} doesn't hold the same as the input", output.display());
+                }
+                info!("verified {} ({})", output.display(), written.content_hash());
+            }
+        }
+        Command::Upload { input, name, chunk_mib } => {
+            let server = ServerConfig::load(server)?;
+            let name = match name {
+                Some(name) => name,
+                None => input.file_stem()
+                    .map(|i| i.to_string_lossy().into_owned())
+                    .ok_or_else(|| eyre!("can't name the schematic after {}, pass --name", input.display()))?,
+            };
+
+            let schematic = load(&input)?;
+            let size = server.upload_schematic_streaming(&name, chunk_mib << 20, |w| schematic.to_writer_with(w, options))?;
+            info!("uploaded {name} ({} MiB)", size >> 20);
+        }
+        Command::Download { name, interactive, output } => {
+            let server = ServerConfig::load(server)?;
+            let name = match name {
+                Some(name) if !interactive => name,
+                name => pick_schematic(&server, name.as_deref())?,
+            };
+
+            let output = output.unwrap_or_else(|| format!("{name}.schem").into());
+            server.download_schematic(&name, &output)?;
+            info!("downloaded {name} to {}", output.display());
+        }
+        Command::Serve { addr } => {
+            api::serve(addr)?;
+        }
+        Command::SelfTest => {
+            selftest::run()?;
+        }
+        Command::History { input } => {
+            for (idx, entry) in load(input)?.history().iter().enumerate() {
+                println!("{idx}: {entry}");
+            }
+        }
+        Command::Deploy { input, origin, rate, chunk, functions } => {
+            let schematic = load(input)?;
+            let commands = setblock_commands(&schematic, [origin[0], origin[1], origin[2]]);
+
+            if let Some(dir) = functions {
+                let files = write_functions(&commands, chunk, dir)?;
+                info!("wrote {} functions", files.len());
+                return Ok(());
+            }
+
+            let server = ServerConfig::load(server)?;
+            let mut client = RconClient::connect(
+                (server.host.as_str(), server.rcon_port),
+                &server.rcon_password()?,
+            )?;
+
+            let config = DeployConfig {
+                commands_per_second: rate,
+                chunk_size: chunk,
+                ..Default::default()
+            };
+            deploy(&mut client, &commands, &config)?;
+        }
+        Command::Secret(SecretCommand::Set { name }) => {
+            secrets::store(&name, &secrets::read_stdin()?)?;
+        }
+        Command::Secret(SecretCommand::Delete { name }) => {
+            secrets::delete(&name)?;
+        }
+        Command::Bundle(BundleCommand::Create { output, inputs }) => {
+            let mut bundle = Bundle::new();
+            for input in inputs {
+                let name = input.file_stem()
+                    .map(|i| i.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                bundle.insert(name, load(input)?);
+            }
+
+            bundle.to_archive_with(output, options)?;
+        }
+        Command::Bundle(BundleCommand::List { archive }) => {
+            for name in Bundle::from_archive(archive)?.names() {
+                println!("{name}");
+            }
+        }
+        Command::Bundle(BundleCommand::Extract { archive, output }) => {
+            for (name, schematic) in Bundle::from_archive(archive)?.iter() {
+                let path = output.join(format!("{name}.schem"));
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                schematic.to_file_with(path, options)?;
+            }
+        }
+        Command::Sign(SignCommand::Keygen { name, public }) => {
+            let key = signing::generate_key(&name)?;
+            signing::write_public_key(&key, public)?;
+        }
+        Command::Sign(SignCommand::File { input, key }) => {
+            let signature = signing::sign_file(input, &key)?;
+            info!("wrote {}", signature.display());
+        }
+        Command::Sign(SignCommand::Verify { input, public }) => {
+            signing::verify_file(&input, &signing::read_public_key(public)?)?;
+            println!("{}: good signature", input.display());
+        }
+        Command::Store(StoreCommand::Add { input, name }) => {
+            let store = Store::open_default()?;
+            // keep the file as it is, so its hash matches the original
+            let data = fs::read(input)?;
+            Schematic::from_bytes(&data)?;
+            let hash = store.add_bytes(&data)?;
+            if let Some(name) = name {
+                store.set_ref(&name, &hash)?;
+            }
+            println!("{hash}");
+        }
+        Command::Store(StoreCommand::List) => {
+            for (name, hash) in Store::open_default()?.refs()? {
+                println!("{name} {hash}");
+            }
+        }
+        Command::Store(StoreCommand::Get { name, output }) => {
+            fs::copy(Store::open_default()?.path(&name)?, output)?;
+        }
+        Command::Store(StoreCommand::Gc { keep }) => {
+            Store::open_default()?.gc(keep)?;
+        }
+        Command::Queue(QueueCommand::List) => {
+            for staged in Queue::open_default()?.entries()? {
+                println!("{} {}", staged.name, staged.hash);
+            }
+        }
+        Command::Queue(QueueCommand::Run { wait }) => {
+            let server = ServerConfig::load(server)?;
+            let workspace = Workspace::find_current()?;
+            let (schedule, webhooks) = match &workspace {
+                Some(workspace) => (workspace.config.schedule.clone(), workspace.config.notify.clone()),
+                None => (Schedule::default(), Vec::new()),
+            };
+            let hooks = Hooks::load()?;
+            let queue = Queue::open_default()?;
+
+            loop {
+                for staged in queue.upload(&server, &schedule)? {
+                    let mut schematic = queue.store().get(&staged.hash)?;
+                    hooks.run(Event::AfterUpload, &mut schematic)?;
+
+                    notify(&webhooks, &Deployment {
+                        program_hash: schematic.history().last().and_then(|i| i.program_hash.clone()),
+                        name: staged.name,
+                        hash: staged.hash,
+                        server: server.host.clone(),
+                    });
+                }
+
+                if !wait || queue.entries()?.is_empty() {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(60));
+            }
+        }
+        Command::Queue(QueueCommand::Clear) => {
+            let removed = Queue::open_default()?.clear()?;
+            info!("removed {removed} schematics from the queue");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use color_eyre::eyre::bail;
+use nbt::Value;
+use perpendicular::Vector3;
+use crate::schematic::{BlockEntity, Schematic};
+
+/// A stack of items in one slot of a container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemStack {
+    pub slot: u8,
+    pub id: String,
+    pub count: u8,
+    /// Enchantments, names and other item data, kept as it was read.
+    pub tag: Option<Value>,
+}
+
+impl ItemStack {
+    pub fn new(slot: u8, id: impl Into<String>, count: u8) -> Self {
+        Self { slot, id: id.into(), count, tag: None }
+    }
+
+    fn from_value(value: &Value) -> color_eyre::Result<Self> {
+        let Value::Compound(item) = value else {
+            bail!("item is not a compound");
+        };
+
+        let (Some(Value::Byte(slot)), Some(Value::String(id)), Some(Value::Byte(count))) =
+            (item.get("Slot"), item.get("id"), item.get("Count")) else {
+            bail!("item without a slot, id or count");
+        };
+
+        Ok(Self {
+            slot: *slot as u8,
+            id: id.clone(),
+            count: *count as u8,
+            tag: item.get("tag").cloned(),
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        let mut item = vec![
+            ("Slot".to_string(), Value::Byte(self.slot as i8)),
+            ("id".to_string(), Value::String(self.id.clone())),
+            ("Count".to_string(), Value::Byte(self.count as i8)),
+        ];
+        if let Some(tag) = &self.tag {
+            item.push(("tag".to_string(), tag.clone()));
+        }
+
+        Value::Compound(item.into_iter().collect())
+    }
+}
+
+/// The items in a chest, barrel, hopper or other container, without the NBT.
+/// Other data of the block entity, like a custom name, is kept as it was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    id: String,
+    pub items: Vec<ItemStack>,
+    other: HashMap<String, Value>,
+}
+
+impl Container {
+    /// An empty container with block entity id `id`, like `minecraft:barrel`.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into(), items: Vec::new(), other: HashMap::new() }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// How many slots the container has, if it's a vanilla container.
+    pub fn slots(&self) -> Option<u8> {
+        match self.id.strip_prefix("minecraft:")? {
+            "chest" | "trapped_chest" | "barrel" | "shulker_box" => Some(27),
+            "dispenser" | "dropper" => Some(9),
+            "hopper" => Some(5),
+            "furnace" | "blast_furnace" | "smoker" => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Put `count` of `id` in the first empty slot, returning that slot.
+    pub fn push(&mut self, id: impl Into<String>, count: u8) -> color_eyre::Result<u8> {
+        let slots = self.slots().unwrap_or(u8::MAX);
+        let Some(slot) = (0..slots).find(|i| self.items.iter().all(|item| item.slot != *i)) else {
+            bail!("{} is full", self.id);
+        };
+
+        self.items.push(ItemStack::new(slot, id, count));
+        Ok(slot)
+    }
+
+    pub fn from_entity(entity: &BlockEntity) -> color_eyre::Result<Self> {
+        let mut other = entity.props().clone();
+        let items = match other.remove("Items") {
+            Some(Value::List(items)) => items.iter()
+                .map(ItemStack::from_value)
+                .collect::<color_eyre::Result<_>>()?,
+            Some(_) => bail!("items of {} are not a list", entity.id()),
+            None => Vec::new(),
+        };
+
+        Ok(Self { id: entity.id().to_string(), items, other })
+    }
+
+    pub fn to_entity(&self) -> BlockEntity {
+        let mut items = self.items.clone();
+        items.sort_by_key(|i| i.slot);
+
+        let mut props = self.other.clone();
+        props.insert("Items".to_string(), Value::List(items.iter().map(ItemStack::to_value).collect()));
+
+        BlockEntity::new(self.id.clone(), props)
+    }
+}
+
+/// The command in a command block, and how it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandBlock {
+    pub command: String,
+    /// Run without a redstone signal.
+    pub auto: bool,
+    /// Keep the output of the last run.
+    pub track_output: bool,
+    other: HashMap<String, Value>,
+}
+
+impl CommandBlock {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self { command: command.into(), auto: false, track_output: false, other: HashMap::new() }
+    }
+
+    pub fn from_entity(entity: &BlockEntity) -> color_eyre::Result<Self> {
+        if !entity.id().ends_with("command_block") {
+            bail!("{} is not a command block", entity.id());
+        }
+
+        let mut other = entity.props().clone();
+        let command = match other.remove("Command") {
+            Some(Value::String(command)) => command,
+            _ => String::new(),
+        };
+        let mut flag = |name: &str| matches!(other.remove(name), Some(Value::Byte(1)));
+
+        Ok(Self {
+            command,
+            auto: flag("auto"),
+            track_output: flag("TrackOutput"),
+            other,
+        })
+    }
+
+    pub fn to_entity(&self) -> BlockEntity {
+        let mut props = self.other.clone();
+        props.insert("Command".to_string(), Value::String(self.command.clone()));
+        props.insert("auto".to_string(), Value::Byte(self.auto as i8));
+        props.insert("TrackOutput".to_string(), Value::Byte(self.track_output as i8));
+
+        BlockEntity::new("minecraft:command_block", props)
+    }
+}
+
+impl Schematic {
+    /// The container at `pos`, if there is a block entity with items there.
+    pub fn container(&self, pos: &Vector3<i64>) -> Option<color_eyre::Result<Container>> {
+        self.block_entity(pos)
+            .filter(|i| i.props().contains_key("Items"))
+            .map(Container::from_entity)
+    }
+
+    /// Set the items of the container at `pos`. The container block itself
+    /// has to be placed separately.
+    pub fn set_container(&mut self, pos: Vector3<i64>, container: &Container) {
+        self.set_block_entity(pos, container.to_entity());
+    }
+
+    /// The command block at `pos`, if there is one.
+    pub fn command_block(&self, pos: &Vector3<i64>) -> Option<color_eyre::Result<CommandBlock>> {
+        self.block_entity(pos).map(CommandBlock::from_entity)
+    }
+
+    /// Set the command of the command block at `pos`. The command block
+    /// itself has to be placed separately.
+    pub fn set_command_block(&mut self, pos: Vector3<i64>, command_block: &CommandBlock) {
+        self.set_block_entity(pos, command_block.to_entity());
+    }
+}
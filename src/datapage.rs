@@ -0,0 +1,158 @@
+use color_eyre::eyre::{bail, eyre};
+use nbt::Value;
+use perpendicular::Vector3;
+use serde_json::json;
+use crate::container::{Container, ItemStack};
+use crate::schematic::{BlockEntity, Schematic};
+
+/// Words on one page of a book, as four lines of eight hex words.
+pub const PAGE_WORDS: usize = 32;
+/// The most pages a written book can have.
+pub const MAX_PAGES: usize = 100;
+/// Words stored in the NBT of one item in a container.
+pub const ITEM_WORDS: usize = 256;
+/// The item that carries the words in a container.
+pub const DATA_ITEM: &str = "minecraft:paper";
+
+/// Where a program is stored when it's written into block entities instead of
+/// a rom of torches. An in-game bootloader reads it from there, so the
+/// program doesn't depend on the layout of any rom.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum DataMedium {
+    /// Pages of a written book in a lectern, as hex text.
+    Lectern,
+    /// Int arrays in the NBT of paper in a chest, barrel or other container,
+    /// [`ITEM_WORDS`] per item, in slot order.
+    Container,
+}
+
+fn page_text(words: &[u16]) -> Value {
+    let text = words.chunks(8)
+        .map(|line| line.iter().map(|i| format!("{i:04x}")).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Value::String(json!({ "text": text }).to_string())
+}
+
+fn parse_page(page: &Value) -> color_eyre::Result<Vec<u16>> {
+    let Value::String(page) = page else {
+        bail!("page is not a string");
+    };
+    let text: serde_json::Value = serde_json::from_str(page)?;
+    let text = text.get("text")
+        .and_then(|i| i.as_str())
+        .ok_or_else(|| eyre!("page without text"))?;
+
+    text.split_whitespace()
+        .map(|i| u16::from_str_radix(i, 16).map_err(|_| eyre!("{i:?} is not a hex word")))
+        .collect()
+}
+
+fn book(words: &[u16]) -> color_eyre::Result<Value> {
+    let pages: Vec<_> = words.chunks(PAGE_WORDS).map(page_text).collect();
+    if pages.len() > MAX_PAGES {
+        bail!("{} words don't fit in a book of {MAX_PAGES} pages", words.len());
+    }
+
+    let tag = [
+        ("title".to_string(), Value::String("program".to_string())),
+        ("author".to_string(), Value::String("schematics".to_string())),
+        ("resolved".to_string(), Value::Byte(1)),
+        ("pages".to_string(), Value::List(pages)),
+    ];
+
+    Ok(Value::Compound([
+        ("id".to_string(), Value::String("minecraft:written_book".to_string())),
+        ("Count".to_string(), Value::Byte(1)),
+        ("tag".to_string(), Value::Compound(tag.into_iter().collect())),
+    ].into_iter().collect()))
+}
+
+impl DataMedium {
+    /// Write `words` into the lectern or container at `pos`, replacing what
+    /// was in it. The block has to be there already; a lectern gets
+    /// `has_book=true`.
+    pub fn write(&self, schematic: &mut Schematic, pos: Vector3<i64>, words: &[u16]) -> color_eyre::Result<()> {
+        let Some(block) = schematic.get_block(pos.clone()) else {
+            bail!("no block at {} {} {}", pos.x(), pos.y(), pos.z());
+        };
+
+        match self {
+            DataMedium::Lectern => {
+                if block.path() != "lectern" {
+                    bail!("expected a lectern, found {block}");
+                }
+
+                schematic.set_block(pos.clone(), block.with_prop("has_book", true));
+
+                let entity = schematic.block_entity(&pos)
+                    .cloned()
+                    .unwrap_or_else(|| BlockEntity::empty("minecraft:lectern"))
+                    .with("Book", book(words)?)
+                    .with("Page", Value::Int(0));
+                schematic.set_block_entity(pos, entity);
+            }
+            DataMedium::Container => {
+                let mut container = match schematic.container(&pos) {
+                    Some(container) => container?,
+                    None => Container::new(block.id()),
+                };
+
+                container.items.clear();
+                for chunk in words.chunks(ITEM_WORDS) {
+                    let slot = container.push(DATA_ITEM, 1)?;
+                    let item = container.items.iter_mut().find(|i| i.slot == slot).unwrap();
+                    item.tag = Some(Value::Compound([
+                        ("Program".to_string(), Value::IntArray(chunk.iter().map(|i| *i as i32).collect())),
+                    ].into_iter().collect()));
+                }
+                schematic.set_container(pos, &container);
+            }
+        }
+
+        schematic.record_program(words);
+        Ok(())
+    }
+
+    /// The words written with [`DataMedium::write`].
+    pub fn read(&self, schematic: &Schematic, pos: &Vector3<i64>) -> color_eyre::Result<Vec<u16>> {
+        match self {
+            DataMedium::Lectern => {
+                let entity = schematic.block_entity(pos)
+                    .ok_or_else(|| eyre!("no lectern at {} {} {}", pos.x(), pos.y(), pos.z()))?;
+                let Some(Value::Compound(book)) = entity.props().get("Book") else {
+                    bail!("lectern without a book");
+                };
+                let Some(Value::Compound(tag)) = book.get("tag") else {
+                    return Ok(Vec::new());
+                };
+                let Some(Value::List(pages)) = tag.get("pages") else {
+                    return Ok(Vec::new());
+                };
+
+                let mut res = Vec::new();
+                for page in pages {
+                    res.extend(parse_page(page)?);
+                }
+                Ok(res)
+            }
+            DataMedium::Container => {
+                let mut container = schematic.container(pos)
+                    .ok_or_else(|| eyre!("no container at {} {} {}", pos.x(), pos.y(), pos.z()))??;
+                container.items.sort_by_key(|i| i.slot);
+
+                let mut res = Vec::new();
+                for ItemStack { tag, .. } in &container.items {
+                    let Some(Value::Compound(tag)) = tag else {
+                        continue;
+                    };
+                    if let Some(Value::IntArray(words)) = tag.get("Program") {
+                        res.extend(words.iter().map(|i| *i as u16));
+                    }
+                }
+                Ok(res)
+            }
+        }
+    }
+}
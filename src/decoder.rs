@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::rc::Rc;
+use color_eyre::eyre::bail;
+use perpendicular::Vector3;
+use crate::rom::{group_slab, position, stride_transform, RomLayout};
+use crate::schematic::{BlockState, Schematic};
+
+/// Number of address bits needed to select one of `groups` groups.
+pub fn address_bits(groups: usize) -> usize {
+    (usize::BITS - groups.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// A block of the address decoder, which is one of two states depending on
+/// one bit of the index of the group it's in.
+#[derive(Debug, Clone)]
+pub struct DecoderBlock {
+    /// Position in the slab of the first group.
+    pub offset: [i64; 3],
+    pub bit: usize,
+    pub zero: Rc<BlockState>,
+    pub one: Rc<BlockState>,
+}
+
+/// The part of a rom that differs between groups: the wiring that selects a
+/// group from the address. It's found by comparing the slabs of all groups
+/// (see [`group_slab`]), and every block that differs has to follow one bit
+/// of the group index.
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    blocks: Vec<DecoderBlock>,
+}
+
+impl Decoder {
+    pub fn detect(
+        schematic: &Schematic,
+        ordered_lines: &[Vec<Vector3<i64>>],
+        layout: &RomLayout,
+        stride: [i64; 3],
+    ) -> color_eyre::Result<Self> {
+        // the bits themselves differ between groups once programmed, but
+        // they're not part of the decoder
+        let bits: HashSet<[i64; 3]> = (0..layout.groups)
+            .flat_map(|group| ordered_lines[group * layout.lines_per_group..(group + 1) * layout.lines_per_group]
+                .iter()
+                .flatten()
+                .map(move |pos| position(&stride_transform(stride, -(group as i64)).apply_vector(pos))))
+            .collect();
+
+        let mut states: BTreeMap<[i64; 3], Vec<Rc<BlockState>>> = BTreeMap::new();
+        for group in 0..layout.groups {
+            for (pos, blk) in group_slab(schematic, ordered_lines, layout, stride, group) {
+                let offset = position(&pos);
+                if bits.contains(&offset) {
+                    continue;
+                }
+
+                states.entry(offset)
+                    .or_insert_with(|| vec![BlockState::air(); layout.groups])[group] = blk;
+            }
+        }
+
+        let mut blocks = Vec::new();
+        for (offset, states) in states {
+            if states.iter().all(|i| i == &states[0]) {
+                continue;
+            }
+
+            let Some(block) = (0..address_bits(layout.groups))
+                .find_map(|bit| Self::follows_bit(offset, bit, &states)) else {
+                bail!("decoder block at {offset:?} doesn't follow any address bit: {}", states.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+            };
+            blocks.push(block);
+        }
+
+        Ok(Self { blocks })
+    }
+
+    fn follows_bit(offset: [i64; 3], bit: usize, states: &[Rc<BlockState>]) -> Option<DecoderBlock> {
+        let zero = states.iter().enumerate().find(|(group, _)| group >> bit & 1 == 0)?.1;
+        let one = states.iter().enumerate().find(|(group, _)| group >> bit & 1 == 1)?.1;
+
+        states.iter()
+            .enumerate()
+            .all(|(group, state)| state == if group >> bit & 1 == 1 { one } else { zero })
+            .then(|| DecoderBlock {
+                offset,
+                bit,
+                zero: zero.clone(),
+                one: one.clone(),
+            })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn blocks(&self) -> &[DecoderBlock] {
+        &self.blocks
+    }
+
+    /// How many address bits the decoder decodes.
+    pub fn address_bits(&self) -> usize {
+        self.blocks.iter().map(|i| i.bit + 1).max().unwrap_or(0)
+    }
+
+    fn bit_blocks(&self, bit: usize) -> Vec<&DecoderBlock> {
+        let mut res: Vec<_> = self.blocks.iter().filter(|i| i.bit == bit).collect();
+        res.sort_by_key(|i| i.offset);
+        res
+    }
+
+    /// Add blocks for address bits up to `bits`, when the template only shows
+    /// fewer. This works when the blocks of the last two bits the template
+    /// has are the same, just shifted, in which case every new bit is shifted
+    /// the same amount again.
+    pub fn extrapolate(&mut self, bits: usize) -> color_eyre::Result<()> {
+        let known = self.address_bits();
+        if bits <= known {
+            return Ok(());
+        }
+        if known < 2 {
+            bail!("the decoder needs {bits} address bits, but the template only shows {known}, too few to continue");
+        }
+
+        let last = self.bit_blocks(known - 1);
+        let before = self.bit_blocks(known - 2);
+        let step = match (last.first(), before.first()) {
+            (Some(a), Some(b)) => [0, 1, 2].map(|axis| a.offset[axis] - b.offset[axis]),
+            _ => bail!("missing decoder blocks for address bit {}", known - 2),
+        };
+
+        let repeats = last.len() == before.len() && last.iter().zip(&before).all(|(a, b)| {
+            a.zero == b.zero
+                && a.one == b.one
+                && [0, 1, 2].map(|axis| a.offset[axis] - b.offset[axis]) == step
+        });
+        if !repeats {
+            bail!("the decoder blocks for address bits {} and {} aren't the same, so a bit {known} can't be added", known - 2, known - 1);
+        }
+
+        let new: Vec<_> = (known..bits)
+            .flat_map(|bit| {
+                let times = (bit - known + 1) as i64;
+                last.iter().map(move |i| DecoderBlock {
+                    offset: [0, 1, 2].map(|axis| i.offset[axis] + step[axis] * times),
+                    bit,
+                    zero: i.zero.clone(),
+                    one: i.one.clone(),
+                })
+            })
+            .collect();
+
+        let occupied: BTreeSet<_> = self.blocks.iter().map(|i| i.offset).collect();
+        if let Some(clash) = new.iter().find(|i| occupied.contains(&i.offset)) {
+            bail!("continuing the decoder would overwrite its block at {:?}", clash.offset);
+        }
+
+        self.blocks.extend(new);
+        Ok(())
+    }
+
+    /// Set the decoder blocks of group `group`.
+    pub fn place(&self, schematic: &mut Schematic, group: usize, stride: [i64; 3]) {
+        for block in &self.blocks {
+            let state = if group >> block.bit & 1 == 1 { &block.one } else { &block.zero };
+            let pos = stride_transform(stride, group as i64).apply_vector(&Vector3::new3(block.offset[0], block.offset[1], block.offset[2]));
+
+            if state.is_air() && schematic.get_block(pos.clone()).is_none() {
+                continue;
+            }
+            schematic.set_block(pos, state.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use perpendicular::Vector3;
+    use crate::rom::{extend, RomLayout};
+    use crate::schematic::{BlockState, Schematic};
+    use super::address_bits;
+
+    /// A rom of `groups` groups of sixteen lines, 20 blocks apart, with a
+    /// decoder block for every address bit next to each group: a lamp for a
+    /// one bit, stone for a zero bit.
+    fn rom(groups: i64) -> Schematic {
+        let torch = BlockState::new("minecraft:soul_wall_torch");
+        let bits = (0..groups)
+            .flat_map(|group| (0..16).map(move |line| (group * 20 + line, line)))
+            .flat_map(|(y, z)| (0..16).map(move |x| [x, y, z]))
+            .map(|pos| (pos, torch.clone()));
+        let decoder = (0..groups)
+            .flat_map(|group| (0..address_bits(groups as usize) as i64).map(move |bit| (group, bit)))
+            .map(|(group, bit)| ([20 + 2 * bit, group * 20, 0], decoder_block(group, bit)));
+
+        Schematic::from_blocks(bits.chain(decoder))
+    }
+
+    fn decoder_block(group: i64, bit: i64) -> std::rc::Rc<BlockState> {
+        if group >> bit & 1 == 1 {
+            BlockState::new("minecraft:redstone_lamp")
+        } else {
+            BlockState::new("minecraft:stone")
+        }
+    }
+
+    #[test]
+    fn counts_address_bits() {
+        assert_eq!(address_bits(1), 0);
+        assert_eq!(address_bits(2), 1);
+        assert_eq!(address_bits(4), 2);
+        assert_eq!(address_bits(5), 3);
+        assert_eq!(address_bits(8), 3);
+    }
+
+    #[test]
+    fn continues_the_decoder_for_new_address_bits() {
+        let layout = RomLayout { groups: 4, lines_per_group: 16, bits: 16 };
+        let (extended, _) = extend(rom(4), &layout, 64).unwrap();
+
+        for group in 0..8 {
+            for bit in 0..3 {
+                let pos = Vector3::new3(20 + 2 * bit, group * 20, 0);
+                assert_eq!(extended.get_block(pos), Some(decoder_block(group, bit)), "group {group}, bit {bit}");
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_decoders_that_do_not_follow_the_address() {
+        let layout = RomLayout { groups: 4, lines_per_group: 16, bits: 16 };
+        let mut template = rom(4);
+        // the same in groups 0 and 3, but different in 1 and 2
+        template.set_block(Vector3::new3(30, 20, 0), BlockState::new("minecraft:redstone_lamp"));
+        template.set_block(Vector3::new3(30, 40, 0), BlockState::new("minecraft:redstone_lamp"));
+
+        assert!(extend(template, &layout, 16).is_err());
+    }
+
+    #[test]
+    fn needs_two_address_bits_to_continue_the_decoder() {
+        let layout = RomLayout { groups: 2, lines_per_group: 16, bits: 16 };
+        assert!(extend(rom(2), &layout, 32).is_err());
+        // staying within one address bit works
+        assert!(extend(rom(2), &layout, 0).is_ok());
+    }
+}
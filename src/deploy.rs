@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use crate::rcon::RconClient;
+use crate::schematic::Schematic;
+
+pub struct DeployConfig {
+    /// How many commands to send per second at most.
+    pub commands_per_second: f64,
+    /// Commands are sent in groups of this size, with a pause in between to
+    /// let the server catch up.
+    pub chunk_size: usize,
+    pub chunk_pause: Duration,
+}
+
+impl Default for DeployConfig {
+    fn default() -> Self {
+        Self {
+            commands_per_second: 200.0,
+            chunk_size: 1000,
+            chunk_pause: Duration::from_secs(1),
+        }
+    }
+}
+
+/// One `setblock` command per block in the schematic, with the schematic's
+/// minimum corner placed at `origin`.
+pub fn setblock_commands(schematic: &Schematic, origin: [i64; 3]) -> Vec<String> {
+    let [ox, oy, oz] = origin;
+    let [mx, my, mz] = schematic.bounds().min();
+
+    schematic.blocks_sorted()
+        .into_iter()
+        .map(|(pos, blk)| format!(
+            "setblock {} {} {} {blk}",
+            pos.x() - mx + ox,
+            pos.y() - my + oy,
+            pos.z() - mz + oz,
+        ))
+        .collect()
+}
+
+/// Send commands over rcon, in chunks and no faster than configured.
+pub fn deploy(client: &mut RconClient, commands: &[String], config: &DeployConfig) -> color_eyre::Result<()> {
+    let interval = Duration::from_secs_f64(1.0 / config.commands_per_second);
+    let chunks = commands.chunks(config.chunk_size.max(1));
+    let total = chunks.len();
+
+    for (idx, chunk) in chunks.enumerate() {
+        for command in chunk {
+            let start = Instant::now();
+
+            let response = client.command(command)?;
+            if !response.is_empty() && !response.starts_with("Changed the block") {
+                warn!("{command}: {response}");
+            }
+
+            if let Some(remaining) = interval.checked_sub(start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+
+        info!("sent chunk {}/{total}", idx + 1);
+        if idx + 1 < total {
+            thread::sleep(config.chunk_pause);
+        }
+    }
+
+    Ok(())
+}
+
+/// Write commands as numbered `.mcfunction` files of at most `chunk_size`
+/// commands each, to be run from a datapack one at a time.
+pub fn write_functions(commands: &[String], chunk_size: usize, dir: impl AsRef<Path>) -> color_eyre::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let mut res = Vec::new();
+    for (idx, chunk) in commands.chunks(chunk_size.max(1)).enumerate() {
+        let path = dir.join(format!("deploy_{idx:04}.mcfunction"));
+        fs::write(&path, chunk.join("\n") + "\n")?;
+        res.push(path);
+    }
+
+    Ok(res)
+}
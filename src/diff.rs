@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use perpendicular::Vector3;
+use crate::mask::Mask;
+use crate::schematic::{BlockEntity, BlockState, Schematic};
+use crate::tags::TagRegistry;
+
+/// What counts as a change when comparing two revisions of a build.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Blocks that don't matter, like `#schematics:cosmetic`. A position is
+    /// only reported if one of its blocks is neither air nor matches this.
+    pub ignore: Option<Mask>,
+    /// Only report positions where one of the blocks is part of a circuit,
+    /// see the `schematics:functional` tag.
+    pub functional_only: bool,
+}
+
+/// A position where two schematics differ.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub pos: Vector3<i64>,
+    pub old: Option<Rc<BlockState>>,
+    pub new: Option<Rc<BlockState>>,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = |state: &Option<Rc<BlockState>>| match state {
+            Some(state) => state.to_string(),
+            None => "nothing".to_string(),
+        };
+
+        write!(f, "{} {} {}: {} -> {}", self.pos.x(), self.pos.y(), self.pos.z(), name(&self.old), name(&self.new))
+    }
+}
+
+/// Every position where `old` and `new` hold a different block, ordered by
+/// y, then z, then x: the block changes of [`Schematic::diff`], without those
+/// `options` leave out.
+pub fn diff(old: &Schematic, new: &Schematic, options: &DiffOptions, tags: &TagRegistry) -> Vec<Change> {
+    let functional = Mask::Tag("schematics:functional".to_string());
+    let ignored = |state: &Option<Rc<BlockState>>| match (state, &options.ignore) {
+        (None, _) => true,
+        (Some(state), _) if state.is_air() => true,
+        (Some(state), Some(ignore)) => ignore.matches_state(state, tags),
+        (Some(_), None) => false,
+    };
+    let is_functional = |state: &Option<Rc<BlockState>>| {
+        state.as_ref().is_some_and(|i| functional.matches_state(i, tags))
+    };
+
+    old.diff(new)
+        .changes()
+        .filter(|change| !(ignored(&change.old) && ignored(&change.new)))
+        .filter(|change| !options.functional_only || is_functional(&change.old) || is_functional(&change.new))
+        .cloned()
+        .collect()
+}
+
+/// A position where the block entity differs between two schematics.
+#[derive(Debug, Clone)]
+pub struct BlockEntityChange {
+    pub pos: Vector3<i64>,
+    pub old: Option<BlockEntity>,
+    pub new: Option<BlockEntity>,
+}
+
+/// Only shows the ids of the block entities: their data is usually too big
+/// to print.
+impl Display for BlockEntityChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let id = |entity: &Option<BlockEntity>| entity.as_ref().map_or("nothing", |i| i.id()).to_string();
+
+        write!(f, "{} {} {}: block entity {} -> {}", self.pos.x(), self.pos.y(), self.pos.z(), id(&self.old), id(&self.new))
+    }
+}
+
+/// Everything that differs between two schematics, see [`Schematic::diff`].
+/// Unlike [`diff`], nothing is left out: air counts like any other block.
+/// All changes are ordered by y, then z, then x.
+#[derive(Debug, Clone, Default)]
+pub struct SchematicDiff {
+    /// Positions that only have a block in the new schematic.
+    pub added: Vec<Change>,
+    /// Positions that only have a block in the old schematic.
+    pub removed: Vec<Change>,
+    /// Positions with a different block in each schematic.
+    pub changed: Vec<Change>,
+    pub block_entities: Vec<BlockEntityChange>,
+}
+
+impl SchematicDiff {
+    /// Add `change` to the added, removed or changed blocks, depending on
+    /// which blocks it has. Changes that don't change anything are left out.
+    pub(crate) fn push(&mut self, change: Change) {
+        match (&change.old, &change.new) {
+            (None, Some(_)) => self.added.push(change),
+            (Some(_), None) => self.removed.push(change),
+            (Some(old), Some(new)) if !BlockState::same(old, new) => self.changed.push(change),
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.block_entities.is_empty()
+    }
+
+    /// Every position where the block differs, whether it was added, removed
+    /// or changed.
+    pub fn changes(&self) -> impl Iterator<Item=&Change> {
+        let mut res: Vec<_> = self.added.iter().chain(&self.removed).chain(&self.changed).collect();
+        res.sort_by_key(|i| (*i.pos.y(), *i.pos.z(), *i.pos.x()));
+        res.into_iter()
+    }
+}
+
+/// Like `3 added, 1 removed, 2 changed, 1 block entity changed`.
+impl Display for SchematicDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} changed, {} block {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.block_entities.len(),
+            if self.block_entities.len() == 1 { "entity" } else { "entities" },
+        )
+    }
+}
+
+impl Schematic {
+    /// What changed going from this schematic to `other`: every block that
+    /// was added, removed or replaced, and every block entity that differs.
+    pub fn diff(&self, other: &Schematic) -> SchematicDiff {
+        let key = |pos: &Vector3<i64>| (*pos.y(), *pos.z(), *pos.x());
+        let mut res = SchematicDiff::default();
+
+        let positions: BTreeSet<_> = self.blocks().chain(other.blocks())
+            .map(|(pos, _)| key(&pos))
+            .collect();
+        for (y, z, x) in positions {
+            let pos = Vector3::new3(x, y, z);
+            res.push(Change { old: self.get_block(pos.clone()), new: other.get_block(pos.clone()), pos });
+        }
+
+        let positions: BTreeSet<_> = self.block_entities().chain(other.block_entities())
+            .map(|(pos, _)| key(pos))
+            .collect();
+        for (y, z, x) in positions {
+            let pos = Vector3::new3(x, y, z);
+            let (old, new) = (self.block_entity(&pos), other.block_entity(&pos));
+            if old != new {
+                res.block_entities.push(BlockEntityChange { old: old.cloned(), new: new.cloned(), pos });
+            }
+        }
+
+        res
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+use nbt::Value;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::schematic::Schematic;
+
+pub const TOOL_VERSION: &str = concat!("schematics ", env!("CARGO_PKG_VERSION"));
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub fn hash_program(program: &[u16]) -> String {
+    let bytes: Vec<u8> = program.iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+
+    hash_bytes(&bytes)
+}
+
+/// Nbt data with its fields sorted by name, and those of every compound nested
+/// in it too. Lists keep their order, which means something, like the slots of
+/// a chest.
+fn sorted_nbt(props: &HashMap<String, Value>) -> String {
+    sorted_compound(props.iter())
+}
+
+fn sorted_compound<'a>(fields: impl Iterator<Item=(&'a String, &'a Value)>) -> String {
+    let fields: Vec<_> = fields.collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(k, v)| format!("{k:?}: {}", sorted_value(v)))
+        .collect();
+
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn sorted_value(value: &Value) -> String {
+    match value {
+        Value::Compound(fields) => sorted_compound(fields.iter()),
+        Value::List(items) => {
+            let items: Vec<_> = items.iter().map(sorted_value).collect();
+            format!("List([{}])", items.join(", "))
+        }
+        value => format!("{value:?}"),
+    }
+}
+
+impl Schematic {
+    /// A hash of everything that makes schematics [equal](PartialEq): the
+    /// same for schematics with the same contents, whatever their storage
+    /// or palette order. Unlike the hash of a file, it doesn't change when
+    /// a schematic is written again, which makes it a key for caching and
+    /// deduplicating schematics.
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+
+        for (pos, blk) in self.blocks_sorted() {
+            hasher.update(format!("block {} {} {} {}\n", pos.x(), pos.y(), pos.z(), blk.canonical()));
+        }
+
+        let mut block_entities: Vec<_> = self.block_entities().collect();
+        block_entities.sort_by_key(|(pos, _)| (*pos.y(), *pos.z(), *pos.x()));
+        for (pos, entity) in block_entities {
+            hasher.update(format!(
+                "block entity {} {} {} {} {}\n",
+                pos.x(), pos.y(), pos.z(), entity.id(), sorted_nbt(entity.props()),
+            ));
+        }
+
+        for entity in self.entities() {
+            hasher.update(format!("entity {} {:?} {}\n", entity.id(), entity.pos(), sorted_nbt(entity.props())));
+        }
+
+        let mut biomes: Vec<_> = self.biomes().collect();
+        biomes.sort();
+        for (x, z, biome) in biomes {
+            hasher.update(format!("biome {x} {z} {biome}\n"));
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// One step in how a schematic was produced. Every time a schematic is written,
+/// an entry is added to the list stored in its metadata, so the full chain from
+/// the original template to a programmed rom can be traced back.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct HistoryEntry {
+    pub tool_version: String,
+    /// Hash of the file this schematic was read from, if it was read from one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+    /// Hash of the program written into the schematic, if one was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program_hash: Option<String>,
+    /// Seconds since the unix epoch.
+    pub timestamp: i64,
+}
+
+impl HistoryEntry {
+    pub fn now(source_hash: Option<String>, program_hash: Option<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|i| i.as_secs() as i64)
+            .unwrap_or(0);
+
+        Self::at(timestamp, source_hash, program_hash)
+    }
+
+    pub fn at(timestamp: i64, source_hash: Option<String>, program_hash: Option<String>) -> Self {
+        Self {
+            tool_version: TOOL_VERSION.to_string(),
+            source_hash,
+            program_hash,
+            timestamp,
+        }
+    }
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} by {}", self.timestamp, self.tool_version)?;
+        if let Some(source) = &self.source_hash {
+            write!(f, ", from {source}")?;
+        }
+        if let Some(program) = &self.program_hash {
+            write!(f, ", program {program}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use nbt::Value;
+    use perpendicular::Vector3;
+    use crate::schematic::{BlockEntity, BlockState, Schematic};
+
+    /// A chest holding one item, with the fields of the item in `order`.
+    fn chest(order: [usize; 2]) -> Schematic {
+        let fields = [
+            ("id".to_string(), Value::String("minecraft:redstone".to_string())),
+            ("Count".to_string(), Value::Byte(64)),
+        ];
+        let item = Value::Compound(order.iter().map(|i| fields[*i].clone()).collect());
+
+        let mut res = Schematic::new();
+        res.set_block([0, 0, 0], BlockState::new("minecraft:chest"));
+        res.set_block_entity(Vector3::new3(0, 0, 0), BlockEntity::new(
+            "minecraft:chest",
+            HashMap::from([("Items".to_string(), Value::List(vec![item]))]),
+        ));
+        res
+    }
+
+    #[test]
+    fn hashes_nested_nbt_in_any_order() {
+        assert_eq!(chest([0, 1]).content_hash(), chest([1, 0]).content_hash());
+    }
+
+    #[test]
+    fn hashes_lists_in_order() {
+        let mut a = chest([0, 1]);
+        let mut b = chest([0, 1]);
+        let items = |first: i8, second: i8| HashMap::from([(
+            "Items".to_string(),
+            Value::List(vec![Value::Byte(first), Value::Byte(second)]),
+        )]);
+        a.set_block_entity(Vector3::new3(0, 0, 0), BlockEntity::new("minecraft:chest", items(1, 2)));
+        b.set_block_entity(Vector3::new3(0, 0, 0), BlockEntity::new("minecraft:chest", items(2, 1)));
+
+        assert_ne!(a.content_hash(), b.content_hash());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Command;
+use color_eyre::eyre::{eyre, WrapErr};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::plugin::WasmPlugin;
+use crate::schematic::Schematic;
+use crate::workspace::Workspace;
+
+/// The points in the pipeline where hooks run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// The template was read, before anything is written into it.
+    Loaded,
+    /// The program was written into the rom.
+    Programmed,
+    /// Just before writing and uploading the result. Changes made here end up
+    /// on the server.
+    BeforeUpload,
+    /// The result is on the server.
+    AfterUpload,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Event::Loaded => "on_loaded",
+            Event::Programmed => "on_programmed",
+            Event::BeforeUpload => "before_upload",
+            Event::AfterUpload => "after_upload",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Commands to run for each event, from the `[hooks]` table of the workspace
+/// config.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct HookConfig {
+    pub on_loaded: Vec<String>,
+    pub on_programmed: Vec<String>,
+    pub before_upload: Vec<String>,
+    pub after_upload: Vec<String>,
+}
+
+pub type Hook = Box<dyn Fn(&mut Schematic) -> color_eyre::Result<()>>;
+
+/// Hooks run at each step of the pipeline, to check or change the schematic
+/// there without changing the pipeline itself: closures registered in code, and
+/// commands from the `[hooks]` table of the workspace config. A hook failing
+/// stops the pipeline.
+///
+/// Commands are run with `sh`, with the path of the schematic as their last
+/// argument and the event in `SCHEMATICS_EVENT`. Whatever the command leaves in
+/// that file is read back, so commands can change the schematic too. Commands
+/// ending in `.wasm` are loaded as a [`WasmPlugin`] instead:
+///
+/// ```toml
+/// [hooks]
+/// on_programmed = ["plugins/rom-codec.wasm"]
+/// before_upload = ["./scripts/check-rom"]
+/// ```
+#[derive(Default)]
+pub struct Hooks {
+    hooks: BTreeMap<Event, Vec<Hook>>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The commands configured in the current workspace, if there is one.
+    pub fn load() -> color_eyre::Result<Self> {
+        let mut res = Self::new();
+        if let Some(workspace) = Workspace::find_current()? {
+            let root = workspace.root().to_path_buf();
+            let config = workspace.config.hooks;
+            for (event, commands) in [
+                (Event::Loaded, config.on_loaded),
+                (Event::Programmed, config.on_programmed),
+                (Event::BeforeUpload, config.before_upload),
+                (Event::AfterUpload, config.after_upload),
+            ] {
+                for command in commands {
+                    if command.ends_with(".wasm") {
+                        res.plugin(event, WasmPlugin::load(root.join(&command))?);
+                    } else {
+                        res.command(event, command);
+                    }
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    pub fn register(&mut self, event: Event, hook: impl Fn(&mut Schematic) -> color_eyre::Result<()> + 'static) {
+        self.hooks.entry(event).or_default().push(Box::new(hook));
+    }
+
+    pub fn on_loaded(&mut self, hook: impl Fn(&mut Schematic) -> color_eyre::Result<()> + 'static) {
+        self.register(Event::Loaded, hook);
+    }
+
+    pub fn on_programmed(&mut self, hook: impl Fn(&mut Schematic) -> color_eyre::Result<()> + 'static) {
+        self.register(Event::Programmed, hook);
+    }
+
+    pub fn before_upload(&mut self, hook: impl Fn(&mut Schematic) -> color_eyre::Result<()> + 'static) {
+        self.register(Event::BeforeUpload, hook);
+    }
+
+    pub fn after_upload(&mut self, hook: impl Fn(&mut Schematic) -> color_eyre::Result<()> + 'static) {
+        self.register(Event::AfterUpload, hook);
+    }
+
+    /// Run the shell command `command` for `event`, see [`Hooks`].
+    pub fn command(&mut self, event: Event, command: String) {
+        self.register(event, move |schematic| run_command(event, &command, schematic));
+    }
+
+    /// Replace the schematic with what `plugin` makes of it.
+    pub fn plugin(&mut self, event: Event, plugin: WasmPlugin) {
+        self.register(event, move |schematic| {
+            *schematic = plugin.transform(schematic)?;
+            Ok(())
+        });
+    }
+
+    /// Run all hooks for `event`, in the order they were registered.
+    pub fn run(&self, event: Event, schematic: &mut Schematic) -> color_eyre::Result<()> {
+        for hook in self.hooks.get(&event).into_iter().flatten() {
+            hook(schematic).wrap_err_with(|| format!("{event} hook"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A new file to hand the schematic to a command in. Its name is random and
+/// it's only opened if it didn't exist yet, so nothing else on the machine can
+/// put a file or link there first.
+fn create_hook_file() -> color_eyre::Result<(PathBuf, File)> {
+    loop {
+        let path = std::env::temp_dir().join(format!("schematics-hook-{:016x}.schem", rand::random::<u64>()));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).wrap_err("create file for hook"),
+        }
+    }
+}
+
+fn run_command(event: Event, command: &str, schematic: &mut Schematic) -> color_eyre::Result<()> {
+    let (path, file) = create_hook_file()?;
+    if let Err(e) = schematic.to_writer(file) {
+        std::fs::remove_file(&path).ok();
+        return Err(e);
+    }
+
+    info!("running {event} hook {command}");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .env("SCHEMATICS_EVENT", event.to_string())
+        .status()
+        .wrap_err_with(|| format!("run {command}"));
+
+    let res = match status {
+        Ok(status) if status.success() => Schematic::from_file(&path).map(|i| *schematic = i),
+        Ok(status) => Err(eyre!("{command} failed with {status}")),
+        Err(e) => Err(e),
+    };
+    std::fs::remove_file(&path).ok();
+
+    res
+}
@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use crate::schematic::BlockState;
+
+/// Hands out one shared `Rc` per distinct block state, so the thousands of
+/// blocks of a schematic, and the same states in other schematics, don't each
+/// allocate their own. Two interned states are equal exactly when they are
+/// the same `Rc`, see [`BlockState::same`].
+///
+/// States are only held weakly: once every schematic using a state is
+/// dropped, the state is freed too.
+#[derive(Default)]
+pub struct BlockStateInterner {
+    /// By the state formatted with sorted properties, the same for equal
+    /// states no matter the order their properties were added in.
+    states: HashMap<String, Weak<BlockState>>,
+    /// Entries at the last time dropped states were cleaned up.
+    cleaned_at: usize,
+}
+
+impl BlockStateInterner {
+    /// The canonical handle for `state`.
+    pub fn intern(&mut self, state: BlockState) -> Rc<BlockState> {
+        let key = state.canonical();
+        if let Some(existing) = self.states.get(&key).and_then(Weak::upgrade) {
+            return existing;
+        }
+
+        let res = Rc::new(state);
+        self.states.insert(key, Rc::downgrade(&res));
+
+        // dropped states leave their entries behind; clear them out whenever
+        // the map doubled, so that takes constant time per state on average
+        if self.states.len() >= (self.cleaned_at * 2).max(1024) {
+            self.states.retain(|_, i| i.strong_count() > 0);
+            self.cleaned_at = self.states.len();
+        }
+
+        res
+    }
+
+    /// How many distinct states are alive.
+    pub fn len(&self) -> usize {
+        self.states.values().filter(|i| i.strong_count() > 0).count()
+    }
+}
+
+thread_local! {
+    // Rc can't be shared between threads anyway
+    static INTERNER: RefCell<BlockStateInterner> = RefCell::new(BlockStateInterner::default());
+}
+
+impl BlockState {
+    /// The canonical handle for `state`, shared with every equal state
+    /// interned on this thread.
+    pub fn intern(state: BlockState) -> Rc<BlockState> {
+        INTERNER.with(|i| i.borrow_mut().intern(state))
+    }
+
+    /// Whether `a` and `b` are the same state. Interned states only have to
+    /// be compared by pointer; others are compared by id and properties.
+    pub fn same(a: &Rc<BlockState>, b: &Rc<BlockState>) -> bool {
+        Rc::ptr_eq(a, b) || a == b
+    }
+}
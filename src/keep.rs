@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use color_eyre::eyre::bail;
+use perpendicular::Vector3;
+use serde::{Deserialize, Serialize};
+use crate::pos::BlockPos;
+use crate::schematic::Schematic;
+
+/// A keep mask as stored in the metadata of a file: one bit per position of
+/// the schematic, in the same order as its blocks (x, then z, then y). Like
+/// the rest of our metadata, WorldEdit leaves it alone but doesn't use it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all="PascalCase")]
+pub(crate) struct KeepMaskFormat {
+    #[serde(serialize_with="nbt::i8_array")]
+    bits: Vec<i8>,
+}
+
+impl KeepMaskFormat {
+    /// The mask of `positions` in a schematic of `size` with its lowest
+    /// corner at `min`. Positions outside of it are left out.
+    pub(crate) fn encode<'a>(positions: impl IntoIterator<Item=&'a Vector3<i64>>, min: [i64; 3], size: [usize; 3]) -> Self {
+        let mut bits = vec![0; size.iter().product::<usize>().div_ceil(8)];
+        for pos in positions {
+            let pos: [i64; 3] = BlockPos::from(pos).into();
+            let relative = [0, 1, 2].map(|axis| pos[axis] - min[axis]);
+            if (0..3).any(|axis| relative[axis] < 0 || relative[axis] >= size[axis] as i64) {
+                continue;
+            }
+
+            let [x, y, z] = relative.map(|i| i as usize);
+            let idx = x + z * size[0] + y * size[0] * size[2];
+            bits[idx / 8] |= 1 << (idx % 8);
+        }
+
+        Self { bits }
+    }
+
+    /// The positions in the mask, for a schematic of `size` with its lowest
+    /// corner at the origin, as schematics are when they're read.
+    pub(crate) fn decode(&self, size: [usize; 3]) -> color_eyre::Result<HashSet<Vector3<i64>>> {
+        let volume: usize = size.iter().product();
+        if self.bits.len() != volume.div_ceil(8) {
+            bail!("keep mask of {} bytes doesn't match {volume} blocks", self.bits.len());
+        }
+
+        Ok((0..volume)
+            .filter(|idx| (self.bits[idx / 8] >> (idx % 8)) & 1 == 1)
+            .map(|idx| Vector3::new3(
+                (idx % size[0]) as i64,
+                (idx / (size[0] * size[2])) as i64,
+                (idx / size[0] % size[2]) as i64,
+            ))
+            .collect())
+    }
+}
+
+/// A keep mask marks the positions of a schematic that matter, like a
+/// WorldEdit `//gmask` that travels with the schematic: pasting it only
+/// places blocks there, and leaves the rest of what's already in the world
+/// alone. A programmed rom marks its torches this way, so pasting it over
+/// the build can't disturb the circuit around them.
+impl Schematic {
+    /// The positions that matter, or `None` if they all do.
+    pub fn keep_mask(&self) -> Option<&HashSet<Vector3<i64>>> {
+        self.keep_mask.as_ref()
+    }
+
+    /// Only keep the blocks at `positions` when pasting this schematic. The
+    /// mask moves along when the schematic is moved or turned, and is written
+    /// to the metadata of the file.
+    pub fn set_keep_mask<P: Into<BlockPos>>(&mut self, positions: impl IntoIterator<Item=P>) {
+        self.keep_mask = Some(positions.into_iter().map(|i| i.into().vector()).collect());
+    }
+
+    /// Keep every block again.
+    pub fn clear_keep_mask(&mut self) {
+        self.keep_mask = None;
+    }
+
+    /// Whether the block at `loc` matters, which all do without a mask.
+    pub fn keeps(&self, loc: impl Into<BlockPos>) -> bool {
+        self.keep_mask.as_ref().map_or(true, |mask| mask.contains(&loc.into().vector()))
+    }
+}
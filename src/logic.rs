@@ -0,0 +1,341 @@
+//! A static model of redstone logic, good enough to enumerate the truth table of
+//! small combinational circuits such as decoders. It only looks for the stable
+//! state of a circuit for a given set of inputs, so timing is ignored entirely,
+//! and it approximates a few rules: wire powers every solid block next to it
+//! regardless of its shape, and comparators behave like repeaters.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use color_eyre::eyre::{bail, WrapErr};
+use serde::Deserialize;
+use crate::props::Facing;
+use crate::schematic::{BlockState, Schematic};
+
+type Pos = [i64; 3];
+
+const MAX_ITERATIONS: usize = 256;
+const MAX_INPUTS: usize = 16;
+
+fn add(a: Pos, b: Pos) -> Pos {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: Pos, b: Pos) -> Pos {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// The offset a block's `facing` property points towards.
+pub fn facing_offset(state: &BlockState) -> Option<Pos> {
+    state.get_enum::<Facing>("facing").map(Facing::offset)
+}
+
+const FACES: [Pos; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+const WIRE_LINKS: [Pos; 12] = [
+    [1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1],
+    [1, 1, 0], [-1, 1, 0], [0, 1, 1], [0, 1, -1],
+    [1, -1, 0], [-1, -1, 0], [0, -1, 1], [0, -1, -1],
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Wire,
+    Torch { attached: Pos },
+    Repeater { input: Pos, output: Pos },
+    Source,
+    Lamp,
+    Solid,
+}
+
+impl Kind {
+    fn of(pos: Pos, state: &BlockState) -> Option<Self> {
+        let name = state.id().strip_prefix("minecraft:").unwrap_or(state.id());
+
+        Some(match name {
+            "air" | "cave_air" | "void_air" => return None,
+            "redstone_wire" => Kind::Wire,
+            "redstone_torch" => Kind::Torch { attached: sub(pos, [0, 1, 0]) },
+            "redstone_wall_torch" => Kind::Torch { attached: sub(pos, facing_offset(state)?) },
+            "repeater" | "comparator" => {
+                let facing = facing_offset(state)?;
+                Kind::Repeater { input: add(pos, facing), output: sub(pos, facing) }
+            }
+            "redstone_block" => Kind::Source,
+            "redstone_lamp" => Kind::Lamp,
+            _ if name.contains("torch") || name.contains("glass") || name.contains("slab") => return None,
+            _ => Kind::Solid,
+        })
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Eq)]
+struct State {
+    wire: BTreeMap<Pos, u8>,
+    lit: HashSet<Pos>,
+}
+
+/// A region of a schematic, reduced to the parts that matter for redstone logic.
+pub struct Circuit {
+    blocks: HashMap<Pos, Kind>,
+}
+
+impl Circuit {
+    /// Build a circuit from the blocks of the schematic between `min` and `max`
+    /// (inclusive), or from the whole schematic.
+    pub fn from_schematic(schematic: &Schematic, region: Option<(Pos, Pos)>) -> Self {
+        let mut blocks = HashMap::new();
+
+        for (pos, blk) in schematic.blocks() {
+            let pos = [*pos.x(), *pos.y(), *pos.z()];
+            if let Some((min, max)) = region {
+                if (0..3).any(|i| pos[i] < min[i] || pos[i] > max[i]) {
+                    continue;
+                }
+            }
+
+            if let Some(kind) = Kind::of(pos, blk) {
+                blocks.insert(pos, kind);
+            }
+        }
+
+        Self { blocks }
+    }
+
+    fn is_source(&self, pos: Pos, inputs: &HashSet<Pos>) -> bool {
+        inputs.contains(&pos) || self.blocks.get(&pos) == Some(&Kind::Source)
+    }
+
+    /// Whether any component emits a signal directly into `pos`.
+    fn driven(&self, pos: Pos, state: &State, inputs: &HashSet<Pos>) -> bool {
+        FACES.iter().any(|offset| {
+            let from = add(pos, *offset);
+            match self.blocks.get(&from) {
+                Some(Kind::Torch { attached }) => *attached != pos && state.lit.contains(&from),
+                Some(Kind::Repeater { output, .. }) => *output == pos && state.lit.contains(&from),
+                _ => self.is_source(from, inputs),
+            }
+        })
+    }
+
+    fn strongly_powered(&self, pos: Pos, state: &State) -> bool {
+        let below = sub(pos, [0, 1, 0]);
+        let torch_below = matches!(self.blocks.get(&below), Some(Kind::Torch { .. }))
+            && state.lit.contains(&below);
+
+        let repeater_into = FACES.iter().any(|offset| {
+            let from = add(pos, *offset);
+            matches!(self.blocks.get(&from), Some(Kind::Repeater { output, .. }) if *output == pos)
+                && state.lit.contains(&from)
+        });
+
+        torch_below || repeater_into
+    }
+
+    fn block_powered(&self, pos: Pos, state: &State, inputs: &HashSet<Pos>) -> bool {
+        if self.is_source(pos, inputs) {
+            return true;
+        }
+        if self.blocks.get(&pos) != Some(&Kind::Solid) {
+            return false;
+        }
+        if self.strongly_powered(pos, state) {
+            return true;
+        }
+
+        [[0, 1, 0], [1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1]].iter()
+            .any(|offset| state.wire.get(&add(pos, *offset)).copied().unwrap_or(0) > 0)
+    }
+
+    /// Whether a position carries a signal, for reading outputs.
+    fn is_on(&self, pos: Pos, state: &State, inputs: &HashSet<Pos>) -> bool {
+        match self.blocks.get(&pos) {
+            Some(Kind::Wire) => state.wire.get(&pos).copied().unwrap_or(0) > 0,
+            Some(Kind::Torch { .. } | Kind::Repeater { .. }) => state.lit.contains(&pos),
+            Some(Kind::Lamp) => {
+                self.driven(pos, state, inputs)
+                    || FACES.iter().any(|offset| {
+                        let from = add(pos, *offset);
+                        state.wire.get(&from).copied().unwrap_or(0) > 0
+                            || self.block_powered(from, state, inputs)
+                    })
+            }
+            _ => self.block_powered(pos, state, inputs),
+        }
+    }
+
+    fn step(&self, state: &State, inputs: &HashSet<Pos>) -> State {
+        let mut next = State::default();
+
+        for (pos, kind) in &self.blocks {
+            match kind {
+                Kind::Torch { attached } => {
+                    if !self.block_powered(*attached, state, inputs) {
+                        next.lit.insert(*pos);
+                    }
+                }
+                Kind::Repeater { input, .. } => {
+                    let on = match self.blocks.get(input) {
+                        Some(Kind::Wire) => state.wire.get(input).copied().unwrap_or(0) > 0,
+                        Some(Kind::Torch { .. }) => state.lit.contains(input),
+                        Some(Kind::Repeater { output, .. }) => *output == *pos && state.lit.contains(input),
+                        _ => self.block_powered(*input, state, inputs),
+                    };
+                    if on {
+                        next.lit.insert(*pos);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // wire directly next to a signal gets full power, which then
+        // falls off by one for every wire it travels through.
+        for (pos, kind) in &self.blocks {
+            if *kind != Kind::Wire {
+                continue;
+            }
+
+            let strong_neighbour = [[0, -1, 0], [1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1]].iter()
+                .any(|offset| self.strongly_powered(add(*pos, *offset), &next));
+
+            let power = if self.driven(*pos, &next, inputs) || strong_neighbour { 15 } else { 0 };
+            next.wire.insert(*pos, power);
+        }
+
+        for _ in 0..15 {
+            let mut changed = false;
+            for (pos, power) in next.wire.clone() {
+                if power <= 1 {
+                    continue;
+                }
+                for offset in WIRE_LINKS {
+                    if let Some(other) = next.wire.get_mut(&add(pos, offset)) {
+                        if *other < power - 1 {
+                            *other = power - 1;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        next
+    }
+
+    /// Find the stable state of the circuit with the given input positions
+    /// powered, and report which of the output positions carry a signal.
+    pub fn evaluate(&self, inputs: &HashSet<Pos>, outputs: &[Pos]) -> color_eyre::Result<Vec<bool>> {
+        let mut state = State::default();
+
+        for _ in 0..MAX_ITERATIONS {
+            let next = self.step(&state, inputs);
+            if next == state {
+                return Ok(outputs.iter()
+                    .map(|i| self.is_on(*i, &state, inputs))
+                    .collect());
+            }
+            state = next;
+        }
+
+        bail!("circuit did not settle after {MAX_ITERATIONS} steps, it probably oscillates")
+    }
+
+    /// Evaluate every combination of inputs. The first input is the leftmost bit
+    /// of each row.
+    pub fn truth_table(&self, inputs: &[Pos], outputs: &[Pos]) -> color_eyre::Result<TruthTable> {
+        if inputs.len() > MAX_INPUTS {
+            bail!("too many inputs ({}), at most {MAX_INPUTS} are supported", inputs.len());
+        }
+
+        let mut rows = BTreeMap::new();
+        for combination in 0..(1usize << inputs.len()) {
+            let bits: Vec<bool> = (0..inputs.len())
+                .map(|i| (combination >> (inputs.len() - 1 - i)) & 1 == 1)
+                .collect();
+
+            let powered = inputs.iter()
+                .zip(&bits)
+                .filter(|(_, on)| **on)
+                .map(|(pos, _)| *pos)
+                .collect();
+
+            let result = self.evaluate(&powered, outputs)?;
+            rows.insert(bits_to_string(&bits), bits_to_string(&result));
+        }
+
+        Ok(TruthTable { rows })
+    }
+}
+
+fn bits_to_string(bits: &[bool]) -> String {
+    bits.iter().map(|i| if *i { '1' } else { '0' }).collect()
+}
+
+pub struct TruthTable {
+    pub rows: BTreeMap<String, String>,
+}
+
+impl TruthTable {
+    /// Rows (as `(inputs, expected, actual)`) where this table differs from the
+    /// expected one. Rows missing from the expected table are not compared.
+    pub fn mismatches<'a>(&'a self, expected: &'a BTreeMap<String, String>) -> Vec<(&'a str, &'a str, &'a str)> {
+        expected.iter()
+            .filter_map(|(inputs, expected)| {
+                let actual = self.rows.get(inputs).map(String::as_str).unwrap_or("-");
+                (actual != expected).then_some((inputs.as_str(), expected.as_str(), actual))
+            })
+            .collect()
+    }
+}
+
+impl Display for TruthTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (inputs, outputs) in &self.rows {
+            writeln!(f, "{inputs} | {outputs}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Region {
+    pub min: Pos,
+    pub max: Pos,
+}
+
+/// The expected behaviour of a combinational region, loaded from TOML:
+///
+/// ```toml
+/// inputs = [[0, 1, 0], [2, 1, 0]]
+/// outputs = [[6, 1, 3]]
+/// region = { min = [0, 0, 0], max = [8, 3, 8] }
+///
+/// [table]
+/// "00" = "0"
+/// "01" = "1"
+/// ```
+#[derive(Deserialize)]
+pub struct LogicSpec {
+    pub inputs: Vec<Pos>,
+    pub outputs: Vec<Pos>,
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub table: BTreeMap<String, String>,
+}
+
+impl LogicSpec {
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let data = fs::read_to_string(path).wrap_err("read logic spec")?;
+        toml::from_str(&data).wrap_err("parse logic spec")
+    }
+
+    pub fn truth_table(&self, schematic: &Schematic) -> color_eyre::Result<TruthTable> {
+        let region = self.region.as_ref().map(|i| (i.min, i.max));
+        Circuit::from_schematic(schematic, region).truth_table(&self.inputs, &self.outputs)
+    }
+}
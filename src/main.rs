@@ -1,48 +1,161 @@
+// program! munches its input one token at a time
+#![recursion_limit = "1024"]
+
 use std::collections::{HashMap, HashSet};
 use std::fs::{File, read};
 use std::iter;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
+use clap::Parser;
 use perpendicular::{Vector, Vector2, Vector3};
 use tracing::info;
-use crate::instruction::Instruction;
-use crate::schematic::{BlockState, Schematic};
+use schematics_cpu::program;
+use crate::mask::Mask;
+use crate::schematic::{BlockState, EntityFilter, Schematic, WriteOptions};
 use crate::server::ServerConfig;
+use crate::tags::TagRegistry;
 
+mod api;
 mod server;
 mod schematic;
-#[macro_use]
-mod instruction;
+mod region;
+mod reader;
+mod storage;
+mod pos;
+mod bounds;
+mod calibration;
+mod keep;
+mod props;
+mod intern;
 mod rom;
+mod builder;
+mod transform;
+mod rotation;
+mod prefab;
+mod decoder;
+mod sponge3;
+mod placement;
+mod plugin;
+mod palette;
+mod diff;
+mod patch;
+mod analysis;
+mod materials;
+mod web;
+mod logic;
+mod bundle;
+mod workspace;
+mod secrets;
+mod rcon;
+mod deploy;
+mod tags;
+mod pattern;
+mod mask;
+mod search;
+mod notify;
+mod queue;
+mod history;
+mod hooks;
+mod sign;
+mod container;
+mod datapage;
+mod selftest;
+mod store;
+mod signing;
+mod cli;
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install().ok();
-    tracing_subscriber::fmt::init();
 
-    let fili = ServerConfig::fili();
+    if let Ok(name) = std::env::var(secrets::ASKPASS_ENV) {
+        return secrets::askpass(&name);
+    }
+
+    // logs go to stderr, so they don't mix with output like diffs and listings
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+
+    let args = cli::Args::parse();
+    let options = WriteOptions {
+        deterministic: args.deterministic,
+        block_entities: EntityFilter::new(args.no_block_entities, args.skip_block_entity),
+        entities: EntityFilter::new(args.no_entities, args.skip_entity),
+        structure_void: args.structure_void,
+        ..Default::default()
+    };
+    match args.command {
+        Some(command) => cli::run(command, &options, args.server.as_deref()),
+        None => program_fili(args.server.as_deref(), args.region, &options),
+    }
+}
+
+fn program_fili(server: Option<&str>, region: Option<Mask>, options: &WriteOptions) -> color_eyre::Result<()> {
+    let fili = ServerConfig::load(server)?;
+
+    let hooks = hooks::Hooks::load()?;
 
     fili.download_schematic("jona-diag-rom-fixed", "input.schem")?;
     let mut rom = Schematic::from_file("input.schem")?;
+    let workspace = workspace::Workspace::find_current()?;
+    let layout_file = calibration::RomCalibration::path_for(workspace.as_ref(), "jona-diag-rom-fixed");
+    if layout_file.is_file() {
+        calibration::RomCalibration::from_file(&layout_file)?.verify(&rom)?;
+    } else {
+        info!("{} doesn't exist, not checking the rom for changes", layout_file.display());
+    }
+    hooks.run(hooks::Event::Loaded, &mut rom)?;
 
 
     let mut program = program! {
+        start:
         nop;
         nop;
         nop;
         nop;
-        jmp 0;
+        jmp @start;
+    };
+
+    let preview = rom::preview_program(&rom, &program.assemble(0)?)?;
+    info!("programming rom: {preview}");
+
+    let original = rom.clone();
+    let programmed = match region {
+        Some(region) => rom::Programmed::Changed(rom::program_rom_in(rom, &program, &region, &TagRegistry::default())?),
+        None => rom::program_rom(rom, &program)?,
     };
+    if programmed.is_unchanged() {
+        info!("rom already holds the program, not uploading");
+        return Ok(());
+    }
+    let mut programmed_rom = programmed.into_schematic();
+    info!("programming changed {}", original.diff(&programmed_rom));
+    hooks.run(hooks::Event::Programmed, &mut programmed_rom)?;
+    hooks.run(hooks::Event::BeforeUpload, &mut programmed_rom)?;
 
 
-    let programmed_rom = rom::program_rom(
-        rom,
-        program,
-    );
+    programmed_rom.to_file_with("generated.schem", options)?;
+    let store = store::Store::open_default()?;
+    let hash = store.add_bytes(&std::fs::read("generated.schem")?)?;
+    store.set_ref("generated", &hash)?;
+    info!("stored generated schematic as {hash}");
+    std::fs::write("generated.lst", program.listing(0)?)?;
 
+    if workspace.as_ref().is_some_and(|i| i.config.schedule.is_enabled()) {
+        // uploaded later, by `queue run`
+        queue::Queue::open(store)?.stage(&std::fs::read("generated.schem")?, "generated")?;
+        return Ok(());
+    }
 
-    programmed_rom.to_file("generated.schem")?;
     fili.upload_schematic("generated.schem", "generated")?;
+    hooks.run(hooks::Event::AfterUpload, &mut programmed_rom)?;
 
+    if let Some(workspace) = workspace {
+        notify::notify(&workspace.config.notify, &notify::Deployment {
+            name: "generated".to_string(),
+            program_hash: Some(history::hash_program(&program.assemble(0)?)),
+            hash,
+            server: fili.host.clone(),
+        });
+    }
 
     Ok(())
 }
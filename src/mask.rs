@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use color_eyre::eyre::bail;
+use crate::pattern::{parse_state, split_top_level};
+use crate::schematic::BlockState;
+use crate::tags::{qualify, TagRegistry};
+
+/// Which positions an operation applies to, in WorldEdit syntax:
+///
+/// * `soul_wall_torch[facing=north]` matches a block id, and the given
+///   properties if there are any
+/// * `#existing` matches anything that isn't air
+/// * `#minecraft:wool` matches a block tag
+/// * `!mask` inverts a mask
+/// * `a,b` matches either `a` or `b`
+/// * `a b` (separated by spaces) matches both `a` and `b`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mask {
+    Existing,
+    Tag(String),
+    Block {
+        id: String,
+        props: HashMap<String, String>,
+    },
+    Not(Box<Mask>),
+    And(Vec<Mask>),
+    Or(Vec<Mask>),
+}
+
+impl Mask {
+    /// A mask matching all states with this id.
+    pub fn block(id: impl AsRef<str>) -> Self {
+        Mask::Block {
+            id: qualify(id.as_ref()),
+            props: HashMap::new(),
+        }
+    }
+
+    /// Whether a position holding `state` (or nothing at all) matches.
+    pub fn matches(&self, state: Option<&BlockState>, tags: &TagRegistry) -> bool {
+        match self {
+            Mask::Existing => state.map(|i| !i.is_air()).unwrap_or(false),
+            Mask::Tag(tag) => state.map(|i| tags.contains(tag, i.id())).unwrap_or(false),
+            Mask::Block { id, props } => state
+                .map(|i| i.id() == id && props.iter().all(|(k, v)| i.props().get(k) == Some(v)))
+                .unwrap_or(false),
+            Mask::Not(inner) => !inner.matches(state, tags),
+            Mask::And(masks) => masks.iter().all(|i| i.matches(state, tags)),
+            Mask::Or(masks) => masks.iter().any(|i| i.matches(state, tags)),
+        }
+    }
+
+    pub fn matches_state(&self, state: &BlockState, tags: &TagRegistry) -> bool {
+        self.matches(Some(state), tags)
+    }
+
+    fn parse_alternative(s: &str) -> color_eyre::Result<Self> {
+        if let Some(inner) = s.strip_prefix('!') {
+            return Ok(Mask::Not(Box::new(Self::parse_alternative(inner)?)));
+        }
+
+        Ok(match s.strip_prefix('#') {
+            Some("existing") => Mask::Existing,
+            Some(tag) => Mask::Tag(qualify(tag)),
+            None => {
+                let state = parse_state(s)?;
+                Mask::Block {
+                    id: state.id().to_string(),
+                    props: state.props().clone(),
+                }
+            }
+        })
+    }
+
+    fn parse_term(s: &str) -> color_eyre::Result<Self> {
+        if let Some(inner) = s.strip_prefix('!') {
+            return Ok(Mask::Not(Box::new(Self::parse_term(inner)?)));
+        }
+
+        let mut alternatives = split_top_level(s)
+            .into_iter()
+            .map(Self::parse_alternative)
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+
+        Ok(if alternatives.len() == 1 {
+            alternatives.remove(0)
+        } else {
+            Mask::Or(alternatives)
+        })
+    }
+}
+
+impl FromStr for Mask {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut terms = s.split_whitespace()
+            .map(Self::parse_term)
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+
+        Ok(match terms.len() {
+            0 => bail!("empty mask"),
+            1 => terms.remove(0),
+            _ => Mask::And(terms),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::schematic::BlockState;
+    use crate::tags::TagRegistry;
+    use super::Mask;
+
+    fn torch(facing: &str) -> BlockState {
+        format!("minecraft:soul_wall_torch[facing={facing}]").parse().unwrap()
+    }
+
+    #[test]
+    fn parses_existing() {
+        assert_eq!("#existing".parse::<Mask>().unwrap(), Mask::Existing);
+        assert_eq!("#wool".parse::<Mask>().unwrap(), Mask::Tag("minecraft:wool".to_string()));
+    }
+
+    #[test]
+    fn parses_inverted_masks() {
+        let mask: Mask = "!air".parse().unwrap();
+        assert_eq!(mask, Mask::Not(Box::new(Mask::block("air"))));
+
+        let tags = TagRegistry::default();
+        assert!(!mask.matches_state(&BlockState::air(), &tags));
+        assert!(mask.matches_state(&BlockState::stone(), &tags));
+    }
+
+    #[test]
+    fn parses_block_states() {
+        let mask: Mask = "soul_wall_torch[facing=north]".parse().unwrap();
+        assert_eq!(mask, Mask::Block {
+            id: "minecraft:soul_wall_torch".to_string(),
+            props: HashMap::from([("facing".to_string(), "north".to_string())]),
+        });
+
+        let tags = TagRegistry::default();
+        assert!(mask.matches_state(&torch("north"), &tags));
+        assert!(!mask.matches_state(&torch("south"), &tags));
+        assert!(!mask.matches(None, &tags));
+    }
+
+    #[test]
+    fn parses_combined_masks() {
+        let mask: Mask = "#existing !soul_wall_torch,stone".parse().unwrap();
+        assert_eq!(mask, Mask::And(vec![
+            Mask::Existing,
+            Mask::Not(Box::new(Mask::Or(vec![Mask::block("soul_wall_torch"), Mask::block("stone")]))),
+        ]));
+    }
+
+    #[test]
+    fn refuses_invalid_masks() {
+        assert!("".parse::<Mask>().is_err());
+        assert!("   ".parse::<Mask>().is_err());
+        assert!("soul_wall_torch[facing]".parse::<Mask>().is_err());
+        assert!("#existing !soul_wall_torch[facing=north,lit]".parse::<Mask>().is_err());
+    }
+}
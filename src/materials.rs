@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write as _};
+use std::fs;
+use std::path::Path;
+use color_eyre::eyre::WrapErr;
+use crate::props::Half;
+use crate::schematic::{BlockState, Schematic};
+
+/// Slots in a shulker box.
+pub const SHULKER_SLOTS: usize = 27;
+
+/// The item placing `state` takes, and how many of it, or `None` for blocks
+/// that can't be placed from an item. Blocks taking up two positions, like
+/// doors and beds, are counted at only one of them.
+pub fn item_for(state: &BlockState) -> Option<(String, usize)> {
+    let id = state.id();
+    let props = state.props();
+    let name = state.path();
+
+    if state.is_air() {
+        return None;
+    }
+
+    match name {
+        "piston_head" | "moving_piston" | "water" | "lava" | "fire" | "soul_fire" | "nether_portal" => return None,
+        "redstone_wire" => return Some(("minecraft:redstone".to_string(), 1)),
+        "tripwire" => return Some(("minecraft:string".to_string(), 1)),
+        _ => {}
+    }
+
+    // doors and tall plants; stairs and trapdoors use top and bottom
+    if state.get_enum::<Half>("half") == Some(Half::Upper) {
+        return None;
+    }
+    if props.get("part").is_some_and(|i| i == "head") {
+        return None;
+    }
+
+    let count = match props.get("type") {
+        Some(t) if t == "double" && name.ends_with("_slab") => 2,
+        _ => ["candles", "pickles", "eggs", "layers"].iter()
+            .find_map(|i| props.get(*i)?.parse().ok())
+            .unwrap_or(1),
+    };
+
+    // wall variants are placed with the normal item
+    let item = match name.split_once("wall_") {
+        Some((prefix, rest)) if ["torch", "sign", "banner", "head", "skull", "fan", "hanging_sign"].iter().any(|i| rest.ends_with(i)) => {
+            format!("{}:{prefix}{rest}", state.namespace())
+        }
+        _ => id.to_string(),
+    };
+
+    Some((item, count))
+}
+
+/// How many of `item` fit in one stack.
+pub fn stack_size(item: &str) -> usize {
+    let name = item.strip_prefix("minecraft:").unwrap_or(item);
+
+    if name.ends_with("_bed") || name.ends_with("shulker_box") || name.ends_with("_bucket") {
+        1
+    } else if name.ends_with("_sign") || name.ends_with("_banner") || name == "armor_stand" || name == "snowball" || name == "ender_pearl" {
+        16
+    } else {
+        64
+    }
+}
+
+/// A number of items, split into shulker boxes, stacks and single items.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Amount {
+    pub count: usize,
+    pub stack_size: usize,
+}
+
+impl Amount {
+    pub fn shulkers(&self) -> usize {
+        self.count / (self.stack_size * SHULKER_SLOTS)
+    }
+
+    /// Full stacks left after filling whole shulker boxes.
+    pub fn stacks(&self) -> usize {
+        self.count % (self.stack_size * SHULKER_SLOTS) / self.stack_size
+    }
+
+    pub fn remainder(&self) -> usize {
+        self.count % self.stack_size
+    }
+}
+
+/// Like `2 shulkers + 3 stacks + 12`, leaving out what's zero.
+impl Display for Amount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = |n: usize, word: &str| format!("{n} {word}{}", if n == 1 { "" } else { "s" });
+
+        let mut parts = Vec::new();
+        if self.shulkers() > 0 {
+            parts.push(plural(self.shulkers(), "shulker"));
+        }
+        if self.stacks() > 0 {
+            parts.push(plural(self.stacks(), "stack"));
+        }
+        if self.remainder() > 0 || parts.is_empty() {
+            parts.push(self.remainder().to_string());
+        }
+
+        write!(f, "{}", parts.join(" + "))
+    }
+}
+
+/// The items needed to build a schematic in survival, by item id.
+#[derive(Debug, Clone, Default)]
+pub struct BillOfMaterials {
+    pub items: BTreeMap<String, usize>,
+}
+
+impl BillOfMaterials {
+    pub fn of(schematic: &Schematic) -> Self {
+        let mut items = BTreeMap::new();
+        for (_, blk) in schematic.blocks() {
+            if let Some((item, count)) = item_for(blk) {
+                *items.entry(item).or_insert(0) += count;
+            }
+        }
+
+        Self { items }
+    }
+
+    pub fn amount(&self, item: &str) -> Option<Amount> {
+        let count = *self.items.get(item)?;
+        Some(Amount { count, stack_size: stack_size(item) })
+    }
+
+    /// Every item with its amount, most needed first.
+    pub fn amounts(&self) -> Vec<(&str, Amount)> {
+        let mut res: Vec<_> = self.items.iter()
+            .map(|(item, count)| (item.as_str(), Amount { count: *count, stack_size: stack_size(item) }))
+            .collect();
+        res.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        res
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut res = "item,count,stack_size,shulkers,stacks,remainder\n".to_string();
+        for (item, amount) in self.amounts() {
+            writeln!(
+                res,
+                "{item},{},{},{},{},{}",
+                amount.count, amount.stack_size, amount.shulkers(), amount.stacks(), amount.remainder(),
+            ).unwrap();
+        }
+
+        res
+    }
+
+    pub fn to_csv_file(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        fs::write(path, self.to_csv()).wrap_err("write bill of materials")
+    }
+}
+
+impl Display for BillOfMaterials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (item, amount) in self.amounts() {
+            writeln!(f, "{}: {amount} ({})", item.strip_prefix("minecraft:").unwrap_or(item), amount.count)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Schematic {
+    /// How many blocks there are of every id, ignoring their properties and
+    /// leaving out air. Unlike [`BillOfMaterials`], these are blocks and not
+    /// items: a door counts twice and a double slab once.
+    pub fn material_list(&self) -> BTreeMap<String, usize> {
+        let mut res = BTreeMap::new();
+        for (_, blk) in self.blocks().filter(|(_, blk)| !blk.is_air()) {
+            *res.entry(blk.id().to_string()).or_insert(0) += 1;
+        }
+
+        res
+    }
+
+    /// The items needed to build the schematic, see [`BillOfMaterials`].
+    pub fn bill_of_materials(&self) -> BillOfMaterials {
+        BillOfMaterials::of(self)
+    }
+}
@@ -0,0 +1,92 @@
+use color_eyre::eyre::{eyre, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+use crate::secrets;
+
+/// What the body of a webhook request looks like.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The [`Deployment`] as JSON.
+    #[default]
+    Generic,
+    /// A message for a Discord channel webhook.
+    Discord,
+}
+
+/// A webhook to tell about new deployments, from the `[[notify]]` tables of
+/// the workspace config. Webhook urls often contain a token, so the url can
+/// also be the name of a secret, as `secret:name`.
+///
+/// ```toml
+/// [[notify]]
+/// url = "secret:discord-webhook"
+/// format = "discord"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+}
+
+/// A schematic that was uploaded to a server.
+#[derive(Serialize, Clone, Debug)]
+pub struct Deployment {
+    /// The name it was uploaded as.
+    pub name: String,
+    /// Hash of the program written into it, if it's known.
+    pub program_hash: Option<String>,
+    /// Hash of the schematic, as in the store.
+    pub hash: String,
+    /// Host of the server it's on.
+    pub server: String,
+}
+
+impl Webhook {
+    fn url(&self) -> color_eyre::Result<String> {
+        match self.url.strip_prefix("secret:") {
+            Some(name) => Ok(secrets::require(name)?.expose().to_string()),
+            None => Ok(self.url.clone()),
+        }
+    }
+
+    pub fn send(&self, deployment: &Deployment) -> color_eyre::Result<()> {
+        let short = |hash: &str| hash[..12.min(hash.len())].to_string();
+        let body = match self.format {
+            WebhookFormat::Generic => serde_json::to_value(deployment)?,
+            WebhookFormat::Discord => json!({
+                "content": format!(
+                    "New rom image `{}` is live on {} (program `{}`, schematic `{}`)",
+                    deployment.name,
+                    deployment.server,
+                    deployment.program_hash.as_deref().map_or("unknown".to_string(), short),
+                    short(&deployment.hash),
+                ),
+            }),
+        };
+
+        ureq::post(&self.url()?)
+            .send_json(body)
+            // the url can contain a token, so don't show it
+            .map_err(|e| match e {
+                ureq::Error::Status(status, _) => eyre!("webhook returned {status}"),
+                ureq::Error::Transport(e) => eyre!("{}", e.kind()),
+            })
+            .wrap_err("send webhook")?;
+
+        Ok(())
+    }
+}
+
+/// Send `deployment` to every webhook. A webhook failing doesn't undo the
+/// deployment, so failures are only logged.
+pub fn notify(webhooks: &[Webhook], deployment: &Deployment) {
+    for webhook in webhooks {
+        match webhook.send(deployment) {
+            Ok(()) => info!("sent {:?} notification", webhook.format),
+            Err(e) => warn!("failed to send notification: {e:#}"),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use color_eyre::eyre::{eyre, WrapErr};
+use tracing::warn;
+use crate::schematic::{read_varints, write_varint, BlockState, Schematic};
+use crate::search::BlockQuery;
+
+/// A block state written with its properties sorted, so two equal states
+/// always produce the same key regardless of hashmap ordering.
+pub fn state_key(state: &BlockState) -> String {
+    state.canonical()
+}
+
+pub fn palette_of(schematic: &Schematic) -> BTreeSet<String> {
+    schematic.blocks()
+        .map(|(_, state)| state_key(state))
+        .collect()
+}
+
+impl Schematic {
+    /// Every distinct block state in the schematic with how many blocks have
+    /// it, the most common first. Air counts too.
+    pub fn palette(&self) -> Vec<(Rc<BlockState>, usize)> {
+        let mut counts: HashMap<String, (Rc<BlockState>, usize)> = HashMap::new();
+        for (_, state) in self.blocks() {
+            counts.entry(state_key(state)).or_insert_with(|| (state.clone(), 0)).1 += 1;
+        }
+
+        let mut res: Vec<_> = counts.into_iter().collect();
+        res.sort_by(|(a_key, (_, a)), (b_key, (_, b))| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+        res.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// How many distinct block states the schematic has.
+    pub fn palette_size(&self) -> usize {
+        palette_of(self).len()
+    }
+
+    /// Renumber freshly encoded blocks to the palette the schematic was read
+    /// with, names and unused entries included, so a schematic that's written
+    /// back unchanged gets the same block data. Only possible when every state
+    /// in `palette` was in the original one; otherwise, and for schematics
+    /// that weren't read from a file, the blocks are returned as they are.
+    pub(crate) fn reuse_palette(
+        &self,
+        block_data: Vec<i8>,
+        palette: BTreeMap<String, i32>,
+    ) -> color_eyre::Result<(Vec<i8>, BTreeMap<String, i32>)> {
+        // a palette with gaps or indices used twice was only read leniently,
+        // and isn't worth writing again
+        let mut ids: Vec<_> = self.original_palette.values().copied().collect();
+        ids.sort_unstable();
+        if ids.is_empty() || ids.iter().enumerate().any(|(idx, id)| *id != idx as i32) {
+            return Ok((block_data, palette));
+        }
+
+        let original: HashMap<String, i32> = self.original_palette.iter()
+            .filter_map(|(name, id)| Some((state_key(&name.parse().ok()?), *id)))
+            .collect();
+        let mut remap = vec![0; palette.len()];
+        for (name, id) in &palette {
+            let state: BlockState = name.parse()?;
+            let Some(original) = original.get(&state_key(&state)) else {
+                return Ok((block_data, palette));
+            };
+            remap[*id as usize] = *original as u32;
+        }
+
+        let mut res = Vec::with_capacity(block_data.len());
+        for i in read_varints(&block_data, false)? {
+            write_varint(&mut res, remap[i]);
+        }
+
+        Ok((res, self.original_palette.clone()))
+    }
+
+    /// Whether any block has id `id`, with any properties. Ids without a
+    /// namespace are in `minecraft`.
+    pub fn contains_block(&self, id: &str) -> bool {
+        let query = BlockQuery::id(id);
+        self.blocks().any(|(_, state)| query.matches(state))
+    }
+}
+
+pub struct PaletteDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+pub fn palette_diff(old: &Schematic, new: &Schematic) -> PaletteDiff {
+    let old = palette_of(old);
+    let new = palette_of(new);
+
+    PaletteDiff {
+        added: new.difference(&old).cloned().collect(),
+        removed: old.difference(&new).cloned().collect(),
+    }
+}
+
+impl PaletteDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Suggest a replacement for every removed state. A removed state maps to an
+    /// added state with the same id if there is exactly one, otherwise to an added
+    /// state with exactly the same properties if there is exactly one. Anything
+    /// else is left for the user to fill in.
+    pub fn remapping(&self) -> Remapping {
+        let added: Vec<BlockState> = self.added.iter()
+            .filter_map(|i| i.parse().ok())
+            .collect();
+
+        let mut entries = BTreeMap::new();
+        for removed in &self.removed {
+            let Ok(state) = removed.parse::<BlockState>() else {
+                entries.insert(removed.clone(), None);
+                continue;
+            };
+
+            let same_id: Vec<_> = added.iter()
+                .filter(|i| i.id() == state.id())
+                .collect();
+            let same_props: Vec<_> = added.iter()
+                .filter(|i| i.props() == state.props())
+                .collect();
+
+            let suggestion = match (same_id.as_slice(), same_props.as_slice()) {
+                ([only], _) => Some(state_key(only)),
+                (_, [only]) => Some(state_key(only)),
+                _ => None,
+            };
+
+            entries.insert(removed.clone(), suggestion);
+        }
+
+        Remapping { entries }
+    }
+}
+
+impl Display for PaletteDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for i in &self.removed {
+            writeln!(f, "- {i}")?;
+        }
+        for i in &self.added {
+            writeln!(f, "+ {i}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A mapping from old block states to new ones. Serialized as one
+/// `old -> new` pair per line, where `?` marks a state that still needs a
+/// replacement picked by hand.
+pub struct Remapping {
+    entries: BTreeMap<String, Option<String>>,
+}
+
+impl Remapping {
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        fs::read_to_string(path)
+            .wrap_err("read remapping file")?
+            .parse()
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        fs::write(path, self.to_string())
+            .wrap_err("write remapping file")
+    }
+
+    /// Replace every block in the schematic that has a mapping, returning the
+    /// number of blocks that changed.
+    pub fn apply(&self, schematic: &mut Schematic) -> color_eyre::Result<usize> {
+        let mut replacements = BTreeMap::new();
+        for (from, to) in &self.entries {
+            match to {
+                Some(to) => {
+                    replacements.insert(from.as_str(), BlockState::intern(to.parse()?));
+                }
+                None => warn!("no replacement for {from}, leaving it unchanged"),
+            }
+        }
+
+        Ok(schematic.replace_where(|_, blk| replacements.get(state_key(blk).as_str()).cloned()))
+    }
+}
+
+impl Display for Remapping {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (from, to) in &self.entries {
+            writeln!(f, "{from} -> {}", to.as_deref().unwrap_or("?"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Remapping {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = BTreeMap::new();
+
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (from, to) = line.split_once("->")
+                .ok_or_else(|| eyre!("line {}: expected `old -> new`", idx + 1))?;
+
+            let to = match to.trim() {
+                "?" => None,
+                to => Some(state_key(&to.parse()?)),
+            };
+
+            entries.insert(state_key(&from.trim().parse()?), to);
+        }
+
+        Ok(Self { entries })
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::rc::Rc;
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use nbt::{from_gzip_reader, from_reader, Value};
+use perpendicular::Vector3;
+use serde::{Deserialize, Serialize};
+use crate::diff::{BlockEntityChange, Change, SchematicDiff};
+use crate::schematic::{read_varint, write_varint, BlockEntity, BlockState, Schematic, WriteOptions, GZIP_MAGIC};
+
+const PATCH_VERSION: i32 = 1;
+
+/// A [`SchematicDiff`] as it's stored in a patch file: gzipped nbt, with
+/// every block state stored once in a palette, like in a schematic. Old
+/// blocks are stored too, so a patch can check it's applied to the schematic
+/// it was made from.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct PatchFile {
+    version: i32,
+    palette: BTreeMap<String, i32>,
+    /// x, y and z of every position where the block changes.
+    #[serde(serialize_with="nbt::i32_array")]
+    positions: Vec<i32>,
+    /// Two varints per position: the palette index of the old block and of
+    /// the new one, plus one, or 0 for no block.
+    #[serde(serialize_with="nbt::i8_array")]
+    blocks: Vec<i8>,
+    #[serde(default)]
+    block_entities: Vec<PatchBlockEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct PatchBlockEntity {
+    #[serde(serialize_with="nbt::i32_array")]
+    pos: Vec<i32>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    old: Option<PatchEntity>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    new: Option<PatchEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct PatchEntity {
+    id: String,
+    #[serde(default)]
+    data: BTreeMap<String, Value>,
+}
+
+impl From<&BlockEntity> for PatchEntity {
+    fn from(entity: &BlockEntity) -> Self {
+        Self {
+            id: entity.id().to_string(),
+            data: entity.props().clone().into_iter().collect(),
+        }
+    }
+}
+
+impl From<PatchEntity> for BlockEntity {
+    fn from(entity: PatchEntity) -> Self {
+        BlockEntity::new(entity.id, entity.data.into_iter().collect())
+    }
+}
+
+fn position(pos: &Vector3<i64>) -> [i32; 3] {
+    [*pos.x() as i32, *pos.y() as i32, *pos.z() as i32]
+}
+
+fn name(state: Option<&Rc<BlockState>>) -> String {
+    state.map_or("nothing".to_string(), |i| i.to_string())
+}
+
+impl SchematicDiff {
+    /// Change `schematic` the way this diff says. It first checks that every
+    /// position still holds the old block and block entity, and fails
+    /// without changing anything if one doesn't. Returns how many blocks
+    /// changed.
+    pub fn apply(&self, schematic: &mut Schematic) -> color_eyre::Result<usize> {
+        for change in self.changes() {
+            let current = schematic.get_block(&change.pos);
+            let matches = match (&current, &change.old) {
+                (Some(current), Some(old)) => BlockState::same(current, old),
+                (current, old) => current.is_none() && old.is_none(),
+            };
+            if !matches {
+                bail!(
+                    "patch doesn't apply at {} {} {}: expected {}, found {}",
+                    change.pos.x(), change.pos.y(), change.pos.z(), name(change.old.as_ref()), name(current.as_ref()),
+                );
+            }
+        }
+        for change in &self.block_entities {
+            if schematic.block_entity(&change.pos) != change.old.as_ref() {
+                bail!("patch doesn't apply at {} {} {}: the block entity differs", change.pos.x(), change.pos.y(), change.pos.z());
+            }
+        }
+
+        let mut count = 0;
+        for change in self.changes() {
+            match &change.new {
+                Some(new) => schematic.set_block(&change.pos, new.clone()),
+                None => {
+                    schematic.remove_block(&change.pos);
+                }
+            }
+            count += 1;
+        }
+        for change in &self.block_entities {
+            match &change.new {
+                Some(new) => schematic.set_block_entity(change.pos.clone(), new.clone()),
+                None => {
+                    schematic.remove_block_entity(&change.pos);
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        let mut file = PatchFile {
+            version: PATCH_VERSION,
+            palette: BTreeMap::new(),
+            positions: Vec::new(),
+            blocks: Vec::new(),
+            block_entities: self.block_entities.iter()
+                .map(|i| PatchBlockEntity {
+                    pos: position(&i.pos).to_vec(),
+                    old: i.old.as_ref().map(Into::into),
+                    new: i.new.as_ref().map(Into::into),
+                })
+                .collect(),
+        };
+
+        for change in self.changes() {
+            file.positions.extend(position(&change.pos));
+            for state in [&change.old, &change.new] {
+                let idx = match state {
+                    Some(state) => {
+                        let next = file.palette.len() as i32;
+                        *file.palette.entry(state.to_string()).or_insert(next) as u32 + 1
+                    }
+                    None => 0,
+                };
+                write_varint(&mut file.blocks, idx);
+            }
+        }
+
+        let mut data = Vec::new();
+        Schematic::write_nbt(&mut data, &file, "Patch", &WriteOptions::default())?;
+        fs::write(path, data).wrap_err("write patch file")
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let data = fs::read(path).wrap_err("read patch file")?;
+        let file: PatchFile = if data.starts_with(&GZIP_MAGIC) {
+            from_gzip_reader(Cursor::new(&data))
+        } else {
+            from_reader(Cursor::new(&data))
+        }.wrap_err("read and decode nbt")?;
+        if file.version != PATCH_VERSION {
+            bail!("unsupported patch version {}", file.version);
+        }
+
+        let mut states = vec![None; file.palette.len()];
+        for (state, idx) in file.palette {
+            let slot = states.get_mut(idx as usize)
+                .ok_or_else(|| eyre!("palette index {idx} out of range"))?;
+            *slot = Some(BlockState::intern(state.parse()?));
+        }
+        let state = |idx: usize| -> color_eyre::Result<Option<Rc<BlockState>>> {
+            if idx == 0 {
+                return Ok(None);
+            }
+            states.get(idx - 1).cloned().flatten()
+                .map(Some)
+                .ok_or_else(|| eyre!("unknown palette index {}", idx - 1))
+        };
+
+        let mut res = SchematicDiff::default();
+        let mut i = 0;
+        for pos in file.positions.chunks_exact(3) {
+            res.push(Change {
+                pos: Vector3::new3(pos[0] as i64, pos[1] as i64, pos[2] as i64),
+                old: state(read_varint(&file.blocks, &mut i).wrap_err("read blocks")?)?,
+                new: state(read_varint(&file.blocks, &mut i).wrap_err("read blocks")?)?,
+            });
+        }
+
+        for entity in file.block_entities {
+            let [x, y, z] = entity.pos[..] else {
+                bail!("block entity position has {} coordinates", entity.pos.len());
+            };
+            res.block_entities.push(BlockEntityChange {
+                pos: Vector3::new3(x as i64, y as i64, z as i64),
+                old: entity.old.map(Into::into),
+                new: entity.new.map(Into::into),
+            });
+        }
+
+        Ok(res)
+    }
+}
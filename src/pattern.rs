@@ -0,0 +1,162 @@
+use std::rc::Rc;
+use std::str::FromStr;
+use color_eyre::eyre::{bail, eyre};
+use rand::Rng;
+use crate::schematic::{BlockState, Schematic};
+use crate::mask::Mask;
+use crate::tags::{qualify, TagRegistry};
+
+/// Split on commas that aren't inside a property list.
+pub fn split_top_level(s: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (idx, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(&s[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(&s[start..]);
+
+    res
+}
+
+/// Parse a block state, adding the `minecraft` namespace when it's missing.
+pub fn parse_state(s: &str) -> color_eyre::Result<BlockState> {
+    let state: BlockState = s.trim().parse()?;
+    Ok(state.same_props_new_id(qualify(state.id())))
+}
+
+/// What to place, in WorldEdit syntax: a single state like `stone[axis=x]`, or a
+/// weighted random mix like `50%stone,50%cobblestone`. Entries without a
+/// percentage get weight 1.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    entries: Vec<(f64, Rc<BlockState>)>,
+}
+
+impl Pattern {
+    pub fn single(state: Rc<BlockState>) -> Self {
+        Self { entries: vec![(1.0, state)] }
+    }
+
+    pub fn pick(&self, rng: &mut impl Rng) -> Rc<BlockState> {
+        if let [(_, only)] = self.entries.as_slice() {
+            return only.clone();
+        }
+
+        let total: f64 = self.entries.iter().map(|(weight, _)| weight).sum();
+        let mut choice = rng.gen_range(0.0..total);
+        for (weight, state) in &self.entries {
+            if choice < *weight {
+                return state.clone();
+            }
+            choice -= weight;
+        }
+
+        self.entries.last().map(|(_, state)| state.clone()).unwrap()
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+
+        for entry in split_top_level(s) {
+            let entry = entry.trim();
+            let (weight, state) = match entry.split_once('%') {
+                Some((weight, state)) if !weight.contains('[') => {
+                    let weight: f64 = weight.trim().parse()
+                        .map_err(|_| eyre!("invalid weight in pattern entry {entry}"))?;
+                    (weight, state)
+                }
+                _ => (1.0, entry),
+            };
+
+            // NaN and infinity would make picking an entry panic
+            if !(weight.is_finite() && weight > 0.0) {
+                bail!("weights in a pattern must be positive, got {weight}");
+            }
+
+            entries.push((weight, BlockState::intern(parse_state(state)?)));
+        }
+
+        let total: f64 = entries.iter().map(|(weight, _)| weight).sum();
+        if !total.is_finite() {
+            bail!("the weights in pattern {s} are too large");
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+impl Schematic {
+    /// Replace every block matching `mask` with a block picked from
+    /// `pattern`, returning how many blocks were replaced.
+    pub fn replace_matching_pattern(
+        &mut self,
+        mask: &Mask,
+        tags: &TagRegistry,
+        pattern: &Pattern,
+        rng: &mut impl Rng,
+    ) -> usize {
+        self.replace_where(|_, blk| mask.matches_state(blk, tags).then(|| pattern.pick(rng)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use super::Pattern;
+
+    fn entries(pattern: &Pattern) -> Vec<(f64, String)> {
+        pattern.entries.iter().map(|(weight, state)| (*weight, state.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_single_states() {
+        let pattern: Pattern = "stone".parse().unwrap();
+        assert_eq!(entries(&pattern), [(1.0, "minecraft:stone".to_string())]);
+
+        let pattern: Pattern = "create:shaft[axis=x]".parse().unwrap();
+        assert_eq!(entries(&pattern), [(1.0, "create:shaft[axis=x]".to_string())]);
+    }
+
+    #[test]
+    fn parses_weighted_entries() {
+        let pattern: Pattern = "50%stone, 25%oak_log[axis=x], cobblestone".parse().unwrap();
+        assert_eq!(entries(&pattern), [
+            (50.0, "minecraft:stone".to_string()),
+            (25.0, "minecraft:oak_log[axis=x]".to_string()),
+            (1.0, "minecraft:cobblestone".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn refuses_invalid_weights() {
+        for pattern in ["0%stone", "-5%stone", "NaN%stone", "inf%stone", "x%stone", "1e308%stone,1e308%dirt"] {
+            assert!(pattern.parse::<Pattern>().is_err(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn picks_by_weight() {
+        let pattern: Pattern = "3%stone,1%dirt".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let stone = (0..4000)
+            .filter(|_| pattern.pick(&mut rng).id() == "minecraft:stone")
+            .count();
+        assert!((2700..3300).contains(&stone), "{stone}");
+    }
+}
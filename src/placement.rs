@@ -0,0 +1,88 @@
+use std::rc::Rc;
+use color_eyre::eyre::bail;
+use perpendicular::Vector3;
+use crate::schematic::{BlockState, Schematic};
+
+/// Horizontal directions, with the offset to the neighbour in that direction.
+pub const HORIZONTAL: [(&str, [i64; 3]); 4] = [
+    ("north", [0, 0, -1]),
+    ("south", [0, 0, 1]),
+    ("east", [1, 0, 0]),
+    ("west", [-1, 0, 0]),
+];
+
+pub fn opposite(facing: &str) -> Option<&'static str> {
+    Some(match facing {
+        "north" => "south",
+        "south" => "north",
+        "east" => "west",
+        "west" => "east",
+        "up" => "down",
+        "down" => "up",
+        _ => return None,
+    })
+}
+
+fn offset(pos: &Vector3<i64>, by: [i64; 3]) -> Vector3<i64> {
+    Vector3::new3(pos.x() + by[0], pos.y() + by[1], pos.z() + by[2])
+}
+
+/// Whether a torch can be attached to this block. Like the rest of the tool,
+/// this is a heuristic on block names rather than a full list.
+pub fn can_support_torch(state: &BlockState) -> bool {
+    let name = state.path();
+    !matches!(name, "air" | "cave_air" | "void_air" | "redstone_wire" | "repeater" | "comparator" | "redstone_block")
+        && !["torch", "glass", "slab", "stairs", "button", "lever", "carpet", "pressure_plate"]
+            .iter()
+            .any(|i| name.contains(i))
+}
+
+/// The directions a wall torch at `pos` could face: away from any
+/// horizontally adjacent block it can be attached to.
+pub fn wall_torch_facings(schematic: &Schematic, pos: &Vector3<i64>) -> Vec<&'static str> {
+    HORIZONTAL.iter()
+        .filter(|(_, by)| schematic.get_block(offset(pos, *by))
+            .map(|i| can_support_torch(&i))
+            .unwrap_or(false))
+        .filter_map(|(towards, _)| opposite(towards))
+        .collect()
+}
+
+/// The state of a torch placed at `pos`, based on the blocks around it. `id`
+/// is the id of the wall variant, like `minecraft:soul_wall_torch`. When the
+/// torch could hang on more than one wall, `prefer` picks which, and
+/// otherwise the first possibility is used. Without a wall to hang on, a
+/// standing torch is used if there's a block below.
+pub fn torch_state(schematic: &Schematic, pos: &Vector3<i64>, id: &str, prefer: Option<&str>) -> color_eyre::Result<Rc<BlockState>> {
+    let facings = wall_torch_facings(schematic, pos);
+    let facing = prefer
+        .and_then(|prefer| facings.iter().find(|i| **i == prefer))
+        .or(facings.first());
+
+    if let Some(facing) = facing {
+        return Ok(BlockState::builder(id).prop("facing", facing).build());
+    }
+
+    let standing = offset(pos, [0, -1, 0]);
+    if schematic.get_block(standing).map(|i| can_support_torch(&i)).unwrap_or(false) {
+        return Ok(BlockState::new(id.replace("_wall_torch", "_torch")));
+    }
+
+    bail!("nothing to attach a torch to at {:?}", [pos.x(), pos.y(), pos.z()])
+}
+
+/// Place a torch at `pos` if there's air there, facing away from the block it
+/// is attached to. Existing blocks are left alone. Returns whether a torch
+/// was placed.
+pub fn place_torch(schematic: &mut Schematic, pos: Vector3<i64>, id: &str, prefer: Option<&str>) -> color_eyre::Result<bool> {
+    if let Some(existing) = schematic.get_block(pos.clone()) {
+        if !existing.is_air() {
+            return Ok(false);
+        }
+    }
+
+    let state = torch_state(schematic, &pos, id, prefer)?;
+    schematic.set_block(pos, state);
+
+    Ok(true)
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use tracing::info;
+use wasmtime::{Config, Engine, Linker, Module, StoreLimits, StoreLimitsBuilder};
+use crate::schematic::{Schematic, WriteOptions};
+
+/// How much a plugin may do before it's stopped, roughly in instructions.
+const FUEL: u64 = 10_000_000_000;
+/// The most memory a plugin can use.
+const MAX_MEMORY: usize = 1 << 30;
+
+/// A custom pass over a schematic, compiled to WebAssembly, so it can be
+/// shipped without recompiling this tool. Plugins run sandboxed: they get no
+/// imports at all, so no files, network or clock, and are limited in memory
+/// and time.
+///
+/// A plugin exports its `memory` and two functions:
+///
+/// - `alloc(len: u32) -> u32`, returning where `len` bytes can be written
+/// - `transform(ptr: u32, len: u32) -> u64`, which is given a schematic file
+///   and returns the resulting schematic file as its pointer in the upper 32
+///   bits and its length in the lower 32 bits
+pub struct WasmPlugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+}
+
+fn wasm_error(e: wasmtime::Error) -> color_eyre::Report {
+    eyre!("{e:#}")
+}
+
+impl WasmPlugin {
+    pub fn load(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let path = path.as_ref();
+        let mut config = Config::new();
+        config.consume_fuel(true);
+
+        let engine = Engine::new(&config).map_err(wasm_error)?;
+        let module = Module::from_file(&engine, path)
+            .map_err(wasm_error)
+            .wrap_err_with(|| format!("load plugin {}", path.display()))?;
+
+        Ok(Self {
+            name: path.display().to_string(),
+            engine,
+            module,
+        })
+    }
+
+    /// Run the plugin on a schematic file, returning the file it produces.
+    pub fn transform_bytes(&self, data: &[u8]) -> color_eyre::Result<Vec<u8>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MAX_MEMORY)
+            .build();
+        let mut store = wasmtime::Store::new(&self.engine, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.add_fuel(FUEL).map_err(wasm_error)?;
+
+        let instance = Linker::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .map_err(wasm_error)?;
+        let memory = instance.get_memory(&mut store, "memory")
+            .ok_or_else(|| eyre!("plugin doesn't export its memory"))?;
+        let alloc = instance.get_typed_func::<u32, u32>(&mut store, "alloc")
+            .map_err(wasm_error)?;
+        let transform = instance.get_typed_func::<(u32, u32), u64>(&mut store, "transform")
+            .map_err(wasm_error)?;
+
+        let len = u32::try_from(data.len()).wrap_err("schematic too large for a plugin")?;
+        let ptr = alloc.call(&mut store, len).map_err(wasm_error)?;
+        memory.write(&mut store, ptr as usize, data).wrap_err("write schematic into plugin memory")?;
+
+        let packed = transform.call(&mut store, (ptr, len)).map_err(wasm_error)?;
+        let (ptr, len) = ((packed >> 32) as usize, packed as u32 as usize);
+        // the plugin reports where its result is, so check that before
+        // allocating room for it
+        if ptr.checked_add(len).map_or(true, |end| end > memory.data_size(&store)) {
+            bail!("plugin returned {len} bytes at {ptr}, outside of its memory");
+        }
+
+        let mut res = vec![0; len];
+        memory.read(&store, ptr, &mut res).wrap_err("read result from plugin memory")?;
+
+        Ok(res)
+    }
+
+    pub fn transform(&self, schematic: &Schematic) -> color_eyre::Result<Schematic> {
+        info!("running plugin {}", self.name);
+        let res = self.transform_bytes(&schematic.to_bytes_with(&WriteOptions::default())?)
+            .wrap_err_with(|| format!("run plugin {}", self.name))?;
+
+        Schematic::from_bytes(res)
+            .wrap_err_with(|| format!("read schematic produced by plugin {}", self.name))
+    }
+}
@@ -0,0 +1,105 @@
+use perpendicular::Vector3;
+
+/// A block position, for passing positions into the api from code that uses
+/// another vector type. Anything that converts into it can be given to
+/// [`Schematic::get_block`](crate::schematic::Schematic::get_block) and the
+/// like: `Vector3<i64>`, `[i64; 3]`, `(i64, i64, i64)`, and with the `glam`
+/// or `nalgebra` features `glam::IVec3`, `glam::I64Vec3` and
+/// `nalgebra::Point3<i64>`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BlockPos {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+impl BlockPos {
+    pub const fn new(x: i64, y: i64, z: i64) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn vector(self) -> Vector3<i64> {
+        Vector3::new3(self.x, self.y, self.z)
+    }
+}
+
+impl From<Vector3<i64>> for BlockPos {
+    fn from(pos: Vector3<i64>) -> Self {
+        Self::new(*pos.x(), *pos.y(), *pos.z())
+    }
+}
+
+impl From<&Vector3<i64>> for BlockPos {
+    fn from(pos: &Vector3<i64>) -> Self {
+        Self::new(*pos.x(), *pos.y(), *pos.z())
+    }
+}
+
+impl From<BlockPos> for Vector3<i64> {
+    fn from(pos: BlockPos) -> Self {
+        pos.vector()
+    }
+}
+
+impl From<[i64; 3]> for BlockPos {
+    fn from([x, y, z]: [i64; 3]) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+impl From<BlockPos> for [i64; 3] {
+    fn from(pos: BlockPos) -> Self {
+        [pos.x, pos.y, pos.z]
+    }
+}
+
+impl From<(i64, i64, i64)> for BlockPos {
+    fn from((x, y, z): (i64, i64, i64)) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<glam::IVec3> for BlockPos {
+    fn from(pos: glam::IVec3) -> Self {
+        Self::new(pos.x as i64, pos.y as i64, pos.z as i64)
+    }
+}
+
+/// Fails for positions that don't fit in an `i32`.
+#[cfg(feature = "glam")]
+impl TryFrom<BlockPos> for glam::IVec3 {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(pos: BlockPos) -> Result<Self, Self::Error> {
+        Ok(glam::IVec3::new(pos.x.try_into()?, pos.y.try_into()?, pos.z.try_into()?))
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<glam::I64Vec3> for BlockPos {
+    fn from(pos: glam::I64Vec3) -> Self {
+        Self::new(pos.x, pos.y, pos.z)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<BlockPos> for glam::I64Vec3 {
+    fn from(pos: BlockPos) -> Self {
+        glam::I64Vec3::new(pos.x, pos.y, pos.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Point3<i64>> for BlockPos {
+    fn from(pos: nalgebra::Point3<i64>) -> Self {
+        Self::new(pos.x, pos.y, pos.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<BlockPos> for nalgebra::Point3<i64> {
+    fn from(pos: BlockPos) -> Self {
+        nalgebra::Point3::new(pos.x, pos.y, pos.z)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::builder::SchematicBuilder;
+use crate::props::Facing;
+use crate::schematic::{BlockState, Schematic};
+
+/// Small redstone components generated from code, to be placed with a
+/// [`SchematicBuilder`]. All of them start at the origin and run along +x or +y,
+/// standing on a row of stone where they need something to stand on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Prefab {
+    /// Repeaters carrying a signal towards +x
+    RepeaterLine,
+    /// Torches stacked on blocks, inverting the signal at every level
+    TorchTower,
+    /// Lamps powered by a wire running over them
+    LampRow,
+}
+
+impl Prefab {
+    /// Generate the component, `size` repeaters, torches or lamps long.
+    pub fn generate(&self, size: usize) -> Schematic {
+        if size == 0 {
+            return Schematic::new();
+        }
+
+        match self {
+            Prefab::RepeaterLine => repeater_line(size),
+            Prefab::TorchTower => torch_tower(size),
+            Prefab::LampRow => lamp_row(size),
+        }
+    }
+}
+
+/// `length` repeaters on a row of stone, with the signal going towards +x.
+pub fn repeater_line(length: usize) -> Schematic {
+    // a repeater's input is on the side it's facing
+    let repeater = BlockState::builder("minecraft:repeater")
+        .prop("facing", Facing::West)
+        .prop("delay", 1)
+        .build();
+    let length = length as i64;
+
+    SchematicBuilder::new()
+        .fill([0, 0, 0], [length - 1, 0, 0], BlockState::stone())
+        .fill([0, 1, 0], [length - 1, 1, 0], repeater)
+        .build()
+}
+
+/// `height` redstone torches, each on top of a block that sits on the torch
+/// below it, so the signal goes up and is inverted at every torch.
+pub fn torch_tower(height: usize) -> Schematic {
+    (0..height)
+        .fold(SchematicBuilder::new().cursor([0, 0, 0]), |cursor, _| cursor
+            .block(BlockState::stone())
+            .up(1)
+            .torch()
+            .up(1))
+        .build()
+}
+
+/// `length` redstone lamps along x, with redstone wire on top of them.
+pub fn lamp_row(length: usize) -> Schematic {
+    let wire = BlockState::builder("minecraft:redstone_wire")
+        .prop("east", "side")
+        .prop("west", "side")
+        .build();
+    let length = length as i64;
+
+    SchematicBuilder::new()
+        .fill([0, 0, 0], [length - 1, 0, 0], BlockState::new("minecraft:redstone_lamp"))
+        .fill([0, 1, 0], [length - 1, 1, 0], wire)
+        .build()
+}
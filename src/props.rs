@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use crate::schematic::{Axis, BlockState};
+
+/// A type that block state properties can be read as, like `bool` for
+/// `lit=true` or [`Facing`] for `facing=north`. Properties are set with
+/// anything that implements [`Display`], which these all do.
+pub trait PropertyValue: Sized {
+    /// `None` if `value` isn't a valid value of this type.
+    fn from_prop(value: &str) -> Option<Self>;
+}
+
+impl PropertyValue for bool {
+    fn from_prop(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl PropertyValue for i64 {
+    fn from_prop(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl PropertyValue for String {
+    fn from_prop(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+/// Generates an enum of property values, with [`PropertyValue`] and
+/// [`Display`] using the names as they are in block states.
+macro_rules! property_enum {
+    ($(#[$meta: meta])* $name: ident { $($variant: ident = $literal: literal),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant),*
+        }
+
+        impl PropertyValue for $name {
+            fn from_prop(value: &str) -> Option<Self> {
+                match value {
+                    $($literal => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str(match self {
+                    $(Self::$variant => $literal),*
+                })
+            }
+        }
+    };
+}
+
+property_enum!(
+    /// The direction in `facing`, and in the names of the side properties of
+    /// wire and fences.
+    Facing {
+        North = "north",
+        South = "south",
+        East = "east",
+        West = "west",
+        Up = "up",
+        Down = "down",
+    }
+);
+
+property_enum!(
+    /// `half` of stairs and trapdoors (top or bottom), and of doors and tall
+    /// plants (upper or lower).
+    Half {
+        Top = "top",
+        Bottom = "bottom",
+        Upper = "upper",
+        Lower = "lower",
+    }
+);
+
+/// The name the game uses for [`Facing`] outside of block states.
+pub type Direction = Facing;
+
+impl Facing {
+    pub const HORIZONTAL: [Facing; 4] = [Facing::North, Facing::East, Facing::South, Facing::West];
+
+    /// The offset of the block this direction points to.
+    pub fn offset(self) -> [i64; 3] {
+        match self {
+            Facing::North => [0, 0, -1],
+            Facing::South => [0, 0, 1],
+            Facing::East => [1, 0, 0],
+            Facing::West => [-1, 0, 0],
+            Facing::Up => [0, 1, 0],
+            Facing::Down => [0, -1, 0],
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Facing::North => Facing::South,
+            Facing::South => Facing::North,
+            Facing::East => Facing::West,
+            Facing::West => Facing::East,
+            Facing::Up => Facing::Down,
+            Facing::Down => Facing::Up,
+        }
+    }
+
+    pub fn axis(self) -> Axis {
+        match self {
+            Facing::East | Facing::West => Axis::X,
+            Facing::Up | Facing::Down => Axis::Y,
+            Facing::North | Facing::South => Axis::Z,
+        }
+    }
+}
+
+impl PropertyValue for Axis {
+    fn from_prop(value: &str) -> Option<Self> {
+        match value {
+            "x" => Some(Axis::X),
+            "y" => Some(Axis::Y),
+            "z" => Some(Axis::Z),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Axis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Axis::X => "x",
+            Axis::Y => "y",
+            Axis::Z => "z",
+        })
+    }
+}
+
+impl BlockState {
+    /// Property `key` as a `T`, or `None` if the state doesn't have it or its
+    /// value isn't a valid `T`.
+    pub fn get_enum<T: PropertyValue>(&self, key: &str) -> Option<T> {
+        T::from_prop(self.props().get(key)?)
+    }
+
+    /// Property `key` if it's `true` or `false`, like `lit` or `powered`.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get_enum(key)
+    }
+
+    /// Property `key` if it's a number, like `delay` or `power`.
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        self.get_enum(key)
+    }
+
+    /// The same state with property `key` set to `value`:
+    /// `torch.with_prop("facing", Facing::North)`.
+    pub fn with_prop(&self, key: impl Into<String>, value: impl Display) -> Rc<BlockState> {
+        let mut props = self.props().clone();
+        props.insert(key.into(), value.to_string());
+
+        BlockState::with_props(self.id(), props)
+    }
+}
+
+/// Builds a block state one property at a time, see [`BlockState::builder`].
+/// Values are written the way the game writes them: `true`, `2`, `north`.
+#[derive(Debug, Clone)]
+pub struct BlockStateBuilder {
+    id: String,
+    props: HashMap<String, String>,
+}
+
+impl BlockStateBuilder {
+    /// Set property `key` to `value`, which can be a string, a number, a bool
+    /// or a property enum like [`Facing`].
+    pub fn prop(mut self, key: impl Into<String>, value: impl Display) -> Self {
+        self.props.insert(key.into(), value.to_string());
+        self
+    }
+
+    pub fn build(self) -> Rc<BlockState> {
+        BlockState::with_props(self.id, self.props)
+    }
+}
+
+impl BlockState {
+    /// Start building a state with id `id`:
+    /// `BlockState::builder("minecraft:repeater").prop("facing", Facing::West).prop("delay", 1).build()`.
+    pub fn builder(id: impl Into<String>) -> BlockStateBuilder {
+        BlockStateBuilder { id: id.into(), props: HashMap::new() }
+    }
+
+    /// A builder starting from this state, to change some of its properties.
+    pub fn to_builder(&self) -> BlockStateBuilder {
+        BlockStateBuilder { id: self.id().to_string(), props: self.props().clone() }
+    }
+}
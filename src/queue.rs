@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use color_eyre::eyre::{bail, WrapErr};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::rcon::RconClient;
+use crate::server::ServerConfig;
+use crate::store::Store;
+
+const QUEUE: &str = "queue";
+
+/// When uploads are allowed, from the `[schedule]` table of the workspace
+/// config. With a schedule, generated schematics are queued instead of
+/// uploaded, and `queue run` uploads them once the schedule allows it, so
+/// large structures aren't pasted during events.
+///
+/// ```toml
+/// [schedule]
+/// # only between 2 and 6 at night, UTC
+/// from_hour = 2
+/// to_hour = 6
+/// # and only when at most 1 player is online
+/// max_players = 1
+/// ```
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Schedule {
+    /// Start of the upload window, in hours UTC.
+    pub from_hour: Option<u8>,
+    /// End of the upload window, in hours UTC. The window can go past
+    /// midnight, from 22 to 4 for example.
+    pub to_hour: Option<u8>,
+    /// The most players that may be online, checked over rcon.
+    pub max_players: Option<u32>,
+}
+
+impl Schedule {
+    pub fn is_enabled(&self) -> bool {
+        self.from_hour.is_some() || self.to_hour.is_some() || self.max_players.is_some()
+    }
+
+    fn in_window(&self, hour: u8) -> bool {
+        let from = self.from_hour.unwrap_or(0);
+        let to = self.to_hour.unwrap_or(24);
+
+        if from <= to {
+            (from..to).contains(&hour)
+        } else {
+            hour >= from || hour < to
+        }
+    }
+
+    /// Why uploading to `server` isn't allowed right now, if it isn't.
+    pub fn blocked(&self, server: &ServerConfig) -> color_eyre::Result<Option<String>> {
+        let hour = (SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 3600 % 24) as u8;
+        if !self.in_window(hour) {
+            return Ok(Some(format!("it's {hour}:00 UTC, outside of the upload window")));
+        }
+
+        if let Some(max) = self.max_players {
+            let players = player_count(server)?;
+            if players > max {
+                return Ok(Some(format!("{players} players are online, more than {max}")));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// The number of players online, from the output of `list`: "There are 3 of a
+/// max of 20 players online: ...".
+fn player_count(server: &ServerConfig) -> color_eyre::Result<u32> {
+    let mut client = RconClient::connect(
+        (server.host.as_str(), server.rcon_port),
+        &server.rcon_password()?,
+    )?;
+    let output = client.command("list")?;
+
+    match output.split_whitespace().find_map(|i| i.parse().ok()) {
+        Some(players) => Ok(players),
+        None => bail!("can't find the number of players in {output:?}"),
+    }
+}
+
+/// A schematic waiting to be uploaded.
+#[derive(Debug, Clone)]
+pub struct Staged {
+    file: PathBuf,
+    /// The name it will be uploaded as.
+    pub name: String,
+    /// Its hash in the store.
+    pub hash: String,
+}
+
+/// Schematics waiting to be uploaded, in the order they were queued. The
+/// schematics themselves are kept in the [`Store`].
+pub struct Queue {
+    store: Store,
+    dir: PathBuf,
+}
+
+impl Queue {
+    /// The queue kept in `store`.
+    pub fn open(store: Store) -> color_eyre::Result<Self> {
+        let dir = store.root().join(QUEUE);
+        fs::create_dir_all(&dir).wrap_err("create queue directory")?;
+
+        Ok(Self { store, dir })
+    }
+
+    pub fn open_default() -> color_eyre::Result<Self> {
+        Self::open(Store::open_default()?)
+    }
+
+    /// Queue the schematic file `data` to be uploaded as `name`.
+    pub fn stage(&self, data: &[u8], name: &str) -> color_eyre::Result<Staged> {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            bail!("invalid schematic name {name:?}");
+        }
+
+        let hash = self.store.add_bytes(data)?;
+        // named so they sort in the order they were queued
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let file = self.dir.join(format!("{nanos:024}-{name}"));
+        fs::write(&file, &hash).wrap_err("write queue entry")?;
+        info!("queued {name} ({hash})");
+
+        Ok(Staged { file, name: name.to_string(), hash })
+    }
+
+    pub fn entries(&self) -> color_eyre::Result<Vec<Staged>> {
+        let mut res = Vec::new();
+        for entry in fs::read_dir(&self.dir).wrap_err("read queue")? {
+            let file = entry?.path();
+            let Some((_, name)) = file.file_name().and_then(|i| i.to_str()).and_then(|i| i.split_once('-')) else {
+                continue;
+            };
+
+            res.push(Staged {
+                name: name.to_string(),
+                hash: fs::read_to_string(&file)?.trim().to_string(),
+                file,
+            });
+        }
+
+        res.sort_by(|a, b| a.file.cmp(&b.file));
+        Ok(res)
+    }
+
+    /// Upload everything in the queue, if `schedule` allows it right now.
+    /// Returns what was uploaded.
+    pub fn upload(&self, server: &ServerConfig, schedule: &Schedule) -> color_eyre::Result<Vec<Staged>> {
+        let entries = self.entries()?;
+        if entries.is_empty() {
+            return Ok(entries);
+        }
+        if let Some(reason) = schedule.blocked(server)? {
+            info!("not uploading {} queued schematics: {reason}", entries.len());
+            return Ok(Vec::new());
+        }
+
+        for staged in &entries {
+            server.upload_schematic(self.store.path(&staged.hash)?, &staged.name)?;
+            fs::remove_file(&staged.file).wrap_err("remove queue entry")?;
+            info!("uploaded queued {}", staged.name);
+        }
+
+        Ok(entries)
+    }
+
+    /// Remove everything from the queue, without uploading it.
+    pub fn clear(&self) -> color_eyre::Result<usize> {
+        let entries = self.entries()?;
+        for staged in &entries {
+            fs::remove_file(&staged.file).wrap_err("remove queue entry")?;
+        }
+
+        Ok(entries.len())
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+}
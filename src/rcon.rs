@@ -0,0 +1,81 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use color_eyre::eyre::{bail, WrapErr};
+use crate::secrets::Secret;
+
+const TYPE_RESPONSE: i32 = 0;
+const TYPE_COMMAND: i32 = 2;
+const TYPE_LOGIN: i32 = 3;
+
+/// The largest payload the server accepts in a single command packet.
+pub const MAX_COMMAND_LENGTH: usize = 1446;
+
+/// A minimal client for Minecraft's RCON protocol.
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    pub fn connect(addr: impl ToSocketAddrs, password: &Secret) -> color_eyre::Result<Self> {
+        let stream = TcpStream::connect(addr).wrap_err("connect to rcon")?;
+        let mut res = Self { stream, next_id: 1 };
+
+        let id = res.send(TYPE_LOGIN, password.expose())?;
+        let (response_id, _, _) = res.receive()?;
+        if response_id == -1 || response_id != id {
+            bail!("rcon login failed, check the password");
+        }
+
+        Ok(res)
+    }
+
+    /// Run a command on the server and return its output.
+    pub fn command(&mut self, command: &str) -> color_eyre::Result<String> {
+        if command.len() > MAX_COMMAND_LENGTH {
+            bail!("rcon command too long ({} bytes, at most {MAX_COMMAND_LENGTH})", command.len());
+        }
+
+        let id = self.send(TYPE_COMMAND, command)?;
+        loop {
+            let (response_id, kind, body) = self.receive()?;
+            if response_id == id && kind == TYPE_RESPONSE {
+                return Ok(body);
+            }
+        }
+    }
+
+    fn send(&mut self, kind: i32, body: &str) -> color_eyre::Result<i32> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let length = (4 + 4 + body.len() + 2) as i32;
+        let mut packet = Vec::with_capacity(length as usize + 4);
+        packet.extend(length.to_le_bytes());
+        packet.extend(id.to_le_bytes());
+        packet.extend(kind.to_le_bytes());
+        packet.extend(body.as_bytes());
+        packet.extend([0, 0]);
+
+        self.stream.write_all(&packet).wrap_err("send rcon packet")?;
+        Ok(id)
+    }
+
+    fn receive(&mut self) -> color_eyre::Result<(i32, i32, String)> {
+        let mut header = [0; 4];
+        self.stream.read_exact(&mut header).wrap_err("receive rcon packet")?;
+        let length = i32::from_le_bytes(header);
+        if !(10..=4096 + 10).contains(&length) {
+            bail!("invalid rcon packet length {length}");
+        }
+
+        let mut packet = vec![0; length as usize];
+        self.stream.read_exact(&mut packet).wrap_err("receive rcon packet")?;
+
+        let id = i32::from_le_bytes(packet[0..4].try_into().unwrap());
+        let kind = i32::from_le_bytes(packet[4..8].try_into().unwrap());
+        let body = String::from_utf8_lossy(&packet[8..packet.len() - 2]).into_owned();
+
+        Ok((id, kind, body))
+    }
+}
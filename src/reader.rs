@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use color_eyre::eyre::{bail, WrapErr};
+use flate2::read::GzDecoder;
+use perpendicular::Vector3;
+use tracing::warn;
+use crate::schematic::{BlockState, DecodedPalette, Schematic};
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// The nbt of a schematic file, gzipped or not.
+fn open_nbt<'r>(reader: impl Read + 'r) -> color_eyre::Result<Box<dyn Read + 'r>> {
+    let mut reader = BufReader::new(reader);
+    let gzipped = reader.fill_buf().wrap_err("read schematic")?.starts_with(&[0x1f, 0x8b]);
+
+    Ok(if gzipped {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    })
+}
+
+fn read_bytes<const N: usize>(r: &mut impl Read) -> color_eyre::Result<[u8; N]> {
+    let mut res = [0; N];
+    r.read_exact(&mut res).wrap_err("read nbt")?;
+    Ok(res)
+}
+
+fn read_u8(r: &mut impl Read) -> color_eyre::Result<u8> {
+    Ok(read_bytes::<1>(r)?[0])
+}
+
+fn read_i16(r: &mut impl Read) -> color_eyre::Result<i16> {
+    Ok(i16::from_be_bytes(read_bytes(r)?))
+}
+
+fn read_i32(r: &mut impl Read) -> color_eyre::Result<i32> {
+    Ok(i32::from_be_bytes(read_bytes(r)?))
+}
+
+fn read_string(r: &mut impl Read) -> color_eyre::Result<String> {
+    let len = u16::from_be_bytes(read_bytes(r)?);
+    let mut res = vec![0; len as usize];
+    r.read_exact(&mut res).wrap_err("read nbt")?;
+
+    // java's modified utf-8 only differs for characters block ids don't use
+    Ok(String::from_utf8_lossy(&res).into_owned())
+}
+
+fn read_len(r: &mut impl Read) -> color_eyre::Result<u64> {
+    let len = read_i32(r)?;
+    if len < 0 {
+        bail!("negative length in nbt");
+    }
+    Ok(len as u64)
+}
+
+fn skip_bytes(r: &mut impl Read, len: u64) -> color_eyre::Result<()> {
+    let skipped = std::io::copy(&mut r.take(len), &mut std::io::sink()).wrap_err("read nbt")?;
+    if skipped != len {
+        bail!("nbt ends early");
+    }
+    Ok(())
+}
+
+/// Skip the payload of a tag of type `tag`.
+fn skip(r: &mut impl Read, tag: u8) -> color_eyre::Result<()> {
+    match tag {
+        TAG_END => Ok(()),
+        TAG_BYTE => skip_bytes(r, 1),
+        TAG_SHORT => skip_bytes(r, 2),
+        TAG_INT | TAG_FLOAT => skip_bytes(r, 4),
+        TAG_LONG | TAG_DOUBLE => skip_bytes(r, 8),
+        TAG_BYTE_ARRAY => {
+            let len = read_len(r)?;
+            skip_bytes(r, len)
+        }
+        TAG_STRING => {
+            let len = u16::from_be_bytes(read_bytes(r)?);
+            skip_bytes(r, len as u64)
+        }
+        TAG_LIST => {
+            let inner = read_u8(r)?;
+            for _ in 0..read_len(r)? {
+                skip(r, inner)?;
+            }
+            Ok(())
+        }
+        TAG_COMPOUND => {
+            loop {
+                let inner = read_u8(r)?;
+                if inner == TAG_END {
+                    return Ok(());
+                }
+                read_string(r)?;
+                skip(r, inner)?;
+            }
+        }
+        TAG_INT_ARRAY => {
+            let len = read_len(r)?;
+            skip_bytes(r, len * 4)
+        }
+        TAG_LONG_ARRAY => {
+            let len = read_len(r)?;
+            skip_bytes(r, len * 8)
+        }
+        _ => bail!("unknown nbt tag {tag}"),
+    }
+}
+
+/// Read a whole `Palette` compound.
+fn read_palette(r: &mut impl Read) -> color_eyre::Result<BTreeMap<String, i32>> {
+    let mut res = BTreeMap::new();
+    loop {
+        match read_u8(r)? {
+            TAG_END => return Ok(res),
+            TAG_INT => {
+                let name = read_string(r)?;
+                res.insert(name, read_i32(r)?);
+            }
+            tag => bail!("palette entry with nbt tag {tag} instead of an int"),
+        }
+    }
+}
+
+/// Whether a field holds the blocks: `BlockData` in version 2, `Data` in the
+/// `Blocks` compound in version 3.
+fn is_block_data(tag: u8, name: &str) -> bool {
+    tag == TAG_BYTE_ARRAY && matches!(name, "BlockData" | "Data")
+}
+
+/// Whether a field is a compound whose fields are read as if they were in the
+/// root: the `Schematic` and `Blocks` compounds of version 3.
+fn is_nested(tag: u8, name: &str) -> bool {
+    tag == TAG_COMPOUND && matches!(name, "Schematic" | "Blocks")
+}
+
+/// Go through the fields of the root compound until the block data, and
+/// return its length. Everything else is skipped.
+fn find_block_data(r: &mut impl Read) -> color_eyre::Result<u64> {
+    if read_u8(r)? != TAG_COMPOUND {
+        bail!("schematic doesn't start with a compound");
+    }
+    read_string(r)?;
+
+    loop {
+        let tag = read_u8(r)?;
+        if tag == TAG_END {
+            bail!("schematic without block data");
+        }
+        let name = read_string(r)?;
+        if is_block_data(tag, &name) {
+            return read_len(r);
+        }
+        if !is_nested(tag, &name) {
+            skip(r, tag)?;
+        }
+    }
+}
+
+/// Goes through the blocks of a schematic file one by one, without keeping
+/// more than the palette in memory, to scan files too large to load as a
+/// [`Schematic`]. Blocks come in the order they are stored in: by y, then z,
+/// then x, with positions like those of [`Schematic::from_file`].
+///
+/// Only the header has to be read before the first block. That needs the
+/// palette, which may come after the blocks in the file; a reader made with
+/// [`SchematicReader::open`] then goes through the file twice, but one made
+/// with [`SchematicReader::new`] has to keep the encoded blocks in memory
+/// (about one byte per block) until it finds the palette.
+pub struct SchematicReader<'r> {
+    blocks: Box<dyn Read + 'r>,
+    palette: DecodedPalette,
+    size: [usize; 3],
+    index: usize,
+}
+
+/// The fields of a schematic file needed to read its blocks.
+#[derive(Default)]
+struct Header {
+    width: Option<i16>,
+    height: Option<i16>,
+    length: Option<i16>,
+    palette: Option<BTreeMap<String, i32>>,
+}
+
+impl Header {
+    fn complete(&self) -> bool {
+        self.width.is_some() && self.height.is_some() && self.length.is_some() && self.palette.is_some()
+    }
+
+    /// Read one field of the root compound, if it's part of the header.
+    /// Returns false for fields that have to be handled by the caller.
+    fn read_field(&mut self, r: &mut impl Read, tag: u8, name: &str) -> color_eyre::Result<bool> {
+        match (tag, name) {
+            (TAG_SHORT, "Width") => self.width = Some(read_i16(r)?),
+            (TAG_SHORT, "Height") => self.height = Some(read_i16(r)?),
+            (TAG_SHORT, "Length") => self.length = Some(read_i16(r)?),
+            (TAG_COMPOUND, "Palette") => self.palette = Some(read_palette(r)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl<'r> SchematicReader<'r> {
+    /// Read a schematic file, going through it twice if the palette comes
+    /// after the blocks.
+    pub fn open(path: impl AsRef<Path>) -> color_eyre::Result<SchematicReader<'static>> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let open = || -> color_eyre::Result<Box<dyn Read>> {
+            open_nbt(File::open(&path).wrap_err("open file")?)
+        };
+
+        let (header, blocks) = SchematicReader::read_header(open()?, false)?;
+        let blocks = match blocks {
+            Some(blocks) => blocks,
+            None => {
+                let mut second = open()?;
+                let len = find_block_data(&mut second)?;
+                Box::new(second.take(len)) as Box<dyn Read>
+            }
+        };
+
+        SchematicReader::with_header(header, blocks)
+    }
+
+    /// Read a schematic from `reader` in one pass.
+    pub fn new(reader: impl Read + 'r) -> color_eyre::Result<Self> {
+        let (header, blocks) = Self::read_header(open_nbt(reader)?, true)?;
+        let Some(blocks) = blocks else {
+            bail!("schematic without block data");
+        };
+
+        Self::with_header(header, blocks)
+    }
+
+    /// Read the root compound up to where blocks can be read. Block data
+    /// that comes before the rest of the header is kept in memory if
+    /// `buffer` is true and skipped otherwise; after the header, block data
+    /// is read lazily from `r`.
+    fn read_header(mut r: Box<dyn Read + 'r>, buffer: bool) -> color_eyre::Result<(Header, Option<Box<dyn Read + 'r>>)> {
+        if read_u8(&mut r)? != TAG_COMPOUND {
+            bail!("schematic doesn't start with a compound");
+        }
+        read_string(&mut r)?;
+
+        let mut header = Header::default();
+        let mut buffered = None;
+        let mut depth = 0;
+        loop {
+            let tag = read_u8(&mut r)?;
+            // the end of a nested compound only ends the header if everything
+            // has been found by then
+            if tag == TAG_END && (header.complete() || depth == 0) {
+                if !header.complete() {
+                    bail!("schematic without a size or palette");
+                }
+                return Ok((header, buffered.map(|i: Vec<u8>| Box::new(Cursor::new(i)) as Box<dyn Read + 'r>)));
+            }
+            if tag == TAG_END {
+                depth -= 1;
+                continue;
+            }
+
+            let name = read_string(&mut r)?;
+            if header.read_field(&mut r, tag, &name)? {
+                continue;
+            }
+            if is_nested(tag, &name) {
+                depth += 1;
+                continue;
+            }
+            if !is_block_data(tag, &name) {
+                skip(&mut r, tag)?;
+                continue;
+            }
+
+            let len = read_len(&mut r)?;
+            if header.complete() {
+                return Ok((header, Some(Box::new(r.take(len)))));
+            }
+            if buffer {
+                let mut data = Vec::new();
+                (&mut r).take(len).read_to_end(&mut data).wrap_err("read block data")?;
+                buffered = Some(data);
+            } else {
+                skip_bytes(&mut r, len)?;
+            }
+        }
+    }
+
+    fn with_header(header: Header, blocks: Box<dyn Read + 'r>) -> color_eyre::Result<Self> {
+        // unsigned in version 3, and negative sizes are invalid in version 2
+        let size = [header.width, header.height, header.length].map(|i| i.unwrap_or(0) as u16 as usize);
+        let palette = Schematic::decode_palette(&header.palette.unwrap_or_default())?;
+
+        Ok(Self { blocks: Box::new(BufReader::new(blocks)), palette, size, index: 0 })
+    }
+
+    /// Width (x), height (y) and length (z).
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+
+    /// Every distinct block state in the schematic, by palette index.
+    pub fn palette(&self) -> impl Iterator<Item=&Rc<BlockState>> {
+        self.palette.iter()
+    }
+
+    fn next_varint(&mut self) -> color_eyre::Result<Option<usize>> {
+        let mut value = 0;
+        for length in 0..5 {
+            let mut byte = [0];
+            if self.blocks.read(&mut byte).wrap_err("read block data")? == 0 {
+                if length == 0 {
+                    return Ok(None);
+                }
+                bail!("data ends in the middle of a varint");
+            }
+
+            value |= ((byte[0] & 127) as usize) << (length * 7);
+            if byte[0] & 128 == 0 {
+                return Ok(Some(value));
+            }
+        }
+
+        bail!("varint length too big (data probably corrupted)")
+    }
+}
+
+impl Iterator for SchematicReader<'_> {
+    type Item = color_eyre::Result<(Vector3<i64>, Rc<BlockState>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [width, height, length] = self.size;
+        if self.index >= width * height * length {
+            return None;
+        }
+
+        let value = match self.next_varint() {
+            Ok(Some(value)) => value,
+            Ok(None) => return None,
+            Err(e) => {
+                // don't keep returning the same error
+                self.index = usize::MAX;
+                return Some(Err(e));
+            }
+        };
+
+        let index = self.index;
+        self.index += 1;
+        let pos = Vector3::new3(
+            (index % width) as i64,
+            (index / (width * length)) as i64,
+            (index % (width * length) / width) as i64,
+        );
+
+        let state = match self.palette.get(value) {
+            Some(state) => state.clone(),
+            _ => {
+                warn!("invalid palette index {value}, using air");
+                BlockState::air()
+            }
+        };
+        Some(Ok((pos, state)))
+    }
+}
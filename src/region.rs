@@ -0,0 +1,129 @@
+use std::rc::Rc;
+use perpendicular::Vector3;
+use crate::pos::BlockPos;
+use crate::schematic::{BlockState, Schematic};
+
+/// A cuboid part of a schematic, addressed in its own coordinates: from 0 up
+/// to the size of the region on every axis, whatever position the region
+/// has in the schematic. Made with [`Schematic::region_mut`].
+pub struct RegionView<'a> {
+    schematic: &'a mut Schematic,
+    /// The lowest corner of the region in the schematic.
+    origin: Vector3<i64>,
+    size: [i64; 3],
+}
+
+impl<'a> RegionView<'a> {
+    pub(crate) fn new(schematic: &'a mut Schematic, min: Vector3<i64>, max: Vector3<i64>) -> Self {
+        let size = [
+            (max.x() - min.x() + 1).max(0),
+            (max.y() - min.y() + 1).max(0),
+            (max.z() - min.z() + 1).max(0),
+        ];
+
+        Self { schematic, origin: min, size }
+    }
+
+    pub fn len_x(&self) -> usize {
+        self.size[0] as usize
+    }
+    pub fn len_y(&self) -> usize {
+        self.size[1] as usize
+    }
+    pub fn len_z(&self) -> usize {
+        self.size[2] as usize
+    }
+
+    /// The lowest corner of the region, in the coordinates of the schematic.
+    pub fn origin(&self) -> &Vector3<i64> {
+        &self.origin
+    }
+
+    pub fn contains(&self, local: &Vector3<i64>) -> bool {
+        [*local.x(), *local.y(), *local.z()].into_iter()
+            .zip(self.size)
+            .all(|(pos, len)| (0..len).contains(&pos))
+    }
+
+    /// The position in the schematic of `local`.
+    pub fn to_global(&self, local: &Vector3<i64>) -> Vector3<i64> {
+        Vector3::new3(
+            self.origin.x() + local.x(),
+            self.origin.y() + local.y(),
+            self.origin.z() + local.z(),
+        )
+    }
+
+    /// The position in the region of `global`, if it's inside the region.
+    pub fn to_local(&self, global: &Vector3<i64>) -> Option<Vector3<i64>> {
+        let local = Vector3::new3(
+            global.x() - self.origin.x(),
+            global.y() - self.origin.y(),
+            global.z() - self.origin.z(),
+        );
+
+        self.contains(&local).then_some(local)
+    }
+
+    /// The block at `local`, or `None` outside the region or where the
+    /// schematic has no block.
+    pub fn get(&self, local: impl Into<BlockPos>) -> Option<Rc<BlockState>> {
+        let local = local.into().vector();
+        if !self.contains(&local) {
+            return None;
+        }
+
+        self.schematic.get_block(self.to_global(&local))
+    }
+
+    /// Place `state` at `local`. Returns whether `local` is inside the
+    /// region; outside of it nothing is placed.
+    pub fn set(&mut self, local: impl Into<BlockPos>, state: Rc<BlockState>) -> bool {
+        let local = local.into().vector();
+        if !self.contains(&local) {
+            return false;
+        }
+
+        let global = self.to_global(&local);
+        self.schematic.set_block(global, state);
+        true
+    }
+
+    /// Set every position between `min` and `max`, both inclusive, to
+    /// `state`. The part of the cuboid outside the region is left alone.
+    pub fn fill(&mut self, min: Vector3<i64>, max: Vector3<i64>, state: Rc<BlockState>) {
+        let clamp = |min: i64, max: i64, len: i64| (min.max(0), max.min(len - 1));
+        let (min_x, max_x) = clamp(*min.x(), *max.x(), self.size[0]);
+        let (min_y, max_y) = clamp(*min.y(), *max.y(), self.size[1]);
+        let (min_z, max_z) = clamp(*min.z(), *max.z(), self.size[2]);
+        if min_x > max_x || min_y > max_y || min_z > max_z {
+            return;
+        }
+
+        self.schematic.fill(
+            self.to_global(&Vector3::new3(min_x, min_y, min_z)),
+            self.to_global(&Vector3::new3(max_x, max_y, max_z)),
+            state,
+        );
+    }
+
+    /// Every position in the region, in local coordinates, with the block
+    /// there. See [`Schematic::iter_region`].
+    pub fn iter(&self) -> impl Iterator<Item=(Vector3<i64>, Option<&Rc<BlockState>>)> + '_ {
+        let max = self.to_global(&Vector3::new3(self.size[0] - 1, self.size[1] - 1, self.size[2] - 1));
+
+        self.schematic.iter_region(self.origin.clone(), max)
+            .map(|(pos, block)| {
+                let local = Vector3::new3(
+                    pos.x() - self.origin.x(),
+                    pos.y() - self.origin.y(),
+                    pos.z() - self.origin.z(),
+                );
+                (local, block)
+            })
+    }
+
+    pub fn schematic(&self) -> &Schematic {
+        self.schematic
+    }
+}
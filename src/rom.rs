@@ -1,22 +1,42 @@
 use perpendicular::{Vector2, Vector3};
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
+use color_eyre::eyre::bail;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use crate::decoder::{address_bits, Decoder};
+use crate::mask::Mask;
+use schematics_cpu::program::Program;
 use crate::schematic::{BlockState, Schematic};
+use crate::search::BlockQuery;
+use crate::tags::TagRegistry;
+use crate::transform::Transform;
 
+#[deprecated(note = "use `Schematic::find_blocks(\"soul_wall_torch\")`")]
 pub fn find_soul_torches(schematic: &Schematic) -> Vec<Vector3<i64>> {
-    let mut res = Vec::new();
+    schematic.find_blocks("soul_wall_torch")
+}
 
-    for (loc, blk) in schematic.blocks() {
-        if blk.id() == "minecraft:soul_wall_torch" {
-            res.push(loc.clone());
-        }
-    }
+/// Find the soul torches that also match `region`, e.g. only the torches
+/// facing one way to program a single bank of a double-sided rom.
+#[deprecated(note = "use `Schematic::find_blocks` with a `BlockQuery` filtered on the region")]
+pub fn find_soul_torches_in(schematic: &Schematic, region: &Mask, tags: &TagRegistry) -> Vec<Vector3<i64>> {
+    schematic.find_blocks(soul_torches_in(region, tags))
+}
 
-    res
+fn soul_torches_in<'a>(region: &'a Mask, tags: &'a TagRegistry) -> BlockQuery<'a> {
+    BlockQuery::id("soul_wall_torch").filter(move |blk| region.matches_state(blk, tags))
 }
 
 pub fn find_program_lines(schematic: &Schematic) -> HashMap<Vector2<i64>, Vec<Vector3<i64>>> {
-    let torch_locations = find_soul_torches(schematic);
+    find_program_lines_in(schematic, &Mask::Existing, &TagRegistry::default())
+}
+
+pub fn find_program_lines_in(schematic: &Schematic, region: &Mask, tags: &TagRegistry) -> HashMap<Vector2<i64>, Vec<Vector3<i64>>> {
+    group_lines(schematic.find_blocks(soul_torches_in(region, tags)))
+}
+
+fn group_lines(torch_locations: Vec<Vector3<i64>>) -> HashMap<Vector2<i64>, Vec<Vector3<i64>>> {
     let mut lines = HashMap::new();
 
     for i in torch_locations {
@@ -31,51 +51,164 @@ pub fn find_program_lines(schematic: &Schematic) -> HashMap<Vector2<i64>, Vec<Ve
     lines
 }
 
-pub fn order_lines(mut lines: HashMap<Vector2<i64>, Vec<Vector3<i64>>>) -> Vec<Vec<Vector3<i64>>> {
-    let mut ordered_lines = vec![Vec::new(); 128];
-    for i in 0..8 {
+/// How the bits of a rom are arranged: `groups` staircases of
+/// `lines_per_group` lines each, every line holding one word of `bits` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomLayout {
+    pub groups: usize,
+    pub lines_per_group: usize,
+    pub bits: usize,
+}
+
+impl Default for RomLayout {
+    fn default() -> Self {
+        Self {
+            groups: 8,
+            lines_per_group: 16,
+            bits: 16,
+        }
+    }
+}
+
+impl RomLayout {
+    pub fn words(&self) -> usize {
+        self.groups * self.lines_per_group
+    }
+
+    /// Work out the layout from the lines found in a rom. Every line in a group
+    /// is at a different z, so the number of different z coordinates is the
+    /// number of lines in a group.
+    pub fn detect(lines: &HashMap<Vector2<i64>, Vec<Vector3<i64>>>) -> color_eyre::Result<Self> {
+        let Some(bits) = lines.values().next().map(Vec::len) else {
+            bail!("no lines of bits found");
+        };
+        if let Some(line) = lines.values().find(|i| i.len() != bits) {
+            bail!("lines have different numbers of bits ({bits} and {})", line.len());
+        }
+
+        let lines_per_group = lines.keys().map(|i| i[1]).collect::<HashSet<_>>().len();
+        if lines.len() % lines_per_group != 0 {
+            bail!("{} lines can't be split into groups of {lines_per_group}", lines.len());
+        }
+
+        Ok(Self {
+            groups: lines.len() / lines_per_group,
+            lines_per_group,
+            bits,
+        })
+    }
+}
+
+pub fn order_lines(lines: HashMap<Vector2<i64>, Vec<Vector3<i64>>>) -> color_eyre::Result<Vec<Vec<Vector3<i64>>>> {
+    order_lines_with(lines, &RomLayout::default())
+}
+
+/// Order the lines by word, failing if they aren't arranged like `layout`.
+pub fn order_lines_with(mut lines: HashMap<Vector2<i64>, Vec<Vector3<i64>>>, layout: &RomLayout) -> color_eyre::Result<Vec<Vec<Vector3<i64>>>> {
+    let per_group = layout.lines_per_group;
+    let mut ordered_lines = vec![Vec::new(); layout.words()];
+    for i in 0..layout.groups {
         // find the lowest line left
-        let (id, bits) = lines
+        let Some((id, bits)) = lines
             .iter()
-            .min_by_key(|(k, _)| k[0])
-            .unwrap();
+            .min_by_key(|(k, _)| k[0]) else {
+            bail!("no lines left for group {i} of {}", layout.groups);
+        };
 
         // save its z
         let mut last_z = id[1];
 
-        ordered_lines[i * 16 + 0] = bits.clone();
+        ordered_lines[i * per_group + 0] = bits.clone();
 
         // remove it
         lines.remove(&id.clone());
 
-        for j in 1..16 {
+        for j in 1..per_group {
             // find the smallest-y line
             // whose z is bigger than the last
-            let (id, bits) = lines.iter()
+            let Some((id, bits)) = lines.iter()
                 .filter(|(k, _)| k[1] > last_z)
-                .min_by_key(|(k, _)| k[0])
-                .unwrap();
+                .min_by_key(|(k, _)| k[0]) else {
+                bail!("group {i} has no line {j} past z {last_z}");
+            };
 
             last_z = id[1];
-            ordered_lines[i * 16 + j] = bits.clone();
+            ordered_lines[i * per_group + j] = bits.clone();
 
             // remove it too
             lines.remove(&id.clone());
         }
     }
 
-    ordered_lines
+    Ok(ordered_lines)
+}
+
+/// The result of [`program_rom`].
+pub enum Programmed {
+    /// Reading back the rom gave exactly the program already, so the
+    /// schematic is returned as it was and doesn't have to be uploaded again.
+    Unchanged(Schematic),
+    Changed(Schematic),
 }
 
-pub fn program_rom(mut schematic: Schematic, program: Vec<u16>) -> Schematic {
-    let mut lines = find_program_lines(&schematic);
+impl Programmed {
+    pub fn is_unchanged(&self) -> bool {
+        matches!(self, Programmed::Unchanged(_))
+    }
+
+    pub fn into_schematic(self) -> Schematic {
+        match self {
+            Programmed::Unchanged(schematic) | Programmed::Changed(schematic) => schematic,
+        }
+    }
+}
+
+/// Assemble `program` at address 0 and write it into the rom, unless the rom
+/// already holds it (see [`read_rom`]).
+pub fn program_rom(mut schematic: Schematic, program: &Program) -> color_eyre::Result<Programmed> {
+    let words = program.assemble(0)?;
+    // a rom that can't be read back is programmed as usual, which reports
+    // what's wrong with it
+    if matches!(preview_program(&schematic, &words), Ok(preview) if preview.is_empty()) {
+        debug!("rom already holds the program");
+        schematic.record_program(&words);
+        return Ok(Programmed::Unchanged(schematic));
+    }
+
+    program_rom_in(schematic, program, &Mask::Existing, &TagRegistry::default()).map(Programmed::Changed)
+}
+
+/// Like [`program_rom`], but only the torches matching `region` make up the
+/// rom. Always programs, since only the whole rom can be read back.
+pub fn program_rom_in(schematic: Schematic, program: &Program, region: &Mask, tags: &TagRegistry) -> color_eyre::Result<Schematic> {
+    let words = program.assemble(0)?;
+    debug!("programming rom with\n{}", program.listing(0)?);
+
+    program_rom_words_in(schematic, words, region, tags)
+}
+
+/// Write raw words into the rom, whether they're instructions or not. All
+/// other blocks become air, and a [keep mask](Schematic::keep_mask) on the
+/// torches keeps that air from being pasted.
+pub fn program_rom_words(schematic: Schematic, program: Vec<u16>) -> color_eyre::Result<Schematic> {
+    program_rom_words_in(schematic, program, &Mask::Existing, &TagRegistry::default())
+}
+
+pub fn program_rom_words_in(mut schematic: Schematic, program: Vec<u16>, region: &Mask, tags: &TagRegistry) -> color_eyre::Result<Schematic> {
+    let lines = find_program_lines_in(&schematic, region, tags);
     // check if we have all bits
-    assert_eq!(lines.len(), 128);
-    for i in lines.values() {
-        assert_eq!(i.len(), 16, "{:?}", i);
+    let layout = RomLayout::detect(&lines)?;
+    if layout.bits != 16 {
+        bail!("expected 16 bits per line, found {}", layout.bits);
+    }
+    if program.len() > layout.words() {
+        bail!("program of {} words doesn't fit in {} lines", program.len(), layout.words());
     }
 
-    let ordered_lines = order_lines(lines);
+    let ordered_lines = order_lines_with(lines, &layout)?;
+    schematic.record_program(&program);
+    // everything but the torches is cleared below, and mustn't be pasted
+    schematic.set_keep_mask(ordered_lines.iter().flatten().cloned());
 
     let mut set_bits = HashSet::new();
 
@@ -87,16 +220,274 @@ pub fn program_rom(mut schematic: Schematic, program: Vec<u16>) -> Schematic {
         }
     }
 
-    for (pos, blk) in schematic.blocks_mut() {
-        if blk.id() == "minecraft:soul_wall_torch" {
-            if set_bits.contains(pos) {
-                let mut redstone_torch = Rc::new(blk.same_props_new_id("minecraft:redstone_wall_torch"));
-                *blk = redstone_torch;
-            }
+    schematic.replace_where(|pos, blk| {
+        if blk.id() != "minecraft:soul_wall_torch" {
+            Some(BlockState::air())
+        } else if set_bits.contains(pos) {
+            Some(BlockState::intern(blk.same_props_new_id("minecraft:redstone_wall_torch")))
         } else {
-            *blk = BlockState::air();
+            None
+        }
+    });
+
+    Ok(schematic)
+}
+
+/// Read the words stored in a programmed rom: every soul torch is a 0 bit and
+/// every redstone torch a 1 bit.
+pub fn read_rom(schematic: &Schematic) -> color_eyre::Result<Vec<u16>> {
+    let mut bits = Vec::new();
+    let mut set_bits = HashSet::new();
+
+    for (pos, blk) in schematic.blocks() {
+        match blk.id() {
+            "minecraft:soul_wall_torch" => bits.push(pos.clone()),
+            "minecraft:redstone_wall_torch" => {
+                bits.push(pos.clone());
+                set_bits.insert(pos.clone());
+            }
+            _ => {}
+        }
+    }
+
+    let lines = group_lines(bits);
+    let layout = RomLayout::detect(&lines)?;
+    if layout.bits != 16 {
+        bail!("expected 16 bits per line, found {}", layout.bits);
+    }
+
+    Ok(order_lines_with(lines, &layout)?
+        .into_iter()
+        .map(|bits| bits.iter()
+            .enumerate()
+            .filter(|(_, bit)| set_bits.contains(*bit))
+            .fold(0, |word, (idx, _)| word | 1 << idx))
+        .collect())
+}
+
+/// A word of the rom that programming changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WordChange {
+    pub address: usize,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// What programming a rom would change, compared to what it holds now, to
+/// check before re-flashing that only the expected words change.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramPreview {
+    /// Torches that become redstone torches.
+    pub torches_on: usize,
+    /// Redstone torches that become soul torches.
+    pub torches_off: usize,
+    pub changes: Vec<WordChange>,
+}
+
+impl ProgramPreview {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Like `3 words change, 5 torches on, 2 off`, followed by one line per word.
+impl std::fmt::Display for ProgramPreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} words change, {} torches on, {} off",
+            self.changes.len(), self.torches_on, self.torches_off,
+        )?;
+        for WordChange { address, old, new } in &self.changes {
+            writeln!(f, "    {address:04x}: {old:04x} -> {new:04x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare `program` against the words the rom holds now (see [`read_rom`]),
+/// without changing anything. Words past the end of `program` are cleared
+/// by programming, so they count as changing to 0.
+pub fn preview_program(schematic: &Schematic, program: &[u16]) -> color_eyre::Result<ProgramPreview> {
+    let current = read_rom(schematic)?;
+    if program.len() > current.len() {
+        bail!("program of {} words doesn't fit in {} lines", program.len(), current.len());
+    }
+
+    let mut res = ProgramPreview::default();
+    for (address, old) in current.into_iter().enumerate() {
+        let new = program.get(address).copied().unwrap_or(0);
+        if old == new {
+            continue;
+        }
+
+        res.torches_on += (new & !old).count_ones() as usize;
+        res.torches_off += (old & !new).count_ones() as usize;
+        res.changes.push(WordChange { address, old, new });
+    }
+
+    Ok(res)
+}
+
+/// Moves a position by `times` group strides.
+pub(crate) fn stride_transform(stride: [i64; 3], times: i64) -> Transform {
+    Transform::translate(stride.map(|i| i * times))
+}
+
+pub(crate) fn position(pos: &Vector3<i64>) -> [i64; 3] {
+    [*pos.x(), *pos.y(), *pos.z()]
+}
+
+/// The offset between the first lines of consecutive groups, which has to be
+/// the same for all groups, and along a single axis.
+pub fn group_stride(ordered_lines: &[Vec<Vector3<i64>>], layout: &RomLayout) -> color_eyre::Result<[i64; 3]> {
+    if layout.groups < 2 {
+        bail!("need at least two groups of lines to see how they repeat");
+    }
+
+    let starts: Vec<_> = (0..layout.groups)
+        .map(|i| position(&ordered_lines[i * layout.lines_per_group][0]))
+        .collect();
+    let stride = [0, 1, 2].map(|axis| starts[1][axis] - starts[0][axis]);
+
+    for (idx, pair) in starts.windows(2).enumerate() {
+        let step = [0, 1, 2].map(|axis| pair[1][axis] - pair[0][axis]);
+        if step != stride {
+            bail!("group {} is offset by {step:?} from the one before, but group 1 by {stride:?}", idx + 1);
         }
     }
 
-    schematic
+    if stride.iter().filter(|i| **i != 0).count() != 1 {
+        bail!("groups repeat diagonally ({stride:?}), which can't be extended");
+    }
+
+    Ok(stride)
+}
+
+/// All blocks in the slab of group `group`: from its first line up to where
+/// the next group starts. Positions are moved back by `group` strides, so the
+/// slabs of different groups can be compared.
+pub fn group_slab(
+    schematic: &Schematic,
+    ordered_lines: &[Vec<Vector3<i64>>],
+    layout: &RomLayout,
+    stride: [i64; 3],
+    group: usize,
+) -> Vec<(Vector3<i64>, Rc<BlockState>)> {
+    let axis = stride.iter().position(|i| *i != 0).expect("stride along one axis");
+    let start = position(&ordered_lines[group * layout.lines_per_group][0])[axis];
+    let (low, high) = if stride[axis] > 0 {
+        (start, start + stride[axis])
+    } else {
+        (start + stride[axis] + 1, start + 1)
+    };
+
+    schematic.blocks()
+        .filter(|(pos, _)| (low..high).contains(&position(pos)[axis]))
+        .map(|(pos, blk)| (stride_transform(stride, -(group as i64)).apply_vector(&pos), blk.clone()))
+        .collect()
+}
+
+/// Add `extra_words` words to a rom template, by copying the last group of
+/// lines, with everything around it, as often as needed. See [`group_slab`]
+/// for what is copied. All bits in the new groups are cleared, and the address
+/// decoder is continued for them and for any new address bits, see
+/// [`Decoder`]. `extra_words` has to be a multiple of the number of lines in a
+/// group.
+pub fn extend(mut schematic: Schematic, layout: &RomLayout, extra_words: usize) -> color_eyre::Result<(Schematic, RomLayout)> {
+    if extra_words % layout.lines_per_group != 0 {
+        bail!("can only add whole groups of {} words, not {extra_words}", layout.lines_per_group);
+    }
+    let extra_groups = extra_words / layout.lines_per_group;
+
+    let ordered_lines = order_lines_with(find_program_lines(&schematic), layout)?;
+    let stride = group_stride(&ordered_lines, layout)?;
+    let slab = group_slab(&schematic, &ordered_lines, layout, stride, layout.groups - 1);
+    let mut decoder = Decoder::detect(&schematic, &ordered_lines, layout, stride)?;
+
+    let extended = RomLayout {
+        groups: layout.groups + extra_groups,
+        ..*layout
+    };
+    if decoder.is_empty() {
+        warn!("no address decoder found, all groups look the same");
+    } else {
+        decoder.extrapolate(address_bits(extended.groups))?;
+    }
+
+    for group in layout.groups..extended.groups {
+        for (pos, blk) in &slab {
+            let blk = if blk.id() == "minecraft:redstone_wall_torch" {
+                BlockState::intern(blk.same_props_new_id("minecraft:soul_wall_torch"))
+            } else {
+                blk.clone()
+            };
+            schematic.set_block(stride_transform(stride, group as i64).apply_vector(pos), blk);
+        }
+    }
+
+    // the old groups need the blocks of new address bits as well, or they'd
+    // be selected together with the new ones
+    for group in 0..extended.groups {
+        decoder.place(&mut schematic, group, stride);
+    }
+
+    let found = RomLayout::detect(&find_program_lines(&schematic))?;
+    if found != extended {
+        bail!("extended rom should have layout {extended:?}, but has {found:?}");
+    }
+
+    Ok((schematic, extended))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schematic::{BlockState, Schematic};
+    use super::{extend, program_rom_words, read_rom, RomLayout};
+
+    #[test]
+    fn fails_on_lines_that_cannot_be_ordered() {
+        // two lines of a single group, where the higher line is at the lower z
+        let torch = BlockState::new("minecraft:soul_wall_torch");
+        let schematic = Schematic::from_blocks((0..16)
+            .flat_map(|x| [[x, 0, 1], [x, 1, 0]])
+            .map(|pos| (pos, torch.clone())));
+
+        assert!(read_rom(&schematic).is_err());
+    }
+
+    #[test]
+    fn fails_on_programs_that_do_not_fit() {
+        assert!(program_rom_words(Schematic::from_blocks([]), vec![1]).is_err());
+
+        // a single line, holding one word
+        let torch = BlockState::new("minecraft:soul_wall_torch");
+        let schematic = Schematic::from_blocks((0..16).map(|x| ([x, 0, 0], torch.clone())));
+        assert!(program_rom_words(schematic.clone(), vec![1, 2]).is_err());
+        assert_eq!(read_rom(&program_rom_words(schematic, vec![0x1234]).unwrap()).unwrap(), [0x1234]);
+    }
+
+    /// Two groups of sixteen lines, the second one 20 blocks above the first.
+    fn two_group_rom() -> Schematic {
+        let torch = BlockState::new("minecraft:soul_wall_torch");
+        Schematic::from_blocks((0..2)
+            .flat_map(|group| (0..16).map(move |line| (group * 20 + line, line)))
+            .flat_map(|(y, z)| (0..16).map(move |x| [x, y, z]))
+            .map(|pos| (pos, torch.clone())))
+    }
+
+    #[test]
+    fn extends_roms_by_whole_groups() {
+        let layout = RomLayout { groups: 2, lines_per_group: 16, bits: 16 };
+        assert!(extend(two_group_rom(), &layout, 8).is_err());
+
+        let (extended, extended_layout) = extend(two_group_rom(), &layout, 32).unwrap();
+        assert_eq!(extended_layout, RomLayout { groups: 4, ..layout });
+        assert_eq!(read_rom(&extended).unwrap(), [0; 64]);
+
+        let program: Vec<u16> = (0..64).map(|i| i * 0x0301 + 1).collect();
+        let programmed = program_rom_words(extended, program.clone()).unwrap();
+        assert_eq!(read_rom(&programmed).unwrap(), program);
+    }
 }
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use color_eyre::eyre::WrapErr;
+use serde::Deserialize;
+use crate::schematic::BlockState;
+use crate::tags::qualify;
+use crate::transform::Transform;
+
+const BUILTIN_ROTATIONS: &str = include_str!("../data/rotations.json");
+
+/// How a block state property changes when the block is turned or mirrored.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PropertyRule {
+    /// The value is a direction, like `facing=north`.
+    Direction,
+    /// The value is an axis, like `axis=x`.
+    Axis,
+    /// The property is named after a direction, like `north=side` on wire.
+    Side,
+    /// A sign's or banner's `rotation`, in sixteenths of a turn.
+    Rotation16,
+    /// Stair `shape`: left and right swap when mirrored.
+    StairShape,
+    /// Rail `shape`, named after the directions the rail connects.
+    RailShape,
+    /// Chest `type`: left and right swap when mirrored.
+    ChestType,
+    /// Door `hinge`: left and right swap when mirrored.
+    Hinge,
+    /// Stays the same, to override a rule for a specific block.
+    Fixed,
+}
+
+/// Which rule applies to which property. `properties` applies to all blocks,
+/// and `blocks` overrides it for specific ids, or for all ids ending in a
+/// suffix when the key starts with `*`. The builtin rules cover vanilla blocks;
+/// rules for modded blocks can be added with [`RotationRules::extend`].
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct RotationRules {
+    #[serde(default)]
+    properties: HashMap<String, PropertyRule>,
+    #[serde(default)]
+    blocks: HashMap<String, HashMap<String, PropertyRule>>,
+}
+
+impl RotationRules {
+    pub fn builtin() -> &'static Self {
+        static BUILTIN: OnceLock<RotationRules> = OnceLock::new();
+        BUILTIN.get_or_init(|| Self::from_json(BUILTIN_ROTATIONS).expect("builtin rotation rules are valid"))
+    }
+
+    pub fn from_json(data: &str) -> color_eyre::Result<Self> {
+        let mut rules: Self = serde_json::from_str(data).wrap_err("parse rotation rules")?;
+        rules.blocks = rules.blocks.into_iter()
+            .map(|(k, v)| if k.starts_with('*') { (k, v) } else { (qualify(&k), v) })
+            .collect();
+
+        Ok(rules)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        Self::from_json(&fs::read_to_string(path).wrap_err("read rotation rules")?)
+    }
+
+    /// Add the rules of `other`, which win where both have a rule.
+    pub fn extend(&mut self, other: RotationRules) {
+        self.properties.extend(other.properties);
+        for (block, rules) in other.blocks {
+            self.blocks.entry(block).or_default().extend(rules);
+        }
+    }
+
+    pub fn rule(&self, id: &str, property: &str) -> Option<PropertyRule> {
+        let exact = self.blocks.get(id).and_then(|i| i.get(property));
+        let suffix = || self.blocks.iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix('*')?, v)))
+            .filter(|(suffix, _)| id.ends_with(suffix))
+            .find_map(|(_, v)| v.get(property));
+
+        exact.or_else(suffix)
+            .or_else(|| self.properties.get(property))
+            .copied()
+    }
+
+    /// The state `state` becomes after being moved by `transform`.
+    pub fn apply(&self, transform: &Transform, state: &BlockState) -> Rc<BlockState> {
+        state.props().iter()
+            .fold(BlockState::builder(state.id()), |builder, (key, value)| {
+                let rule = self.rule(state.id(), key).unwrap_or(PropertyRule::Fixed);
+                let (key, value) = apply_rule(rule, transform, key, value);
+                builder.prop(key, value)
+            })
+            .build()
+    }
+}
+
+fn swap_left_right(value: &str) -> String {
+    if value.contains("left") {
+        value.replace("left", "right")
+    } else {
+        value.replace("right", "left")
+    }
+}
+
+/// Sixteenths of a turn clockwise from south, for the four horizontal directions.
+const ROTATION16: [(&str, i64); 4] = [("south", 0), ("west", 4), ("north", 8), ("east", 12)];
+
+fn rotation16_of(direction: &str) -> Option<i64> {
+    ROTATION16.iter().find(|(name, _)| *name == direction).map(|(_, i)| *i)
+}
+
+fn apply_rule(rule: PropertyRule, transform: &Transform, key: &str, value: &str) -> (String, String) {
+    let unchanged = || (key.to_string(), value.to_string());
+
+    match rule {
+        PropertyRule::Fixed => unchanged(),
+        PropertyRule::Direction => match transform.apply_direction(value) {
+            Some(direction) => (key.to_string(), direction.to_string()),
+            None => unchanged(),
+        },
+        PropertyRule::Side => match transform.apply_direction(key) {
+            Some(side) => (side.to_string(), value.to_string()),
+            None => unchanged(),
+        },
+        PropertyRule::Axis => {
+            let unit = match value {
+                "x" => [1, 0, 0],
+                "y" => [0, 1, 0],
+                "z" => [0, 0, 1],
+                _ => return unchanged(),
+            };
+            let moved = transform.apply_offset(unit).map(i64::abs);
+            let axis = ["x", "y", "z"][moved.iter().position(|i| *i == 1).unwrap_or(2)];
+            (key.to_string(), axis.to_string())
+        }
+        PropertyRule::Rotation16 => {
+            let (Ok(rotation), Some(south), Some(west)) = (
+                value.parse::<i64>(),
+                transform.apply_direction("south").and_then(rotation16_of),
+                transform.apply_direction("west").and_then(rotation16_of),
+            ) else {
+                return unchanged();
+            };
+
+            // a turn keeps west a quarter turn clockwise of south, a mirror
+            // puts it a quarter turn counterclockwise
+            let rotation = if (west - south).rem_euclid(16) == 4 {
+                rotation + south
+            } else {
+                south - rotation
+            };
+            (key.to_string(), rotation.rem_euclid(16).to_string())
+        }
+        PropertyRule::StairShape | PropertyRule::ChestType | PropertyRule::Hinge => {
+            if transform.is_mirrored() {
+                (key.to_string(), swap_left_right(value))
+            } else {
+                unchanged()
+            }
+        }
+        PropertyRule::RailShape => {
+            let moved = |direction: &str| transform.apply_direction(direction).unwrap_or("north");
+
+            let shape = if let Some(direction) = value.strip_prefix("ascending_") {
+                format!("ascending_{}", moved(direction))
+            } else if let Some((a, b)) = value.split_once('_') {
+                let (a, b) = (moved(a), moved(b));
+                // rails are named north/south first
+                if matches!(b, "north" | "south") {
+                    format!("{b}_{a}")
+                } else {
+                    format!("{a}_{b}")
+                }
+            } else {
+                return unchanged();
+            };
+
+            (key.to_string(), shape)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schematic::{Axis, BlockState};
+    use crate::transform::Transform;
+    use super::{PropertyRule, RotationRules};
+
+    fn moved(transform: &Transform, state: &str) -> String {
+        RotationRules::builtin().apply(transform, &state.parse::<BlockState>().unwrap()).to_string()
+    }
+
+    #[test]
+    fn picks_the_rule_for_each_property() {
+        let rules = RotationRules::builtin();
+        assert_eq!(rules.rule("minecraft:oak_stairs", "shape"), Some(PropertyRule::StairShape));
+        assert_eq!(rules.rule("minecraft:rail", "shape"), Some(PropertyRule::RailShape));
+        assert_eq!(rules.rule("minecraft:chest", "type"), Some(PropertyRule::ChestType));
+        assert_eq!(rules.rule("minecraft:stone_slab", "type"), None);
+        assert_eq!(rules.rule("minecraft:redstone_wire", "north"), Some(PropertyRule::Side));
+    }
+
+    #[test]
+    fn turns_properties() {
+        let turn = Transform::rotate_y(1);
+        assert_eq!(moved(&turn, "minecraft:soul_wall_torch[facing=north]"), "minecraft:soul_wall_torch[facing=east]");
+        assert_eq!(moved(&turn, "minecraft:oak_log[axis=x]"), "minecraft:oak_log[axis=z]");
+        assert_eq!(moved(&turn, "minecraft:oak_log[axis=y]"), "minecraft:oak_log[axis=y]");
+        assert_eq!(moved(&turn, "minecraft:redstone_wire[north=side,west=none]"), "minecraft:redstone_wire[east=side,north=none]");
+        assert_eq!(moved(&turn, "minecraft:oak_sign[rotation=0]"), "minecraft:oak_sign[rotation=4]");
+        assert_eq!(moved(&turn, "minecraft:rail[shape=north_east]"), "minecraft:rail[shape=south_east]");
+        assert_eq!(moved(&turn, "minecraft:rail[shape=ascending_north]"), "minecraft:rail[shape=ascending_east]");
+        assert_eq!(moved(&turn, "minecraft:oak_stairs[facing=south,shape=inner_left]"), "minecraft:oak_stairs[facing=west,shape=inner_left]");
+    }
+
+    #[test]
+    fn mirrors_properties() {
+        let mirror = Transform::mirror(Axis::X);
+        assert_eq!(moved(&mirror, "minecraft:soul_wall_torch[facing=east]"), "minecraft:soul_wall_torch[facing=west]");
+        assert_eq!(moved(&mirror, "minecraft:soul_wall_torch[facing=north]"), "minecraft:soul_wall_torch[facing=north]");
+        assert_eq!(moved(&mirror, "minecraft:oak_sign[rotation=4]"), "minecraft:oak_sign[rotation=12]");
+        assert_eq!(moved(&mirror, "minecraft:oak_stairs[facing=east,shape=inner_left]"), "minecraft:oak_stairs[facing=west,shape=inner_right]");
+        assert_eq!(moved(&mirror, "minecraft:oak_door[hinge=left]"), "minecraft:oak_door[hinge=right]");
+        assert_eq!(moved(&mirror, "minecraft:chest[type=right]"), "minecraft:chest[type=left]");
+        assert_eq!(moved(&mirror, "minecraft:rail[shape=north_east]"), "minecraft:rail[shape=north_west]");
+    }
+
+    #[test]
+    fn leaves_unknown_properties_alone() {
+        let turn = Transform::rotate_y(1);
+        assert_eq!(moved(&turn, "create:shaft[axis=x,powered=true]"), "create:shaft[axis=z,powered=true]");
+        assert_eq!(moved(&turn, "minecraft:stone_slab[type=top]"), "minecraft:stone_slab[type=top]");
+    }
+}
@@ -1,66 +1,232 @@
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
 use std::fs::{File, read};
 use std::io::{Cursor, Read, Write};
 use std::ops::Deref;
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::str::FromStr;
 use color_eyre::eyre::{bail, ContextCompat, eyre, WrapErr};
-use nbt::{from_gzip_reader, from_reader, to_gzip_writer, to_writer, Value};
-use perpendicular::Vector3;
+use flate2::{Compression, GzBuilder};
+use nbt::{from_gzip_reader, from_reader, to_writer, Value};
+use memmap2::Mmap;
+use perpendicular::{Vector2, Vector3};
+use rayon::prelude::*;
 use serde::{Serialize, Deserialize};
-use tracing::info;
-
-#[derive(Serialize, Deserialize)]
+use tracing::{info, warn};
+use crate::bounds::BoundingBox;
+use crate::keep::KeepMaskFormat;
+use crate::container::{Container, ItemStack};
+use crate::history::{hash_bytes, hash_program, HistoryEntry};
+use crate::pos::BlockPos;
+use crate::region::RegionView;
+use crate::rotation::RotationRules;
+use crate::sponge3::{self, SchemFileV3};
+use crate::storage::{BlockStorage, Storage};
+use crate::transform::Transform;
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all="PascalCase")]
-struct SchemBlockEntity {
-    id: String,
+pub(crate) struct SchemBlockEntity {
+    pub(crate) id: String,
 
     #[serde(serialize_with="nbt::i32_array")]
-    pos: Vec<i32>,
+    pub(crate) pos: Vec<i32>,
 
     #[serde(flatten)]
-    props: HashMap<String, Value>
+    pub(crate) props: BTreeMap<String, Value>
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all="PascalCase")]
+pub(crate) struct SchemEntity {
+    pub(crate) id: String,
+
+    pub(crate) pos: Vec<f64>,
+
+    #[serde(flatten)]
+    pub(crate) props: BTreeMap<String, Value>
+}
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
-struct Metadata {
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(crate) struct Metadata {
     #[serde(rename="WEOffsetX")]
-    offset_x: i32,
+    pub(crate) offset_x: i32,
     #[serde(rename="WEOffsetY")]
-    offset_y: i32,
+    pub(crate) offset_y: i32,
     #[serde(rename="WEOffsetZ")]
-    offset_z: i32,
+    pub(crate) offset_z: i32,
+    #[serde(rename="SchematicsHistory", default, skip_serializing_if="Vec::is_empty")]
+    pub(crate) history: Vec<HistoryEntry>,
+    /// Only in files: once read, the mask is kept with the blocks.
+    #[serde(rename="SchematicsKeepMask", default, skip_serializing_if="Option::is_none")]
+    pub(crate) keep_mask: Option<KeepMaskFormat>,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all="PascalCase")]
-struct SchemFormat {
+pub(crate) struct SchemFormat {
     #[serde(serialize_with="nbt::i8_array")]
-    block_data: Vec<i8>,
-    block_entities: Vec<SchemBlockEntity>,
-    data_version: i32,
-    height: i16,
-    length: i16,
-    metadata: Metadata,
+    pub(crate) block_data: Vec<i8>,
+    pub(crate) block_entities: Vec<SchemBlockEntity>,
+    pub(crate) data_version: i32,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub(crate) entities: Vec<SchemEntity>,
+    /// The biome of every column, as indices into `biome_palette`.
+    #[serde(default, skip_serializing_if="Vec::is_empty", serialize_with="nbt::i8_array")]
+    pub(crate) biome_data: Vec<i8>,
+    #[serde(default, skip_serializing_if="BTreeMap::is_empty")]
+    pub(crate) biome_palette: BTreeMap<String, i32>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub(crate) biome_palette_max: Option<i32>,
+    pub(crate) height: i16,
+    pub(crate) length: i16,
+    pub(crate) metadata: Metadata,
     #[serde(serialize_with="nbt::i32_array")]
-    offset: Vec<i32>,
-    palette: HashMap<String, i32>,
-    palette_max: i32,
-    version: i32,
-    width: i16,
+    pub(crate) offset: Vec<i32>,
+    pub(crate) palette: BTreeMap<String, i32>,
+    pub(crate) palette_max: i32,
+    pub(crate) version: i32,
+    pub(crate) width: i16,
+}
+
+/// The block states of a palette, by palette index. Tools for modded servers
+/// sometimes leave gaps in the indices, so the ones past the end of the
+/// palette are kept apart: a single large index shouldn't make us allocate a
+/// list that long.
+pub(crate) struct DecodedPalette {
+    pub(crate) states: Vec<Option<Rc<BlockState>>>,
+    pub(crate) beyond: HashMap<usize, Rc<BlockState>>,
+}
+
+impl DecodedPalette {
+    pub(crate) fn get(&self, index: usize) -> Option<&Rc<BlockState>> {
+        match self.states.get(index) {
+            Some(state) => state.as_ref(),
+            None => self.beyond.get(&index),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item=&Rc<BlockState>> {
+        self.states.iter().flatten().chain(self.beyond.values())
+    }
+}
+
+impl SchemFormat {
+    /// Width (x), height (y) and length (z). Version 3 sizes are unsigned.
+    pub(crate) fn size(&self) -> [usize; 3] {
+        [self.width, self.height, self.length].map(|i| match self.version {
+            3.. => i as u16 as usize,
+            _ => i.max(0) as usize,
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
+/// The largest size along any axis of a version 2 and a version 3 file.
+pub const MAX_SIZE_V2: usize = i16::MAX as usize;
+pub const MAX_SIZE_V3: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlockEntity {
     id: String,
     props: HashMap<String, Value>,
 }
 
-#[derive(Debug, Clone)]
+impl BlockEntity {
+    pub fn new(id: impl Into<String>, props: HashMap<String, Value>) -> Self {
+        Self { id: id.into(), props }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn props(&self) -> &HashMap<String, Value> {
+        &self.props
+    }
+
+    pub fn props_mut(&mut self) -> &mut HashMap<String, Value> {
+        &mut self.props
+    }
+
+    /// A block entity without any data, like an empty chest.
+    pub fn empty(id: impl Into<String>) -> Self {
+        Self::new(id, HashMap::new())
+    }
+
+    /// Set the tag `key` to `value`.
+    pub fn with(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.props.insert(key.into(), value);
+        self
+    }
+
+    /// A container, like a chest, barrel or hopper, holding `items`: the
+    /// slot, item id and count of each stack. See [`Container`] to change
+    /// the items of existing containers, and
+    /// [`Sign::to_entity`](crate::sign::Sign::to_entity) for signs.
+    pub fn container<'i>(id: impl Into<String>, items: impl IntoIterator<Item=(u8, &'i str, u8)>) -> Self {
+        let mut container = Container::new(id);
+        container.items = items.into_iter()
+            .map(|(slot, id, count)| ItemStack::new(slot, id, count))
+            .collect();
+
+        container.to_entity()
+    }
+
+    pub fn chest<'i>(items: impl IntoIterator<Item=(u8, &'i str, u8)>) -> Self {
+        Self::container("minecraft:chest", items)
+    }
+}
+
+/// An entity, like an armor stand, item frame or minecart. Its position is in
+/// the same coordinates as the blocks, where the block at 0, 0, 0 covers 0.0
+/// up to 1.0 on every axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    id: String,
+    pos: [f64; 3],
+    props: HashMap<String, Value>,
+}
+
+impl Entity {
+    pub fn new(id: impl Into<String>, pos: [f64; 3], props: HashMap<String, Value>) -> Self {
+        Self { id: id.into(), pos, props }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn pos(&self) -> [f64; 3] {
+        self.pos
+    }
+
+    pub fn set_pos(&mut self, pos: [f64; 3]) {
+        self.pos = pos;
+    }
+
+    /// The block the entity is in.
+    pub fn block_pos(&self) -> Vector3<i64> {
+        let [x, y, z] = self.pos.map(|i| i.floor() as i64);
+        Vector3::new3(x, y, z)
+    }
+
+    pub fn props(&self) -> &HashMap<String, Value> {
+        &self.props
+    }
+
+    pub fn props_mut(&mut self) -> &mut HashMap<String, Value> {
+        &mut self.props
+    }
+}
+
+/// A block id with its properties. Ids and properties are kept exactly as they
+/// were read: nothing is validated against the vanilla registry, so states from
+/// other namespaces (e.g. modded `create:cogwheel[axis=x]`) pass through every
+/// operation untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlockState {
     id: String,
     props: HashMap<String, String>,
@@ -80,12 +246,54 @@ impl BlockState {
     define_standard_block_states!(
         air = "minecraft:air",
         stone = "minecraft:stone",
+        structure_void = "minecraft:structure_void",
     );
 
     pub fn id(&self) -> &str {
         &self.id
     }
 
+    pub fn props(&self) -> &HashMap<String, String> {
+        &self.props
+    }
+
+    /// The properties sorted by name, the order they are written in.
+    pub fn sorted_props(&self) -> BTreeMap<&str, &str> {
+        self.props.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+    }
+
+    /// The state written like in a palette, `id[key=value,...]` with the
+    /// properties sorted by name, so equal states always give the same
+    /// string. The same as formatting it with [`Display`].
+    pub fn canonical(&self) -> String {
+        self.to_string()
+    }
+
+    /// The namespace of the id, `minecraft` if the id doesn't have one.
+    pub fn namespace(&self) -> &str {
+        self.id.split_once(':').map(|(ns, _)| ns).unwrap_or("minecraft")
+    }
+
+    /// The id without its namespace.
+    pub fn path(&self) -> &str {
+        self.id.split_once(':').map(|(_, path)| path).unwrap_or(&self.id)
+    }
+
+    pub fn is_vanilla(&self) -> bool {
+        self.namespace() == "minecraft"
+    }
+
+    /// Any of the kinds of air.
+    pub fn is_air(&self) -> bool {
+        self.is_vanilla() && matches!(self.path(), "air" | "cave_air" | "void_air")
+    }
+
+    /// Structure void, which marks positions without a block in files, see
+    /// [`WriteOptions::structure_void`].
+    pub fn is_structure_void(&self) -> bool {
+        self.is_vanilla() && self.path() == "structure_void"
+    }
+
     pub fn same_props_new_id(&self, id: impl AsRef<str>) -> Self {
         Self { id: id.as_ref().to_string(), props: self.props.clone() }
     }
@@ -94,22 +302,24 @@ impl BlockState {
         Self::with_props(name, HashMap::new())
     }
 
+    /// An interned state, see [`BlockState::intern`].
     pub fn with_props(name: impl AsRef<str>, props: HashMap<String, String>) -> Rc<BlockState> {
-        Rc::new(Self {
+        Self::intern(Self {
             id: name.as_ref().to_string(),
             props: props,
         })
     }
 }
 
+/// Writes the canonical form, see [`BlockState::canonical`].
 impl Display for BlockState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let props = &self.props;
+        let props = self.sorted_props();
         write!(f, "{}", self.id)?;
         if !props.is_empty() {
             write!(f, "[")?;
             let len = props.len();
-            for (idx, (k, v)) in props.iter().enumerate() {
+            for (idx, (k, v)) in props.into_iter().enumerate() {
                 write!(f, "{k}={v}")?;
                 if idx < len - 1 {
                     write!(f, ",")?;
@@ -133,8 +343,10 @@ impl FromStr for BlockState {
             });
         };
 
+        let props_data = props_data.strip_suffix(']').unwrap_or(props_data);
+
         let mut props = HashMap::new();
-        for i in props_data.trim_end_matches(']').split(',') {
+        for i in props_data.split(',').filter(|i| !i.is_empty()) {
             let (l, r) = i.split_once('=').ok_or(eyre!("no equal in palette prop"))?;
             props.insert(l.to_string(), r.to_string());
         }
@@ -146,6 +358,303 @@ impl FromStr for BlockState {
     }
 }
 
+/// Top level fields of the sponge schematic format, version 2.
+const KNOWN_FIELDS: [&str; 15] = [
+    "BiomeData", "BiomePalette", "BiomePaletteMax", "BlockData", "BlockEntities", "DataVersion",
+    "Entities", "Height", "Length", "Metadata", "Offset", "Palette", "PaletteMax", "Version", "Width",
+];
+
+/// The biome of columns without one, when a schematic has biomes for some
+/// of its columns only.
+const DEFAULT_BIOME: &str = "minecraft:plains";
+
+/// Where the column at `pos` ends up after `transform`. Schematics are only
+/// turned around the y axis or mirrored, which keeps columns upright.
+fn move_column(transform: &Transform, pos: &Vector2<i64>) -> Vector2<i64> {
+    let [x, _, z] = transform.apply([*pos.x(), 0, *pos.y()]);
+    Vector2::new2(x, z)
+}
+
+pub(crate) fn read_varint(data: &[i8], i: &mut usize) -> color_eyre::Result<usize> {
+    let mut value = 0;
+    for length in 0..5 {
+        let Some(byte) = data.get(*i) else {
+            bail!("data ends in the middle of a varint");
+        };
+        *i += 1;
+
+        value |= ((*byte as u8 & 127) as usize) << (length * 7);
+        if *byte as u8 & 128 == 0 {
+            return Ok(value);
+        }
+    }
+
+    bail!("varint length too big (data probably corrupted)")
+}
+
+/// Decoding fewer bytes than this isn't split over threads.
+const PARALLEL_CHUNK: usize = 1 << 16;
+
+/// Every varint in `data`. With `parallel`, large data is split into chunks
+/// that are decoded on all cores: a byte without the continuation bit ends a
+/// varint, so a chunk can start right after any such byte.
+pub(crate) fn read_varints(data: &[i8], parallel: bool) -> color_eyre::Result<Vec<usize>> {
+    let serial = |data: &[i8]| -> color_eyre::Result<Vec<usize>> {
+        let mut res = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            res.push(read_varint(data, &mut i)?);
+        }
+        Ok(res)
+    };
+
+    if !parallel || data.len() < 2 * PARALLEL_CHUNK {
+        return serial(data);
+    }
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let mut end = (start + PARALLEL_CHUNK).min(data.len());
+        while end < data.len() && data[end - 1] as u8 & 128 != 0 {
+            end += 1;
+        }
+        chunks.push(&data[start..end]);
+        start = end;
+    }
+
+    let decoded = chunks.into_par_iter()
+        .map(serial)
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+    Ok(decoded.concat())
+}
+
+pub(crate) fn write_varint(data: &mut Vec<i8>, mut value: u32) {
+    while value >= 128 {
+        data.push((value & 127 | 128) as u8 as i8);
+        value >>= 7;
+    }
+    data.push(value as i8);
+}
+
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// How careful to be when reading a schematic. The default is lenient: it
+/// loads what it can from slightly broken files, which suits interactive use,
+/// while automation can ask for anything unusual to be an error.
+#[derive(Debug, Copy, Clone)]
+pub struct ParseOptions {
+    /// Check the file against the format instead of only taking what's needed,
+    /// and fail on blocks that aren't in the palette instead of using air.
+    pub strict: bool,
+    /// Refuse files larger than this many bytes.
+    pub max_size: Option<u64>,
+    pub allow_unknown_fields: bool,
+    /// Decode the blocks on all cores. Only worth it for schematics with
+    /// millions of blocks, see [`Schematic::from_reader_parallel`].
+    pub parallel: bool,
+    /// Leave positions with structure void without a block, instead of
+    /// storing the structure void. Reads back what was written with
+    /// [`WriteOptions::structure_void`].
+    pub structure_void: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            max_size: None,
+            allow_unknown_fields: true,
+            parallel: false,
+            structure_void: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Make the output depend only on the schematic's contents: the history
+    /// entry gets a fixed timestamp (`SOURCE_DATE_EPOCH` if set, otherwise 0)
+    /// instead of the current time, and the palette is always in the order
+    /// blocks are found in, instead of reusing the one of the file the
+    /// schematic was read from. Property order, block entity order and the
+    /// gzip header are always fixed.
+    pub deterministic: bool,
+    pub compression: NbtCompression,
+    /// The data version to write instead of the one the schematic was read
+    /// with. Only the number changes: blocks aren't upgraded or downgraded.
+    pub data_version: Option<i32>,
+    /// The sponge format version to write, 2 or 3. By default 2, or 3 for
+    /// schematics larger than [`MAX_SIZE_V2`] along some axis.
+    pub version: Option<i32>,
+    /// Which block entities to write. Leaving some out is a workaround for
+    /// server plugins that crash on their data; the blocks themselves are
+    /// still written.
+    pub block_entities: EntityFilter,
+    /// Which entities to write.
+    pub entities: EntityFilter,
+    /// Write structure void where the schematic has no block, instead of
+    /// air. Air can't be told apart from placed air once written, so pasting
+    /// the file would clear whatever is already there.
+    pub structure_void: bool,
+}
+
+/// Which block entities or entities to write, see [`WriteOptions`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EntityFilter {
+    #[default]
+    All,
+    Nothing,
+    /// All but the ones with these ids. Ids without a namespace are in the
+    /// `minecraft` namespace, like those of blocks.
+    Except(Vec<String>),
+}
+
+impl EntityFilter {
+    /// [`Nothing`](Self::Nothing) with `nothing`, otherwise all but `except`.
+    pub fn new(nothing: bool, except: Vec<String>) -> Self {
+        match (nothing, except.is_empty()) {
+            (true, _) => EntityFilter::Nothing,
+            (false, true) => EntityFilter::All,
+            (false, false) => EntityFilter::Except(except),
+        }
+    }
+
+    /// Whether to write a (block) entity with id `id`.
+    pub fn keeps(&self, id: &str) -> bool {
+        let namespaced = |id: &str| match id.contains(':') {
+            true => id.to_string(),
+            false => format!("minecraft:{id}"),
+        };
+
+        match self {
+            EntityFilter::All => true,
+            EntityFilter::Nothing => false,
+            EntityFilter::Except(ids) => !ids.iter().any(|i| namespaced(i) == namespaced(id)),
+        }
+    }
+}
+
+/// How the nbt in a schematic file is compressed. Sponge schematics are
+/// normally gzipped, but WorldEdit reads uncompressed ones too.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NbtCompression {
+    #[default]
+    Gzip,
+    None,
+}
+
+impl WriteOptions {
+    /// The timestamp to record in the history, if it shouldn't be the current time.
+    pub fn timestamp(&self) -> Option<i64> {
+        if !self.deterministic {
+            return None;
+        }
+
+        let epoch = std::env::var("SOURCE_DATE_EPOCH").ok()
+            .and_then(|i| i.parse().ok())
+            .unwrap_or(0);
+
+        Some(epoch)
+    }
+
+    /// The format version and the size fields to write a schematic of `size`
+    /// with, failing if it doesn't fit in the version.
+    pub(crate) fn format_size(&self, size: [usize; 3]) -> color_eyre::Result<(i32, [i16; 3])> {
+        let largest = size.iter().copied().max().unwrap_or(0);
+        let version = match self.version {
+            Some(version @ (2 | 3)) => version,
+            Some(version) => bail!("can't write schematic version {version}, only 2 and 3"),
+            None if largest > MAX_SIZE_V2 => 3,
+            None => 2,
+        };
+
+        let max = if version == 2 { MAX_SIZE_V2 } else { MAX_SIZE_V3 };
+        for (len, axis) in size.iter().zip(["wide", "high", "long"]) {
+            if *len > max {
+                bail!("schematic is {len} blocks {axis}, more than the {max} a version {version} file can hold");
+            }
+        }
+
+        Ok((version, size.map(|i| i as u16 as i16)))
+    }
+
+    /// The history entry for a schematic written with these options.
+    pub fn history_entry(&self, source_hash: Option<String>, program_hash: Option<String>) -> HistoryEntry {
+        match self.timestamp() {
+            Some(timestamp) => HistoryEntry::at(timestamp, source_hash, program_hash),
+            None => HistoryEntry::now(source_hash, program_hash),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Which blocks [`Schematic::paste`] places.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PasteMode {
+    /// Every block, air included.
+    #[default]
+    Replace,
+    /// Every block except air, so what's already there shows through.
+    SkipAir,
+    /// Only where there's nothing yet, or air.
+    OnlyAir,
+}
+
+/// What a position of a schematic holds. Air is a block like any other, that
+/// clears what it's pasted over; positions without a block leave it alone.
+/// Files have no way to leave a position out, so those are written as air,
+/// or as structure void with [`WriteOptions::structure_void`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Slot {
+    Unset,
+    /// Any of the kinds of air, see [`BlockState::is_air`].
+    Air,
+    Block(Rc<BlockState>),
+}
+
+impl From<Option<Rc<BlockState>>> for Slot {
+    fn from(value: Option<Rc<BlockState>>) -> Self {
+        match value {
+            None => Slot::Unset,
+            Some(state) if state.is_air() => Slot::Air,
+            Some(state) => Slot::Block(state),
+        }
+    }
+}
+
+/// What [`Schematic::merge`] does where both schematics have a different block.
+pub enum ConflictPolicy {
+    /// Keep the block that's already there.
+    KeepSelf,
+    /// Take the block from the other schematic.
+    TakeOther,
+    /// Fail without changing anything.
+    Error,
+    /// Let a closure decide, given the position, the block that's there and
+    /// the other block.
+    Resolve(Box<dyn Fn(&Vector3<i64>, &Rc<BlockState>, &Rc<BlockState>) -> Rc<BlockState>>),
+}
+
+/// Offsets of the six blocks sharing a face with a position.
+pub const FACE_NEIGHBOURS: [[i64; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+/// The data version written to schematics made from scratch (1.19.3).
+pub const DATA_VERSION: i32 = 3218;
+
 #[derive(Clone)]
 pub struct Schematic {
     pub original_width: usize,
@@ -154,29 +663,158 @@ pub struct Schematic {
     pub original_offset: [i32; 3],
     pub original_data_version: i32,
     original_metadata: Metadata,
-    block_data: HashMap<Vector3<i64>, Rc<BlockState>>,
+    source_hash: Option<String>,
+    /// A hash of the nbt the file this was read from decoded to, to tell
+    /// whether writing the schematic back would change anything.
+    format_hash: Option<String>,
+    program_hash: Option<String>,
+    block_data: BlockStorage,
     block_entities: HashMap<Vector3<i64>, BlockEntity>,
+    entities: Vec<Entity>,
+    /// Biomes by x and z.
+    biomes: HashMap<Vector2<i64>, String>,
+    /// See [`Schematic::keep_mask`].
+    pub(crate) keep_mask: Option<HashSet<Vector3<i64>>>,
+    /// The palette of the file this was read from, see
+    /// [`Schematic::reuse_palette`]. Empty for schematics made from scratch.
+    pub(crate) original_palette: BTreeMap<String, i32>,
 }
 
 impl Schematic {
-    fn block_at(&self, loc: Vector3<i64>) -> Option<Rc<BlockState>> {
-        self.block_data.get(&loc).cloned()
+    /// An empty schematic, to build something from scratch.
+    pub fn new() -> Self {
+        Self::with_storage(Storage::Automatic)
+    }
+
+    /// An empty schematic that keeps its blocks in `storage`. Schematics made
+    /// with [`Schematic::new`] or read from a file use [`Storage::Automatic`],
+    /// which is only worth overriding when it's known up front how full the
+    /// schematic will be.
+    pub fn with_storage(storage: Storage) -> Self {
+        Self {
+            original_width: 0,
+            original_length: 0,
+            original_height: 0,
+            original_offset: [0, 0, 0],
+            original_data_version: DATA_VERSION,
+            original_metadata: Metadata::default(),
+            source_hash: None,
+            format_hash: None,
+            program_hash: None,
+            block_data: BlockStorage::new(storage),
+            block_entities: HashMap::new(),
+            entities: Vec::new(),
+            biomes: HashMap::new(),
+            keep_mask: None,
+            original_palette: BTreeMap::new(),
+        }
+    }
+
+    /// Where the blocks are kept right now: [`Storage::Sparse`],
+    /// [`Storage::Dense`] or [`Storage::Ordered`], never
+    /// [`Storage::Automatic`].
+    pub fn storage(&self) -> Storage {
+        self.block_data.storage()
+    }
+
+    /// Keep the blocks in `storage` from now on.
+    pub fn set_storage(&mut self, storage: Storage) {
+        if storage != self.block_data.requested() {
+            self.block_data = self.block_data.converted(storage);
+        }
+    }
+
+    /// The block at `loc`, or `None` outside the schematic. Air is stored
+    /// like any other block, so it is returned as a block too.
+    pub fn get_block(&self, loc: impl Into<BlockPos>) -> Option<Rc<BlockState>> {
+        self.block_data.get(&loc.into().vector()).cloned()
+    }
+
+    /// What is at `loc`, telling air apart from having no block at all.
+    pub fn slot(&self, loc: impl Into<BlockPos>) -> Slot {
+        self.get_block(loc).into()
+    }
+
+    /// Place what `slot` holds at `loc`, or remove the block there for
+    /// [`Slot::Unset`].
+    pub fn set_slot(&mut self, loc: impl Into<BlockPos>, slot: Slot) {
+        match slot {
+            Slot::Unset => {
+                self.remove_block(loc);
+            }
+            Slot::Air => self.set_block(loc, BlockState::air()),
+            Slot::Block(state) => self.set_block(loc, state),
+        }
+    }
+
+    /// Place `state` at `loc`, growing the schematic if `loc` is outside it.
+    pub fn set_block(&mut self, loc: impl Into<BlockPos>, state: Rc<BlockState>) {
+        self.block_data.insert(loc.into().vector(), state);
+    }
+
+    /// Set every position in the cuboid between `min` and `max`, both
+    /// inclusive, to `state`.
+    pub fn fill(&mut self, min: impl Into<BlockPos>, max: impl Into<BlockPos>, state: Rc<BlockState>) {
+        let (min, max) = (min.into(), max.into());
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                for x in min.x..=max.x {
+                    self.block_data.insert(Vector3::new3(x, y, z), state.clone());
+                }
+            }
+        }
+    }
+
+    /// Remove the block at `loc`, along with its block entity. Unlike placing
+    /// air, this can shrink the schematic. Returns the removed block.
+    pub fn remove_block(&mut self, loc: impl Into<BlockPos>) -> Option<Rc<BlockState>> {
+        let loc = loc.into().vector();
+        self.block_entities.remove(&loc);
+        self.block_data.remove(&loc)
+    }
+
+    /// Replace every block for which `replacement` returns a new state, e.g. to
+    /// clear all soul torches. Block entities are kept when the new block has
+    /// the same id, and dropped otherwise. Returns how many blocks changed.
+    pub fn replace_where(
+        &mut self,
+        mut replacement: impl FnMut(&Vector3<i64>, &BlockState) -> Option<Rc<BlockState>>,
+    ) -> usize {
+        let changes: Vec<_> = self.block_data.iter()
+            .filter_map(|(pos, blk)| {
+                let new = replacement(&pos, blk)?;
+                Some((pos, new.id() != blk.id(), new))
+            })
+            .collect();
+
+        let count = changes.len();
+        for (pos, other_id, new) in changes {
+            if other_id {
+                self.block_entities.remove(&pos);
+            }
+            self.block_data.insert(pos, new);
+        }
+
+        count
+    }
+
+    /// Replace every block with id `from` by `to`, whatever its properties.
+    pub fn replace(&mut self, from: &str, to: Rc<BlockState>) -> usize {
+        self.replace_where(|_, blk| (blk.id() == from).then(|| to.clone()))
     }
 
-    fn encode_block_data(&self) -> color_eyre::Result<(
+    fn encode_block_data(&self, unset: &BlockState) -> color_eyre::Result<(
         Vec<i8>,
-        HashMap<String, i32>,
+        BTreeMap<String, i32>,
     )> {
-        let x_min = self.min_x();
-        let y_min = self.min_y();
-        let z_min = self.min_z();
+        let [x_min, y_min, z_min] = self.bounds().min();
 
         let height = self.height();
         let length = self.length();
         let width = self.width();
 
         let mut block_data = Vec::new();
-        let mut palette = HashMap::new();
+        let mut palette = BTreeMap::new();
         let mut id = 0;
 
         for y in 0..height {
@@ -186,10 +824,10 @@ impl Schematic {
                     let z0 = z_min + z as i64;
                     let x0 = x_min + x as i64;
 
-                    let block_at = self.block_at(Vector3::new3(x0, y0, z0));
+                    let block_at = self.get_block(Vector3::new3(x0, y0, z0));
                     let block = match block_at.as_deref() {
                         None => {
-                            "minecraft:air".to_string()
+                            unset.to_string()
                         }
                         Some(b) => {
                             b.to_string()
@@ -210,65 +848,224 @@ impl Schematic {
             }
         }
 
-        Ok((
-            block_data,
-            palette,
-        ))
+        Ok((block_data, palette))
+    }
+
+    pub fn to_writer(&self, w: impl Write) -> color_eyre::Result<()> {
+        self.to_writer_with(w, &WriteOptions::default())
     }
 
-    pub fn to_writer(&self, mut w: impl Write) -> color_eyre::Result<()> {
+    pub fn to_writer_with(&self, w: impl Write, options: &WriteOptions) -> color_eyre::Result<()> {
         let offset = self.original_offset;
-        let (block_data, palette) = self.encode_block_data()?;
-        println!("{:?}", offset);
+        let unset = if options.structure_void { BlockState::structure_void() } else { BlockState::air() };
+        let (block_data, palette) = self.encode_block_data(&unset)?;
+        let (block_data, palette) = match options.deterministic {
+            true => (block_data, palette),
+            false => self.reuse_palette(block_data, palette)?,
+        };
 
-        let block_entities = self.block_entities
+        let mut block_entities: Vec<_> = self.block_entities
             .iter()
             .map(|(k, v)| SchemBlockEntity {
                 id: v.id.clone(),
                 pos: vec![*k.x() as i32, *k.y() as i32, *k.z() as i32],
-                props: v.props.clone(),
+                props: v.props.clone().into_iter().collect(),
+            })
+            .collect();
+        block_entities.sort_by(|a, b| a.pos.cmp(&b.pos));
+
+        // blocks are written from the lowest corner, so entities are too
+        let min = self.bounds().min();
+        let entities = self.entities.iter()
+            .map(|i| SchemEntity {
+                id: i.id.clone(),
+                pos: [0, 1, 2].map(|axis| i.pos[axis] - min[axis] as f64).to_vec(),
+                props: i.props.clone().into_iter().collect(),
             })
             .collect();
 
-        let format = SchemFormat {
-            width: self.len_x() as i16,
-            length: self.len_z() as i16,
-            height: self.len_y() as i16,
+        let (biome_data, biome_palette) = if self.biomes.is_empty() {
+            Default::default()
+        } else {
+            let columns = (0..self.length() as i64)
+                .flat_map(|z| (0..self.width() as i64).map(move |x| (x, z)))
+                .map(|(x, z)| self.biome(min[0] + x, min[2] + z).unwrap_or(DEFAULT_BIOME));
+            Self::encode_biomes(columns)
+        };
+
+        let size = self.bounds().size().map(|i| i as usize);
+        let mut metadata = self.original_metadata.clone();
+        metadata.keep_mask = self.keep_mask.as_ref().map(|mask| KeepMaskFormat::encode(mask, min, size));
+
+        let (version, [width, height, length]) = options.format_size(size)?;
+        let mut format = SchemFormat {
+            width,
+            length,
+            height,
             block_data,
             palette_max: palette.len() as i32,
             palette,
             offset: offset.to_vec(),
             block_entities,
-            data_version: self.original_data_version,
-            metadata: self.original_metadata,
-            version: 2,
+            data_version: options.data_version.unwrap_or(self.original_data_version),
+            entities,
+            biome_palette_max: (!biome_palette.is_empty()).then_some(biome_palette.len() as i32),
+            biome_palette,
+            biome_data,
+            metadata,
+            version,
         };
+        format.block_entities.retain(|i| options.block_entities.keeps(&i.id));
+        format.entities.retain(|i| options.entities.keeps(&i.id));
+
+        // writing back what was read doesn't add to the history, so an
+        // unchanged schematic round-trips to the same file
+        if self.format_hash.is_none() || Some(Self::format_hash(&format)?) != self.format_hash {
+            let entry = options.history_entry(self.source_hash.clone(), self.program_hash.clone());
+            format.metadata.history.push(entry);
+        }
+
+        Self::write_format(w, format, options)
+    }
+
+    /// A hash of `format` as uncompressed nbt.
+    fn format_hash(format: &SchemFormat) -> color_eyre::Result<String> {
+        let mut data = Vec::new();
+        to_writer(&mut data, format, Some("Schematic"))?;
+
+        Ok(hash_bytes(&data))
+    }
+
+    /// The biome of every column, as written to a file: ordered by z, then x.
+    pub(crate) fn encode_biomes<'a>(columns: impl Iterator<Item=&'a str>) -> (Vec<i8>, BTreeMap<String, i32>) {
+        let mut data = Vec::new();
+        let mut palette = BTreeMap::new();
+        for biome in columns {
+            let next = palette.len() as i32;
+            let id = *palette.entry(biome.to_string()).or_insert(next);
+            write_varint(&mut data, id as u32);
+        }
+
+        (data, palette)
+    }
+
+    /// The biome of every column of a file, ordered by z, then x, or nothing
+    /// if the file has no biomes.
+    pub(crate) fn decode_biomes(format: &SchemFormat) -> color_eyre::Result<Vec<String>> {
+        let mut names = vec![None; format.biome_palette.len()];
+        for (name, id) in &format.biome_palette {
+            match names.get_mut(*id as usize) {
+                Some(slot) => *slot = Some(name.clone()),
+                None => bail!("biome palette index {id} of {name} is out of range"),
+            }
+        }
+
+        let mut res = Vec::new();
+        let mut i = 0;
+        while i < format.biome_data.len() {
+            match names.get(read_varint(&format.biome_data, &mut i)?) {
+                Some(Some(name)) => res.push(name.clone()),
+                _ => bail!("invalid biome palette index"),
+            }
+        }
+
+        let [width, _, length] = format.size();
+        let columns = width * length;
+        if !res.is_empty() && res.len() != columns {
+            bail!("expected biomes for {columns} columns, found {}", res.len());
+        }
 
-        to_gzip_writer(&mut w, &format, Some("Schematic"))?;
+        Ok(res)
+    }
+
+    /// Write `format` in the layout of its version.
+    pub(crate) fn write_format(w: impl Write, format: SchemFormat, options: &WriteOptions) -> color_eyre::Result<()> {
+        match format.version {
+            3.. => Self::write_nbt(w, &SchemFileV3::from(format), "", options),
+            _ => Self::write_nbt(w, &format, "Schematic", options),
+        }
+    }
+
+    pub(crate) fn write_nbt(mut w: impl Write, value: &impl Serialize, name: &str, options: &WriteOptions) -> color_eyre::Result<()> {
+        match options.compression {
+            NbtCompression::Gzip => {
+                // an explicit header, so the gzip stream doesn't depend on the
+                // machine or time it was written on.
+                let mut encoder = GzBuilder::new()
+                    .mtime(0)
+                    .write(w, Compression::default());
+                to_writer(&mut encoder, value, Some(name))?;
+                encoder.finish()?;
+            }
+            NbtCompression::None => to_writer(&mut w, value, Some(name))?,
+        }
 
         Ok(())
     }
 
     pub fn to_file(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        self.to_file_with(path, &WriteOptions::default())
+    }
+
+    pub fn to_file_with(&self, path: impl AsRef<Path>, options: &WriteOptions) -> color_eyre::Result<()> {
         let mut f = File::create(path)?;
-        self.to_writer(f)?;
+        self.to_writer_with(f, options)?;
 
         Ok(())
     }
 
     pub fn to_bytes(&self) -> color_eyre::Result<Vec<u8>> {
+        self.to_bytes_with(&WriteOptions::default())
+    }
+
+    pub fn to_bytes_with(&self, options: &WriteOptions) -> color_eyre::Result<Vec<u8>> {
         let mut res = Vec::new();
-        self.to_writer(Cursor::new(&mut res))?;
+        self.to_writer_with(Cursor::new(&mut res), options)?;
 
         Ok(res)
     }
 
     pub fn from_reader(reader: impl Read) -> color_eyre::Result<Self> {
-        let format: SchemFormat = from_gzip_reader(reader)
-            .wrap_err("read and decode nbt")?;
+        Self::from_reader_with(reader, &ParseOptions::default())
+    }
+
+    /// Like [`Schematic::from_reader`], decoding the blocks on all cores.
+    pub fn from_reader_parallel(reader: impl Read) -> color_eyre::Result<Self> {
+        Self::from_reader_with(reader, &ParseOptions { parallel: true, ..Default::default() })
+    }
+
+    pub fn from_reader_with(reader: impl Read, options: &ParseOptions) -> color_eyre::Result<Self> {
+        let mut data = Vec::new();
+        match options.max_size {
+            Some(max) => {
+                reader.take(max + 1).read_to_end(&mut data).wrap_err("read schematic")?;
+            }
+            None => {
+                let mut reader = reader;
+                reader.read_to_end(&mut data).wrap_err("read schematic")?;
+            }
+        }
+
+        Self::from_slice_with(&data, options)
+    }
 
-        let decoded_palette = Self::decode_palette(&format)?;
-        let mut decoded_block_data = Self::decode_block_data(&format, &decoded_palette)?;
+    /// Decode a schematic file that's already in memory.
+    pub fn from_slice_with(data: &[u8], options: &ParseOptions) -> color_eyre::Result<Self> {
+        let mut format = Self::read_format(data, options)?;
+        let format_hash = Self::format_hash(&format)?;
+        let decoded_palette = Self::decode_palette(&format.palette)?;
+        let mut decoded_block_data = Self::decode_block_data(&format, &decoded_palette, options)?;
+        if options.structure_void {
+            let unset: Vec<_> = decoded_block_data.iter()
+                .filter(|(_, blk)| blk.is_structure_void())
+                .map(|(pos, _)| pos)
+                .collect();
+            for pos in unset {
+                decoded_block_data.remove(&pos);
+            }
+        }
+        let decoded_biomes = Self::decode_biomes(&format)?;
+        let [width, height, length] = format.size();
         let mut block_entities = HashMap::new();
 
         info!("{}", format.palette.len());
@@ -283,151 +1080,876 @@ impl Schematic {
                 ),
                 BlockEntity {
                     id: i.id,
-                    props: i.props,
+                    props: i.props.into_iter().collect(),
                 }
             );
         }
 
+        let keep_mask = format.metadata.keep_mask.take()
+            .map(|i| i.decode([width, height, length]))
+            .transpose()?;
+        let mut biomes = HashMap::new();
+        for (idx, biome) in decoded_biomes.into_iter().enumerate() {
+            biomes.insert(Vector2::new2((idx % width) as i64, (idx / width) as i64), biome);
+        }
+
+        let mut entities = Vec::new();
+        for i in format.entities {
+            let [x, y, z] = i.pos[..] else {
+                bail!("entity {} has a position of {} coordinates", i.id, i.pos.len());
+            };
+            entities.push(Entity {
+                id: i.id,
+                pos: [x, y, z],
+                props: i.props.into_iter().collect(),
+            });
+        }
+
         Ok(Self {
-            original_width: format.width as usize,
-            original_length: format.length as usize,
-            original_height: format.height as usize,
+            original_width: width,
+            original_length: length,
+            original_height: height,
             original_offset: [format.offset[0], format.offset[1], format.offset[2]],
             original_data_version: format.data_version,
             original_metadata: format.metadata,
+            source_hash: Some(hash_bytes(data)),
+            format_hash: Some(format_hash),
+            program_hash: None,
             block_data: decoded_block_data,
             block_entities,
+            entities,
+            biomes,
+            keep_mask,
+            original_palette: format.palette,
         })
     }
 
-    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
-        let file = File::open(path)
-            .wrap_err("open file")?;
-
-        Self::from_reader(file)
+    /// Parse the nbt of a schematic file, without decoding the blocks yet.
+    pub(crate) fn read_format(data: &[u8], options: &ParseOptions) -> color_eyre::Result<SchemFormat> {
+        if let Some(max) = options.max_size {
+            if data.len() as u64 > max {
+                bail!("schematic is larger than the maximum of {max} bytes");
+            }
+        }
+
+        // uncompressed files start with the tag of the root compound instead
+        // of the gzip magic number
+        let gzipped = data.starts_with(&GZIP_MAGIC);
+
+        if sponge3::is_v3(data, gzipped)? {
+            let file: SchemFileV3 = if gzipped {
+                from_gzip_reader(Cursor::new(data))
+            } else {
+                from_reader(Cursor::new(data))
+            }.wrap_err("read and decode nbt")?;
+            let format = file.into_format()?;
+            if options.strict {
+                Self::check_format(&format)?;
+            }
+
+            return Ok(format);
+        }
+
+        if !options.allow_unknown_fields {
+            let fields: HashMap<String, Value> = if gzipped {
+                from_gzip_reader(Cursor::new(data))
+            } else {
+                from_reader(Cursor::new(data))
+            }.wrap_err("read and decode nbt")?;
+            if let Some(unknown) = fields.keys().find(|i| !KNOWN_FIELDS.contains(&i.as_str())) {
+                bail!("unknown field {unknown} in schematic");
+            }
+        }
+
+        let format: SchemFormat = if gzipped {
+            from_gzip_reader(Cursor::new(data))
+        } else {
+            from_reader(Cursor::new(data))
+        }.wrap_err("read and decode nbt")?;
+        if options.strict {
+            Self::check_format(&format)?;
+        }
+
+        Ok(format)
     }
 
-    pub fn from_bytes(data: impl AsRef<[u8]>) -> color_eyre::Result<Self> {
-        Self::from_reader(Cursor::new(data.as_ref()))
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        Self::from_file_with(path, &ParseOptions::default())
     }
 
-    fn decode_palette(format: &SchemFormat) -> color_eyre::Result<Vec<Option<Rc<BlockState>>>> {
-        let mut res = Vec::new();
-        res.resize(format.palette.len(), None);
+    /// Read a schematic file by mapping it into memory, so large files aren't
+    /// copied before decoding.
+    pub fn from_file_with(path: impl AsRef<Path>, options: &ParseOptions) -> color_eyre::Result<Self> {
+        let file = File::open(path)
+            .wrap_err("open file")?;
 
-        for (name, i) in &format.palette {
-            if *i as usize > res.len() {
-                res.resize(*i as usize, None);
+        if let Some(max) = options.max_size {
+            let size = file.metadata().wrap_err("read file size")?.len();
+            if size > max {
+                bail!("schematic is larger than the maximum of {max} bytes");
             }
-            res[*i as usize] = Some(Rc::new(name.parse()?));
         }
 
-        Ok(res)
+        // an empty file can't be mapped, but isn't a schematic either
+        if file.metadata().wrap_err("read file size")?.len() == 0 {
+            bail!("empty schematic file");
+        }
+
+        // SAFETY: the mapping is only read while decoding. Another process
+        // truncating the file in the meantime is the usual risk of mmap, which
+        // we accept for schematics we've been asked to read.
+        let map = unsafe { Mmap::map(&file) }.wrap_err("map file")?;
+        Self::from_slice_with(&map, options)
     }
 
-    fn decode_block_data(format: &SchemFormat, palette: &[Option<Rc<BlockState>>]) -> color_eyre::Result<HashMap<Vector3<i64>, Rc<BlockState>>> {
-        let mut buffer = HashMap::new();
-        let ref block_data = format.block_data;
+    /// The checks made in strict mode, on top of what decoding checks anyway.
+    fn check_format(format: &SchemFormat) -> color_eyre::Result<()> {
+        if !matches!(format.version, 2 | 3) {
+            bail!("unsupported schematic version {}", format.version);
+        }
+        if format.palette_max as usize != format.palette.len() {
+            bail!("palette has {} entries, but PaletteMax is {}", format.palette.len(), format.palette_max);
+        }
+        if format.version == 2 && (format.width < 0 || format.height < 0 || format.length < 0) {
+            bail!("negative size {}x{}x{}", format.width, format.height, format.length);
+        }
 
-        let mut index: i64 = 0;
-        let mut i = 0;
-        let mut value: usize = 0;
-        let mut varint_length = 0;
-        while i < block_data.len() {
-            value = 0;
-            varint_length = 0;
-
-            loop {
-                value |= ((block_data[i] as u8 & 127) as usize) << (varint_length * 7);
-                varint_length += 1;
-                if varint_length > 5 {
-                    bail!("varint length too big (data probably corrupted)")
+        let mut seen = HashSet::new();
+        for (name, id) in &format.palette {
+            if *id < 0 || *id >= format.palette_max {
+                bail!("palette index {id} of {name} is out of range");
+            }
+            if !seen.insert(*id) {
+                bail!("palette index {id} is used more than once");
+            }
+        }
+
+        for entity in &format.block_entities {
+            let [x, y, z] = entity.pos[..] else {
+                bail!("block entity {} has a position of {} coordinates", entity.id, entity.pos.len());
+            };
+            let [width, height, length] = format.size().map(|i| i as i32);
+            if x < 0 || y < 0 || z < 0 || x >= width || y >= height || z >= length {
+                bail!("block entity {} at {:?} is outside the schematic", entity.id, entity.pos);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn from_bytes(data: impl AsRef<[u8]>) -> color_eyre::Result<Self> {
+        Self::from_slice_with(data.as_ref(), &ParseOptions::default())
+    }
+
+    pub(crate) fn decode_palette(palette: &BTreeMap<String, i32>) -> color_eyre::Result<DecodedPalette> {
+        let mut res = DecodedPalette { states: vec![None; palette.len()], beyond: HashMap::new() };
+
+        for (name, i) in palette {
+            let Ok(index) = usize::try_from(*i) else {
+                bail!("palette index {i} of {name} is negative");
+            };
+            let state = BlockState::intern(name.parse()?);
+            match res.states.get_mut(index) {
+                Some(slot) => *slot = Some(state),
+                None => {
+                    res.beyond.insert(index, state);
                 }
-                if (block_data[i] as u8) & 128 != 128 {
-                    i += 1;
-                    break;
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Decode the blocks. Outside of strict mode, blocks with a palette index
+    /// that isn't in the palette become air instead of failing, and blocks
+    /// past the end of the schematic are left out.
+    fn decode_block_data(format: &SchemFormat, palette: &DecodedPalette, options: &ParseOptions) -> color_eyre::Result<BlockStorage> {
+        let size = format.size();
+        let volume: usize = size.iter().product();
+        let values = read_varints(&format.block_data, options.parallel)?;
+
+        // states can't be shared between threads, so look up which indices
+        // are valid in a plain list
+        let valid: Vec<bool> = palette.states.iter().map(Option::is_some).collect();
+        let lookup = |value: &usize| match valid.get(*value) {
+            Some(true) => *value as u32,
+            _ => u32::MAX,
+        };
+        let mut blocks: Vec<u32> = if options.parallel {
+            values.par_iter().take(volume).map(lookup).collect()
+        } else {
+            values.iter().take(volume).map(lookup).collect()
+        };
+
+        // unused palette entries are kept as air, so indices don't need remapping
+        let mut states: Vec<_> = palette.states.iter()
+            .map(|i| i.clone().unwrap_or_else(BlockState::air))
+            .collect();
+        // except for the ones past the end of the palette, which are added
+        // once they're used
+        let mut beyond = HashMap::new();
+        let mut air = None;
+        for (block, value) in blocks.iter_mut().zip(&values).filter(|(i, _)| **i == u32::MAX) {
+            *block = match (palette.states.get(*value), palette.beyond.get(value)) {
+                (_, Some(state)) => *beyond.entry(*value).or_insert_with(|| {
+                    states.push(state.clone());
+                    states.len() as u32 - 1
+                }),
+                _ if !options.strict => {
+                    warn!("invalid palette index {value}, using air");
+                    *air.get_or_insert_with(|| {
+                        states.push(BlockState::air());
+                        states.len() as u32 - 1
+                    })
                 }
-                i += 1;
+                (None, _) => bail!("invalid palette index"),
+                (Some(_), _) => bail!("missing palette index"),
+            };
+        }
+
+        if values.len() != volume {
+            if options.strict {
+                bail!("expected {volume} blocks, found {}", values.len());
             }
+            warn!("expected {volume} blocks, found {}", values.len());
+        }
 
-            let y = (index /  (format.width as i64 * format.length as i64)) as i64;
-            let z = ((index % (format.width as i64 * format.length as i64)) / format.width as i64) as i64;
-            let x = ((index % (format.width as i64 * format.length as i64)) % format.width as i64) as i64;
-            let state = palette.get(value)
-                .ok_or_else(|| eyre!("invalid palette index"))?
-                .clone()
-                .ok_or_else(|| eyre!("missing palette index"))?;
-            buffer.insert(
-                Vector3::new3(
-                    x,
-                    y,
-                    z,
-                ),
-                state
-            );
+        Ok(BlockStorage::from_cuboid(size, states, blocks))
+    }
+
+    /// A copy of the part of this schematic between `min` and `max`, both
+    /// inclusive. See [`Schematic::rebase`] for how the offsets change.
+    pub fn crop(&self, min: Vector3<i64>, max: Vector3<i64>) -> Schematic {
+        let region = BoundingBox { min, max };
+        let inside = |pos: &Vector3<i64>| region.contains(pos);
+        let (min, max) = (&region.min, &region.max);
+
+        let mut res = Schematic {
+            block_data: self.block_data.filtered(inside),
+            block_entities: self.block_entities.iter()
+                .filter(|(pos, _)| inside(pos))
+                .map(|(pos, entity)| (pos.clone(), entity.clone()))
+                .collect(),
+            entities: self.entities.iter()
+                .filter(|i| inside(&i.block_pos()))
+                .cloned()
+                .collect(),
+            biomes: self.biomes.iter()
+                .filter(|(pos, _)| (*min.x()..=*max.x()).contains(pos.x()) && (*min.z()..=*max.z()).contains(pos.y()))
+                .map(|(pos, biome)| (pos.clone(), biome.clone()))
+                .collect(),
+            keep_mask: self.keep_mask.as_ref()
+                .map(|mask| mask.iter().filter(|pos| inside(pos)).cloned().collect()),
+            ..self.clone()
+        };
+        res.rebase();
+
+        res
+    }
 
-            index += 1;
+    /// The part of this schematic between `min` and `max`, both inclusive, as
+    /// a schematic of its own: unlike [`crop`](Self::crop), it doesn't carry
+    /// over the history or WorldEdit paste offset of this one. Its offset is
+    /// where the part was in the world.
+    pub fn extract(&self, min: Vector3<i64>, max: Vector3<i64>) -> Schematic {
+        let cropped = self.crop(min, max);
+
+        Schematic {
+            original_metadata: Metadata::default(),
+            source_hash: None,
+            format_hash: None,
+            program_hash: None,
+            original_palette: BTreeMap::new(),
+            ..cropped
         }
+    }
+
+    /// A copy of this schematic cut down to the smallest box holding every
+    /// block that isn't air. Without any such blocks, the result is empty.
+    pub fn trim(&self) -> Schematic {
+        self.crop_around(|blk| !blk.is_air())
+    }
+
+    /// A copy of this schematic cut down to the smallest box holding every
+    /// block for which `keep` is true. Other blocks inside that box stay.
+    pub(crate) fn crop_around(&self, keep: impl Fn(&BlockState) -> bool) -> Schematic {
+        let kept = BoundingBox::around(self.block_data.iter()
+            .filter(|(_, blk)| keep(blk))
+            .map(|(pos, _)| pos));
+
+        self.crop(kept.min, kept.max)
+    }
+
+    /// Move all blocks so the lowest corner of the schematic is at 0, 0, 0,
+    /// which is where it is when the schematic is written anyway. The offset
+    /// and the WorldEdit paste offset move the other way, so the blocks still
+    /// end up in the same place in the world.
+    fn rebase(&mut self) {
+        let shift = self.bounds().min();
+        if shift != [0, 0, 0] {
+            self.move_blocks(&Transform::translate(shift.map(|i| -i)));
+        }
+        self.move_offsets(shift);
+
+        self.original_width = self.width();
+        self.original_height = self.height();
+        self.original_length = self.length();
+    }
+
+    /// Move the schematic by `offset`: all blocks and block entities, and where
+    /// it ends up in the world, both through the offset and the WorldEdit
+    /// paste offset.
+    pub fn translate(&mut self, offset: Vector3<i64>) {
+        let offset = [*offset.x(), *offset.y(), *offset.z()];
+        self.move_blocks(&Transform::translate(offset));
+        self.move_offsets(offset);
+    }
+
+    fn move_blocks(&mut self, transform: &Transform) {
+        self.block_data = self.block_data.mapped(|pos| transform.apply_vector(pos), Rc::clone);
+        self.block_entities = self.block_entities.drain()
+            .map(|(pos, entity)| (transform.apply_vector(&pos), entity))
+            .collect();
+        for entity in &mut self.entities {
+            entity.pos = transform.apply_point(entity.pos);
+        }
+        self.biomes = self.biomes.drain()
+            .map(|(pos, biome)| (move_column(transform, &pos), biome))
+            .collect();
+        if let Some(mask) = &mut self.keep_mask {
+            *mask = mask.drain().map(|pos| transform.apply_vector(&pos)).collect();
+        }
+    }
+
+    fn move_offsets(&mut self, offset: [i64; 3]) {
+        for axis in 0..3 {
+            self.original_offset[axis] += offset[axis] as i32;
+        }
+        self.original_metadata.offset_x += offset[0] as i32;
+        self.original_metadata.offset_y += offset[1] as i32;
+        self.original_metadata.offset_z += offset[2] as i32;
+    }
+
+    /// Copy the blocks of `other` into this schematic, with its lowest corner
+    /// at `at`. Block entities come along with their blocks, and those of
+    /// blocks that are pasted over are removed. Positions where `other` has
+    /// no block ([`Slot::Unset`]) are left alone whatever the mode. Returns
+    /// how many blocks were placed.
+    pub fn paste(&mut self, other: &Schematic, at: Vector3<i64>, mode: PasteMode) -> usize {
+        let min = other.bounds().min();
+        let transform = Transform::translate([
+            *at.x() - min[0],
+            *at.y() - min[1],
+            *at.z() - min[2],
+        ]);
+        let mut placed = 0;
+
+        for (pos, blk) in other.block_data.iter() {
+            if !other.keeps(pos.clone()) {
+                continue;
+            }
+
+            let target = transform.apply_vector(&pos);
+            let place = match mode {
+                PasteMode::Replace => true,
+                PasteMode::SkipAir => !blk.is_air(),
+                PasteMode::OnlyAir => self.block_data.get(&target).map_or(true, |i| i.is_air()),
+            };
+            if !place {
+                continue;
+            }
+
+            match other.block_entities.get(&pos) {
+                Some(entity) => self.block_entities.insert(target.clone(), entity.clone()),
+                None => self.block_entities.remove(&target),
+            };
+            self.block_data.insert(target, blk.clone());
+            placed += 1;
+        }
+        for entity in &other.entities {
+            self.entities.push(Entity { pos: transform.apply_point(entity.pos), ..entity.clone() });
+        }
+
+        placed
+    }
+
+    /// Combine `other` into this schematic, position for position, e.g. to
+    /// put together several edits of the same build. Where both have a
+    /// different block, `policy` decides. Block entities go with the block
+    /// that ends up at a position. Returns the number of conflicts.
+    pub fn merge(&mut self, other: &Schematic, policy: ConflictPolicy) -> color_eyre::Result<usize> {
+        let conflicts: Vec<_> = other.block_data.iter()
+            .filter(|(pos, blk)| self.block_data.get(pos).is_some_and(|i| i != *blk))
+            .map(|(pos, _)| pos)
+            .collect();
+
+        if let (ConflictPolicy::Error, Some(first)) = (&policy, conflicts.first()) {
+            bail!("{} positions differ, the first at {:?}", conflicts.len(), [*first.x(), *first.y(), *first.z()]);
+        }
+
+        for (pos, blk) in other.block_data.iter() {
+            let new = match self.block_data.get(&pos) {
+                Some(existing) if !BlockState::same(existing, blk) => match &policy {
+                    ConflictPolicy::KeepSelf | ConflictPolicy::Error => continue,
+                    ConflictPolicy::TakeOther => blk.clone(),
+                    ConflictPolicy::Resolve(resolve) => resolve(&pos, existing, blk),
+                },
+                _ => blk.clone(),
+            };
+
+            if BlockState::same(&new, blk) {
+                match other.block_entities.get(&pos) {
+                    Some(entity) => self.block_entities.insert(pos.clone(), entity.clone()),
+                    None => self.block_entities.remove(&pos),
+                };
+            } else if self.block_data.get(&pos) != Some(&new) {
+                self.block_entities.remove(&pos);
+            }
+            self.block_data.insert(pos, new);
+        }
+
+        Ok(conflicts.len())
+    }
+
+    /// Copy all blocks and block entities of `other` into this schematic,
+    /// shifted by `offset`.
+    pub fn insert_translated(&mut self, other: &Schematic, offset: [i64; 3]) {
+        self.insert_transformed(other, &Transform::translate(offset));
+    }
+
+    /// A copy of this schematic with every block moved, and turned, by
+    /// `transform`.
+    pub fn transformed(&self, transform: &Transform) -> Schematic {
+        self.transformed_with(transform, RotationRules::builtin())
+    }
+
+    /// Turn the schematic clockwise around the y axis, seen from above,
+    /// `quarter_turns` times. Block properties like `facing` or a rail's
+    /// `shape` are turned along with it, following [`RotationRules::builtin`].
+    pub fn rotate_y(&mut self, quarter_turns: i64) {
+        *self = self.transformed(&Transform::rotate_y(quarter_turns));
+    }
+
+    /// Mirror the schematic along `axis`, e.g. to build the other half of a
+    /// symmetric circuit. Block properties are mirrored too: `facing`, stair
+    /// and rail `shape`, door `hinge` and so on, see [`RotationRules::builtin`].
+    pub fn mirror(&mut self, axis: Axis) {
+        *self = self.transformed(&Transform::mirror(axis));
+    }
+
+    /// Like [`Schematic::transformed`], with custom rules for turning block states.
+    pub fn transformed_with(&self, transform: &Transform, rules: &RotationRules) -> Schematic {
+        let mut res = Schematic {
+            block_data: self.block_data.mapped(|pos| transform.apply_vector(pos), |blk| rules.apply(transform, blk)),
+            block_entities: HashMap::new(),
+            entities: Vec::new(),
+            biomes: HashMap::new(),
+            keep_mask: self.keep_mask.as_ref()
+                .map(|mask| mask.iter().map(|pos| transform.apply_vector(pos)).collect()),
+            ..self.clone()
+        };
+
+        for (pos, entity) in &self.block_entities {
+            res.block_entities.insert(transform.apply_vector(pos), entity.clone());
+        }
+        for entity in &self.entities {
+            res.entities.push(Entity { pos: transform.apply_point(entity.pos), ..entity.clone() });
+        }
+        for (pos, biome) in &self.biomes {
+            res.biomes.insert(move_column(transform, pos), biome.clone());
+        }
+
+        res
+    }
+
+    /// Copy all blocks and block entities of `other` into this schematic,
+    /// moved by `transform`.
+    pub fn insert_transformed(&mut self, other: &Schematic, transform: &Transform) {
+        for (pos, blk) in other.block_data.iter() {
+            self.block_data.insert(transform.apply_vector(&pos), transform.apply_state(blk));
+        }
+        for (pos, entity) in &other.block_entities {
+            self.block_entities.insert(transform.apply_vector(pos), entity.clone());
+        }
+        for entity in &other.entities {
+            self.entities.push(Entity { pos: transform.apply_point(entity.pos), ..entity.clone() });
+        }
+        for (pos, biome) in &other.biomes {
+            self.biomes.insert(move_column(transform, pos), biome.clone());
+        }
+    }
+
+    /// Place schematics next to each other along `axis`, with `gap` blocks of air
+    /// in between. The other axes are aligned on their minimum. The result keeps
+    /// the metadata of the first schematic.
+    pub fn concat(parts: &[Schematic], axis: Axis, gap: i64) -> color_eyre::Result<Schematic> {
+        let Some((first, rest)) = parts.split_first() else {
+            bail!("no schematics to concatenate");
+        };
+
+        let mut res = first.clone();
+        for part in rest {
+            let (bounds, part_min) = (res.bounds(), part.bounds().min());
+            let mut offset = [0, 1, 2].map(|axis| bounds.min()[axis] - part_min[axis]);
+
+            let along = match axis {
+                Axis::X => 0,
+                Axis::Y => 1,
+                Axis::Z => 2,
+            };
+            offset[along] = bounds.max()[along] + 1 + gap - part_min[along];
+
+            res.insert_translated(part, offset);
+        }
+
+        Ok(res)
+    }
+
+    /// The chain of tools and inputs that produced this schematic, oldest first.
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.original_metadata.history
+    }
+
+    /// Remember that `program` was written into this schematic, so the next
+    /// history entry records it.
+    pub fn record_program(&mut self, program: &[u16]) {
+        self.program_hash = Some(hash_program(program));
+    }
+
+    pub fn block_entity(&self, pos: &Vector3<i64>) -> Option<&BlockEntity> {
+        self.block_entities.get(pos)
+    }
+
+    pub fn block_entity_mut(&mut self, pos: &Vector3<i64>) -> Option<&mut BlockEntity> {
+        self.block_entities.get_mut(pos)
+    }
+
+    /// Set the block entity at `pos`, replacing the one that was there. The
+    /// block itself has to be placed separately.
+    pub fn set_block_entity(&mut self, pos: Vector3<i64>, entity: BlockEntity) {
+        self.block_entities.insert(pos, entity);
+    }
+
+    /// Remove the block entity at `pos`, leaving the block. Returns the
+    /// removed block entity.
+    pub fn remove_block_entity(&mut self, pos: &Vector3<i64>) -> Option<BlockEntity> {
+        self.block_entities.remove(pos)
+    }
 
-        Ok(buffer)
+    pub fn block_entities(&self) -> impl Iterator<Item=(&Vector3<i64>, &BlockEntity)> {
+        self.block_entities.iter()
     }
 
-    pub fn blocks(&self) -> impl Iterator<Item=(&Vector3<i64>, &Rc<BlockState>)> {
+    /// Every block with its position. With [`Storage::Dense`] and
+    /// [`Storage::Ordered`] they come by y, then z, then x; with
+    /// [`Storage::Sparse`] (and so sometimes [`Storage::Automatic`]) in no
+    /// particular order, which can differ between runs. Use
+    /// [`Schematic::blocks_sorted`] when the order matters.
+    pub fn blocks(&self) -> impl Iterator<Item=(Vector3<i64>, &Rc<BlockState>)> + '_ {
         self.block_data.iter()
     }
 
-    pub fn blocks_mut(&mut self) -> impl Iterator<Item=(&Vector3<i64>, &mut Rc<BlockState>)> {
-        self.block_data.iter_mut()
+    /// Every block with its position, by y, then z, then x, the order of the
+    /// file format. Only sorts when the storage isn't ordered already.
+    pub fn blocks_sorted(&self) -> Vec<(Vector3<i64>, &Rc<BlockState>)> {
+        let mut res: Vec<_> = self.block_data.iter().collect();
+        if !self.block_data.is_ordered() {
+            res.sort_unstable_by_key(|(pos, _)| (*pos.y(), *pos.z(), *pos.x()));
+        }
+
+        res
+    }
+
+    /// The armor stands, item frames, minecarts and other entities in the
+    /// schematic.
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn entities_mut(&mut self) -> &mut Vec<Entity> {
+        &mut self.entities
+    }
+
+    pub fn add_entity(&mut self, entity: Entity) {
+        self.entities.push(entity);
+    }
+
+    /// The biome of the column at `x`, `z`, if the schematic has one for it.
+    /// Schematics store one biome per column, not per block.
+    pub fn biome(&self, x: i64, z: i64) -> Option<&str> {
+        self.biomes.get(&Vector2::new2(x, z)).map(String::as_str)
+    }
+
+    /// Set the biome of the column at `x`, `z`, like `minecraft:desert`. Once
+    /// any column has a biome, columns without one are written as plains.
+    pub fn set_biome(&mut self, x: i64, z: i64, biome: impl Into<String>) {
+        self.biomes.insert(Vector2::new2(x, z), biome.into());
+    }
+
+    /// Every column with a biome, as `x`, `z` and the biome.
+    pub fn biomes(&self) -> impl Iterator<Item=(i64, i64, &str)> {
+        self.biomes.iter().map(|(pos, biome)| (*pos.x(), *pos.y(), biome.as_str()))
+    }
+
+    /// Every position in the cuboid between `min` and `max`, both inclusive,
+    /// with the block there. Unlike [`Schematic::blocks`], positions without
+    /// a block are included too, as `None`. Goes through the cuboid in the
+    /// order blocks are stored in a file: by y, then z, then x.
+    pub fn iter_region(
+        &self,
+        min: Vector3<i64>,
+        max: Vector3<i64>,
+    ) -> impl Iterator<Item=(Vector3<i64>, Option<&Rc<BlockState>>)> + '_ {
+        let (xs, zs) = (*min.x()..=*max.x(), *min.z()..=*max.z());
+
+        (*min.y()..=*max.y())
+            .flat_map(move |y| zs.clone().map(move |z| (y, z)))
+            .flat_map(move |(y, z)| xs.clone().map(move |x| Vector3::new3(x, y, z)))
+            .map(|pos| {
+                let block = self.block_data.get(&pos);
+                (pos, block)
+            })
+    }
+
+    /// A view of the cuboid between `min` and `max`, both inclusive, that
+    /// reads and places blocks in coordinates relative to `min`.
+    pub fn region_mut(&mut self, min: Vector3<i64>, max: Vector3<i64>) -> RegionView<'_> {
+        RegionView::new(self, min, max)
+    }
+
+    /// Select every position connected to `start` through shared faces whose
+    /// block matches `predicate`, stopping once `max` positions are selected.
+    pub fn flood_select(
+        &self,
+        start: Vector3<i64>,
+        predicate: impl Fn(&Vector3<i64>, &BlockState) -> bool,
+        max: usize,
+    ) -> HashSet<Vector3<i64>> {
+        self.flood_select_with(start, &FACE_NEIGHBOURS, predicate, max)
+    }
+
+    /// Like [`flood_select`](Self::flood_select), but with a custom set of
+    /// neighbour offsets (e.g. to follow redstone wire up and down stairs).
+    pub fn flood_select_with(
+        &self,
+        start: Vector3<i64>,
+        neighbours: &[[i64; 3]],
+        predicate: impl Fn(&Vector3<i64>, &BlockState) -> bool,
+        max: usize,
+    ) -> HashSet<Vector3<i64>> {
+        let mut selected = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+
+        while let Some(pos) = queue.pop_front() {
+            if selected.len() >= max {
+                break;
+            }
+            if selected.contains(&pos) {
+                continue;
+            }
+            let Some(state) = self.block_data.get(&pos) else {
+                continue;
+            };
+            if !predicate(&pos, &**state) {
+                continue;
+            }
+
+            for [dx, dy, dz] in neighbours {
+                queue.push_back(Vector3::new3(pos.x() + dx, pos.y() + dy, pos.z() + dz));
+            }
+            selected.insert(pos);
+        }
+
+        selected
+    }
+
+    /// The smallest box holding every block, which is empty for a schematic
+    /// without blocks. Note that [`max_x`](Self::max_x) and friends are one
+    /// past the maximum of the box.
+    pub fn bounds(&self) -> BoundingBox {
+        BoundingBox::around(self.block_data.iter().map(|(pos, _)| pos))
     }
 
     pub fn len_x(&self) -> usize {
-        (self.max_x() - self.min_x()) as usize
+        self.bounds().size()[0] as usize
     }
     pub fn len_y(&self) -> usize {
-        (self.max_y() - self.min_y()) as usize
+        self.bounds().size()[1] as usize
     }
     pub fn len_z(&self) -> usize {
-        (self.max_z() - self.min_z()) as usize
+        self.bounds().size()[2] as usize
     }
 
     pub fn height(&self) -> usize {self.len_y()}
     pub fn width(&self) -> usize {self.len_x()}
     pub fn length(&self) -> usize {self.len_z()}
 
-    fn min(&self, f: impl Fn(&Vector3<i64>) -> i64) -> Option<i64> {
-        self.block_data.keys()
-            .min_by_key(|i| f(i))
-            .map(f)
-    }
-
-    fn max(&self, f: impl Fn(&Vector3<i64>) -> i64) -> Option<i64> {
-        self.block_data.keys()
-            .max_by_key(|i| f(i))
-            .map(f)
-    }
-
     pub fn min_x(&self) -> i64 {
-        self.min(|i| *i.x()).unwrap_or(0)
+        self.bounds().min()[0]
     }
 
     pub fn max_x(&self) -> i64 {
-        self.max(|i| *i.x()).map(|i| i + 1).unwrap_or(0)
+        self.bounds().max()[0] + 1
     }
 
     pub fn min_y(&self) -> i64 {
-        self.min(|i| *i.y()).unwrap_or(0)
+        self.bounds().min()[1]
     }
 
     pub fn max_y(&self) -> i64 {
-        self.max(|i| *i.y()).map(|i| i + 1).unwrap_or(0)
+        self.bounds().max()[1] + 1
     }
 
     pub fn min_z(&self) -> i64 {
-        self.min(|i| *i.z()).unwrap_or(0)
+        self.bounds().min()[2]
     }
 
     pub fn max_z(&self) -> i64 {
-        self.max(|i| *i.z()).map(|i| i + 1).unwrap_or(0)
+        self.bounds().max()[2] + 1
+    }
+}
+
+/// Schematics are equal when they hold the same blocks, block entities,
+/// entities and biomes, however their blocks are stored. The metadata, the
+/// history and the offsets are about where a schematic came from and where
+/// it goes, and don't count. See [`Schematic::content_hash`] for a hash that
+/// agrees with this.
+impl PartialEq for Schematic {
+    fn eq(&self, other: &Self) -> bool {
+        self.block_data.len() == other.block_data.len()
+            && self.block_data.iter().all(|(pos, blk)| {
+                other.block_data.get(&pos).is_some_and(|i| BlockState::same(i, blk))
+            })
+            && self.block_entities == other.block_entities
+            && self.entities == other.entities
+            && self.biomes == other.biomes
+    }
+}
+
+/// An immutable schematic that can be cloned for free and sent to other
+/// threads, e.g. to run several analysis passes over the same schematic at
+/// once. Every distinct block state is stored once. Use [`Schematic::freeze`]
+/// and [`FrozenSchematic::thaw`] to convert between the two.
+#[derive(Clone)]
+pub struct FrozenSchematic {
+    inner: Arc<Frozen>,
+}
+
+struct Frozen {
+    width: usize,
+    length: usize,
+    height: usize,
+    offset: [i32; 3],
+    data_version: i32,
+    metadata: Metadata,
+    source_hash: Option<String>,
+    format_hash: Option<String>,
+    program_hash: Option<String>,
+    states: Vec<BlockState>,
+    storage: Storage,
+    block_data: HashMap<Vector3<i64>, usize>,
+    block_entities: HashMap<Vector3<i64>, BlockEntity>,
+    entities: Vec<Entity>,
+    biomes: HashMap<Vector2<i64>, String>,
+    keep_mask: Option<HashSet<Vector3<i64>>>,
+    original_palette: BTreeMap<String, i32>,
+}
+
+impl Schematic {
+    pub fn freeze(self) -> FrozenSchematic {
+        // blocks share their states, so only convert each shared state once
+        let mut indices = HashMap::new();
+        let mut states = Vec::new();
+        let block_data = self.block_data.iter()
+            .map(|(pos, blk)| {
+                let idx = *indices.entry(Rc::as_ptr(blk)).or_insert_with(|| {
+                    states.push(blk.as_ref().clone());
+                    states.len() - 1
+                });
+                (pos, idx)
+            })
+            .collect();
+
+        FrozenSchematic {
+            inner: Arc::new(Frozen {
+                width: self.original_width,
+                length: self.original_length,
+                height: self.original_height,
+                offset: self.original_offset,
+                data_version: self.original_data_version,
+                metadata: self.original_metadata,
+                source_hash: self.source_hash,
+                format_hash: self.format_hash,
+                program_hash: self.program_hash,
+                states,
+                storage: self.block_data.requested(),
+                block_data,
+                block_entities: self.block_entities,
+                entities: self.entities,
+                biomes: self.biomes,
+                keep_mask: self.keep_mask,
+                original_palette: self.original_palette,
+            }),
+        }
+    }
+}
+
+impl FrozenSchematic {
+    /// A mutable copy of this schematic.
+    pub fn thaw(&self) -> Schematic {
+        let states: Vec<_> = self.inner.states.iter()
+            .map(|i| BlockState::intern(i.clone()))
+            .collect();
+
+        Schematic {
+            original_width: self.inner.width,
+            original_length: self.inner.length,
+            original_height: self.inner.height,
+            original_offset: self.inner.offset,
+            original_data_version: self.inner.data_version,
+            original_metadata: self.inner.metadata.clone(),
+            source_hash: self.inner.source_hash.clone(),
+            format_hash: self.inner.format_hash.clone(),
+            program_hash: self.inner.program_hash.clone(),
+            block_data: BlockStorage::from_blocks(
+                self.inner.storage,
+                self.inner.block_data.iter().map(|(pos, idx)| (pos.clone(), states[*idx].clone())),
+            ),
+            block_entities: self.inner.block_entities.clone(),
+            entities: self.inner.entities.clone(),
+            biomes: self.inner.biomes.clone(),
+            keep_mask: self.inner.keep_mask.clone(),
+            original_palette: self.inner.original_palette.clone(),
+        }
+    }
+
+    pub fn get_block(&self, loc: &Vector3<i64>) -> Option<&BlockState> {
+        self.inner.block_data.get(loc).map(|idx| &self.inner.states[*idx])
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item=(&Vector3<i64>, &BlockState)> {
+        self.inner.block_data.iter().map(|(pos, idx)| (pos, &self.inner.states[*idx]))
+    }
+
+    pub fn block_entity(&self, pos: &Vector3<i64>) -> Option<&BlockEntity> {
+        self.inner.block_entities.get(pos)
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.inner.entities
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.inner.metadata.history
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.block_data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.block_data.is_empty()
     }
 }
 
@@ -438,3 +1960,228 @@ pub struct Block {
     data: u8,
 }
 
+
+#[cfg(test)]
+impl Schematic {
+    /// A schematic holding just `blocks`, to build fixtures from.
+    pub(crate) fn from_blocks(blocks: impl IntoIterator<Item=([i64; 3], Rc<BlockState>)>) -> Self {
+        let mut res = Self::new();
+        for ([x, y, z], blk) in blocks {
+            res.set_block(Vector3::new3(x, y, z), blk);
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::Mask;
+    use crate::tags::TagRegistry;
+
+    /// A 2x1x1 schematic with `palette`, where the blocks refer to `blocks`.
+    fn format_with(palette: &[(&str, i32)], blocks: [i8; 2]) -> SchemFormat {
+        SchemFormat {
+            block_data: blocks.to_vec(),
+            block_entities: Vec::new(),
+            entities: Vec::new(),
+            biome_data: Vec::new(),
+            biome_palette: BTreeMap::new(),
+            biome_palette_max: None,
+            data_version: 0,
+            height: 1,
+            length: 1,
+            metadata: Metadata::default(),
+            offset: vec![0, 0, 0],
+            palette: palette.iter().map(|(name, i)| (name.to_string(), *i)).collect(),
+            palette_max: palette.len() as i32,
+            version: 2,
+            width: 2,
+        }
+    }
+
+    fn write_format(format: &SchemFormat) -> Vec<u8> {
+        let mut encoder = GzBuilder::new().write(Vec::new(), Compression::default());
+        to_writer(&mut encoder, format, Some("Schematic")).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn file_with(palette: &[(&str, i32)], blocks: [i8; 2]) -> Vec<u8> {
+        write_format(&format_with(palette, blocks))
+    }
+
+    #[test]
+    fn reads_modded_palettes_with_gaps() {
+        let data = file_with(&[("minecraft:stone", 0), ("create:cogwheel[axis=y]", 7)], [7, 0]);
+        let schematic = Schematic::from_bytes(data).unwrap();
+
+        let block = |x| schematic.get_block(Vector3::new3(x, 0, 0)).unwrap().to_string();
+        assert_eq!(block(0), "create:cogwheel[axis=y]");
+        assert_eq!(block(1), "minecraft:stone");
+    }
+
+    #[test]
+    fn writes_unchanged_schematics_back_the_same() {
+        // numbered differently than the blocks are found in
+        let data = file_with(&[("minecraft:stone", 0), ("minecraft:air", 1)], [1, 0]);
+        let schematic = Schematic::from_bytes(data.clone()).unwrap();
+
+        let written = schematic.to_bytes().unwrap();
+        assert_eq!(written, data);
+        assert!(Schematic::from_bytes(written).unwrap().history().is_empty());
+    }
+
+    #[test]
+    fn adds_history_when_writing_changed_schematics() {
+        let data = file_with(&[("minecraft:stone", 0), ("minecraft:air", 1)], [1, 0]);
+        let mut schematic = Schematic::from_bytes(data).unwrap();
+        schematic.set_block([0, 0, 0], BlockState::stone());
+
+        let written = Schematic::from_bytes(schematic.to_bytes().unwrap()).unwrap();
+        assert_eq!(written.history().len(), 1);
+    }
+
+    #[test]
+    fn numbers_blocks_in_order_when_deterministic() {
+        let data = file_with(&[("minecraft:stone", 0), ("minecraft:air", 1)], [1, 0]);
+        let schematic = Schematic::from_bytes(data).unwrap();
+
+        let options = WriteOptions { deterministic: true, ..Default::default() };
+        let written = Schematic::read_format(&schematic.to_bytes_with(&options).unwrap(), &ParseOptions::default()).unwrap();
+        assert_eq!(written.palette, BTreeMap::from([
+            ("minecraft:air".to_string(), 0),
+            ("minecraft:stone".to_string(), 1),
+        ]));
+        assert_eq!(written.metadata.history.len(), 1);
+    }
+
+    #[test]
+    fn leaves_out_large_palette_indices_until_they_are_used() {
+        let palette = BTreeMap::from([
+            ("minecraft:stone".to_string(), 0),
+            ("create:shaft[axis=x]".to_string(), i32::MAX),
+        ]);
+        let decoded = Schematic::decode_palette(&palette).unwrap();
+
+        assert_eq!(decoded.states.len(), 2);
+        assert_eq!(decoded.get(i32::MAX as usize).unwrap().to_string(), "create:shaft[axis=x]");
+
+        let data = file_with(&[("minecraft:stone", 0), ("create:shaft[axis=x]", i32::MAX)], [0, 0]);
+        let schematic = Schematic::from_bytes(data).unwrap();
+        assert!(schematic.blocks().all(|(_, blk)| blk.to_string() == "minecraft:stone"));
+    }
+
+    #[test]
+    fn refuses_negative_palette_indices() {
+        let data = file_with(&[("minecraft:stone", 0), ("create:shaft[axis=x]", -1)], [0, 0]);
+        assert!(Schematic::from_bytes(data).is_err());
+    }
+
+    #[test]
+    fn reads_varints_the_same_in_parallel() {
+        // a three byte varint across the end of the first chunk, followed by
+        // a mix of one to four byte varints over several more chunks
+        let mut values = vec![1; PARALLEL_CHUNK - 1];
+        values.push(300_000);
+        values.extend((0..3 * PARALLEL_CHUNK as u32).map(|i| i.wrapping_mul(2_654_435_761) >> (i % 4 * 7 + 4)));
+
+        let mut data = Vec::new();
+        for i in &values {
+            write_varint(&mut data, *i);
+        }
+        assert!(data[PARALLEL_CHUNK - 1] as u8 & 128 != 0);
+        assert!(data.len() > 2 * PARALLEL_CHUNK);
+
+        let expected: Vec<_> = values.iter().map(|i| *i as usize).collect();
+        assert_eq!(read_varints(&data, false).unwrap(), expected);
+        assert_eq!(read_varints(&data, true).unwrap(), expected);
+
+        // a varint cut off at the end fails either way
+        data.push(-1);
+        assert!(read_varints(&data, false).is_err());
+        assert!(read_varints(&data, true).is_err());
+    }
+
+    /// A 4x4x4 cube of stone, offset by 10, 20, 30 with a paste offset of -1, -2, -3.
+    fn cube() -> Schematic {
+        let mut res = Schematic::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    res.set_block(Vector3::new3(x, y, z), BlockState::stone());
+                }
+            }
+        }
+        res.original_offset = [10, 20, 30];
+        res.original_metadata.offset_x = -1;
+        res.original_metadata.offset_y = -2;
+        res.original_metadata.offset_z = -3;
+        res
+    }
+
+    fn offsets(schematic: &Schematic) -> ([i32; 3], [i32; 3]) {
+        let metadata = &schematic.original_metadata;
+        (schematic.original_offset, [metadata.offset_x, metadata.offset_y, metadata.offset_z])
+    }
+
+    #[test]
+    fn crop_moves_the_offsets_to_the_cropped_corner() {
+        let cropped = cube().crop(Vector3::new3(1, 2, 3), Vector3::new3(2, 3, 5));
+
+        assert_eq!([cropped.width(), cropped.height(), cropped.length()], [2, 2, 1]);
+        assert_eq!([cropped.min_x(), cropped.min_y(), cropped.min_z()], [0, 0, 0]);
+        assert_eq!(offsets(&cropped), ([11, 22, 33], [0, 0, 0]));
+    }
+
+    #[test]
+    fn trim_keeps_blocks_in_place_in_the_world() {
+        let mut schematic = cube();
+        schematic.replace_where(|pos, _| (*pos != Vector3::new3(2, 1, 3)).then(BlockState::air));
+        let trimmed = schematic.trim();
+
+        assert_eq!(trimmed.blocks().count(), 1);
+        assert_eq!(trimmed.get_block(Vector3::new3(0, 0, 0)), Some(BlockState::stone()));
+        assert_eq!(offsets(&trimmed), ([12, 21, 33], [1, -1, 0]));
+    }
+
+    #[test]
+    fn crop_matching_keeps_the_box_around_matching_blocks() {
+        let mut schematic = cube();
+        schematic.set_block(Vector3::new3(1, 1, 1), BlockState::new("minecraft:redstone_lamp"));
+        schematic.set_block(Vector3::new3(2, 3, 1), BlockState::new("minecraft:redstone_lamp"));
+        let cropped = schematic.crop_matching(&Mask::block("redstone_lamp"), &TagRegistry::default());
+
+        assert_eq!([cropped.width(), cropped.height(), cropped.length()], [2, 3, 1]);
+        // the stone between the lamps stays
+        assert_eq!(cropped.blocks().count(), 6);
+        assert_eq!(offsets(&cropped), ([11, 21, 31], [0, -1, -2]));
+
+        assert_eq!(schematic.crop_matching(&Mask::block("dirt"), &TagRegistry::default()).blocks().count(), 0);
+    }
+
+    #[test]
+    fn translate_moves_blocks_and_offsets_together() {
+        let mut schematic = cube();
+        schematic.translate(Vector3::new3(5, -1, 0));
+
+        assert_eq!([schematic.min_x(), schematic.min_y(), schematic.min_z()], [5, -1, 0]);
+        assert_eq!(schematic.get_block(Vector3::new3(5, -1, 0)), Some(BlockState::stone()));
+        assert_eq!(offsets(&schematic), ([15, 19, 30], [4, -3, -3]));
+    }
+
+    #[test]
+    fn keeps_short_files_claiming_a_huge_size_sparse() {
+        let mut format = format_with(&[("minecraft:stone", 0)], [0, 0]);
+        [format.width, format.height, format.length] = [i16::MAX; 3];
+        let data = write_format(&format);
+
+        let schematic = Schematic::from_bytes(&data).unwrap();
+        assert_eq!(schematic.storage(), Storage::Sparse);
+        assert_eq!(schematic.blocks().count(), 2);
+        assert_eq!(schematic.get_block(Vector3::new3(1, 0, 0)), Some(BlockState::stone()));
+
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        assert!(Schematic::from_slice_with(&data, &strict).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use perpendicular::Vector3;
+use crate::schematic::{BlockState, Schematic};
+
+/// Which blocks [`Schematic::find_blocks`] looks for. Made from a block id,
+/// like `"soul_wall_torch"` or `"minecraft:lever"`, or from a predicate, and
+/// narrowed down further with [`prop`](Self::prop) and [`filter`](Self::filter).
+pub struct BlockQuery<'a> {
+    /// Without a namespace if [`any_namespace`](Self::any_namespace) was used.
+    id: Option<String>,
+    any_namespace: bool,
+    props: HashMap<String, String>,
+    filters: Vec<Box<dyn Fn(&BlockState) -> bool + 'a>>,
+}
+
+impl<'a> BlockQuery<'a> {
+    /// Blocks with id `id`. Ids without a namespace are in `minecraft`, like
+    /// in game.
+    pub fn id(id: impl AsRef<str>) -> Self {
+        let id = id.as_ref();
+        let id = if id.contains(':') { id.to_string() } else { format!("minecraft:{id}") };
+
+        Self { id: Some(id), ..Self::all() }
+    }
+
+    /// Every block.
+    pub fn all() -> Self {
+        Self { id: None, any_namespace: false, props: HashMap::new(), filters: Vec::new() }
+    }
+
+    /// Blocks for which `predicate` returns true.
+    pub fn predicate(predicate: impl Fn(&BlockState) -> bool + 'a) -> Self {
+        Self::all().filter(predicate)
+    }
+
+    /// Match the id in any namespace, e.g. to find both vanilla blocks and
+    /// the same blocks from a mod.
+    pub fn any_namespace(mut self) -> Self {
+        self.any_namespace = true;
+        self
+    }
+
+    /// Only blocks where property `key` is `value`. Blocks without the
+    /// property don't match.
+    pub fn prop(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.props.insert(key.into(), value.into());
+        self
+    }
+
+    /// Only blocks for which `predicate` returns true as well.
+    pub fn filter(mut self, predicate: impl Fn(&BlockState) -> bool + 'a) -> Self {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    pub fn matches(&self, state: &BlockState) -> bool {
+        let id_matches = match &self.id {
+            None => true,
+            Some(id) if self.any_namespace => id.split_once(':').is_some_and(|(_, path)| path == state.path()),
+            Some(id) => id == state.id(),
+        };
+
+        id_matches
+            && self.props.iter().all(|(key, value)| state.props().get(key) == Some(value))
+            && self.filters.iter().all(|i| i(state))
+    }
+}
+
+impl From<&str> for BlockQuery<'_> {
+    fn from(id: &str) -> Self {
+        Self::id(id)
+    }
+}
+
+impl Schematic {
+    /// The positions of every block matching `query`, ordered by y, then z,
+    /// then x. `query` can be a block id:
+    /// `schematic.find_blocks("soul_wall_torch")`.
+    pub fn find_blocks<'a>(&self, query: impl Into<BlockQuery<'a>>) -> Vec<Vector3<i64>> {
+        let query = query.into();
+        self.blocks_sorted()
+            .into_iter()
+            .filter(|(_, blk)| query.matches(blk))
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{BufRead, Write};
+use color_eyre::eyre::{eyre, WrapErr};
+
+/// Name under which secrets are stored in the system keyring.
+pub const KEYRING_SERVICE: &str = "schematics";
+
+/// When this variable is set, the binary acts as an `SSH_ASKPASS` helper
+/// that prints the secret named by the variable, instead of running normally.
+pub const ASKPASS_ENV: &str = "SCHEMATICS_ASKPASS";
+
+/// A credential, like a password or key passphrase. It never shows up when
+/// printed or logged; use [`Secret::expose`] to get to the actual value.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
+
+/// The environment variable a secret can be given in, e.g. `rcon@donsz.nl`
+/// becomes `SCHEMATICS_RCON_DONSZ_NL`.
+pub fn env_var(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+
+    format!("SCHEMATICS_{name}")
+}
+
+/// Look up a secret in the system keyring, falling back to its environment
+/// variable (see [`env_var`]).
+pub fn lookup(name: &str) -> color_eyre::Result<Option<Secret>> {
+    match keyring::Entry::new(KEYRING_SERVICE, name).and_then(|i| i.get_password()) {
+        Ok(password) => return Ok(Some(Secret(password))),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => tracing::debug!("keyring unavailable for {name}: {e}"),
+    }
+
+    Ok(std::env::var(env_var(name)).ok().map(Secret))
+}
+
+/// Like [`lookup`], but fails when the secret isn't configured anywhere.
+pub fn require(name: &str) -> color_eyre::Result<Secret> {
+    lookup(name)?.ok_or_else(|| eyre!(
+        "no secret {name} configured, store it with `schematics secret set {name}` or set {}",
+        env_var(name),
+    ))
+}
+
+pub fn store(name: &str, secret: &Secret) -> color_eyre::Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, name)
+        .and_then(|i| i.set_password(secret.expose()))
+        .wrap_err("store secret in keyring")
+}
+
+pub fn delete(name: &str) -> color_eyre::Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, name)
+        .and_then(|i| i.delete_password())
+        .wrap_err("delete secret from keyring")
+}
+
+/// Read a secret from the first line of stdin.
+pub fn read_stdin() -> color_eyre::Result<Secret> {
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+
+    Ok(Secret(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Answer an ssh passphrase prompt with the secret named in [`ASKPASS_ENV`].
+pub fn askpass(name: &str) -> color_eyre::Result<()> {
+    let secret = require(name)?;
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", secret.expose())?;
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use color_eyre::eyre::{bail, WrapErr};
+use tracing::info;
+use crate::rom;
+use crate::schematic::Schematic;
+
+/// A minimal rom: 128 lines of 16 soul torches, in the same staircase layout as
+/// the real one, without any of the surrounding circuitry.
+const REFERENCE_ROM: &[u8] = include_bytes!("../assets/reference-rom.schem");
+
+/// Program the reference rom, read the program back, and check it survives a
+/// round trip through the schematic format.
+pub fn run() -> color_eyre::Result<()> {
+    let reference = Schematic::from_bytes(REFERENCE_ROM)
+        .wrap_err("load reference rom")?;
+
+    let program: Vec<u16> = (0..128u16)
+        .map(|i| i.wrapping_mul(0x0101) ^ 0x5a5a)
+        .collect();
+
+    let programmed = rom::program_rom_words(reference, program.clone())?;
+    if rom::read_rom(&programmed)? != program {
+        bail!("programmed rom doesn't contain the program");
+    }
+    info!("programming works");
+
+    let reloaded = Schematic::from_bytes(programmed.to_bytes()?)
+        .wrap_err("reload programmed rom")?;
+    if rom::read_rom(&reloaded)? != program {
+        bail!("program changed after writing and reading the schematic");
+    }
+    info!("serialization works");
+
+    info!("self-test passed");
+    Ok(())
+}
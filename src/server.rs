@@ -1,12 +1,101 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
 use std::path::Path;
-use std::process::Command;
-use color_eyre::eyre::bail;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use color_eyre::eyre::{bail, WrapErr};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use crate::history::hash_bytes;
+use crate::secrets::{self, Secret, ASKPASS_ENV};
+use crate::workspace::Workspace;
 
+pub const DEFAULT_SERVER: &str = "fili";
+
+/// How long to wait for someone else's lock on a schematic.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+/// Locks older than this were left behind by a run that didn't finish, and are
+/// taken over.
+const LOCK_STALE: Duration = Duration::from_secs(10 * 60);
+/// How much of a streamed upload is sent at once by default.
+pub const DEFAULT_UPLOAD_CHUNK: usize = 64 << 20;
+
+/// Which plugin the server loads schematics with. They keep them in different
+/// places.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SchematicPlugin {
+    #[default]
+    WorldEdit,
+    /// FastAsyncWorldEdit, which can keep a separate folder per player.
+    Fawe,
+}
+
+/// How the schematic folder of a server is reached.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerBackend {
+    /// Over ssh and scp.
+    #[default]
+    Ssh,
+    /// A directory on this machine, laid out like the server's world
+    /// directory. Runs the pipeline without a server, for tests or to try
+    /// out a workspace.
+    Local,
+}
+
+/// A server profile, configured under `[servers.<name>]` in `pipeline.toml`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ServerConfig {
     pub host: String,
     pub user: String,
+    #[serde(default = "default_port")]
     pub port: u16,
+    #[serde(default = "default_rcon_port")]
+    pub rcon_port: u16,
+    /// Directory the world and its plugins live in.
+    #[serde(default = "default_world_dir")]
+    pub world_dir: String,
+    #[serde(default)]
+    pub plugin: SchematicPlugin,
+    /// Overrides the schematic directory derived from `world_dir` and `plugin`.
+    #[serde(default)]
+    pub schematic_dir: Option<String>,
+    /// Uuid of the player whose folder to use, with FAWE's per player schematics.
+    #[serde(default)]
+    pub player: Option<String>,
+    #[serde(default = "default_extension")]
+    pub extension: String,
+    /// Upload to a temporary file, check it and rename it into place, so the
+    /// plugin never reads a half written schematic. Needs `sha256sum` on the
+    /// server.
+    #[serde(default = "default_atomic_upload")]
+    pub atomic_upload: bool,
+    #[serde(default)]
+    pub backend: ServerBackend,
+}
+
+fn default_port() -> u16 {
+    22
+}
+
+fn default_rcon_port() -> u16 {
+    25575
+}
+
+fn default_world_dir() -> String {
+    "/minecraft/active-world".to_string()
+}
+
+fn default_extension() -> String {
+    "schem".to_string()
+}
+
+fn default_atomic_upload() -> bool {
+    true
 }
 
 impl ServerConfig {
@@ -14,16 +103,99 @@ impl ServerConfig {
         Self {
             host: "donsz.nl".to_string(),
             user: "jonathan".to_string(),
-            port: 22,
+            port: default_port(),
+            rcon_port: default_rcon_port(),
+            world_dir: default_world_dir(),
+            plugin: SchematicPlugin::WorldEdit,
+            schematic_dir: None,
+            player: None,
+            extension: default_extension(),
+            atomic_upload: default_atomic_upload(),
+            backend: ServerBackend::Ssh,
+        }
+    }
+
+    /// Load the server profile called `name`, or the workspace's default
+    /// profile. Without a workspace only the built in `fili` profile exists.
+    pub fn load(name: Option<&str>) -> color_eyre::Result<Self> {
+        let config = Workspace::find_current()?.map(|i| i.config).unwrap_or_default();
+        let name = name
+            .or(config.server.as_deref())
+            .unwrap_or(DEFAULT_SERVER);
+
+        if let Some(server) = config.servers.get(name) {
+            return Ok(server.clone());
+        }
+        if name == DEFAULT_SERVER {
+            return Ok(Self::fili());
+        }
+
+        let known = config.servers.keys().map(String::as_str).join(", ");
+        bail!("no server profile called {name} (known profiles: {known})")
+    }
+
+    /// The directory on the server that schematics are loaded from.
+    pub fn schematic_dir(&self) -> String {
+        if let Some(dir) = &self.schematic_dir {
+            return dir.clone();
+        }
+
+        let world = self.world_dir.trim_end_matches('/');
+        match (self.plugin, &self.player) {
+            (SchematicPlugin::WorldEdit, _) => format!("{world}/plugins/WorldEdit/schematics"),
+            (SchematicPlugin::Fawe, None) => format!("{world}/plugins/FastAsyncWorldEdit/schematics"),
+            (SchematicPlugin::Fawe, Some(player)) => format!("{world}/plugins/FastAsyncWorldEdit/schematics/{player}"),
+        }
+    }
+
+    /// The path on the server of the schematic called `name`.
+    pub fn schematic_path(&self, name: &str) -> String {
+        format!("{}/{name}.{}", self.schematic_dir(), self.extension)
+    }
+
+    /// Name of the secret holding the passphrase of the ssh key for this server.
+    pub fn ssh_passphrase_name(&self) -> String {
+        format!("ssh:{}@{}", self.user, self.host)
+    }
+
+    /// Name of the secret holding the rcon password for this server.
+    pub fn rcon_password_name(&self) -> String {
+        format!("rcon:{}", self.host)
+    }
+
+    pub fn rcon_password(&self) -> color_eyre::Result<Secret> {
+        secrets::require(&self.rcon_password_name())
+    }
+
+    /// Run `scp` or `ssh`, answering passphrase prompts from the configured
+    /// secret if there is one. The passphrase itself is never put on the
+    /// command line or in the environment; ssh asks this binary for it through
+    /// `SSH_ASKPASS`.
+    fn ssh_command(&self, program: &str) -> color_eyre::Result<Command> {
+        let mut cmd = Command::new(program);
+
+        let name = self.ssh_passphrase_name();
+        if secrets::lookup(&name)?.is_some() {
+            cmd
+                .env("SSH_ASKPASS", std::env::current_exe()?)
+                .env("SSH_ASKPASS_REQUIRE", "force")
+                .env(ASKPASS_ENV, name);
         }
+
+        Ok(cmd)
     }
 
     fn download_file(&self, file: &Path, to: &Path) -> color_eyre::Result<()> {
-        let ServerConfig { host, user, port } = self;
+        if self.backend == ServerBackend::Local {
+            fs::copy(file, to).wrap_err_with(|| format!("copy {}", file.display()))?;
+            return Ok(());
+        }
+
+        let ServerConfig { host, user, port, .. } = self;
         let file = file.to_string_lossy();
         let to = to.to_string_lossy();
 
-        let mut cmd = Command::new("scp");
+        let mut cmd = self.ssh_command("scp")?;
         cmd
             .args(["-P", port.to_string().as_ref()])
             .arg(format!("{user}@{host}:{file}"))
@@ -40,11 +212,16 @@ impl ServerConfig {
     }
 
     fn upload_file(&self, file: &Path, from: &Path) -> color_eyre::Result<()> {
-        let ServerConfig { host, user, port } = self;
+        if self.backend == ServerBackend::Local {
+            fs::copy(from, file).wrap_err_with(|| format!("copy to {}", file.display()))?;
+            return Ok(());
+        }
+
+        let ServerConfig { host, user, port, .. } = self;
         let file = file.to_string_lossy();
         let from = from.to_string_lossy();
 
-        let mut cmd = Command::new("scp");
+        let mut cmd = self.ssh_command("scp")?;
         cmd
             .args(["-P", port.to_string().as_ref()])
             .arg(format!("{from}"))
@@ -60,11 +237,283 @@ impl ServerConfig {
         Ok(())
     }
 
+    /// Append `data` to `file` on the server.
+    fn append_file(&self, file: &str, data: &[u8]) -> color_eyre::Result<()> {
+        if self.backend == ServerBackend::Local {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .and_then(|mut f| f.write_all(data))
+                .wrap_err_with(|| format!("append to {file}"))?;
+            return Ok(());
+        }
+
+        let mut child = self.remote_command(&format!("cat >> {}", quote(file)))?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // dropping stdin closes it, so cat finishes
+        child.stdin.take().unwrap().write_all(data).wrap_err("send chunk")?;
+
+        let out = child.wait_with_output()?;
+        if !out.status.success() {
+            bail!("ssh unsuccessful: {}", String::from_utf8_lossy(&out.stderr));
+        }
+
+        Ok(())
+    }
+
+    /// Run `command` on the server with ssh, returning what it printed. With
+    /// the local backend it runs here.
+    fn ssh(&self, command: &str) -> color_eyre::Result<String> {
+        let mut cmd = self.remote_command(command)?;
+        tracing::info!("{} {}", cmd.get_program().to_string_lossy(), cmd.get_args().map(|i| i.to_string_lossy()).join(" "));
+
+        let out = cmd.output()?;
+        if !out.status.success() {
+            bail!("ssh unsuccessful: {}", String::from_utf8_lossy(&out.stderr));
+        }
+
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    /// The command running `command` on the server, or here with the local
+    /// backend.
+    fn remote_command(&self, command: &str) -> color_eyre::Result<Command> {
+        let ServerConfig { host, user, port, .. } = self;
+
+        let cmd = match self.backend {
+            ServerBackend::Ssh => {
+                let mut cmd = self.ssh_command("ssh")?;
+                cmd
+                    .args(["-p", port.to_string().as_ref()])
+                    .arg(format!("{user}@{host}"))
+                    .arg(command);
+                cmd
+            }
+            ServerBackend::Local => {
+                let mut cmd = Command::new("sh");
+                cmd.args(["-c", command]);
+                cmd
+            }
+        };
+
+        Ok(cmd)
+    }
+
+    /// The names of all schematics on the server.
+    pub fn list_schematics(&self) -> color_eyre::Result<Vec<String>> {
+        let out = self.ssh(&format!("ls -1 {}", quote(&self.schematic_dir())))?;
+
+        let extension = format!(".{}", self.extension);
+        Ok(out
+            .lines()
+            .filter_map(|i| i.strip_suffix(&extension))
+            .map(ToString::to_string)
+            .sorted()
+            .collect())
+    }
+
+    /// Take the advisory lock on the schematic called `name`, waiting for
+    /// whoever holds it now. The lock is a `.lock` file next to the schematic
+    /// saying who took it and when, created with the shell's `noclobber` so
+    /// only one of two people taking it at the same time gets it. Every
+    /// upload and download takes it, so nobody reads a schematic while it's
+    /// being written.
+    pub fn lock_schematic(&self, name: &str) -> color_eyre::Result<RemoteLock<'_>> {
+        let path = format!("{}.lock", self.schematic_path(name));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let contents = format!("{} {}", lock_owner(), now.as_secs());
+
+        let script = format!(
+            "if (set -C; printf '%s\\n' {} > {}) 2>/dev/null; then echo locked; else cat {}; fi",
+            quote(&contents), quote(&path), quote(&path),
+        );
+
+        let start = SystemTime::now();
+        loop {
+            let out = self.ssh(&script)?;
+            if out.trim() == "locked" {
+                return Ok(RemoteLock { server: self, path });
+            }
+
+            // empty when the lock was removed since trying to take it
+            if let Some((owner, taken)) = out.trim().rsplit_once(' ') {
+                let taken = taken.parse().map(Duration::from_secs).unwrap_or_default();
+                let age = SystemTime::now().duration_since(UNIX_EPOCH)?.saturating_sub(taken);
+
+                if age > LOCK_STALE {
+                    warn!("taking over the lock on {name}, left behind by {owner} {}s ago", age.as_secs());
+                    self.ssh(&format!("rm -f {}", quote(&path)))?;
+                    continue;
+                }
+                if start.elapsed()? > LOCK_TIMEOUT {
+                    bail!("{name} is locked by {owner} since {}s ago, remove {path} if that's wrong", age.as_secs());
+                }
+
+                tracing::info!("waiting for {owner} to release the lock on {name}");
+            }
+
+            thread::sleep(Duration::from_secs(2));
+        }
+    }
+
     pub fn download_schematic(&self, name: impl AsRef<str>, to: impl AsRef<Path>) -> color_eyre::Result<()> {
-        self.download_file(format!("/minecraft/active-world/plugins/WorldEdit/schematics/{}.schem", name.as_ref()).as_ref(), to.as_ref())
+        let _lock = self.lock_schematic(name.as_ref())?;
+        self.download_file(self.schematic_path(name.as_ref()).as_ref(), to.as_ref())
     }
 
     pub fn upload_schematic(&self, from: impl AsRef<Path>, name: impl AsRef<str>) -> color_eyre::Result<()> {
-        self.upload_file(format!("/minecraft/active-world/plugins/WorldEdit/schematics/{}.schem", name.as_ref()).as_ref(), from.as_ref())
+        let _lock = self.lock_schematic(name.as_ref())?;
+        let path = self.schematic_path(name.as_ref());
+        if !self.atomic_upload {
+            return self.upload_file(path.as_ref(), from.as_ref());
+        }
+
+        let tmp = format!("{path}.tmp");
+        self.upload_file(tmp.as_ref(), from.as_ref())?;
+
+        let expected = hash_bytes(&std::fs::read(from)?);
+        self.verify_and_move(&tmp, &path, &expected)
+            .wrap_err_with(|| format!("verify upload of {}", name.as_ref()))
+    }
+
+    /// Upload the file `write` writes as `name`, without keeping all of it in
+    /// memory or on disk here: it's sent `chunk_size` bytes at a time and
+    /// appended to a temporary file on the server, which is checked and moved
+    /// into place at the end, like with `atomic_upload`. Only the file is
+    /// streamed: [`Schematic::to_writer_with`](crate::schematic::Schematic::to_writer_with)
+    /// still encodes all blocks in memory before it writes anything, so this
+    /// saves the memory of the compressed file, not of the schematic. Returns
+    /// the number of bytes uploaded.
+    pub fn upload_schematic_streaming(
+        &self,
+        name: &str,
+        chunk_size: usize,
+        write: impl FnOnce(&mut dyn Write) -> color_eyre::Result<()>,
+    ) -> color_eyre::Result<u64> {
+        let _lock = self.lock_schematic(name)?;
+        let path = self.schematic_path(name);
+        let tmp = format!("{path}.tmp");
+        self.ssh(&format!("rm -f {}", quote(&tmp)))?;
+
+        let mut upload = ChunkedUpload {
+            server: self,
+            path: &tmp,
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size: chunk_size.max(1),
+            hasher: Sha256::new(),
+            uploaded: 0,
+        };
+        let res = write(&mut upload).and_then(|()| {
+            upload.flush()?;
+            Ok(())
+        });
+        if let Err(e) = res {
+            if let Err(e) = self.ssh(&format!("rm -f {}", quote(&tmp))) {
+                warn!("failed to remove {tmp}: {e:#}");
+            }
+            return Err(e.wrap_err(format!("upload {name}")));
+        }
+
+        let expected = format!("{:x}", upload.hasher.finalize());
+        self.verify_and_move(&tmp, &path, &expected)
+            .wrap_err_with(|| format!("verify upload of {name}"))?;
+
+        Ok(upload.uploaded)
     }
-}
\ No newline at end of file
+
+    /// Check that `tmp` on the server has hash `expected` and move it to
+    /// `path`, or remove it if it doesn't.
+    fn verify_and_move(&self, tmp: &str, path: &str, expected: &str) -> color_eyre::Result<()> {
+        let check = self.ssh(&format!("sha256sum {}", quote(&tmp)))
+            .and_then(|out| match out.split_whitespace().next() {
+                Some(hash) if hash == expected => Ok(()),
+                Some(hash) => bail!("uploaded file has hash {hash}, expected {expected}"),
+                None => bail!("sha256sum printed nothing"),
+            });
+        if let Err(e) = check {
+            if let Err(e) = self.ssh(&format!("rm -f {}", quote(tmp))) {
+                warn!("failed to remove {tmp}: {e:#}");
+            }
+            return Err(e);
+        }
+
+        // a rename within a directory replaces the file in one step
+        self.ssh(&format!("mv -f {} {}", quote(tmp), quote(path)))?;
+        Ok(())
+    }
+}
+
+/// Sends what's written to it to a file on a server in chunks, see
+/// [`ServerConfig::upload_schematic_streaming`].
+struct ChunkedUpload<'a> {
+    server: &'a ServerConfig,
+    path: &'a str,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    /// Of everything written so far, to check the file on the server with.
+    hasher: Sha256,
+    uploaded: u64,
+}
+
+impl Write for ChunkedUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() >= self.chunk_size {
+            self.flush()?;
+        }
+
+        Ok(len)
+    }
+
+    /// Send what's buffered now, even if it's less than a chunk.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.server.append_file(self.path, &self.buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:#}")))?;
+        self.hasher.update(&self.buffer);
+        self.uploaded += self.buffer.len() as u64;
+        tracing::info!("uploaded {} MiB", self.uploaded >> 20);
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+/// The advisory lock on a schematic on a server, released when dropped.
+pub struct RemoteLock<'a> {
+    server: &'a ServerConfig,
+    path: String,
+}
+
+impl Drop for RemoteLock<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.server.ssh(&format!("rm -f {}", quote(&self.path))) {
+            warn!("failed to release the lock {}: {e:#}", self.path);
+        }
+    }
+}
+
+/// Who is taking a lock, to tell other people waiting for it.
+fn lock_owner() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let host = std::fs::read_to_string("/etc/hostname")
+        .map(|i| i.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    format!("{user}@{host}/{}", std::process::id())
+}
+
+/// Quote `s` as one word for the remote shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
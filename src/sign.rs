@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use color_eyre::eyre::bail;
+use nbt::Value;
+use perpendicular::Vector3;
+use serde_json::json;
+use crate::schematic::{BlockEntity, Schematic};
+
+pub const LINES: usize = 4;
+
+/// The text on a sign, without the NBT. Lines are plain text; formatting in
+/// existing signs is dropped when reading them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sign {
+    front: [String; LINES],
+    back: [String; LINES],
+    pub waxed: bool,
+}
+
+/// Turn a JSON text component into plain text.
+fn plain_text(component: &serde_json::Value) -> String {
+    match component {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(parts) => parts.iter().map(plain_text).collect(),
+        serde_json::Value::Object(obj) => {
+            let mut res = obj.get("text").and_then(|i| i.as_str()).unwrap_or_default().to_string();
+            if let Some(extra) = obj.get("extra") {
+                res.push_str(&plain_text(extra));
+            }
+            res
+        }
+        _ => String::new(),
+    }
+}
+
+fn parse_line(line: &Value) -> String {
+    match line {
+        Value::String(s) => serde_json::from_str(s)
+            .map(|i| plain_text(&i))
+            .unwrap_or_else(|_| s.clone()),
+        _ => String::new(),
+    }
+}
+
+fn encode_line(line: &str) -> Value {
+    Value::String(json!({ "text": line }).to_string())
+}
+
+fn fill_lines(lines: &[&str]) -> color_eyre::Result<[String; LINES]> {
+    if lines.len() > LINES {
+        bail!("a sign has {LINES} lines, not {}", lines.len());
+    }
+
+    Ok(std::array::from_fn(|i| lines.get(i).unwrap_or(&"").to_string()))
+}
+
+fn text_compound(lines: &[String; LINES]) -> Value {
+    Value::Compound([
+        ("messages".to_string(), Value::List(lines.iter().map(|i| encode_line(i)).collect())),
+        ("color".to_string(), Value::String("black".to_string())),
+        ("has_glowing_text".to_string(), Value::Byte(0)),
+    ].into_iter().collect())
+}
+
+impl Sign {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the lines on the front, leaving the rest empty.
+    pub fn set_lines(&mut self, lines: &[&str]) -> color_eyre::Result<()> {
+        self.front = fill_lines(lines)?;
+        Ok(())
+    }
+
+    pub fn get_lines(&self) -> &[String; LINES] {
+        &self.front
+    }
+
+    pub fn set_back_lines(&mut self, lines: &[&str]) -> color_eyre::Result<()> {
+        self.back = fill_lines(lines)?;
+        Ok(())
+    }
+
+    pub fn get_back_lines(&self) -> &[String; LINES] {
+        &self.back
+    }
+
+    /// Read a sign's block entity, in the 1.20 format with `front_text` and
+    /// `back_text`, or the older one with `Text1` to `Text4`.
+    pub fn from_entity(entity: &BlockEntity) -> color_eyre::Result<Self> {
+        if !entity.id().ends_with("sign") {
+            bail!("{} is not a sign", entity.id());
+        }
+
+        let props = entity.props();
+        let mut res = Self::new();
+
+        let read_side = |side: &str, into: &mut [String; LINES]| {
+            if let Some(Value::Compound(text)) = props.get(side) {
+                if let Some(Value::List(messages)) = text.get("messages") {
+                    for (line, message) in into.iter_mut().zip(messages) {
+                        *line = parse_line(message);
+                    }
+                }
+            }
+        };
+        read_side("front_text", &mut res.front);
+        read_side("back_text", &mut res.back);
+
+        for (idx, line) in res.front.iter_mut().enumerate() {
+            if let Some(old) = props.get(&format!("Text{}", idx + 1)) {
+                *line = parse_line(old);
+            }
+        }
+
+        res.waxed = matches!(props.get("is_waxed"), Some(Value::Byte(1)));
+        Ok(res)
+    }
+
+    /// The block entity for this sign, in the 1.20 format.
+    pub fn to_entity(&self) -> BlockEntity {
+        let props = HashMap::from([
+            ("front_text".to_string(), text_compound(&self.front)),
+            ("back_text".to_string(), text_compound(&self.back)),
+            ("is_waxed".to_string(), Value::Byte(self.waxed as i8)),
+        ]);
+
+        BlockEntity::new("minecraft:sign", props)
+    }
+}
+
+impl Schematic {
+    /// The sign at `pos`, if there is one.
+    pub fn sign(&self, pos: &Vector3<i64>) -> Option<color_eyre::Result<Sign>> {
+        self.block_entity(pos).map(Sign::from_entity)
+    }
+
+    /// Set the text of the sign at `pos`. The sign block itself has to be
+    /// placed separately.
+    pub fn set_sign(&mut self, pos: Vector3<i64>, sign: &Sign) {
+        self.set_block_entity(pos, sign.to_entity());
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use crate::secrets::{self, Secret};
+
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Name of the secret holding the signing key called `name`.
+pub fn signing_key_name(name: &str) -> String {
+    format!("signing:{name}")
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|i| format!("{i:02x}")).collect()
+}
+
+fn from_hex<const N: usize>(s: &str) -> color_eyre::Result<[u8; N]> {
+    let s = s.trim();
+    if s.len() != N * 2 || !s.is_ascii() {
+        bail!("expected {} hex digits, got {}", N * 2, s.len());
+    }
+
+    let mut res = [0; N];
+    for (idx, byte) in res.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[idx * 2..idx * 2 + 2], 16)
+            .map_err(|_| eyre!("invalid hex digits {:?}", &s[idx * 2..idx * 2 + 2]))?;
+    }
+
+    Ok(res)
+}
+
+/// Where the detached signature of `path` is stored: next to it, with `.sig`
+/// appended.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut res = path.as_os_str().to_owned();
+    res.push(".");
+    res.push(SIGNATURE_EXTENSION);
+    res.into()
+}
+
+/// Generate a new signing key, keep it in the secret store, and return the
+/// public key that signatures can be verified with.
+pub fn generate_key(name: &str) -> color_eyre::Result<VerifyingKey> {
+    let key = SigningKey::generate(&mut OsRng);
+    secrets::store(&signing_key_name(name), &Secret::new(to_hex(&key.to_bytes())))?;
+
+    Ok(key.verifying_key())
+}
+
+fn signing_key(name: &str) -> color_eyre::Result<SigningKey> {
+    let secret = secrets::require(&signing_key_name(name))?;
+    Ok(SigningKey::from_bytes(&from_hex(secret.expose()).wrap_err("invalid signing key")?))
+}
+
+pub fn write_public_key(key: &VerifyingKey, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+    fs::write(path, to_hex(key.as_bytes()) + "\n").wrap_err("write public key")
+}
+
+pub fn read_public_key(path: impl AsRef<Path>) -> color_eyre::Result<VerifyingKey> {
+    let key = fs::read_to_string(path).wrap_err("read public key")?;
+    Ok(VerifyingKey::from_bytes(&from_hex(&key)?)?)
+}
+
+/// Sign the file at `path` with the signing key `key_name`, writing the
+/// signature next to it. Returns the path of the signature.
+pub fn sign_file(path: impl AsRef<Path>, key_name: &str) -> color_eyre::Result<PathBuf> {
+    let path = path.as_ref();
+    let data = fs::read(path).wrap_err("read file to sign")?;
+
+    let signature = signing_key(key_name)?.sign(&data);
+    let signature_path = signature_path(path);
+    fs::write(&signature_path, to_hex(&signature.to_bytes()) + "\n")
+        .wrap_err("write signature")?;
+
+    Ok(signature_path)
+}
+
+/// Check the detached signature of the file at `path` against `key`.
+pub fn verify_file(path: impl AsRef<Path>, key: &VerifyingKey) -> color_eyre::Result<()> {
+    let path = path.as_ref();
+    let data = fs::read(path).wrap_err("read file to verify")?;
+
+    let signature_path = signature_path(path);
+    let signature = fs::read_to_string(&signature_path)
+        .wrap_err_with(|| format!("read signature {}", signature_path.display()))?;
+    let signature = Signature::from_bytes(&from_hex(&signature)?);
+
+    key.verify(&data, &signature)
+        .map_err(|_| eyre!("{} is not signed by this key", path.display()))
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use color_eyre::eyre::{bail, WrapErr};
+use flate2::read::GzDecoder;
+use nbt::Value;
+use serde::{Deserialize, Serialize};
+use crate::schematic::{read_varint, Metadata, SchemBlockEntity, SchemEntity, SchemFormat};
+
+/// Version 3 of the sponge schematic format. It holds the same as version 2,
+/// laid out differently: everything is nested in a `Schematic` compound,
+/// blocks and biomes get a compound each, and biomes are stored per block
+/// instead of per column. Sizes are unsigned, so a schematic can be up to
+/// 65535 blocks along each axis instead of 32767.
+///
+/// Schematics are read from and written to version 3 files by converting
+/// from and to [`SchemFormat`].
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SchemFileV3 {
+    #[serde(rename="Schematic")]
+    schematic: SchemFormatV3,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct SchemFormatV3 {
+    version: i32,
+    data_version: i32,
+    #[serde(default)]
+    metadata: Metadata,
+    width: i16,
+    height: i16,
+    length: i16,
+    #[serde(serialize_with="nbt::i32_array")]
+    offset: Vec<i32>,
+    blocks: Blocks,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    biomes: Option<Biomes>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    entities: Vec<EntityV3>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct Blocks {
+    palette: BTreeMap<String, i32>,
+    #[serde(serialize_with="nbt::i8_array")]
+    data: Vec<i8>,
+    #[serde(default)]
+    block_entities: Vec<BlockEntityV3>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct Biomes {
+    palette: BTreeMap<String, i32>,
+    /// One biome per block, ordered by y, then z, then x.
+    #[serde(serialize_with="nbt::i8_array")]
+    data: Vec<i8>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct BlockEntityV3 {
+    id: String,
+    #[serde(serialize_with="nbt::i32_array")]
+    pos: Vec<i32>,
+    #[serde(default)]
+    data: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="PascalCase")]
+struct EntityV3 {
+    id: String,
+    pos: Vec<f64>,
+    #[serde(default)]
+    data: BTreeMap<String, Value>,
+}
+
+impl From<SchemFormat> for SchemFileV3 {
+    fn from(format: SchemFormat) -> Self {
+        // a layer of blocks has its biomes in the same order as the columns,
+        // so every layer gets a copy of the column data
+        let height = format.size()[1];
+        let biomes = if format.biome_palette.is_empty() {
+            None
+        } else {
+            Some(Biomes {
+                data: format.biome_data.repeat(height),
+                palette: format.biome_palette,
+            })
+        };
+
+        Self {
+            schematic: SchemFormatV3 {
+                version: 3,
+                data_version: format.data_version,
+                metadata: format.metadata,
+                width: format.width,
+                height: format.height,
+                length: format.length,
+                offset: format.offset,
+                blocks: Blocks {
+                    palette: format.palette,
+                    data: format.block_data,
+                    block_entities: format.block_entities.into_iter()
+                        .map(|i| BlockEntityV3 { id: i.id, pos: i.pos, data: i.props })
+                        .collect(),
+                },
+                biomes,
+                entities: format.entities.into_iter()
+                    .map(|i| EntityV3 { id: i.id, pos: i.pos, data: i.props })
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl SchemFileV3 {
+    /// The same schematic as a version 2 format. Only the biomes of the
+    /// lowest layer are kept, since version 2 has one biome per column.
+    pub(crate) fn into_format(self) -> color_eyre::Result<SchemFormat> {
+        let format = self.schematic;
+        let (biome_data, biome_palette) = match format.biomes {
+            Some(biomes) => {
+                let columns = (format.width as u16 as usize) * (format.length as u16 as usize);
+                let mut end = 0;
+                for _ in 0..columns {
+                    read_varint(&biomes.data, &mut end).wrap_err("read biomes")?;
+                }
+                (biomes.data[..end].to_vec(), biomes.palette)
+            }
+            None => Default::default(),
+        };
+
+        Ok(SchemFormat {
+            palette_max: format.blocks.palette.len() as i32,
+            palette: format.blocks.palette,
+            block_data: format.blocks.data,
+            block_entities: format.blocks.block_entities.into_iter()
+                .map(|i| SchemBlockEntity { id: i.id, pos: i.pos, props: i.data })
+                .collect(),
+            data_version: format.data_version,
+            entities: format.entities.into_iter()
+                .map(|i| SchemEntity { id: i.id, pos: i.pos, props: i.data })
+                .collect(),
+            biome_palette_max: (!biome_palette.is_empty()).then_some(biome_palette.len() as i32),
+            biome_data,
+            biome_palette,
+            height: format.height,
+            length: format.length,
+            metadata: format.metadata,
+            offset: format.offset,
+            version: format.version,
+            width: format.width,
+        })
+    }
+}
+
+/// The type and name of the next tag, or 0 and nothing at the end of a
+/// compound.
+fn read_named(r: &mut impl Read) -> color_eyre::Result<(u8, String)> {
+    let mut tag = [0];
+    r.read_exact(&mut tag).wrap_err("read nbt")?;
+    if tag[0] == 0 {
+        return Ok((0, String::new()));
+    }
+
+    let mut len = [0; 2];
+    r.read_exact(&mut len).wrap_err("read nbt")?;
+    let mut name = vec![0; u16::from_be_bytes(len) as usize];
+    r.read_exact(&mut name).wrap_err("read nbt")?;
+    Ok((tag[0], String::from_utf8_lossy(&name).into_owned()))
+}
+
+/// Whether the nbt in `data` is a version 3 schematic: a root compound with a
+/// `Schematic` compound as its first field. Version 2 files have the fields
+/// of the schematic directly in the root.
+pub(crate) fn is_v3(data: &[u8], gzipped: bool) -> color_eyre::Result<bool> {
+    let mut r: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(Cursor::new(data)))
+    } else {
+        Box::new(Cursor::new(data))
+    };
+
+    let (root, _) = read_named(&mut r)?;
+    if root != 10 {
+        bail!("schematic doesn't start with a compound");
+    }
+    let (tag, field) = read_named(&mut r)?;
+    Ok(tag == 10 && field == "Schematic")
+}
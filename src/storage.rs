@@ -0,0 +1,660 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use perpendicular::Vector3;
+use crate::schematic::BlockState;
+
+/// How a [`Schematic`](crate::schematic::Schematic) keeps its blocks. They
+/// all behave the same, they only differ in how fast they are and how much
+/// memory they take.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Storage {
+    /// Whichever of the two below fits how full the box around all blocks is,
+    /// switching as blocks are placed and removed. See [`DENSE_ABOVE`] and
+    /// [`SPARSE_BELOW`].
+    #[default]
+    Automatic,
+    /// A map from position to block. Best for a few blocks spread out over a
+    /// large area, like a few edits to a rom.
+    Sparse,
+    /// Like the file format: every distinct block state once, and one index
+    /// per position in the box around all blocks. Best for full cuboids, like
+    /// schematics read from a file, which take several times less memory this
+    /// way.
+    Dense,
+    /// A map from position to block like [`Storage::Sparse`], but ordered by
+    /// y, then z, then x, so [`Schematic::blocks`](crate::schematic::Schematic::blocks)
+    /// always returns blocks in the same order. Slower to edit than sparse
+    /// storage; meant for reports and tests that have to be reproducible.
+    Ordered,
+}
+
+/// With [`Storage::Automatic`], switch to dense storage once more than this
+/// part of the box around all blocks is filled. A position in dense storage
+/// takes 4 bytes, a block in sparse storage about ten times as much.
+pub const DENSE_ABOVE: f64 = 0.25;
+/// With [`Storage::Automatic`], switch back to sparse storage once less than
+/// this part of the box is filled. Lower than [`DENSE_ABOVE`], so editing
+/// around the threshold doesn't convert back and forth all the time.
+pub const SPARSE_BELOW: f64 = 0.0625;
+/// Schematics with fewer blocks than this always stay sparse with
+/// [`Storage::Automatic`]; the difference doesn't matter for them.
+const MIN_DENSE_BLOCKS: usize = 4096;
+
+/// Positions without a block in [`DenseBlocks::blocks`].
+const EMPTY: u32 = u32::MAX;
+
+/// The largest box of blocks read from a file that is kept dense, 1 GiB of
+/// indices.
+const MAX_DENSE_VOLUME: usize = 1 << 28;
+
+#[derive(Clone)]
+pub(crate) struct BlockStorage {
+    blocks: Blocks,
+    automatic: bool,
+    /// Blocks placed or removed since the density was last checked.
+    changes: usize,
+}
+
+#[derive(Clone)]
+enum Blocks {
+    Sparse(HashMap<Vector3<i64>, Rc<BlockState>>),
+    Dense(DenseBlocks),
+    /// Keyed by y, z and x, in that order.
+    Ordered(BTreeMap<[i64; 3], Rc<BlockState>>),
+}
+
+fn ordered_key(pos: &Vector3<i64>) -> [i64; 3] {
+    [*pos.y(), *pos.z(), *pos.x()]
+}
+
+impl BlockStorage {
+    pub(crate) fn new(storage: Storage) -> Self {
+        Self::from_blocks(storage, [])
+    }
+
+    /// Blocks in `storage`, with states that are the same `Rc` stored once.
+    pub(crate) fn from_blocks(storage: Storage, blocks: impl IntoIterator<Item=(Vector3<i64>, Rc<BlockState>)>) -> Self {
+        let blocks = match storage {
+            Storage::Sparse | Storage::Automatic => Blocks::Sparse(blocks.into_iter().collect()),
+            Storage::Dense => {
+                let mut states = Vec::new();
+                let mut indices = HashMap::new();
+                let blocks = blocks.into_iter()
+                    .map(|(pos, blk)| {
+                        let idx = *indices.entry(Rc::as_ptr(&blk)).or_insert_with(|| {
+                            states.push(blk.clone());
+                            states.len() as u32 - 1
+                        });
+                        ([*pos.x(), *pos.y(), *pos.z()], idx)
+                    })
+                    .collect();
+
+                Blocks::Dense(DenseBlocks::from_positions(states, blocks))
+            }
+            Storage::Ordered => Blocks::Ordered(blocks.into_iter().map(|(pos, blk)| (ordered_key(&pos), blk)).collect()),
+        };
+
+        Self::with_blocks(blocks, storage == Storage::Automatic)
+    }
+
+    /// The blocks of a schematic file: a box at 0, 0, 0 of `size`, with
+    /// `blocks` ordered by y, then z, then x. They stay dense until enough of
+    /// them are removed, unless the file holds fewer blocks than fit in the
+    /// box, or the box is too large to allocate up front: a lenient read of a
+    /// damaged file shouldn't take more memory than the blocks it actually
+    /// holds.
+    pub(crate) fn from_cuboid(size: [usize; 3], states: Vec<Rc<BlockState>>, blocks: Vec<u32>) -> Self {
+        let volume = size.iter().product::<usize>();
+        if blocks.len() == volume && volume <= MAX_DENSE_VOLUME {
+            return Self::with_blocks(Blocks::Dense(DenseBlocks::from_cuboid(size, states, blocks)), true);
+        }
+
+        let [width, _, length] = size.map(|i| i as i64);
+        let blocks = blocks.into_iter()
+            .enumerate()
+            .map(|(index, idx)| {
+                let index = index as i64;
+                let pos = Vector3::new3(index % width, index / (width * length), index / width % length);
+                (pos, states[idx as usize].clone())
+            })
+            .collect();
+        Self::with_blocks(Blocks::Sparse(blocks), true)
+    }
+
+    fn with_blocks(blocks: Blocks, automatic: bool) -> Self {
+        let mut res = Self { blocks, automatic, changes: 0 };
+        res.rebalance();
+
+        res
+    }
+
+    /// Where the blocks are kept right now, anything but
+    /// [`Storage::Automatic`].
+    pub(crate) fn storage(&self) -> Storage {
+        match self.blocks {
+            Blocks::Sparse(_) => Storage::Sparse,
+            Blocks::Dense(_) => Storage::Dense,
+            Blocks::Ordered(_) => Storage::Ordered,
+        }
+    }
+
+    /// What the blocks were asked to be kept in.
+    pub(crate) fn requested(&self) -> Storage {
+        if self.automatic {
+            Storage::Automatic
+        } else {
+            self.storage()
+        }
+    }
+
+    /// The same blocks, kept in `storage`.
+    pub(crate) fn converted(&self, storage: Storage) -> Self {
+        match (&self.blocks, storage) {
+            (Blocks::Sparse(_), Storage::Sparse)
+            | (Blocks::Dense(_), Storage::Dense)
+            | (Blocks::Ordered(_), Storage::Ordered) => {
+                Self { automatic: false, ..self.clone() }
+            }
+            (Blocks::Ordered(_), Storage::Automatic) => {
+                Self::with_blocks(Self::from_blocks(Storage::Sparse, self.cloned()).blocks, true)
+            }
+            (_, Storage::Automatic) => Self::with_blocks(self.blocks.clone(), true),
+            _ => Self::from_blocks(storage, self.cloned()),
+        }
+    }
+
+    fn cloned(&self) -> impl Iterator<Item=(Vector3<i64>, Rc<BlockState>)> + '_ {
+        self.iter().map(|(pos, blk)| (pos, blk.clone()))
+    }
+
+    pub(crate) fn get(&self, pos: &Vector3<i64>) -> Option<&Rc<BlockState>> {
+        match &self.blocks {
+            Blocks::Sparse(blocks) => blocks.get(pos),
+            Blocks::Dense(blocks) => blocks.get([*pos.x(), *pos.y(), *pos.z()]),
+            Blocks::Ordered(blocks) => blocks.get(&ordered_key(pos)),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, pos: Vector3<i64>, state: Rc<BlockState>) {
+        // growing the box for a block far away could take more memory than
+        // all blocks together, so switch to sparse storage before it does
+        if let Blocks::Dense(blocks) = &self.blocks {
+            let at = [*pos.x(), *pos.y(), *pos.z()];
+            if self.automatic && blocks.index(at).is_none() {
+                let (_, size) = blocks.grown(at);
+                let volume: f64 = size.iter().map(|i| *i as f64).product();
+                if (blocks.len + 1) as f64 / volume < SPARSE_BELOW {
+                    self.blocks = Self::from_blocks(Storage::Sparse, self.iter().map(|(pos, blk)| (pos, blk.clone()))).blocks;
+                    self.changes = 0;
+                }
+            }
+        }
+
+        match &mut self.blocks {
+            Blocks::Sparse(blocks) => {
+                blocks.insert(pos, state);
+            }
+            Blocks::Dense(blocks) => blocks.insert([*pos.x(), *pos.y(), *pos.z()], state),
+            Blocks::Ordered(blocks) => {
+                blocks.insert(ordered_key(&pos), state);
+            }
+        }
+        self.changed();
+    }
+
+    pub(crate) fn remove(&mut self, pos: &Vector3<i64>) -> Option<Rc<BlockState>> {
+        let res = match &mut self.blocks {
+            Blocks::Sparse(blocks) => blocks.remove(pos),
+            Blocks::Dense(blocks) => blocks.remove([*pos.x(), *pos.y(), *pos.z()]),
+            Blocks::Ordered(blocks) => blocks.remove(&ordered_key(pos)),
+        };
+        if res.is_some() {
+            self.changed();
+        }
+
+        res
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.blocks {
+            Blocks::Sparse(blocks) => blocks.len(),
+            Blocks::Dense(blocks) => blocks.len,
+            Blocks::Ordered(blocks) => blocks.len(),
+        }
+    }
+
+    /// Every block. Dense and ordered storage return them by y, then z, then
+    /// x; sparse storage in no particular order.
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item=(Vector3<i64>, &Rc<BlockState>)> + '_> {
+        match &self.blocks {
+            Blocks::Sparse(blocks) => Box::new(blocks.iter().map(|(pos, blk)| (pos.clone(), blk))),
+            Blocks::Dense(blocks) => Box::new(blocks.iter().map(|([x, y, z], blk)| (Vector3::new3(x, y, z), blk))),
+            Blocks::Ordered(blocks) => Box::new(blocks.iter().map(|([y, z, x], blk)| (Vector3::new3(*x, *y, *z), blk))),
+        }
+    }
+
+    /// Whether [`BlockStorage::iter`] returns blocks by y, then z, then x.
+    pub(crate) fn is_ordered(&self) -> bool {
+        !matches!(self.blocks, Blocks::Sparse(_))
+    }
+
+    /// Only the blocks for which `keep` returns true.
+    pub(crate) fn filtered(&self, keep: impl Fn(&Vector3<i64>) -> bool) -> Self {
+        let blocks = match &self.blocks {
+            Blocks::Sparse(blocks) => Blocks::Sparse(blocks.iter()
+                .filter(|(pos, _)| keep(pos))
+                .map(|(pos, blk)| (pos.clone(), blk.clone()))
+                .collect()),
+            Blocks::Dense(blocks) => Blocks::Dense(DenseBlocks::from_positions(
+                blocks.states.clone(),
+                blocks.indices()
+                    .filter(|([x, y, z], _)| keep(&Vector3::new3(*x, *y, *z)))
+                    .collect(),
+            )),
+            Blocks::Ordered(blocks) => Blocks::Ordered(blocks.iter()
+                .filter(|([y, z, x], _)| keep(&Vector3::new3(*x, *y, *z)))
+                .map(|(key, blk)| (*key, blk.clone()))
+                .collect()),
+        };
+
+        Self::with_blocks(blocks, self.automatic)
+    }
+
+    /// Every block moved by `pos` and changed by `state`. With dense storage,
+    /// `state` is only called once per distinct state.
+    pub(crate) fn mapped(
+        &self,
+        pos: impl Fn(&Vector3<i64>) -> Vector3<i64>,
+        mut state: impl FnMut(&Rc<BlockState>) -> Rc<BlockState>,
+    ) -> Self {
+        let blocks = match &self.blocks {
+            Blocks::Sparse(blocks) => Blocks::Sparse(blocks.iter()
+                .map(|(p, blk)| (pos(p), state(blk)))
+                .collect()),
+            Blocks::Dense(blocks) => Blocks::Dense(DenseBlocks::from_positions(
+                blocks.states.iter().map(&mut state).collect(),
+                blocks.indices()
+                    .map(|([x, y, z], idx)| {
+                        let new = pos(&Vector3::new3(x, y, z));
+                        ([*new.x(), *new.y(), *new.z()], idx)
+                    })
+                    .collect(),
+            )),
+            Blocks::Ordered(blocks) => Blocks::Ordered(blocks.iter()
+                .map(|([y, z, x], blk)| (ordered_key(&pos(&Vector3::new3(*x, *y, *z))), state(blk)))
+                .collect()),
+        };
+
+        Self::with_blocks(blocks, self.automatic)
+    }
+
+    /// Count a placed or removed block, and check the density once enough
+    /// blocks changed. Checking takes time proportional to the number of
+    /// blocks, so waiting for a quarter of them to change keeps it cheap.
+    fn changed(&mut self) {
+        if !self.automatic {
+            return;
+        }
+
+        self.changes += 1;
+        if self.changes >= (self.len() / 4).max(MIN_DENSE_BLOCKS / 4) {
+            self.rebalance();
+        }
+    }
+
+    /// With automatic storage, switch to whichever storage fits the density.
+    fn rebalance(&mut self) {
+        self.changes = 0;
+        if !self.automatic {
+            return;
+        }
+
+        let len = self.len();
+        let density = match self.volume() {
+            0 => 0.0,
+            volume => len as f64 / volume as f64,
+        };
+        let target = match self.storage() {
+            Storage::Sparse if len >= MIN_DENSE_BLOCKS && density > DENSE_ABOVE => Storage::Dense,
+            Storage::Dense if len < MIN_DENSE_BLOCKS || density < SPARSE_BELOW => Storage::Sparse,
+            _ => return,
+        };
+
+        let converted = Self::from_blocks(target, self.cloned());
+        self.blocks = converted.blocks;
+    }
+
+    /// The volume of the box the blocks take up: for dense storage the box
+    /// that's allocated, for sparse storage the smallest box around them.
+    fn volume(&self) -> usize {
+        match &self.blocks {
+            Blocks::Sparse(blocks) => {
+                let mut positions = blocks.keys().map(|i| [*i.x(), *i.y(), *i.z()]);
+                let Some(first) = positions.next() else {
+                    return 0;
+                };
+
+                let (mut min, mut max) = (first, first);
+                for pos in positions {
+                    for axis in 0..3 {
+                        min[axis] = min[axis].min(pos[axis]);
+                        max[axis] = max[axis].max(pos[axis]);
+                    }
+                }
+                (0..3).map(|axis| (max[axis] - min[axis]) as usize + 1).product()
+            }
+            Blocks::Dense(blocks) => blocks.blocks.len(),
+            // never automatic
+            Blocks::Ordered(blocks) => blocks.len(),
+        }
+    }
+}
+
+/// Blocks in a box, stored as indices into a list of states.
+#[derive(Clone, Default)]
+struct DenseBlocks {
+    /// The lowest corner of the box.
+    min: [i64; 3],
+    /// Width (x), height (y) and length (z) of the box.
+    size: [usize; 3],
+    /// Every state placed so far. States are never removed, so the pointers
+    /// in `indices` stay valid.
+    states: Vec<Rc<BlockState>>,
+    indices: HashMap<*const BlockState, u32>,
+    /// Indices into `states`, or [`EMPTY`], ordered by y, then z, then x.
+    blocks: Vec<u32>,
+    /// The number of positions that aren't empty.
+    len: usize,
+}
+
+impl DenseBlocks {
+    /// A full box at 0, 0, 0 of `size`, with `blocks` ordered by y, then z,
+    /// then x.
+    fn from_cuboid(size: [usize; 3], states: Vec<Rc<BlockState>>, blocks: Vec<u32>) -> Self {
+        debug_assert_eq!(blocks.len(), size.iter().product::<usize>());
+        let mut res = Self {
+            min: [0, 0, 0],
+            size,
+            indices: HashMap::new(),
+            len: blocks.iter().filter(|i| **i != EMPTY).count(),
+            states,
+            blocks,
+        };
+        res.index_states();
+
+        res
+    }
+
+    /// Blocks at arbitrary positions, in the smallest box holding all of them.
+    fn from_positions(states: Vec<Rc<BlockState>>, blocks: Vec<([i64; 3], u32)>) -> Self {
+        let Some((first, _)) = blocks.first() else {
+            let mut res = Self { states, ..Self::default() };
+            res.index_states();
+            return res;
+        };
+
+        let (mut min, mut max) = (*first, *first);
+        for (pos, _) in &blocks {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(pos[axis]);
+                max[axis] = max[axis].max(pos[axis]);
+            }
+        }
+
+        let size = [0, 1, 2].map(|axis| (max[axis] - min[axis]) as usize + 1);
+        let mut res = Self {
+            min,
+            size,
+            states,
+            indices: HashMap::new(),
+            blocks: vec![EMPTY; size.iter().product()],
+            len: 0,
+        };
+        for (pos, idx) in blocks {
+            let i = res.index(pos).unwrap();
+            if res.blocks[i] == EMPTY {
+                res.len += 1;
+            }
+            res.blocks[i] = idx;
+        }
+        res.index_states();
+
+        res
+    }
+
+    fn index_states(&mut self) {
+        for (idx, state) in self.states.iter().enumerate() {
+            self.indices.entry(Rc::as_ptr(state)).or_insert(idx as u32);
+        }
+    }
+
+    fn index(&self, pos: [i64; 3]) -> Option<usize> {
+        let mut local = [0; 3];
+        for axis in 0..3 {
+            let offset = pos[axis] - self.min[axis];
+            if offset < 0 || offset as usize >= self.size[axis] {
+                return None;
+            }
+            local[axis] = offset as usize;
+        }
+
+        let [x, y, z] = local;
+        Some((y * self.size[2] + z) * self.size[0] + x)
+    }
+
+    fn position(&self, index: usize) -> [i64; 3] {
+        let layer = self.size[0] * self.size[2];
+        let local = [index % self.size[0], index / layer, index % layer / self.size[0]];
+        [0, 1, 2].map(|axis| self.min[axis] + local[axis] as i64)
+    }
+
+    fn get(&self, pos: [i64; 3]) -> Option<&Rc<BlockState>> {
+        match self.blocks[self.index(pos)?] {
+            EMPTY => None,
+            idx => Some(&self.states[idx as usize]),
+        }
+    }
+
+    fn insert(&mut self, pos: [i64; 3], state: Rc<BlockState>) {
+        let idx = match self.indices.get(&Rc::as_ptr(&state)) {
+            Some(idx) => *idx,
+            None => {
+                self.indices.insert(Rc::as_ptr(&state), self.states.len() as u32);
+                self.states.push(state);
+                self.states.len() as u32 - 1
+            }
+        };
+
+        let i = match self.index(pos) {
+            Some(i) => i,
+            None => {
+                self.grow(pos);
+                self.index(pos).unwrap()
+            }
+        };
+        if self.blocks[i] == EMPTY {
+            self.len += 1;
+        }
+        self.blocks[i] = idx;
+    }
+
+    fn remove(&mut self, pos: [i64; 3]) -> Option<Rc<BlockState>> {
+        let i = self.index(pos)?;
+        match std::mem::replace(&mut self.blocks[i], EMPTY) {
+            EMPTY => None,
+            idx => {
+                self.len -= 1;
+                Some(self.states[idx as usize].clone())
+            }
+        }
+    }
+
+    /// The lowest corner and size of the box after [growing](Self::grow) it
+    /// to hold `pos`.
+    fn grown(&self, pos: [i64; 3]) -> ([i64; 3], [usize; 3]) {
+        if self.blocks.is_empty() {
+            return (pos, [1, 1, 1]);
+        }
+
+        let mut min = self.min;
+        let mut size = self.size;
+        for axis in 0..3 {
+            let (low, high) = (self.min[axis], self.min[axis] + self.size[axis] as i64);
+            let slack = (self.size[axis] / 2) as i64;
+            let new_low = if pos[axis] < low { pos[axis].min(low - slack) } else { low };
+            let new_high = if pos[axis] >= high { (pos[axis] + 1).max(high + slack) } else { high };
+            min[axis] = new_low;
+            size[axis] = (new_high - new_low) as usize;
+        }
+
+        (min, size)
+    }
+
+    /// Make the box large enough to hold `pos`. It grows by at least half its
+    /// size on every side that needs to grow, so placing blocks one by one in
+    /// a line doesn't copy everything for every block.
+    fn grow(&mut self, pos: [i64; 3]) {
+        if self.blocks.is_empty() {
+            *self = Self { min: pos, size: [1, 1, 1], blocks: vec![EMPTY], ..self.clone() };
+            return;
+        }
+
+        let (min, size) = self.grown(pos);
+        let old = std::mem::replace(self, Self {
+            min,
+            size,
+            states: Vec::new(),
+            indices: HashMap::new(),
+            blocks: vec![EMPTY; size.iter().product()],
+            len: self.len,
+        });
+        self.states = old.states;
+        self.indices = old.indices;
+
+        // copy row by row, rows along x stay contiguous
+        for y in 0..old.size[1] {
+            for z in 0..old.size[2] {
+                let from = (y * old.size[2] + z) * old.size[0];
+                let start = [old.min[0], old.min[1] + y as i64, old.min[2] + z as i64];
+                let to = self.index(start).unwrap();
+                self.blocks[to..to + old.size[0]].copy_from_slice(&old.blocks[from..from + old.size[0]]);
+            }
+        }
+    }
+
+    /// The position and state index of every block.
+    fn indices(&self) -> impl Iterator<Item=([i64; 3], u32)> + '_ {
+        self.blocks.iter()
+            .enumerate()
+            .filter(|(_, idx)| **idx != EMPTY)
+            .map(|(i, idx)| (self.position(i), *idx))
+    }
+
+    fn iter(&self) -> impl Iterator<Item=([i64; 3], &Rc<BlockState>)> + '_ {
+        self.indices().map(|(pos, idx)| (pos, &self.states[idx as usize]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use perpendicular::Vector3;
+    use crate::schematic::BlockState;
+    use super::{BlockStorage, Storage, DENSE_ABOVE, SPARSE_BELOW};
+
+    /// A full 16x16x16 cube, which automatic storage keeps dense.
+    fn cube() -> BlockStorage {
+        let blocks = (0..16).flat_map(|x| (0..16).flat_map(move |y| (0..16).map(move |z| {
+            (Vector3::new3(x, y, z), BlockState::stone())
+        })));
+        BlockStorage::from_blocks(Storage::Automatic, blocks)
+    }
+
+    #[test]
+    fn switches_to_sparse_instead_of_growing_far() {
+        let mut blocks = cube();
+        assert_eq!(blocks.storage(), Storage::Dense);
+
+        let far = Vector3::new3(1_000_000, 0, 0);
+        blocks.insert(far.clone(), BlockState::stone());
+        assert_eq!(blocks.storage(), Storage::Sparse);
+        assert_eq!(blocks.get(&far), Some(&BlockState::stone()));
+        assert_eq!(blocks.len(), 16 * 16 * 16 + 1);
+    }
+
+    #[test]
+    fn grows_dense_storage_nearby() {
+        let mut blocks = cube();
+        blocks.insert(Vector3::new3(16, 0, 0), BlockState::stone());
+        assert_eq!(blocks.storage(), Storage::Dense);
+        assert_eq!(blocks.len(), 16 * 16 * 16 + 1);
+    }
+
+    /// The blocks by position, so storages that return them in a different
+    /// order can be compared.
+    fn sorted(blocks: &BlockStorage) -> Vec<([i64; 3], String)> {
+        let mut res: Vec<_> = blocks.iter()
+            .map(|(pos, blk)| ([*pos.x(), *pos.y(), *pos.z()], blk.to_string()))
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn switches_to_dense_storage_once_filled() {
+        let mut blocks = BlockStorage::new(Storage::Automatic);
+        // the corners first, so the box around the blocks is 32x32x32 from the start
+        blocks.insert(Vector3::new3(0, 0, 0), BlockState::stone());
+        blocks.insert(Vector3::new3(31, 31, 31), BlockState::stone());
+
+        let mut layers = 0;
+        for y in 0..32 {
+            for z in 0..32 {
+                for x in 0..32 {
+                    blocks.insert(Vector3::new3(x, y, z), BlockState::stone());
+                }
+            }
+            layers += 1;
+
+            let density = blocks.len() as f64 / (32 * 32 * 32) as f64;
+            if density < DENSE_ABOVE {
+                assert_eq!(blocks.storage(), Storage::Sparse, "after {layers} layers");
+            }
+        }
+        let mut expected = Vec::new();
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    expected.push(([x, y, z], "minecraft:stone".to_string()));
+                }
+            }
+        }
+
+        assert_eq!(blocks.storage(), Storage::Dense);
+        assert_eq!(blocks.requested(), Storage::Automatic);
+        assert_eq!(sorted(&blocks), expected);
+    }
+
+    #[test]
+    fn switches_to_sparse_storage_once_cleared() {
+        let mut blocks = BlockStorage::from_cuboid([64, 64, 64], vec![BlockState::stone()], vec![0; 64 * 64 * 64]);
+        assert_eq!(blocks.storage(), Storage::Dense);
+
+        // keep the slices at x = 0 and x = 32, which is less than
+        // SPARSE_BELOW of the box but more blocks than are always kept sparse
+        let all: Vec<_> = blocks.iter().map(|(pos, _)| pos).collect();
+        let mut expected = sorted(&blocks);
+        expected.retain(|([x, _, _], _)| x % 32 == 0);
+        for pos in all.iter().filter(|pos| pos.x() % 32 != 0) {
+            blocks.remove(pos);
+
+            let density = blocks.len() as f64 / (64 * 64 * 64) as f64;
+            if density > SPARSE_BELOW {
+                assert_eq!(blocks.storage(), Storage::Dense, "with {} blocks", blocks.len());
+            }
+        }
+
+        assert_eq!(blocks.storage(), Storage::Sparse);
+        assert_eq!(blocks.len(), 2 * 64 * 64);
+        assert_eq!(sorted(&blocks), expected);
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use color_eyre::eyre::{bail, WrapErr};
+use tracing::info;
+use crate::history::hash_bytes;
+use crate::queue::Queue;
+use crate::schematic::{Schematic, WriteOptions};
+use crate::workspace::Workspace;
+
+/// Directory of the store inside a workspace.
+pub const WORKSPACE_STORE: &str = ".store";
+
+const OBJECTS: &str = "objects";
+const REFS: &str = "refs";
+
+/// Fail unless `hash` looks like a hash of [`hash_bytes`]: 64 lowercase hex
+/// digits. Hashes become paths in the store, and come from refs on disk and
+/// from requests to the server, so anything else is refused before it's used.
+fn check_hash(hash: &str) -> color_eyre::Result<()> {
+    if hash.len() != 64 || !hash.bytes().all(|i| matches!(i, b'0'..=b'9' | b'a'..=b'f')) {
+        bail!("invalid object hash {hash:?}");
+    }
+
+    Ok(())
+}
+
+/// A content addressed store of generated schematics. Every schematic is kept
+/// under the hash of its file, and can be given names (refs) which point at a
+/// hash. Objects no ref points at are removed by [`Store::gc`].
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    pub fn open(root: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        for dir in [OBJECTS, REFS] {
+            fs::create_dir_all(root.join(dir))
+                .wrap_err_with(|| format!("create store {dir} directory"))?;
+        }
+
+        Ok(Self { root })
+    }
+
+    /// The store of the current workspace, or the one in the user's cache
+    /// directory outside of workspaces.
+    pub fn open_default() -> color_eyre::Result<Self> {
+        if let Some(workspace) = Workspace::find_current()? {
+            return Self::open(workspace.root().join(WORKSPACE_STORE));
+        }
+
+        let cache = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".cache"),
+                None => bail!("can't find a cache directory for the store, set XDG_CACHE_HOME"),
+            },
+        };
+
+        Self::open(cache.join("schematics").join("store"))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn object_path(&self, hash: &str) -> color_eyre::Result<PathBuf> {
+        check_hash(hash)?;
+        Ok(self.root.join(OBJECTS).join(&hash[..2]).join(format!("{hash}.schem")))
+    }
+
+    fn ref_path(&self, name: &str) -> color_eyre::Result<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            bail!("invalid ref name {name:?}");
+        }
+
+        Ok(self.root.join(REFS).join(name))
+    }
+
+    /// Store the bytes of a schematic file, returning its hash.
+    pub fn add_bytes(&self, data: &[u8]) -> color_eyre::Result<String> {
+        let hash = hash_bytes(data);
+        let path = self.object_path(&hash)?;
+
+        if !path.exists() {
+            fs::create_dir_all(path.parent().expect("objects are in a directory"))?;
+            fs::write(&path, data).wrap_err("write object")?;
+        }
+
+        Ok(hash)
+    }
+
+    pub fn add(&self, schematic: &Schematic, options: &WriteOptions) -> color_eyre::Result<String> {
+        self.add_bytes(&schematic.to_bytes_with(options)?)
+    }
+
+    /// Point the ref `name` at `hash`, replacing what it pointed at before.
+    pub fn set_ref(&self, name: &str, hash: &str) -> color_eyre::Result<()> {
+        if !self.object_path(hash)?.is_file() {
+            bail!("no object {hash} in the store");
+        }
+
+        fs::write(self.ref_path(name)?, hash).wrap_err_with(|| format!("write ref {name}"))
+    }
+
+    /// All refs and the hashes they point at, sorted by name.
+    pub fn refs(&self) -> color_eyre::Result<Vec<(String, String)>> {
+        let mut res = Vec::new();
+        for entry in fs::read_dir(self.root.join(REFS))? {
+            let entry = entry?;
+            let hash = fs::read_to_string(entry.path())?.trim().to_string();
+            res.push((entry.file_name().to_string_lossy().into_owned(), hash));
+        }
+        res.sort();
+
+        Ok(res)
+    }
+
+    /// Resolve a ref name, a hash or a prefix of a hash to the hash of an object.
+    pub fn resolve(&self, name: &str) -> color_eyre::Result<String> {
+        if let Ok(path) = self.ref_path(name) {
+            if path.is_file() {
+                let hash = fs::read_to_string(path)?.trim().to_string();
+                check_hash(&hash).wrap_err_with(|| format!("ref {name} is corrupt"))?;
+                return Ok(hash);
+            }
+        }
+
+        if name.len() >= 4 && name.chars().all(|i| i.is_ascii_hexdigit()) {
+            let matching: Vec<_> = self.objects()?
+                .into_iter()
+                .filter(|(hash, _)| hash.starts_with(name))
+                .collect();
+
+            match matching.as_slice() {
+                [(hash, _)] => return Ok(hash.clone()),
+                [] => {}
+                _ => bail!("{name} is ambiguous, it matches {} objects", matching.len()),
+            }
+        }
+
+        bail!("no ref or object called {name} in the store")
+    }
+
+    /// The path of the object `name` resolves to.
+    pub fn path(&self, name: &str) -> color_eyre::Result<PathBuf> {
+        self.object_path(&self.resolve(name)?)
+    }
+
+    pub fn get(&self, name: &str) -> color_eyre::Result<Schematic> {
+        Schematic::from_file(self.path(name)?)
+    }
+
+    /// Every object in the store, with its path.
+    fn objects(&self) -> color_eyre::Result<Vec<(String, PathBuf)>> {
+        let mut res = Vec::new();
+        for dir in fs::read_dir(self.root.join(OBJECTS))? {
+            for entry in fs::read_dir(dir?.path())? {
+                let path = entry?.path();
+                if let Some(hash) = path.file_stem() {
+                    res.push((hash.to_string_lossy().into_owned(), path));
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Remove objects no ref points at and that aren't queued for upload,
+    /// except the `keep` most recently added ones. Returns how many objects
+    /// were removed.
+    pub fn gc(&self, keep: usize) -> color_eyre::Result<usize> {
+        let mut referenced: Vec<_> = self.refs()?.into_iter().map(|(_, hash)| hash).collect();
+        // queued schematics haven't been uploaded yet
+        let queue = Queue::open(Store { root: self.root.clone() })?;
+        referenced.extend(queue.entries()?.into_iter().map(|i| i.hash));
+
+        let mut unreferenced = Vec::new();
+        for (hash, path) in self.objects()? {
+            if !referenced.contains(&hash) {
+                let modified = fs::metadata(&path)?.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                unreferenced.push((modified, path));
+            }
+        }
+
+        // newest first
+        unreferenced.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let mut removed = 0;
+        for (_, path) in unreferenced.into_iter().skip(keep) {
+            fs::remove_file(&path).wrap_err_with(|| format!("remove {}", path.display()))?;
+            removed += 1;
+        }
+
+        info!("removed {removed} objects from the store");
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::hash_bytes;
+    use super::check_hash;
+
+    #[test]
+    fn refuses_hashes_that_would_leave_the_store() {
+        assert!(check_hash(&hash_bytes(b"schematic")).is_ok());
+        assert!(check_hash("../../../pipeline.toml").is_err());
+        assert!(check_hash(&"A".repeat(64)).is_err());
+        assert!(check_hash("").is_err());
+    }
+}
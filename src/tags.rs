@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use color_eyre::eyre::WrapErr;
+use perpendicular::Vector3;
+use crate::mask::Mask;
+use crate::schematic::{BlockState, Schematic};
+
+const BUILTIN_TAGS: &str = include_str!("../data/tags.json");
+
+/// Nested tags are followed at most this deep, which also stops cycles.
+const MAX_DEPTH: usize = 16;
+
+/// Add the `minecraft` namespace to ids that don't have one.
+pub fn qualify(id: &str) -> String {
+    if id.contains(':') {
+        id.to_string()
+    } else {
+        format!("minecraft:{id}")
+    }
+}
+
+/// Groups of block ids, like the block tags in a datapack. Tags are stored in a
+/// json object mapping tag names to lists of ids, where entries starting with
+/// `#` include another tag.
+#[derive(Default, Clone)]
+pub struct TagRegistry {
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl TagRegistry {
+    /// The tags shipped with this crate: the common vanilla groups plus a few
+    /// of our own under the `schematics` namespace.
+    pub fn builtin() -> Self {
+        Self::from_json(BUILTIN_TAGS).expect("builtin tags are valid")
+    }
+
+    pub fn from_json(data: &str) -> color_eyre::Result<Self> {
+        let tags: HashMap<String, Vec<String>> = serde_json::from_str(data)
+            .wrap_err("parse tags")?;
+
+        Ok(Self {
+            tags: tags.into_iter()
+                .map(|(k, v)| (qualify(&k), v))
+                .collect(),
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        Self::from_json(&fs::read_to_string(path).wrap_err("read tags file")?)
+    }
+
+    /// Add the tags of `other`, appending to tags that exist in both.
+    pub fn extend(&mut self, other: TagRegistry) {
+        for (k, v) in other.tags {
+            self.tags.entry(k).or_default().extend(v);
+        }
+    }
+
+    pub fn is_tag(&self, tag: &str) -> bool {
+        self.tags.contains_key(&qualify(tag))
+    }
+
+    /// Whether `id` is part of `tag` (written without the `#`).
+    pub fn contains(&self, tag: &str, id: &str) -> bool {
+        self.contains_inner(&qualify(tag), id, 0)
+    }
+
+    fn contains_inner(&self, tag: &str, id: &str, depth: usize) -> bool {
+        if depth > MAX_DEPTH {
+            return false;
+        }
+
+        let Some(entries) = self.tags.get(tag) else {
+            return false;
+        };
+
+        entries.iter().any(|entry| match entry.strip_prefix('#') {
+            Some(nested) => self.contains_inner(&qualify(nested), id, depth + 1),
+            None => qualify(entry) == id,
+        })
+    }
+}
+
+impl Schematic {
+    pub fn find_matching(&self, mask: &Mask, tags: &TagRegistry) -> Vec<Vector3<i64>> {
+        self.blocks_sorted()
+            .into_iter()
+            .filter(|(_, blk)| mask.matches_state(blk, tags))
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+
+    /// A copy of this schematic cut down to the smallest box holding every
+    /// block matching `mask`, like [`Schematic::trim`] does for blocks that
+    /// aren't air. Without any matching blocks, the result is empty.
+    pub fn crop_matching(&self, mask: &Mask, tags: &TagRegistry) -> Schematic {
+        self.crop_around(|blk| mask.matches_state(blk, tags))
+    }
+
+    /// Replace every block matching `mask` with `state`, returning how many
+    /// blocks were replaced.
+    pub fn replace_matching(&mut self, mask: &Mask, tags: &TagRegistry, state: Rc<BlockState>) -> usize {
+        self.replace_where(|_, blk| mask.matches_state(blk, tags).then(|| state.clone()))
+    }
+}
@@ -0,0 +1,170 @@
+use std::rc::Rc;
+use perpendicular::Vector3;
+use crate::logic::Region;
+use crate::rotation::RotationRules;
+use crate::schematic::{Axis, BlockState};
+
+const DIRECTIONS: [(&str, [i64; 3]); 6] = [
+    ("north", [0, 0, -1]),
+    ("south", [0, 0, 1]),
+    ("east", [1, 0, 0]),
+    ("west", [-1, 0, 0]),
+    ("up", [0, 1, 0]),
+    ("down", [0, -1, 0]),
+];
+
+fn direction_name(offset: [i64; 3]) -> Option<&'static str> {
+    DIRECTIONS.iter().find(|(_, i)| *i == offset).map(|(name, _)| *name)
+}
+
+fn direction_offset(name: &str) -> Option<[i64; 3]> {
+    DIRECTIONS.iter().find(|(i, _)| *i == name).map(|(_, offset)| *offset)
+}
+
+/// A rigid transformation of block positions: any combination of quarter turns,
+/// mirroring and translation. Transforms are composed with [`Transform::then`],
+/// and apply to positions, regions and block states alike, so that a rotated
+/// schematic keeps its torches attached to the right walls.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Transform {
+    /// Rows of the linear part, always a signed permutation matrix.
+    matrix: [[i64; 3]; 3],
+    translation: [i64; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        matrix: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+        translation: [0, 0, 0],
+    };
+
+    pub fn translate(by: [i64; 3]) -> Self {
+        Self {
+            translation: by,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// Turn clockwise around the y axis, seen from above, `quarter_turns` times.
+    pub fn rotate_y(quarter_turns: i64) -> Self {
+        let matrix = match quarter_turns.rem_euclid(4) {
+            0 => Self::IDENTITY.matrix,
+            // east becomes south
+            1 => [[0, 0, -1], [0, 1, 0], [1, 0, 0]],
+            2 => [[-1, 0, 0], [0, 1, 0], [0, 0, -1]],
+            _ => [[0, 0, 1], [0, 1, 0], [-1, 0, 0]],
+        };
+
+        Self {
+            matrix,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// Mirror along `axis`, so that coordinate is negated.
+    pub fn mirror(axis: Axis) -> Self {
+        let mut res = Self::IDENTITY;
+        let idx = match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        };
+        res.matrix[idx][idx] = -1;
+        res
+    }
+
+    /// First apply this transform, then `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        let mut matrix = [[0; 3]; 3];
+        for (row, out) in matrix.iter_mut().enumerate() {
+            for (col, value) in out.iter_mut().enumerate() {
+                *value = (0..3).map(|k| next.matrix[row][k] * self.matrix[k][col]).sum();
+            }
+        }
+
+        Transform {
+            matrix,
+            translation: next.apply(self.translation),
+        }
+    }
+
+    /// The transform that undoes this one.
+    pub fn inverse(&self) -> Transform {
+        // the inverse of a signed permutation matrix is its transpose
+        let mut matrix = [[0; 3]; 3];
+        for (row, out) in matrix.iter_mut().enumerate() {
+            for (col, value) in out.iter_mut().enumerate() {
+                *value = self.matrix[col][row];
+            }
+        }
+
+        let linear = Transform { matrix, translation: [0, 0, 0] };
+        let back = linear.apply_offset(self.translation);
+        Transform {
+            matrix,
+            translation: [-back[0], -back[1], -back[2]],
+        }
+    }
+
+    /// Transform a direction or a difference between positions, which
+    /// translation doesn't affect.
+    pub fn apply_offset(&self, offset: [i64; 3]) -> [i64; 3] {
+        self.matrix.map(|row| (0..3).map(|i| row[i] * offset[i]).sum())
+    }
+
+    pub fn apply(&self, pos: [i64; 3]) -> [i64; 3] {
+        let linear = self.apply_offset(pos);
+        [0, 1, 2].map(|i| linear[i] + self.translation[i])
+    }
+
+    pub fn apply_vector(&self, pos: &Vector3<i64>) -> Vector3<i64> {
+        let [x, y, z] = self.apply([*pos.x(), *pos.y(), *pos.z()]);
+        Vector3::new3(x, y, z)
+    }
+
+    /// Transform a point that can be anywhere inside a block, like the
+    /// position of an entity. The block at `pos` covers `pos` up to `pos + 1`,
+    /// so points are moved along with the block they're in.
+    pub fn apply_point(&self, point: [f64; 3]) -> [f64; 3] {
+        let centered = point.map(|i| i - 0.5);
+        let linear = self.matrix.map(|row| (0..3).map(|i| row[i] as f64 * centered[i]).sum::<f64>());
+        [0, 1, 2].map(|i| linear[i] + self.translation[i] as f64 + 0.5)
+    }
+
+    pub fn apply_region(&self, region: &Region) -> Region {
+        let a = self.apply(region.min);
+        let b = self.apply(region.max);
+
+        Region {
+            min: [0, 1, 2].map(|i| a[i].min(b[i])),
+            max: [0, 1, 2].map(|i| a[i].max(b[i])),
+        }
+    }
+
+    /// Transform a direction name like `north`.
+    pub fn apply_direction(&self, direction: &str) -> Option<&'static str> {
+        direction_name(self.apply_offset(direction_offset(direction)?))
+    }
+
+    /// Whether this transform mirrors, rather than only turns and moves.
+    pub fn is_mirrored(&self) -> bool {
+        let m = &self.matrix;
+        let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+
+        determinant < 0
+    }
+
+    /// Transform the properties of a block state that refer to directions,
+    /// using the builtin [`RotationRules`].
+    pub fn apply_state(&self, state: &BlockState) -> Rc<BlockState> {
+        RotationRules::builtin().apply(self, state)
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use color_eyre::eyre::WrapErr;
+use perpendicular::Vector3;
+use serde::Serialize;
+use crate::schematic::{BlockState, Schematic, FACE_NEIGHBOURS};
+
+const INDEX_HTML: &str = include_str!("../assets/web/index.html");
+
+/// The faces of a schematic that can be seen, as triangles, laid out the way
+/// a three.js `BufferGeometry` takes them. Every block except air is drawn
+/// as a full cube.
+#[derive(Serialize, Default)]
+pub struct Geometry {
+    /// Width (x), height (y) and length (z), to point the camera at.
+    pub size: [usize; 3],
+    /// Three coordinates per vertex, six vertices per face.
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    /// Red, green and blue per vertex, from 0 to 1.
+    pub colors: Vec<f32>,
+    /// For every face, the index in `states` of the block it belongs to.
+    pub faces: Vec<u32>,
+    pub states: Vec<String>,
+}
+
+impl Geometry {
+    pub fn of(schematic: &Schematic) -> Self {
+        let min = schematic.bounds().min();
+        let mut res = Self {
+            size: [schematic.width(), schematic.height(), schematic.length()],
+            ..Self::default()
+        };
+        let mut states = HashMap::new();
+
+        for (pos, blk) in schematic.blocks_sorted() {
+            if blk.is_air() {
+                continue;
+            }
+
+            let color = color_of(blk);
+            let name = blk.to_string();
+            let next = states.len() as u32;
+            let state = *states.entry(name.clone()).or_insert_with(|| {
+                res.states.push(name);
+                next
+            });
+            let local = [*pos.x() - min[0], *pos.y() - min[1], *pos.z() - min[2]].map(|i| i as f32);
+
+            for normal in &FACE_NEIGHBOURS {
+                let [dx, dy, dz] = *normal;
+                let covered = schematic.get_block(Vector3::new3(pos.x() + dx, pos.y() + dy, pos.z() + dz))
+                    .is_some_and(|i| is_opaque(&i));
+                if !covered {
+                    res.push_face(local, *normal, color, state);
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Two triangles covering the side of the block at `pos` facing `normal`.
+    fn push_face(&mut self, pos: [f32; 3], normal: [i64; 3], color: [f32; 3], state: u32) {
+        let axis = normal.iter().position(|i| *i != 0).unwrap();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        let corner = |du: f32, dv: f32| {
+            let mut res = pos;
+            if normal[axis] > 0 {
+                res[axis] += 1.0;
+            }
+            res[u] += du;
+            res[v] += dv;
+            res
+        };
+        let corners = [corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)];
+
+        for i in [0, 1, 2, 0, 2, 3] {
+            self.positions.extend(corners[i]);
+            self.normals.extend(normal.map(|i| i as f32));
+            self.colors.extend(color);
+        }
+        self.faces.push(state);
+    }
+}
+
+/// Whether a block hides the faces of the blocks next to it. Glass, redstone
+/// components and other blocks that aren't full cubes don't.
+fn is_opaque(state: &BlockState) -> bool {
+    const SEE_THROUGH: &[&str] = &[
+        "glass", "pane", "torch", "redstone_wire", "repeater", "comparator", "lever", "button",
+        "pressure_plate", "rail", "carpet", "slab", "stairs", "door", "sign", "banner", "fence", "wall",
+        "leaves", "ladder", "water", "lava", "lantern", "chain", "barrier",
+    ];
+
+    !state.is_air() && !SEE_THROUGH.iter().any(|i| state.path().contains(i))
+}
+
+/// The colour to draw a block in. Common blocks have a colour close to their
+/// texture, all others get one made up from their id.
+fn color_of(state: &BlockState) -> [f32; 3] {
+    let name = state.path();
+    let known = [
+        ("redstone", 0xaa1010),
+        ("cobblestone", 0x6e6e6e),
+        ("stone", 0x7f7f7f),
+        ("glass", 0xc0e0f0),
+        ("quartz", 0xece6df),
+        ("concrete", 0xb0b0b0),
+        ("wool", 0xdddddd),
+        ("planks", 0xa2824e),
+        ("log", 0x6b5130),
+        ("sand", 0xdbd3a0),
+        ("dirt", 0x866043),
+        ("grass_block", 0x5d9b3a),
+        ("water", 0x3f76e4),
+        ("lava", 0xd96415),
+        ("iron", 0xd8d8d8),
+        ("gold", 0xf5da2a),
+        ("diamond", 0x62e0d6),
+        ("emerald", 0x2fc05a),
+        ("lapis", 0x1f4aa0),
+        ("obsidian", 0x1a1423),
+        ("piston", 0x9c8a62),
+        ("observer", 0x626262),
+        ("lamp", 0xb27a3c),
+        ("soul", 0x4fd0d5),
+    ];
+
+    let rgb = match known.iter().find(|(i, _)| name.contains(i)) {
+        Some((_, rgb)) => *rgb,
+        None => {
+            // FNV-1a, so the same id gets the same colour in every export
+            let hash = name.bytes().fold(0x811c9dc5u32, |hash, i| (hash ^ i as u32).wrapping_mul(0x01000193));
+            hash & 0x7f7f7f | 0x404040
+        }
+    };
+
+    [16, 8, 0].map(|shift| ((rgb >> shift) & 0xff) as f32 / 255.0)
+}
+
+/// Write a page showing `schematic` in 3D to `out_dir`, which can be opened
+/// straight from disk or put on any static file host. three.js is loaded
+/// from a CDN; the geometry is baked into `geometry.js` next to the page.
+pub fn export_web(schematic: &Schematic, title: &str, out_dir: impl AsRef<Path>) -> color_eyre::Result<()> {
+    let out_dir = out_dir.as_ref();
+    fs::create_dir_all(out_dir).wrap_err("create output directory")?;
+
+    // a plain script instead of a json file, since browsers don't let pages
+    // opened from disk fetch other files
+    let geometry = serde_json::to_string(&Geometry::of(schematic)).wrap_err("serialize geometry")?;
+    fs::write(out_dir.join("geometry.js"), format!("window.GEOMETRY = {geometry};\n"))
+        .wrap_err("write geometry")?;
+
+    let title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    fs::write(out_dir.join("index.html"), INDEX_HTML.replace("{{title}}", &title))
+        .wrap_err("write viewer")?;
+
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use color_eyre::eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::hooks::HookConfig;
+use crate::notify::Webhook;
+use crate::queue::Schedule;
+use crate::server::ServerConfig;
+
+pub const CONFIG_FILE: &str = "pipeline.toml";
+
+pub const PROGRAMS: &str = "programs";
+pub const LAYOUTS: &str = "layouts";
+pub const TEMPLATES: &str = "templates";
+pub const BUNDLES: &str = "bundles";
+
+const DEFAULT_CONFIG: &str = r#"# schematic in templates/ that programs are written into
+template = "rom"
+# name the programmed schematic is uploaded as
+output = "generated"
+# server profile to use when none is given with --server
+server = "fili"
+
+# [servers.fili]
+# host = "donsz.nl"
+# user = "jonathan"
+# world_dir = "/minecraft/active-world"
+# # "worldedit" or "fawe"
+# plugin = "worldedit"
+# extension = "schem"
+# # upload to a temporary file and rename it into place, needs sha256sum
+# atomic_upload = true
+# # "ssh", or "local" to use world_dir on this machine
+# backend = "ssh"
+
+# commands run at each step of the pipeline, given the path of the schematic
+# [hooks]
+# on_loaded = []
+# on_programmed = []
+# before_upload = []
+# after_upload = []
+
+# webhooks told about every upload, "generic" or "discord"
+# [[notify]]
+# url = "secret:discord-webhook"
+# format = "discord"
+
+# only upload at these hours (UTC) and with at most this many players online,
+# schematics generated at other times are queued, see `schematics queue`
+# [schedule]
+# from_hour = 2
+# to_hour = 6
+# max_players = 1
+"#;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PipelineConfig {
+    pub template: Option<String>,
+    pub output: Option<String>,
+    /// The default server profile.
+    pub server: Option<String>,
+    #[serde(default)]
+    pub servers: BTreeMap<String, ServerConfig>,
+    #[serde(default)]
+    pub hooks: HookConfig,
+    /// Webhooks to tell about every upload.
+    #[serde(default)]
+    pub notify: Vec<Webhook>,
+    /// When uploads are allowed.
+    #[serde(default)]
+    pub schedule: Schedule,
+}
+
+/// A project directory, recognised by the `pipeline.toml` at its root.
+pub struct Workspace {
+    root: PathBuf,
+    pub config: PipelineConfig,
+}
+
+impl Workspace {
+    /// Create the workspace layout in `root`. Existing files are left alone.
+    pub fn init(root: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let root = root.as_ref();
+
+        for dir in [PROGRAMS, LAYOUTS, TEMPLATES, BUNDLES] {
+            fs::create_dir_all(root.join(dir))
+                .wrap_err_with(|| format!("create {dir}"))?;
+        }
+
+        let config = root.join(CONFIG_FILE);
+        if !config.exists() {
+            fs::write(&config, DEFAULT_CONFIG).wrap_err("write pipeline config")?;
+        }
+
+        info!("initialized workspace in {}", root.display());
+        Self::open(root)
+    }
+
+    pub fn open(root: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let config = fs::read_to_string(root.join(CONFIG_FILE))
+            .wrap_err("read pipeline config")?;
+        let config = toml::from_str(&config)
+            .wrap_err("parse pipeline config")?;
+
+        Ok(Self { root, config })
+    }
+
+    /// Find the workspace containing `dir`, looking through all of its parents.
+    pub fn find(dir: impl AsRef<Path>) -> color_eyre::Result<Option<Self>> {
+        for dir in dir.as_ref().ancestors() {
+            if dir.join(CONFIG_FILE).is_file() {
+                return Self::open(dir).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn find_current() -> color_eyre::Result<Option<Self>> {
+        Self::find(std::env::current_dir()?)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn dir(&self, kind: &str) -> PathBuf {
+        self.root.join(kind)
+    }
+
+    /// Look up `name` in the `kind` directory of the workspace, trying it both
+    /// as given and with `extension` appended.
+    pub fn resolve_in(&self, kind: &str, name: &str, extension: &str) -> Option<PathBuf> {
+        let dir = self.dir(kind);
+        [dir.join(name), dir.join(format!("{name}.{extension}"))]
+            .into_iter()
+            .find(|i| i.is_file())
+    }
+
+    /// Resolve the name of a schematic, which is either a path or the name of a
+    /// template in the workspace.
+    pub fn resolve_schematic(&self, name: &str) -> PathBuf {
+        if Path::new(name).exists() {
+            return name.into();
+        }
+
+        self.resolve_in(TEMPLATES, name, "schem")
+            .unwrap_or_else(|| name.into())
+    }
+
+    /// Resolve the name of an archive, which is either a path or the name of a
+    /// bundle in the workspace.
+    pub fn resolve_bundle(&self, name: &str) -> PathBuf {
+        if Path::new(name).exists() {
+            return name.into();
+        }
+
+        ["zip", "tar", "tar.gz", "tgz"].iter()
+            .find_map(|ext| self.resolve_in(BUNDLES, name, ext))
+            .unwrap_or_else(|| name.into())
+    }
+}
@@ -0,0 +1,213 @@
+//! The whole pipeline, from downloading the rom to uploading the programmed
+//! one, against a local directory laid out like a server's world directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const REFERENCE_ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/reference-rom.schem");
+
+/// A workspace using the `local` backend, with the reference rom on the
+/// "server" as the rom `schematics` downloads by default.
+struct MockServerBackend {
+    workspace: PathBuf,
+}
+
+impl MockServerBackend {
+    fn new(name: &str, extra_config: &str) -> Self {
+        let workspace = std::env::temp_dir().join(format!("schematics-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&workspace);
+        fs::create_dir_all(&workspace).unwrap();
+
+        let world = workspace.join("world");
+        fs::write(workspace.join("pipeline.toml"), format!(r#"
+server = "local"
+{extra_config}
+
+[servers.local]
+host = "localhost"
+user = "test"
+world_dir = "{}"
+backend = "local"
+"#, world.display())).unwrap();
+
+        let res = Self { workspace };
+        fs::create_dir_all(res.schematic_dir()).unwrap();
+        fs::copy(REFERENCE_ROM, res.schematic("jona-diag-rom-fixed")).unwrap();
+
+        res
+    }
+
+    fn schematic_dir(&self) -> PathBuf {
+        self.workspace.join("world/plugins/WorldEdit/schematics")
+    }
+
+    fn schematic(&self, name: &str) -> PathBuf {
+        self.schematic_dir().join(format!("{name}.schem"))
+    }
+
+    /// The files in the schematic folder, sorted.
+    fn files(&self) -> Vec<String> {
+        let mut res: Vec<_> = fs::read_dir(self.schematic_dir()).unwrap()
+            .map(|i| i.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        res.sort();
+        res
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let out = Command::new(env!("CARGO_BIN_EXE_minecraft"))
+            .args(args)
+            .current_dir(&self.workspace)
+            .output()
+            .unwrap();
+
+        assert!(
+            out.status.success(),
+            "schematics {} failed:\n{}{}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stdout),
+            String::from_utf8_lossy(&out.stderr),
+        );
+        out
+    }
+
+    /// Like [`run`](Self::run), for commands that should fail.
+    fn fail(&self, args: &[&str]) -> Output {
+        let out = Command::new(env!("CARGO_BIN_EXE_minecraft"))
+            .args(args)
+            .current_dir(&self.workspace)
+            .output()
+            .unwrap();
+
+        assert!(!out.status.success(), "schematics {} succeeded", args.join(" "));
+        out
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.workspace.join(file)
+    }
+}
+
+impl Drop for MockServerBackend {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.workspace);
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> Vec<u8> {
+    fs::read(path.as_ref()).unwrap_or_else(|e| panic!("read {}: {e}", path.as_ref().display()))
+}
+
+#[test]
+fn programs_and_uploads_the_rom() {
+    let server = MockServerBackend::new("upload", "");
+    server.run(&[]);
+
+    assert_eq!(read(server.path("input.schem")), read(REFERENCE_ROM));
+    assert_eq!(read(server.schematic("generated")), read(server.path("generated.schem")));
+    assert!(server.path("generated.lst").is_file());
+    // no locks or temporary files left behind
+    assert_eq!(server.files(), ["generated.schem", "jona-diag-rom-fixed.schem"]);
+}
+
+#[test]
+fn writes_byte_identical_schematics() {
+    let server = MockServerBackend::new("deterministic", "");
+    server.run(&["--deterministic"]);
+    let first = read(server.path("generated.schem"));
+    server.run(&["--deterministic"]);
+
+    assert_eq!(read(server.path("generated.schem")), first);
+}
+
+#[test]
+fn downloads_schematics() {
+    let server = MockServerBackend::new("download", "");
+    server.run(&["download", "jona-diag-rom-fixed", "-o", "out.schem"]);
+
+    assert_eq!(read(server.path("out.schem")), read(REFERENCE_ROM));
+}
+
+#[test]
+fn takes_over_stale_locks() {
+    let server = MockServerBackend::new("stale-lock", "");
+    fs::write(server.schematic_dir().join("generated.schem.lock"), "someone@elsewhere/1 0\n").unwrap();
+    server.run(&[]);
+
+    assert_eq!(server.files(), ["generated.schem", "jona-diag-rom-fixed.schem"]);
+}
+
+#[test]
+fn queues_outside_of_the_schedule() {
+    // an empty upload window
+    let server = MockServerBackend::new("queue", "[schedule]\nfrom_hour = 0\nto_hour = 0");
+    server.run(&[]);
+
+    assert_eq!(server.files(), ["jona-diag-rom-fixed.schem"]);
+    let list = server.run(&["queue", "list"]);
+    assert!(String::from_utf8_lossy(&list.stdout).contains("generated"));
+
+    server.run(&["queue", "run"]);
+    assert_eq!(server.files(), ["jona-diag-rom-fixed.schem"]);
+
+    server.run(&["queue", "clear"]);
+    let list = server.run(&["queue", "list"]);
+    assert!(!String::from_utf8_lossy(&list.stdout).contains("generated"));
+}
+
+#[test]
+fn transforms_many_schematics_like_one() {
+    let server = MockServerBackend::new("transform-all", "");
+    server.run(&["--deterministic", "transform", REFERENCE_ROM, "--rotate", "1", "--mirror", "x", "-o", "one.schem"]);
+    server.run(&["--deterministic", "transform-all", REFERENCE_ROM, "--rotate", "1", "--mirror", "x", "-o", "all"]);
+
+    assert_eq!(read(server.path("all/reference-rom.schem")), read(server.path("one.schem")));
+}
+
+#[test]
+fn patches_reproduce_the_programmed_rom() {
+    let server = MockServerBackend::new("patch", "");
+    server.run(&[]);
+    server.run(&["diff", "--exact", "input.schem", "generated.schem", "--patch", "program.patch"]);
+    server.run(&["apply-patch", "input.schem", "program.patch", "-o", "patched.schem"]);
+
+    let remaining = server.run(&["diff", "--exact", "generated.schem", "patched.schem"]);
+    assert_eq!(String::from_utf8_lossy(&remaining.stdout), "");
+}
+
+#[test]
+fn pastes_only_the_torches_of_the_programmed_rom() {
+    let server = MockServerBackend::new("keep-mask", "");
+    server.run(&[]);
+    server.run(&["paste", "input.schem", "generated.schem", "--at", "0", "0", "0", "-o", "pasted.schem"]);
+
+    // the air around the torches is left out, so only torches change
+    let changes = server.run(&["diff", "--exact", "input.schem", "pasted.schem"]);
+    let changes = String::from_utf8_lossy(&changes.stdout);
+    assert!(changes.lines().count() > 0);
+    assert!(changes.lines().all(|i| i.contains("-> minecraft:redstone_wall_torch")), "{changes}");
+}
+
+#[test]
+fn refuses_roms_that_changed_since_calibration() {
+    let server = MockServerBackend::new("calibration", "");
+    let layout = "layouts/jona-diag-rom-fixed.romlayout";
+    server.run(&["calibrate-rom", REFERENCE_ROM, "-o", layout]);
+    server.run(&[]);
+
+    let calibrated = fs::read_to_string(server.path(layout)).unwrap();
+    let fingerprint = calibrated.lines().find(|i| i.starts_with("fingerprint")).unwrap();
+    fs::write(server.path(layout), calibrated.replace(fingerprint, "fingerprint = \"0\"")).unwrap();
+
+    let out = server.fail(&[]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("changed shape"));
+}
+
+#[test]
+fn converts_without_changing_the_schematic() {
+    let server = MockServerBackend::new("convert", "");
+    for version in ["2", "3"] {
+        server.run(&["convert", REFERENCE_ROM, &format!("v{version}.schem"), "--format-version", version, "--verify"]);
+    }
+}